@@ -33,6 +33,14 @@ read_expected_ret_code() {
     echo $excepted_ret_code
 }
 
+# Flags of a case beyond the common ones, e.g. --relaxed-cycles
+read_extra_args() {
+    file_path=$1/.extra_args
+    if [ -f $file_path ]; then
+        cat $file_path
+    fi
+}
+
 to_absolute_path() {
     echo "$(cd "$(dirname "$1")"; pwd)/$(basename "$1")"
 }
@@ -61,7 +69,9 @@ for test_case in $test_cases; do
     abs_dummy_dir=$(to_absolute_path dummy)
     abs_output_dir=$(to_absolute_path output)
 
-    $deployfix_bin k8s go $abs_test_case $abs_dummy_dir $abs_output_dir --cycle-check > /dev/null 2>&1
+    extra_args=$(read_extra_args $test_case)
+
+    $deployfix_bin k8s go $abs_test_case $abs_dummy_dir $abs_output_dir --cycle-check $extra_args > /dev/null 2>&1
     ret_code=$?
 
     excepted_ret_code=$(read_expected_ret_code $test_case)
@@ -3,10 +3,10 @@ import os
 import json
 
 def load_outputs():
-    if not os.path.exists("output/conflicts-node.yaml"):
+    if not os.path.exists("output/node/conflicts.yaml"):
         deployfix_conflict_names = []
     else: 
-        with open("output/conflicts-node.yaml", "r") as r:
+        with open("output/node/conflicts.yaml", "r") as r:
             deployfix_yaml = yaml.safe_load(r)
 
             deployfix_conflict_names = deployfix_yaml["unscheduable_entities"]
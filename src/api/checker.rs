@@ -0,0 +1,273 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::PathBuf,
+};
+
+use crate::{
+    cli::{collect_inputs, detect_format, recommend_actions, RecommendedAction},
+    model::{
+        apply_layers, get_parser, load_env_files, merge_entities, Entity, EntityRule,
+        EnvParseError, LayerChange, ParserError,
+    },
+    plugin::k8s::recommend_rules,
+    solver::{
+        decode_snapshot, get_solver_with, EntityMap, EntityMapError, SnapshotError, SolverError,
+        SolverOptions, SolverOutput, SNAPSHOT_EXTENSION,
+    },
+    util,
+};
+
+use super::RecommendPolicy;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CheckError {
+    #[error("Unable to detect the format of {0}")]
+    UnknownFormat(PathBuf),
+    #[error("No model found in the inputs")]
+    NoInputs,
+    #[error("Failed to read {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to parse {path}: {source}")]
+    Parse { path: PathBuf, source: ParserError },
+    #[error("Failed to read snapshot {path}: {source}")]
+    Snapshot {
+        path: PathBuf,
+        source: SnapshotError,
+    },
+    #[error("Failed to load env files: {0}")]
+    Env(#[from] EnvParseError),
+    #[error(transparent)]
+    Model(#[from] EntityMapError),
+    #[error(transparent)]
+    Solver(#[from] SolverError),
+}
+
+// An entity the solvers cannot schedule, with the rules making it so
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub entity: String,
+    pub rules: Vec<EntityRule>,
+    // Solvers which reported it
+    pub solvers: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CheckResult {
+    // The checked model, the inputs merged with their layers applied
+    pub entities: Vec<Entity>,
+    // Changes of the layer directives of the inputs
+    pub layer_changes: Vec<LayerChange>,
+    pub conflicts: Vec<Conflict>,
+    // Entities schedulable without some of their preferences, e.g. preferred affinities
+    pub unmet_preferences: Vec<Conflict>,
+    // Entities a solver gave up on, with its reason
+    pub undetermined: BTreeMap<String, String>,
+    // Rules to change picked by the recommend policy, and how to change each of them
+    pub recommendations: Vec<EntityRule>,
+    pub actions: Vec<RecommendedAction>,
+}
+
+impl CheckResult {
+    // No conflict and no entity left undecided, what `check` exits with 0 for
+    pub fn is_conflict_free(&self) -> bool {
+        self.conflicts.is_empty() && self.undetermined.is_empty()
+    }
+
+    pub fn conflict(&self, entity: &str) -> Option<&Conflict> {
+        self.conflicts
+            .iter()
+            .find(|conflict| conflict.entity == entity)
+    }
+}
+
+/*
+    Configuration of a check, built like:
+
+    Checker::new().format("yaml").solvers(["z3", "structural"]).cycle_check(false)
+
+    By default the format of each input follows its extension, z3 and the ring solver run
+    with the default `SolverOptions`, no env file places the entities and no rule is
+    recommended. Kubernetes manifests are
+    read with `plugin::k8s::import_manifests` and given to `check`.
+*/
+#[derive(Debug, Clone)]
+pub struct Checker {
+    format: Option<String>,
+    solvers: Vec<String>,
+    env_files: Vec<PathBuf>,
+    cycle_check: bool,
+    options: SolverOptions,
+    recommend: Option<RecommendPolicy>,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Self {
+            format: None,
+            solvers: vec!["z3".to_string()],
+            env_files: vec![],
+            cycle_check: true,
+            options: SolverOptions::default(),
+            recommend: None,
+        }
+    }
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Format of every input, `deployfix`, `json` or `yaml`, instead of their extensions
+    pub fn format(mut self, format: impl Into<String>) -> Self {
+        self.format = Some(format.into());
+        self
+    }
+
+    // Solvers to run, any of `SOLVER_NAMES`, replacing z3
+    pub fn solvers<S: Into<String>>(mut self, solvers: impl IntoIterator<Item = S>) -> Self {
+        self.solvers = solvers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    // Env file z3 places the entities on, later files override earlier ones
+    pub fn env_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.env_files.push(path.into());
+        self
+    }
+
+    // Whether the ring solver reports require cycles, on by default like `check`
+    pub fn cycle_check(mut self, cycle_check: bool) -> Self {
+        self.cycle_check = cycle_check;
+        self
+    }
+
+    // Cycle options and z3 timeout of the solvers, like the global flags of `check`
+    pub fn solver_options(mut self, options: SolverOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    // Recommend the rules to change with `policy`, like `k8s go --recommend`
+    pub fn recommend(mut self, policy: RecommendPolicy) -> Self {
+        self.recommend = Some(policy);
+        self
+    }
+
+    // Entities of the inputs, read like `check` reads its paths
+    pub fn import(&self, paths: &[PathBuf]) -> Result<(Vec<Entity>, Vec<LayerChange>), CheckError> {
+        let layers = collect_inputs(paths)
+            .into_iter()
+            .map(|path| {
+                let format = detect_format(&path, self.format.as_deref())
+                    .ok_or_else(|| CheckError::UnknownFormat(path.clone()))?;
+
+                if format == SNAPSHOT_EXTENSION {
+                    let data = std::fs::read(&path).map_err(|source| CheckError::Read {
+                        path: path.clone(),
+                        source,
+                    })?;
+
+                    return decode_snapshot(&data)
+                        .map_err(|source| CheckError::Snapshot { path, source });
+                }
+
+                let parser = get_parser(&format).map_err(|source| CheckError::Parse {
+                    path: path.clone(),
+                    source,
+                })?;
+                let data = util::read_source(&path).map_err(|source| CheckError::Read {
+                    path: path.clone(),
+                    source,
+                })?;
+
+                parser
+                    .parse(&data, path.clone().into())
+                    .map_err(|source| CheckError::Parse { path, source })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if !paths.is_empty() && layers.is_empty() {
+            return Err(CheckError::NoInputs);
+        }
+
+        let (entities, changes) = apply_layers(layers);
+        Ok((merge_entities(entities, None), changes))
+    }
+
+    pub fn check_paths(&self, paths: &[PathBuf]) -> Result<CheckResult, CheckError> {
+        let (entities, layer_changes) = self.import(paths)?;
+
+        Ok(CheckResult {
+            layer_changes,
+            ..self.check(entities)?
+        })
+    }
+
+    pub fn check(&self, entities: Vec<Entity>) -> Result<CheckResult, CheckError> {
+        let mut names = self.solvers.clone();
+        if self.cycle_check && !names.iter().any(|name| name.starts_with("ring")) {
+            names.push("ring".to_string());
+        }
+        let backends = names
+            .iter()
+            .map(|name| get_solver_with(name, &self.options))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let envs = load_env_files(&self.env_files)?;
+        if !envs.is_empty() {
+            if let Some(z3) = names.iter().position(|name| name == "z3") {
+                backends[z3].set_envs(envs);
+            }
+        }
+
+        let entity_map: EntityMap = entities.try_into()?;
+        let result = backends
+            .iter()
+            .map(|solver| solver.solve(&entity_map))
+            .fold(SolverOutput::Ok, SolverOutput::merge);
+
+        let (result, undetermined) = result.take_undetermined();
+        let (result, soft) = result.take_soft();
+        let entities = entity_map.entities;
+
+        let (conflicts, solvers) = match result {
+            SolverOutput::Conflict(conflicts, solvers) => (conflicts, solvers),
+            _ => Default::default(),
+        };
+        let (recommendations, actions) = match self.recommend {
+            Some(policy) if !conflicts.is_empty() => {
+                let rules = recommend_rules(policy, &entities, &conflicts);
+                let actions = recommend_actions(&entities, &conflicts, &rules);
+                (rules, actions)
+            }
+            _ => (vec![], vec![]),
+        };
+
+        let by_entity = |conflicts: HashMap<String, Vec<EntityRule>>| {
+            let mut conflicts = conflicts
+                .into_iter()
+                .map(|(entity, rules)| Conflict {
+                    solvers: solvers.get(&entity).cloned().unwrap_or_default(),
+                    entity,
+                    rules,
+                })
+                .collect::<Vec<_>>();
+            conflicts.sort_by(|a, b| a.entity.cmp(&b.entity));
+            conflicts
+        };
+
+        Ok(CheckResult {
+            conflicts: by_entity(conflicts),
+            unmet_preferences: by_entity(soft),
+            undetermined: undetermined.map(|u| u.entities).unwrap_or_default(),
+            recommendations,
+            actions,
+            entities,
+            layer_changes: vec![],
+        })
+    }
+}
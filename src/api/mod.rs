@@ -0,0 +1,18 @@
+/*
+    Checks of deployfix for other Rust programs: the models are read, solved and explained
+    like `deployfix-cli check` does, with every failure returned instead of ending the
+    process.
+
+    let result = Checker::new()
+        .env_file("nodes.env")
+        .recommend(RecommendPolicy::All)
+        .check_paths(&["model.ir".into()])?;
+    for conflict in result.conflicts.iter() {
+        println!("{} is unschedulable", conflict.entity);
+    }
+*/
+mod checker;
+
+pub use checker::{CheckError, CheckResult, Checker, Conflict};
+
+pub use crate::{plugin::k8s::RecommendPolicy, solver::SolverOptions};
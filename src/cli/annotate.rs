@@ -1,53 +1,176 @@
-use crate::model::EntityRule;
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{model::EntityRule, util::read_source};
 use annotate_snippets::{Annotation, AnnotationType, Renderer, Slice, Snippet, SourceAnnotation};
 
+// Files larger than this are not read, their conflicts get a summary line instead of a snippet
+pub const MAX_ANNOTATED_FILE_BYTES: u64 = 1024 * 1024;
+// Lines of a snippet at most, a rule range may cover a whole generated manifest
+const MAX_SNIPPET_LINES: usize = 16;
+// Characters kept of each snippet line, generated manifests often have very long ones
+const MAX_SNIPPET_LINE_CHARS: usize = 240;
+
+#[derive(Debug)]
+enum Source {
+    Text(String),
+    // Size of a file over `MAX_ANNOTATED_FILE_BYTES`
+    TooLarge(u64),
+    Unreadable(String),
+}
+
+// Source files read once for all the conflicts of a run, each rule of a large manifest
+// would otherwise read it again
+#[derive(Debug, Default)]
+pub struct SourceCache {
+    files: RefCell<HashMap<String, Rc<Source>>>,
+}
+
+impl SourceCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn get(&self, file: &str) -> Rc<Source> {
+        self.files
+            .borrow_mut()
+            .entry(file.to_string())
+            .or_insert_with(|| {
+                let source = match std::fs::metadata(file) {
+                    Ok(metadata) if metadata.len() > MAX_ANNOTATED_FILE_BYTES => {
+                        Source::TooLarge(metadata.len())
+                    }
+                    Ok(_) => read_source(file)
+                        .map(Source::Text)
+                        .unwrap_or_else(|e| Source::Unreadable(e.to_string())),
+                    Err(e) => Source::Unreadable(e.to_string()),
+                };
+
+                Rc::new(source)
+            })
+            .clone()
+    }
+}
+
 pub struct ConflictAnnotater<'a> {
     entity_name: &'a str,
     entity_source: String,
     entity_origin: String,
     rule_range: (usize, usize),
     rule_line: usize,
+    // The rule in plain words, labels the annotated range
+    rule_description: String,
+    // Why no snippet is shown, the annotation is then a single summary line
+    summary: Option<String>,
+    // Rendered below the snippet, e.g. why each alternative of a multi rule fails
+    notes: Vec<String>,
+}
+
+// At most `MAX_SNIPPET_LINE_CHARS` characters of `line`
+fn cap_line(line: &str) -> &str {
+    match line.char_indices().nth(MAX_SNIPPET_LINE_CHARS) {
+        Some((end, _)) => &line[..end],
+        None => line,
+    }
 }
 
 impl<'a> ConflictAnnotater<'a> {
-    fn read_source(entity_rule: &'a EntityRule) -> String {
-        match entity_rule.meta_file() {
-            Some(file) => {
-                let source = std::fs::read_to_string(file).unwrap();
-                let range = entity_rule.range();
-
-                let lines = source.lines().collect::<Vec<_>>();
-                let line = entity_rule.meta_line().unwrap_or(0);
-                // If the range is specified, use it
-                if let Some((start, end)) = range {
-                    let start_line = source[..start].matches('\n').count() - 1;
-                    let end_line = source[..end].matches('\n').count() - 1;
-
-                    let start = (start_line - 1).max(0);
-                    let end = (end_line + 1).min(lines.len() - 1);
-
-                    lines[start..=end].join("\n")
-                } else if line > 0 {
-                    let start = (line - 2).max(0);
-                    let end = (line + 6).min(lines.len() - 1);
-
-                    lines[start..=end].join("\n")
-                } else {
-                    source
-                }
+    // Lines around the rule, or why there are none, and the range of the rule in them
+    fn read_source(
+        entity_rule: &'a EntityRule,
+        sources: &SourceCache,
+    ) -> (String, Option<String>, (usize, usize)) {
+        let Some(file) = entity_rule.meta_file().or(entity_rule.file()) else {
+            return ("unknown".to_string(), None, (0, 0));
+        };
+
+        let source = sources.get(file);
+        let source = match source.as_ref() {
+            Source::Text(source) => source,
+            Source::TooLarge(size) => {
+                return (
+                    String::new(),
+                    Some(format!(
+                        "{} is {} KiB, over the {} KiB annotation limit, source not shown",
+                        file,
+                        size / 1024,
+                        MAX_ANNOTATED_FILE_BYTES / 1024
+                    )),
+                    (0, 0),
+                )
+            }
+            Source::Unreadable(err) => {
+                return (
+                    String::new(),
+                    Some(format!("{} cannot be read: {}", file, err)),
+                    (0, 0),
+                )
             }
-            None => "unknown".to_string(),
+        };
+
+        let lines = source.lines().collect::<Vec<_>>();
+        let last = lines.len().saturating_sub(1);
+        let line = entity_rule.meta_line().unwrap_or(0);
+
+        // If the range is specified, use it
+        let (start, end) = if let Some((start, end)) = entity_rule.range() {
+            let line_of = |offset: usize| {
+                source.as_bytes()[..offset.min(source.len())]
+                    .iter()
+                    .filter(|b| **b == b'\n')
+                    .count()
+                    .saturating_sub(1)
+            };
+
+            (
+                line_of(start).saturating_sub(1),
+                (line_of(end) + 1).min(last),
+            )
+        } else if line > 0 {
+            (line.saturating_sub(2), (line + 6).min(last))
+        } else {
+            (0, last)
+        };
+
+        let range = entity_rule.range().unwrap_or((0, 0));
+        if lines.is_empty() || start > end {
+            return (source.to_string(), None, range);
         }
+
+        let end = end.min(start + MAX_SNIPPET_LINES - 1);
+        let snippet = lines[start..=end]
+            .iter()
+            .map(|line| cap_line(line))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        // Offsets of the range are in the file, the snippet starts at line `start` of it
+        let offset = lines[..start]
+            .iter()
+            .map(|line| line.len() + 1)
+            .sum::<usize>();
+        let range = (
+            range.0.saturating_sub(offset),
+            range.1.saturating_sub(offset),
+        );
+
+        (snippet, None, range)
     }
 
     pub fn new(entity_name: &'a str, entity_rule: &'a EntityRule) -> ConflictAnnotater<'a> {
-        let entity_source = Self::read_source(entity_rule);
+        Self::new_with_cache(entity_name, entity_rule, &SourceCache::new())
+    }
+
+    pub fn new_with_cache(
+        entity_name: &'a str,
+        entity_rule: &'a EntityRule,
+        sources: &SourceCache,
+    ) -> ConflictAnnotater<'a> {
+        let (entity_source, summary, rule_range) = Self::read_source(entity_rule, sources);
         let entity_origin = entity_rule
             .meta_file()
             .or(entity_rule.file())
             .unwrap_or("unknown")
             .to_string();
-        let rule_range = entity_rule.range().unwrap_or((0, 0));
         let rule_line = entity_rule.meta_line().or(entity_rule.line()).unwrap_or(0);
 
         ConflictAnnotater {
@@ -56,9 +179,17 @@ impl<'a> ConflictAnnotater<'a> {
             entity_origin,
             rule_range,
             rule_line,
+            rule_description: entity_rule.describe(),
+            summary,
+            notes: vec![],
         }
     }
 
+    pub fn with_notes(mut self, notes: Vec<String>) -> Self {
+        self.notes = notes;
+        self
+    }
+
     pub fn get_entity_name(&self) -> &str {
         self.entity_name
     }
@@ -68,7 +199,24 @@ impl<'a> ConflictAnnotater<'a> {
     }
 
     pub fn annotate(&self) -> String {
+        self.render(Renderer::styled())
+    }
+
+    // Without the terminal colors, for outputs which are not a terminal of their own
+    pub fn annotate_plain(&self) -> String {
+        self.render(Renderer::plain())
+    }
+
+    fn render(&self, renderer: Renderer) -> String {
         let label = format!("Unscheduable entity: {}", self.entity_name);
+        let summary = self
+            .summary
+            .as_ref()
+            .map(|summary| format!("{}:{}: {}", self.entity_origin, self.rule_line, summary));
+
+        // The range must stay within the snippet, which was cut down from the file
+        let len = self.entity_source.len();
+        let range = (self.rule_range.0.min(len), self.rule_range.1.min(len));
 
         let snippet = Snippet {
             title: Some(Annotation {
@@ -76,21 +224,31 @@ impl<'a> ConflictAnnotater<'a> {
                 label: Some(label.as_str()),
                 annotation_type: AnnotationType::Error,
             }),
-            footer: vec![],
-            slices: vec![Slice {
-                source: self.entity_source.as_str(),
-                line_start: self.rule_line,
-                origin: Some(self.entity_origin.as_str()),
-                fold: false,
-                annotations: vec![SourceAnnotation {
-                    label: &label,
-                    annotation_type: AnnotationType::Error,
-                    range: self.rule_range,
+            footer: summary
+                .iter()
+                .chain(self.notes.iter())
+                .map(|note| Annotation {
+                    id: None,
+                    label: Some(note.as_str()),
+                    annotation_type: AnnotationType::Note,
+                })
+                .collect(),
+            slices: match summary {
+                Some(_) => vec![],
+                None => vec![Slice {
+                    source: self.entity_source.as_str(),
+                    line_start: self.rule_line,
+                    origin: Some(self.entity_origin.as_str()),
+                    fold: false,
+                    annotations: vec![SourceAnnotation {
+                        label: &self.rule_description,
+                        annotation_type: AnnotationType::Error,
+                        range,
+                    }],
                 }],
-            }],
+            },
         };
 
-        let renderer = Renderer::styled();
         let output = format!("{}", renderer.render(snippet));
 
         output
@@ -0,0 +1,147 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::model::{
+    rule_location, rule_text, Entity, EntityRuleTopologyKey, EntityRuleType, METADATA_TOPOLOGY_KEY,
+};
+
+// A container running on a node, with the entities (e.g. allocation tags) it stands for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    pub node: String,
+    pub rack: Option<String>,
+    pub tags: Vec<String>,
+}
+
+// A rule broken by the containers of an entity on one node
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PlacementViolation {
+    pub entity: String,
+    pub node: String,
+    pub rule: String,
+    pub location: Option<String>,
+    // Containers of the entity on the node
+    pub containers: usize,
+    pub message: String,
+}
+
+// Domain of a node on `topology`, racks for rack rules and the node itself otherwise
+fn domain<'a>(placement: &'a Placement, topology: &str) -> &'a str {
+    if topology == EntityRuleTopologyKey::Rack.to_string() {
+        if let Some(rack) = placement.rack.as_deref() {
+            return rack;
+        }
+    }
+
+    &placement.node
+}
+
+// Checks the rules of `entities` against where their containers actually run. A container
+// breaks a require rule when no container of the targets runs in its domain, and an exclude
+// rule when one does; a container never counts as its own target.
+pub fn audit_placements(entities: &[Entity], placements: &[Placement]) -> Vec<PlacementViolation> {
+    // Containers of each tag per (topology, domain), topologies are filled on demand
+    let mut counts: HashMap<String, HashMap<(&str, &str), usize>> = HashMap::new();
+    let mut count = |topology: &str, domain: &str, tag: &str| -> usize {
+        let counts = counts.entry(topology.to_string()).or_insert_with(|| {
+            let mut counts = HashMap::new();
+            for placement in placements {
+                for tag in &placement.tags {
+                    *counts
+                        .entry((self::domain(placement, topology), tag.as_str()))
+                        .or_default() += 1;
+                }
+            }
+            counts
+        });
+
+        counts.get(&(domain, tag)).copied().unwrap_or(0)
+    };
+
+    let by_name = entities
+        .iter()
+        .map(|entity| (entity.name.as_ref(), entity))
+        .collect::<HashMap<_, _>>();
+
+    // Containers of each entity per node
+    let mut nodes: BTreeMap<(&str, &str), (&Placement, usize)> = BTreeMap::new();
+    for placement in placements {
+        for tag in &placement.tags {
+            if by_name.contains_key(tag.as_str()) {
+                nodes
+                    .entry((tag.as_str(), placement.node.as_str()))
+                    .or_insert((placement, 0))
+                    .1 += 1;
+            }
+        }
+    }
+
+    let mut violations = vec![];
+    for ((name, node), (placement, containers)) in nodes {
+        for rule in by_name[name].rules() {
+            let topology = rule.metadata(METADATA_TOPOLOGY_KEY).unwrap_or("node");
+            let domain = domain(placement, topology);
+            let placed = rule
+                .targets()
+                .into_iter()
+                .filter(|target| {
+                    let own = usize::from(target.as_ref() == name);
+                    count(topology, domain, target.as_ref()) > own
+                })
+                .map(|target| target.as_ref())
+                .collect::<Vec<_>>();
+
+            let message = match rule.r#type() {
+                EntityRuleType::Require if placed.is_empty() => {
+                    format!(
+                        "no container of its targets runs on {} {}",
+                        topology, domain
+                    )
+                }
+                EntityRuleType::Exclude if !placed.is_empty() => {
+                    format!("runs on {} {} with {}", topology, domain, placed.join(", "))
+                }
+                _ => continue,
+            };
+
+            violations.push(PlacementViolation {
+                entity: name.to_string(),
+                node: node.to_string(),
+                rule: rule_text(rule),
+                location: rule_location(rule),
+                containers,
+                message,
+            });
+        }
+    }
+
+    violations.sort();
+    violations
+}
+
+/*
+    One line per rule broken on a node, with where the rule is declared:
+
+    hbase on host1 (2 containers): runs on rack /rack1 with zk
+      rule: hbase exclude zk (rule01.spec:1)
+*/
+pub fn format_violations(violations: &[PlacementViolation]) -> String {
+    let mut output = String::new();
+
+    for violation in violations {
+        output.push_str(&format!(
+            "{} on {} ({} container{}): {}\n  rule: {}",
+            violation.entity,
+            violation.node,
+            violation.containers,
+            if violation.containers == 1 { "" } else { "s" },
+            violation.message,
+            violation.rule
+        ));
+        if let Some(location) = violation.location.as_deref() {
+            output.push_str(&format!(" ({})", location));
+        }
+        output.push('\n');
+    }
+
+    output
+}
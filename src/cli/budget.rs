@@ -0,0 +1,76 @@
+use clap::Args;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::solver::ProblemSize;
+
+pub const DEFAULT_MAX_VARIABLES: usize = 20000;
+pub const DEFAULT_MAX_CLAUSES: usize = 200000;
+
+// Upper bounds on the estimated size of a single solve, exceeding them only warns
+#[derive(Debug, Clone, Args, Serialize, Deserialize)]
+pub struct BudgetArgs {
+    #[clap(
+        long,
+        default_value_t = DEFAULT_MAX_VARIABLES,
+        help = "Warn when a solve needs more z3 variables than this"
+    )]
+    max_variables: usize,
+    #[clap(
+        long,
+        default_value_t = DEFAULT_MAX_CLAUSES,
+        help = "Warn when a solve needs more z3 clauses than this"
+    )]
+    max_clauses: usize,
+}
+
+impl BudgetArgs {
+    // Limits of a profile, for the bounds left at their default
+    pub fn or_limits(mut self, max_variables: Option<usize>, max_clauses: Option<usize>) -> Self {
+        if self.max_variables == DEFAULT_MAX_VARIABLES {
+            self.max_variables = max_variables.unwrap_or(self.max_variables);
+        }
+        if self.max_clauses == DEFAULT_MAX_CLAUSES {
+            self.max_clauses = max_clauses.unwrap_or(self.max_clauses);
+        }
+
+        self
+    }
+
+    // Returns whether the problem fits in the budget
+    pub fn check(&self, size: &ProblemSize, hint: &str) -> bool {
+        let mut fits = true;
+
+        if size.variables() > self.max_variables {
+            warn!(
+                "The model needs about {} variables ({} entities, {} rules), more than the budget of {}",
+                size.variables(),
+                size.entities,
+                size.rules,
+                self.max_variables
+            );
+            fits = false;
+        }
+
+        if size.clauses > self.max_clauses {
+            warn!(
+                "The model needs about {} clauses, more than the budget of {}",
+                size.clauses, self.max_clauses
+            );
+            fits = false;
+        }
+
+        if !fits {
+            if size.split_entities > 0 {
+                warn!(
+                    "{} self-conflicting entities were split in two, which expands their rules",
+                    size.split_entities
+                );
+            }
+
+            warn!("Solving may take very long, consider {}", hint);
+        }
+
+        fits
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clap::Args;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::util;
+
+use super::{collect_inputs, doctor::z3_version, ConflictReport, SessionInput, StaleSource};
+
+// Version of the certificate format, certificates of another version are rejected
+pub const CERTIFICATE_VERSION: u32 = 1;
+
+// Key the certificates are signed with when `--certificate-key` is not given
+pub const CERTIFICATE_KEY_ENV: &str = "DEPLOYFIX_CERTIFICATE_KEY";
+
+// Result of the runs a certificate is issued for
+pub const CONFLICT_FREE: &str = "conflict-free";
+
+/*
+    Proof that a check passed on the exact files being deployed, written by `check
+    --certificate` and `k8s go --certificate` and read by `verify-certificate`:
+
+    {
+      "version": 1,
+      "deployfix": "0.1.0",
+      "solvers": { "z3": "Z3 4.12.2.0" },
+      "issued_at": "2024-01-31T10:00:00Z",
+      "command": "k8s go",
+      "inputs": [{ "path": "manifests/web.yaml", "digest": "9f86d081884c7d65..." }],
+      "result": "conflict-free",
+      "summary": "deployfix summary: 0 conflicts across 0 entities in 0 files",
+      "signature": "5d41402abc4b2a76..."
+    }
+
+    Inputs are digested with SHA-256, the signature is the HMAC-SHA256 of the certificate
+    without its signature, as compact JSON.
+*/
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Certificate {
+    pub version: u32,
+    pub deployfix: String,
+    // Versions of the solving backends
+    pub solvers: BTreeMap<String, String>,
+    pub issued_at: DateTime<Utc>,
+    pub command: String,
+    pub inputs: Vec<SessionInput>,
+    pub result: String,
+    pub summary: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CertificateError {
+    #[error("the certificate is not signed")]
+    Unsigned,
+    #[error("the signature does not match the certificate or the key")]
+    Signature,
+    #[error(transparent)]
+    Stale(#[from] StaleSource),
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> String {
+    const BLOCK: usize = 64;
+
+    let mut block = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<_>>();
+
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(message)
+        .finalize();
+    let outer = Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize();
+
+    format!("{:x}", outer)
+}
+
+impl Certificate {
+    // Digests every input of `paths`, an input which cannot be read fails the certificate
+    pub fn new(command: &str, paths: &[PathBuf], summary: String) -> anyhow::Result<Self> {
+        let inputs = collect_inputs(paths)
+            .into_iter()
+            .map(|path| {
+                let data = std::fs::read(&path)
+                    .with_context(|| format!("Failed to digest {}", path.display()))?;
+
+                Ok(SessionInput {
+                    path: path.display().to_string(),
+                    digest: sha256(&data),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            version: CERTIFICATE_VERSION,
+            deployfix: env!("CARGO_PKG_VERSION").to_string(),
+            solvers: BTreeMap::from([("z3".to_string(), z3_version())]),
+            issued_at: Utc::now(),
+            command: command.to_string(),
+            inputs,
+            result: CONFLICT_FREE.to_string(),
+            summary,
+            signature: None,
+        })
+    }
+
+    // What the signature covers: the certificate without its signature
+    fn payload(&self) -> Vec<u8> {
+        let unsigned = Self {
+            signature: None,
+            ..self.clone()
+        };
+
+        serde_json::to_vec(&unsigned).expect("certificates always serialize")
+    }
+
+    pub fn sign(mut self, key: &[u8]) -> Self {
+        self.signature = Some(hmac_sha256(key, &self.payload()));
+        self
+    }
+
+    /*
+        Problems making the certificate unfit for the files on disk: inputs changed or
+        removed since the check and, with `key`, a missing or mismatching signature. Without
+        a key the signature is not checked.
+    */
+    pub fn verify(&self, key: Option<&[u8]>) -> Vec<CertificateError> {
+        let mut errors = vec![];
+
+        if let Some(key) = key {
+            match self.signature.as_deref() {
+                None => errors.push(CertificateError::Unsigned),
+                Some(signature)
+                    if !util::same_secret(signature, &hmac_sha256(key, &self.payload())) =>
+                {
+                    errors.push(CertificateError::Signature)
+                }
+                Some(_) => {}
+            }
+        }
+
+        for input in self.inputs.iter() {
+            match std::fs::read(&input.path) {
+                Ok(data) if sha256(&data) == input.digest => {}
+                Ok(_) => errors.push(StaleSource::Changed(input.path.clone()).into()),
+                Err(_) => errors.push(StaleSource::Missing(input.path.clone()).into()),
+            }
+        }
+
+        errors
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read certificate {}", path.display()))?;
+        let certificate: Self = serde_json::from_str(&data)
+            .with_context(|| format!("Invalid certificate {}", path.display()))?;
+
+        if certificate.version != CERTIFICATE_VERSION {
+            anyhow::bail!(
+                "Certificate {} has version {}, this release reads version {}",
+                path.display(),
+                certificate.version,
+                CERTIFICATE_VERSION
+            );
+        }
+
+        Ok(certificate)
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let data = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, data + "\n")
+            .with_context(|| format!("Failed to write certificate {}", path.display()))
+    }
+}
+
+// The key of `path` without its trailing line break, or of `$DEPLOYFIX_CERTIFICATE_KEY`
+pub fn certificate_key(path: Option<&Path>) -> anyhow::Result<Option<Vec<u8>>> {
+    let key = match path {
+        Some(path) => std::fs::read(path)
+            .with_context(|| format!("Failed to read certificate key {}", path.display()))?,
+        None => match std::env::var(CERTIFICATE_KEY_ENV) {
+            Ok(key) => key.into_bytes(),
+            Err(_) => return Ok(None),
+        },
+    };
+
+    let key = key.trim_ascii_end().to_vec();
+    if key.is_empty() {
+        anyhow::bail!("The certificate key is empty");
+    }
+
+    Ok(Some(key))
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct CertificateArgs {
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Write a certificate of the inputs, solvers and result when no conflict is found"
+    )]
+    pub certificate: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "FILE",
+        requires = "certificate",
+        help = "Sign the certificate with the key in this file, $DEPLOYFIX_CERTIFICATE_KEY otherwise"
+    )]
+    pub certificate_key: Option<PathBuf>,
+}
+
+impl CertificateArgs {
+    // Writes the certificate of a run which neither found a conflict nor gave up on an entity
+    pub fn issue(
+        &self,
+        command: &str,
+        inputs: &[PathBuf],
+        report: &ConflictReport,
+    ) -> anyhow::Result<()> {
+        let Some(path) = self.certificate.as_deref() else {
+            return Ok(());
+        };
+        if report.has_conflict() || report.has_undetermined() {
+            warn!(
+                "No certificate written to {}, the check failed",
+                path.display()
+            );
+            return Ok(());
+        }
+
+        let certificate = Certificate::new(command, inputs, report.summary())?;
+        let certificate = match certificate_key(self.certificate_key.as_deref())? {
+            Some(key) => certificate.sign(&key),
+            None => {
+                warn!(
+                    "Certificate {} is not signed, pass --certificate-key or set {}",
+                    path.display(),
+                    CERTIFICATE_KEY_ENV
+                );
+                certificate
+            }
+        };
+
+        certificate.write(path)?;
+        info!("Certificate written to {}", path.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::model::{rule_location, rule_text, Entity, EntityRule, Env, METADATA_TOPOLOGY_KEY};
+
+// A rule declared in one environment only, with where it is declared there
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EnvRule {
+    pub rule: String,
+    pub topology: String,
+    pub location: Option<String>,
+}
+
+// Node labels which may let an entity conflicting in one environment be scheduled in the
+// other, those named by the rules of the entity only
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvHint {
+    pub entity: String,
+    // Labels of the nodes of the environment without the conflict only
+    pub present: Vec<String>,
+    // Labels of the nodes of the conflicting environment only
+    pub absent: Vec<String>,
+}
+
+// Differences between the models of two environments, entities aligned by name
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnvComparison {
+    pub left_entities: Vec<String>,
+    pub right_entities: Vec<String>,
+    pub left_rules: Vec<EnvRule>,
+    pub right_rules: Vec<EnvRule>,
+    // Conflicting entities of one environment that do not conflict in the other
+    pub left_conflicts: Vec<String>,
+    pub right_conflicts: Vec<String>,
+    // Hints for the conflicts of one environment only, when solved against env files
+    pub left_hints: Vec<EnvHint>,
+    pub right_hints: Vec<EnvHint>,
+}
+
+// Rules are the same in both environments when they read the same on the same topology,
+// wherever they are declared
+fn env_rules(entities: &[Entity]) -> BTreeMap<(String, String), &EntityRule> {
+    entities
+        .iter()
+        .flat_map(|entity| entity.rules())
+        .map(|rule| {
+            let topology = rule.metadata(METADATA_TOPOLOGY_KEY).unwrap_or("node");
+            ((rule_text(rule), topology.to_string()), rule)
+        })
+        .collect()
+}
+
+fn only_in(
+    rules: &BTreeMap<(String, String), &EntityRule>,
+    other: &BTreeMap<(String, String), &EntityRule>,
+) -> Vec<EnvRule> {
+    rules
+        .iter()
+        .filter(|(key, _)| !other.contains_key(*key))
+        .map(|((text, topology), rule)| EnvRule {
+            rule: text.clone(),
+            topology: topology.clone(),
+            location: rule_location(rule),
+        })
+        .collect()
+}
+
+pub fn compare_envs(left: &[Entity], right: &[Entity]) -> EnvComparison {
+    // Entities only targeted by rules exist as much as declared ones
+    let names = |entities: &[Entity]| {
+        entities
+            .iter()
+            .flat_map(|entity| {
+                std::iter::once(&entity.name).chain(entity.rules().flat_map(|rule| rule.targets()))
+            })
+            .map(|name| name.as_ref().to_string())
+            .collect::<BTreeSet<_>>()
+    };
+    let (left_names, right_names) = (names(left), names(right));
+    let (left_rules, right_rules) = (env_rules(left), env_rules(right));
+
+    EnvComparison {
+        left_entities: left_names.difference(&right_names).cloned().collect(),
+        right_entities: right_names.difference(&left_names).cloned().collect(),
+        left_rules: only_in(&left_rules, &right_rules),
+        right_rules: only_in(&right_rules, &left_rules),
+        ..Default::default()
+    }
+}
+
+impl EnvComparison {
+    // Adds the conflicts found in each environment, keeping those the other one does not have
+    pub fn with_conflicts(mut self, left: &BTreeSet<String>, right: &BTreeSet<String>) -> Self {
+        self.left_conflicts = left.difference(right).cloned().collect();
+        self.right_conflicts = right.difference(left).cloned().collect();
+        self
+    }
+
+    /*
+        Hints for the conflicts found in one environment only, from the labels of the nodes of
+        both: a label the rules of the entity name which only the other environment has, or
+        only the conflicting one has. Conflicts without such a label get no hint, they come
+        from the rules rather than the nodes.
+    */
+    pub fn with_env_hints(
+        mut self,
+        (left, left_envs): (&[Entity], &[Env]),
+        (right, right_envs): (&[Entity], &[Env]),
+    ) -> Self {
+        self.left_hints = env_hints(&self.left_conflicts, left, left_envs, right_envs);
+        self.right_hints = env_hints(&self.right_conflicts, right, right_envs, left_envs);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+fn env_labels(envs: &[Env]) -> BTreeSet<&str> {
+    envs.iter()
+        .flat_map(|env| env.labels.iter().map(String::as_str))
+        .collect()
+}
+
+fn env_hints(
+    conflicts: &[String],
+    entities: &[Entity],
+    envs: &[Env],
+    other_envs: &[Env],
+) -> Vec<EnvHint> {
+    let (labels, other_labels) = (env_labels(envs), env_labels(other_envs));
+
+    conflicts
+        .iter()
+        .filter_map(|name| {
+            let entity = entities.iter().find(|e| e.name.as_ref() == name)?;
+            let named = entity
+                .rules()
+                .flat_map(EntityRule::targets)
+                .map(|target| target.as_ref())
+                .collect::<BTreeSet<_>>();
+            let only = |labels: &BTreeSet<&str>, other: &BTreeSet<&str>| {
+                labels
+                    .difference(other)
+                    .filter(|label| named.contains(*label))
+                    .map(|label| label.to_string())
+                    .collect::<Vec<_>>()
+            };
+
+            let hint = EnvHint {
+                entity: name.clone(),
+                present: only(&other_labels, &labels),
+                absent: only(&labels, &other_labels),
+            };
+            (!hint.present.is_empty() || !hint.absent.is_empty()).then_some(hint)
+        })
+        .collect()
+}
+
+fn push_names(output: &mut String, title: &str, env: &str, names: &[String]) {
+    if names.is_empty() {
+        return;
+    }
+
+    output.push_str(&format!("{} only in {}:\n", title, env));
+    for name in names {
+        output.push_str(&format!("  {}\n", name));
+    }
+}
+
+/*
+    One section per difference, the left environment first:
+
+    Entities only in staging:
+      app=debug
+    Rules only in prod:
+      app=web exclude app=batch on zone (prod/web.yaml:21)
+    Conflicts only in prod:
+      app=web
+        satisfiable in staging because node=gpu exists, app=batch is absent
+*/
+pub fn format_comparison(comparison: &EnvComparison, left: &str, right: &str) -> String {
+    let mut output = String::new();
+
+    push_names(&mut output, "Entities", left, &comparison.left_entities);
+    push_names(&mut output, "Entities", right, &comparison.right_entities);
+
+    for (env, rules) in [
+        (left, &comparison.left_rules),
+        (right, &comparison.right_rules),
+    ] {
+        if rules.is_empty() {
+            continue;
+        }
+
+        output.push_str(&format!("Rules only in {}:\n", env));
+        for rule in rules {
+            output.push_str(&format!("  {} on {}", rule.rule, rule.topology));
+            if let Some(location) = rule.location.as_deref() {
+                output.push_str(&format!(" ({})", location));
+            }
+            output.push('\n');
+        }
+    }
+
+    for (env, other, conflicts, hints) in [
+        (
+            left,
+            right,
+            &comparison.left_conflicts,
+            &comparison.left_hints,
+        ),
+        (
+            right,
+            left,
+            &comparison.right_conflicts,
+            &comparison.right_hints,
+        ),
+    ] {
+        if conflicts.is_empty() {
+            continue;
+        }
+
+        output.push_str(&format!("Conflicts only in {}:\n", env));
+        for name in conflicts {
+            output.push_str(&format!("  {}\n", name));
+            if let Some(hint) = hints.iter().find(|hint| &hint.entity == name) {
+                let reasons = hint
+                    .present
+                    .iter()
+                    .map(|label| format!("{} exists", label))
+                    .chain(
+                        hint.absent
+                            .iter()
+                            .map(|label| format!("{} is absent", label)),
+                    )
+                    .collect::<Vec<_>>();
+                output.push_str(&format!(
+                    "    satisfiable in {} because {}\n",
+                    other,
+                    reasons.join(", ")
+                ));
+            }
+        }
+    }
+
+    output
+}
@@ -0,0 +1,59 @@
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+// Config file read when --config is not given
+pub const DEFAULT_CONFIG_FILE: &str = "deployfix.yaml";
+
+// A top-level section of the config file owned by one plugin, e.g.
+//
+//   k8s:
+//     rack_label: example.com/rack
+//   yarn:
+//     default_scope: RACK
+pub trait ConfigSection: DeserializeOwned + Default {
+    const NAME: &'static str;
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Config {
+    sections: serde_yaml::Mapping,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+
+        let sections = match serde_yaml::from_str::<Option<serde_yaml::Mapping>>(&data)
+            .with_context(|| format!("Invalid config {}", path.display()))?
+        {
+            Some(sections) => sections,
+            None => serde_yaml::Mapping::new(),
+        };
+
+        Ok(Self { sections })
+    }
+
+    // Loads `path`, or `deployfix.yaml` in the working directory when it exists
+    pub fn load_or_default(path: Option<&Path>) -> anyhow::Result<Self> {
+        match path {
+            Some(path) => Self::load(path),
+            None if Path::new(DEFAULT_CONFIG_FILE).is_file() => {
+                Self::load(Path::new(DEFAULT_CONFIG_FILE))
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    // Missing sections fall back to the defaults of the plugin
+    pub fn section<T: ConfigSection>(&self) -> anyhow::Result<T> {
+        match self.sections.get(T::NAME) {
+            Some(value) => serde_yaml::from_value(value.clone())
+                .with_context(|| format!("Invalid `{}` section in config", T::NAME)),
+            None => Ok(T::default()),
+        }
+    }
+}
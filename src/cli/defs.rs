@@ -0,0 +1,268 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use clap::Subcommand;
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::model::{Entity, EntityPriority, EntityRule};
+
+use super::{import_inputs, DEFINITIONS_FILE};
+
+#[derive(Subcommand)]
+pub enum DefsCommands {
+    #[clap(about = "Show where an entity is defined and which rules reference it")]
+    Query {
+        #[clap(
+            value_name = "NAME",
+            required_unless_present = "reverse",
+            help = "Entity to look up"
+        )]
+        name: Option<String>,
+        #[clap(
+            long,
+            value_name = "FILE:LINE",
+            conflicts_with = "name",
+            help = "List the entities referenced at a location, `FILE` alone matches every line"
+        )]
+        reverse: Option<String>,
+        #[clap(
+            long,
+            value_name = "PATH",
+            default_value = DEFINITIONS_FILE,
+            help = "Definitions artifact written by `k8s go`"
+        )]
+        definitions: PathBuf,
+        #[clap(
+            long,
+            value_name = "PATH",
+            help = "Build the definitions from these models instead of the artifact"
+        )]
+        model: Vec<PathBuf>,
+    },
+}
+
+enum DefinitionEntry {
+    Source(Definition),
+    Reference {
+        name: String,
+        file: String,
+        line: usize,
+    },
+}
+
+fn dump_definition(entity: &Entity) -> Vec<DefinitionEntry> {
+    let mut ret = vec![DefinitionEntry::Source(Definition {
+        name: entity.name.0.clone(),
+        source: entity.source.as_ref().to_string(),
+        owner: entity
+            .rules()
+            .find_map(EntityRule::owner)
+            .map(str::to_string),
+        priority: entity.priority.clone(),
+        replicas: entity.rules().find_map(EntityRule::replicas),
+        min_replicas: entity.rules().find_map(EntityRule::min_replicas),
+        max_replicas: entity.rules().find_map(EntityRule::max_replicas),
+        rules: RuleCounts {
+            requires: entity.requires.len(),
+            excludes: entity.excludes.len(),
+        },
+        references: vec![],
+    })];
+
+    for rule in entity.rules() {
+        match rule {
+            EntityRule::Mono {
+                target,
+                rule_source,
+                ..
+            } => {
+                let name = target.0.clone();
+                let file = rule_source.file().unwrap_or("unknown").to_string();
+                let line = rule_source.line().unwrap_or(0);
+
+                ret.push(DefinitionEntry::Reference { name, file, line });
+            }
+            EntityRule::Multi {
+                targets,
+                rule_source,
+                ..
+            } => {
+                for target in targets {
+                    let name = target.0.clone();
+                    let file = rule_source.file().unwrap_or("unknown").to_string();
+                    let line = rule_source.line().unwrap_or(0);
+
+                    ret.push(DefinitionEntry::Reference { name, file, line });
+                }
+            }
+        }
+    }
+
+    ret
+}
+
+// Rules declared by an entity
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleCounts {
+    pub requires: usize,
+    pub excludes: usize,
+}
+
+/*
+    Where an entity is defined, what it declares and the `file:line` locations of the rules
+    referencing it. Entities only referenced keep an `unknown` source and no rules, fields
+    missing from the artifacts of older releases read as their default.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Definition {
+    pub name: String,
+    pub source: String,
+    // From the `owner` metadata of its rules (`deployfix.io/owner` in K8s)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub priority: EntityPriority,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replicas: Option<usize>,
+    // Bounds of its autoscaler
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_replicas: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_replicas: Option<usize>,
+    #[serde(default)]
+    pub rules: RuleCounts,
+    pub references: Vec<String>,
+}
+
+// Sorted by name, references by file then line, so that the artifact diffs cleanly between runs
+fn build_definitions(entities: &[Entity]) -> anyhow::Result<Vec<Definition>> {
+    let mut definitions = HashMap::<String, Definition>::new();
+    let mut references = Vec::new();
+
+    for e in entities.iter().flat_map(dump_definition) {
+        match e {
+            DefinitionEntry::Source(d) => {
+                if definitions.contains_key(&d.name) {
+                    anyhow::bail!("Duplicate definition found: {}", d.name);
+                }
+
+                definitions.insert(d.name.clone(), d);
+            }
+            DefinitionEntry::Reference { name, file, line } => {
+                references.push((name, format!("{}:{}", file, line)))
+            }
+        }
+    }
+
+    for (name, location) in references {
+        if let Some(d) = definitions.get_mut(&name) {
+            d.references.push(location);
+        } else {
+            definitions.insert(
+                name.clone(),
+                Definition {
+                    name,
+                    source: "unknown".to_string(),
+                    references: vec![location],
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    let mut definitions = definitions
+        .into_iter()
+        .map(|(_, mut d)| {
+            d.references
+                .sort_by(|a, b| parse_location(a).cmp(&parse_location(b)));
+            d.references.dedup();
+            d
+        })
+        .collect::<Vec<_>>();
+    definitions.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(definitions)
+}
+
+// Writes the `definitions.yaml` artifact of `k8s go`
+pub fn dump_definitions(entities: &[Entity]) -> anyhow::Result<String> {
+    Ok(serde_yaml::to_string(&build_definitions(entities)?)?)
+}
+
+fn load_definitions(path: &PathBuf, model: &[PathBuf]) -> anyhow::Result<Vec<Definition>> {
+    if !model.is_empty() {
+        return build_definitions(&import_inputs(model, None));
+    }
+
+    let data = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+
+    Ok(serde_yaml::from_str(&data)?)
+}
+
+// Splits `file:line` into its parts, a location without a line matches the whole file
+fn parse_location(location: &str) -> (&str, Option<usize>) {
+    match location.rsplit_once(':') {
+        Some((file, line)) => match line.parse() {
+            Ok(line) => (file, Some(line)),
+            Err(_) => (location, None),
+        },
+        None => (location, None),
+    }
+}
+
+fn matches_location(reference: &str, file: &str, line: Option<usize>) -> bool {
+    let (ref_file, ref_line) = parse_location(reference);
+
+    // Artifacts keep the paths given to `k8s go`, accept any suffix of them
+    let same_file = ref_file == file || ref_file.ends_with(&format!("/{}", file));
+
+    same_file && (line.is_none() || line == ref_line)
+}
+
+// Entities referenced at `location`, keeping only the matching references
+pub fn reverse_lookup(definitions: Vec<Definition>, location: &str) -> Vec<Definition> {
+    let (file, line) = parse_location(location);
+
+    definitions
+        .into_iter()
+        .filter_map(|mut d| {
+            d.references
+                .retain(|reference| matches_location(reference, file, line));
+
+            (!d.references.is_empty()).then_some(d)
+        })
+        .collect()
+}
+
+pub fn execute(command: DefsCommands) {
+    match command {
+        DefsCommands::Query {
+            name,
+            reverse,
+            definitions,
+            model,
+        } => {
+            let definitions = load_definitions(&definitions, &model).unwrap_or_else(|err| {
+                error!("{:#}", err);
+                std::process::exit(1);
+            });
+
+            let found = match (name, reverse) {
+                (Some(name), _) => definitions
+                    .into_iter()
+                    .filter(|d| d.name == name)
+                    .collect::<Vec<_>>(),
+                (None, Some(location)) => reverse_lookup(definitions, &location),
+                (None, None) => unreachable!(),
+            };
+
+            if found.is_empty() {
+                error!("No definition found");
+                std::process::exit(1);
+            }
+
+            print!("{}", serde_yaml::to_string(&found).unwrap());
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use log::{error, warn};
+use sha2::{Digest, Sha256};
+
+use crate::plugin::expand_paths;
+
+// Kinds of the artifacts stamped with the digests of the sources they were computed from
+pub const STAMPED_KINDS: [&str; 3] = ["dump", "conflicts", "recommendations"];
+
+const MARKER: &str = "source-digest:";
+
+// SHA-256 of the file, stable across releases and platforms unlike the std hashers
+pub fn digest(path: &Path) -> std::io::Result<String> {
+    Ok(format!("{:x}", Sha256::digest(std::fs::read(path)?)))
+}
+
+/*
+    Digests of the sources an artifact was computed from, one comment line per file at the
+    top of the artifact, `//` in IR files and `#` in YAML ones:
+
+    // source-digest: 9f86d081884c7d65... manifests/web.yaml
+
+    Paths are kept as given on the command line, the artifact is checked from the same
+    directory it was computed in.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceDigests(pub BTreeMap<String, String>);
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum StaleSource {
+    #[error("{0} changed since the analysis")]
+    Changed(String),
+    #[error("{0} was removed since the analysis")]
+    Missing(String),
+}
+
+impl SourceDigests {
+    pub fn of(paths: &[PathBuf]) -> Self {
+        let digests = paths
+            .iter()
+            .filter_map(|path| {
+                let digest = digest(path)
+                    .map_err(|err| warn!("Failed to digest {}: {}", path.display(), err))
+                    .ok()?;
+
+                Some((path.display().to_string(), digest))
+            })
+            .collect();
+
+        Self(digests)
+    }
+
+    // Comment lines of the digests, `comment` is the comment prefix of the artifact
+    pub fn header(&self, comment: &str) -> String {
+        self.0
+            .iter()
+            .map(|(path, digest)| format!("{} {} {} {}\n", comment, MARKER, digest, path))
+            .collect()
+    }
+
+    // Digests recorded in an artifact, whatever its comment prefix
+    pub fn parse(content: &str) -> Self {
+        let digests = content
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim_start();
+                let line = line.strip_prefix("//").or_else(|| line.strip_prefix('#'))?;
+                let (digest, path) = line
+                    .trim_start()
+                    .strip_prefix(MARKER)?
+                    .trim()
+                    .split_once(' ')?;
+
+                Some((path.to_string(), digest.to_string()))
+            })
+            .collect();
+
+        Self(digests)
+    }
+
+    // Sources which no longer have their recorded digest
+    pub fn stale(&self) -> Vec<StaleSource> {
+        self.0
+            .iter()
+            .filter_map(|(path, recorded)| match digest(Path::new(path)) {
+                Ok(digest) if &digest == recorded => None,
+                Ok(_) => Some(StaleSource::Changed(path.clone())),
+                Err(_) => Some(StaleSource::Missing(path.clone())),
+            })
+            .collect()
+    }
+}
+
+// Prefixes `content` with the digests, in the comment syntax of the file `path`
+pub fn stamp(sources: &SourceDigests, path: &str, content: &str) -> String {
+    let comment = match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("ir") => "//",
+        Some("yaml" | "yml") => "#",
+        _ => return content.to_string(),
+    };
+
+    sources.header(comment) + content
+}
+
+// Refuses artifacts computed from sources changed since, only warns about them with
+// `allow_stale`. Artifacts without digests were not written by `k8s go` and are let through.
+pub fn check_artifact_sources(paths: &[PathBuf], allow_stale: bool) {
+    let artifacts = expand_paths(paths, |path| {
+        path.extension().is_some_and(|ext| ext == "ir")
+    });
+
+    let mut stale = false;
+    for artifact in artifacts {
+        let Ok(content) = std::fs::read_to_string(&artifact) else {
+            continue;
+        };
+
+        for source in SourceDigests::parse(&content).stale() {
+            stale = true;
+            match allow_stale {
+                true => warn!("{}: {}", artifact.display(), source),
+                false => error!("{}: {}", artifact.display(), source),
+            }
+        }
+    }
+
+    if stale && !allow_stale {
+        error!("Rerun the analysis, or pass --allow-stale to use the artifacts anyway");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use log::warn;
+
+use crate::{
+    model::{Entity, EntityRule, Env, METADATA_MAX_UNAVAILABLE_KEY, METADATA_MIN_AVAILABLE_KEY},
+    solver::SolverOutput,
+};
+
+use super::headroom;
+
+// Solver the conflicts of disruption budgets are reported under
+pub const DISRUPTION_SOLVER: &str = "disruption";
+
+// A PodDisruptionBudget which never lets a pod of its entity be evicted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisruptionConflict {
+    pub entity: String,
+    // As written in the manifest, e.g. `minAvailable=2` or `maxUnavailable=0`
+    pub budget: String,
+    pub replicas: usize,
+    // Pods the budget keeps available
+    pub available: usize,
+    // Pods able to run at once, fewer than `replicas` when a self anti-affinity spreads them
+    // over fewer domains
+    pub running: usize,
+    // The self anti-affinity spreading the pods, or the first rule carrying the budget
+    pub rule: EntityRule,
+}
+
+// Pods out of `replicas`, percentages rounded up like the disruption controller does
+fn pods(value: &str, replicas: usize) -> Option<usize> {
+    match value.trim().strip_suffix('%') {
+        Some(percent) => percent
+            .parse::<usize>()
+            .ok()
+            .map(|percent| (percent * replicas).div_ceil(100)),
+        None => value.trim().parse().ok(),
+    }
+}
+
+// Budget of `rule` and the pods it keeps available out of `replicas`
+fn available(rule: &EntityRule, replicas: usize) -> Option<(String, usize)> {
+    if let Some(value) = rule.metadata(METADATA_MIN_AVAILABLE_KEY) {
+        return pods(value, replicas).map(|pods| (format!("minAvailable={}", value), pods));
+    }
+
+    let value = rule.metadata(METADATA_MAX_UNAVAILABLE_KEY)?;
+    pods(value, replicas).map(|pods| {
+        (
+            format!("maxUnavailable={}", value),
+            replicas.saturating_sub(pods),
+        )
+    })
+}
+
+/*
+    Budgets of the entities checked against their replicas and, with `envs`, against the
+    domains a self anti-affinity spreads them over. A budget keeping available every pod able
+    to run blocks all evictions, so the nodes hosting them can never be drained. Autoscaled
+    entities are checked at the minimum of their autoscaler.
+*/
+pub fn check_disruption_budgets(entities: &[Entity], envs: &[Env]) -> Vec<DisruptionConflict> {
+    let domains = if envs.is_empty() {
+        HashMap::new()
+    } else {
+        headroom(entities, envs)
+            .into_iter()
+            .map(|h| (h.entity, h.domains))
+            .collect()
+    };
+
+    let mut conflicts = entities
+        .iter()
+        .filter_map(|entity| {
+            let name = entity.name.as_ref();
+            let rule = entity.rules().find(|rule| {
+                rule.metadata(METADATA_MIN_AVAILABLE_KEY).is_some()
+                    || rule.metadata(METADATA_MAX_UNAVAILABLE_KEY).is_some()
+            })?;
+
+            let replicas = entity
+                .rules()
+                .find_map(EntityRule::min_replicas)
+                .or(entity.rules().find_map(EntityRule::replicas))
+                .unwrap_or(1);
+            let Some((budget, available)) = available(rule, replicas) else {
+                warn!("Ignoring the invalid disruption budget of {}", name);
+                return None;
+            };
+
+            let spread = entity.excludes.iter().find(|rule| rule.is_in_target(name));
+            let running = match (spread, domains.get(name)) {
+                // Without any node the entity is already reported as unschedulable
+                (_, Some(0)) => return None,
+                (Some(_), Some(domains)) => replicas.min(*domains),
+                _ => replicas,
+            };
+            if replicas == 0 || running > available {
+                return None;
+            }
+
+            Some(DisruptionConflict {
+                entity: name.to_string(),
+                budget,
+                replicas,
+                available,
+                running,
+                rule: spread.unwrap_or(rule).clone(),
+            })
+        })
+        .collect::<Vec<_>>();
+    conflicts.sort_by(|a, b| a.entity.cmp(&b.entity));
+
+    conflicts
+}
+
+// The conflicts as a solver reports them, under `DISRUPTION_SOLVER`
+pub fn disruption_output(conflicts: &[DisruptionConflict]) -> SolverOutput {
+    if conflicts.is_empty() {
+        return SolverOutput::Ok;
+    }
+
+    for conflict in conflicts {
+        if conflict.running < conflict.replicas {
+            warn!(
+                "{} of {} keeps {} pods available, only {} of its {} replicas can be spread, no pod can ever be evicted",
+                conflict.budget, conflict.entity, conflict.available, conflict.running, conflict.replicas
+            );
+        } else {
+            warn!(
+                "{} of {} keeps {} of its {} replicas available, no pod can ever be evicted",
+                conflict.budget, conflict.entity, conflict.available, conflict.replicas
+            );
+        }
+    }
+
+    SolverOutput::Conflict(
+        conflicts
+            .iter()
+            .map(|conflict| (conflict.entity.clone(), vec![conflict.rule.clone()]))
+            .collect(),
+        HashMap::new(),
+    )
+    .with_solver(DISRUPTION_SOLVER)
+}
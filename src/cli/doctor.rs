@@ -0,0 +1,101 @@
+use std::{
+    ffi::CStr,
+    path::{Path, PathBuf},
+};
+
+use log::{error, info};
+
+use crate::{
+    model::{get_parser, Entity, EntitySource},
+    solver::{get_solver, SOLVER_NAMES},
+};
+
+// A model every solver accepts
+static SAT_CASE: &str = "app1 require app2\napp2 exclude app3\napp3 exclude app2\n";
+
+// A model the given solver must reject
+fn unsat_case(solver: &str) -> Option<&'static str> {
+    match solver {
+        "z3" | "structural" => Some("app1 require app2\napp1 exclude app2\napp2 exclude app1\n"),
+        "ring" => Some("app1 require app2\napp2 require app1\n"),
+        "ring-relaxed" => Some("app1 require app2\napp2 require app1\napp2 exclude app1\n"),
+        "unknown" => Some("app1 require app2\n"),
+        _ => None,
+    }
+}
+
+pub(super) fn z3_version() -> String {
+    let version = unsafe { z3_sys::Z3_get_full_version() };
+
+    if version.is_null() {
+        return "unknown".to_string();
+    }
+
+    unsafe { CStr::from_ptr(version) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn parse(data: &str) -> Result<Vec<Entity>, String> {
+    get_parser("deployfix")
+        .and_then(|parser| parser.parse(data, EntitySource::Unknown))
+        .map_err(|e| e.to_string())
+}
+
+fn check_solver(name: &str) -> Result<(), String> {
+    let solver = get_solver(name).map_err(|e| e.to_string())?;
+
+    let entity_map = parse(SAT_CASE)?
+        .try_into()
+        .map_err(|e| format!("{:?}", e))?;
+    if !solver.solve(&entity_map).is_ok() {
+        return Err("reported a conflict on a satisfiable model".to_string());
+    }
+
+    if let Some(case) = unsat_case(name) {
+        let entity_map = parse(case)?.try_into().map_err(|e| format!("{:?}", e))?;
+        if solver.solve(&entity_map).is_ok() {
+            return Err("missed the conflict of an unsatisfiable model".to_string());
+        }
+    }
+
+    Ok(())
+}
+
+fn check_writable(dir: &Path) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+
+    let probe = dir.join(format!(".deployfix-doctor-{}", std::process::id()));
+    std::fs::write(&probe, "").map_err(|e| e.to_string())?;
+    std::fs::remove_file(&probe).map_err(|e| e.to_string())
+}
+
+// Runs every check and returns whether all of them passed
+pub fn doctor(dirs: &[PathBuf]) -> bool {
+    let mut ok = true;
+
+    info!("deployfix {}", env!("CARGO_PKG_VERSION"));
+    info!("z3 {}", z3_version());
+
+    for name in SOLVER_NAMES {
+        match check_solver(name) {
+            Ok(()) => info!("[ok] solver {}", name),
+            Err(err) => {
+                error!("[failed] solver {}: {}", name, err);
+                ok = false;
+            }
+        }
+    }
+
+    for dir in dirs {
+        match check_writable(dir) {
+            Ok(()) => info!("[ok] {} is writable", dir.display()),
+            Err(err) => {
+                error!("[failed] {} is not writable: {}", dir.display(), err);
+                ok = false;
+            }
+        }
+    }
+
+    ok
+}
@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+
+use crate::model::{
+    Entity, METADATA_ANNOTATION_KEY, METADATA_DESC_KEY, METADATA_GENERATED_KEY, METADATA_GROUP_KEY,
+    METADATA_HINT_KEY, METADATA_LAYER_KEY, METADATA_LIFECYCLE_KEY, METADATA_MAX_REPLICAS_KEY,
+    METADATA_MAX_UNAVAILABLE_KEY, METADATA_MIN_AVAILABLE_KEY, METADATA_MIN_REPLICAS_KEY,
+    METADATA_POD_KEY, METADATA_POD_QUOTA_KEY, METADATA_PROVENANCE_KEY, METADATA_REPLICAS_KEY,
+    METADATA_SCALE_OUT_KEY, METADATA_TAGS_KEY, METADATA_WEIGHT_KEY, METADATA_WINDOW_KEY,
+};
+
+// Keys describing a single rule rather than the part of the cluster it belongs to
+const RULE_KEYS: &[&str] = &[
+    "key",
+    "operator",
+    "inverse",
+    "type",
+    "topology_key",
+    "resource_type",
+    "passthrough",
+    "selector",
+    METADATA_ANNOTATION_KEY,
+    METADATA_DESC_KEY,
+    METADATA_GENERATED_KEY,
+    METADATA_GROUP_KEY,
+    METADATA_HINT_KEY,
+    METADATA_LAYER_KEY,
+    METADATA_LIFECYCLE_KEY,
+    METADATA_MAX_REPLICAS_KEY,
+    METADATA_MAX_UNAVAILABLE_KEY,
+    METADATA_MIN_AVAILABLE_KEY,
+    METADATA_MIN_REPLICAS_KEY,
+    METADATA_POD_KEY,
+    METADATA_POD_QUOTA_KEY,
+    METADATA_PROVENANCE_KEY,
+    METADATA_REPLICAS_KEY,
+    METADATA_SCALE_OUT_KEY,
+    METADATA_TAGS_KEY,
+    METADATA_WEIGHT_KEY,
+    METADATA_WINDOW_KEY,
+];
+
+// A metadata key `check --domain` could split the model by
+#[derive(Debug, Clone, PartialEq)]
+pub struct DomainCandidate {
+    pub key: String,
+    // Number of rules of each value
+    pub values: BTreeMap<String, usize>,
+    // Rules of the model, with or without the key
+    pub rules: usize,
+    pub suggested: bool,
+}
+
+impl DomainCandidate {
+    pub fn coverage(&self) -> f64 {
+        self.values.values().sum::<usize>() as f64 / self.rules.max(1) as f64
+    }
+
+    // Most common value, the domain of the rules without the key
+    pub fn default_value(&self) -> Option<&str> {
+        self.values
+            .iter()
+            .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then_with(|| b.cmp(a)))
+            .map(|(value, _)| value.as_str())
+    }
+}
+
+// Metadata keys of the rules with the number of rules of each value, suggested keys first.
+// A key is suggested when most rules carry it and its values each gather several rules, so
+// that splitting by it gives a few sizeable domains rather than one domain per rule.
+pub fn domain_candidates(entities: &[Entity]) -> Vec<DomainCandidate> {
+    let mut keys: BTreeMap<&str, BTreeMap<String, usize>> = BTreeMap::new();
+    let mut rules = 0;
+
+    for rule in entities.iter().flat_map(|entity| entity.rules()) {
+        rules += 1;
+
+        let metadata = rule
+            .metadata_ref()
+            .and_then(|metadata| metadata.get_metadata());
+        for (key, value) in metadata.into_iter().flatten() {
+            if RULE_KEYS.contains(&key.as_str()) {
+                continue;
+            }
+
+            *keys
+                .entry(key)
+                .or_default()
+                .entry(value.clone())
+                .or_default() += 1;
+        }
+    }
+
+    let mut candidates = keys
+        .into_iter()
+        .map(|(key, values)| {
+            let tagged = values.values().sum::<usize>();
+            let suggested = values.len() >= 2 && tagged * 2 >= rules && values.len() * 2 <= tagged;
+
+            DomainCandidate {
+                key: key.to_string(),
+                values,
+                rules,
+                suggested,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    candidates.sort_by(|a, b| {
+        b.suggested
+            .cmp(&a.suggested)
+            .then_with(|| b.coverage().total_cmp(&a.coverage()))
+            .then_with(|| a.values.len().cmp(&b.values.len()))
+            .then_with(|| a.key.cmp(&b.key))
+    });
+
+    candidates
+}
+
+/*
+    One line per key, the values with their number of rules:
+
+    KEY       RULES     VALUES
+    tenant    9/12      3 (blue: 4, green: 3, red: 2)  suggested
+    topology  12/12     1 (node: 12)
+
+    Suggested: check --domain tenant --default-domain-key blue
+*/
+pub fn format_candidates(candidates: &[DomainCandidate]) -> String {
+    let width = candidates
+        .iter()
+        .map(|c| c.key.len())
+        .max()
+        .unwrap_or(0)
+        .max("KEY".len());
+
+    let mut output = format!("{:width$}  {:8}  VALUES\n", "KEY", "RULES");
+    for candidate in candidates {
+        let tagged = candidate.values.values().sum::<usize>();
+        let values = candidate
+            .values
+            .iter()
+            .map(|(value, count)| format!("{}: {}", value, count))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let line = format!(
+            "{:width$}  {:8}  {} ({})",
+            candidate.key,
+            format!("{}/{}", tagged, candidate.rules),
+            candidate.values.len(),
+            values
+        );
+        output.push_str(&line);
+        if candidate.suggested {
+            output.push_str("  suggested");
+        }
+        output.push('\n');
+    }
+
+    if let Some(best) = candidates.iter().find(|c| c.suggested) {
+        output.push_str(&format!(
+            "\nSuggested: check --domain {} --default-domain-key {}\n",
+            best.key,
+            best.default_value().unwrap_or("default")
+        ));
+    }
+
+    output
+}
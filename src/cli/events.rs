@@ -0,0 +1,82 @@
+use std::{path::PathBuf, sync::mpsc::Sender};
+
+use serde::Serialize;
+
+use super::{
+    check_entities, import_inputs_with_events, load_impact_weights, load_suppressions, Config,
+    ConflictReport, OutputLevel, RunOptions, SessionConflict, SessionOptions, SessionOutputs,
+};
+
+// Progress of an analysis, sent while it runs so that an embedding UI can show it live
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum AnalysisEvent {
+    // An input file was read, before the layers are stacked and merged
+    FileParsed {
+        path: String,
+        entities: usize,
+    },
+    // A solve started, on the whole model when `domain` is none
+    DomainStarted {
+        domain: Option<String>,
+        entities: usize,
+    },
+    // A conflicting rule was reported, after the suppressions
+    ConflictFound(SessionConflict),
+    // An alternative of a conflicting multi rule which would schedule the entity
+    RecommendationReady {
+        entity: String,
+        rule: String,
+        recommendation: String,
+    },
+    // The analysis is over, with the summary line of the report
+    Finished {
+        summary: String,
+    },
+}
+
+// Receives the events of an analysis, e.g. a closure or the sending half of a channel
+pub trait EventListener {
+    fn on_event(&self, event: AnalysisEvent);
+}
+
+impl<F: Fn(AnalysisEvent)> EventListener for F {
+    fn on_event(&self, event: AnalysisEvent) {
+        self(event)
+    }
+}
+
+impl EventListener for Sender<AnalysisEvent> {
+    // A receiver that went away only stops the events, not the analysis
+    fn on_event(&self, event: AnalysisEvent) {
+        let _ = self.send(event);
+    }
+}
+
+// Runs `check` on `paths` like the CLI does with the flags of `run`, without printing the
+// conflicts, and sends every step to `listener` as it happens. Returns what the report recorded.
+pub fn analyze(
+    paths: &[PathBuf],
+    options: &SessionOptions,
+    run: &RunOptions,
+    config: &Config,
+    listener: &dyn EventListener,
+) -> SessionOutputs {
+    let entities = import_inputs_with_events(paths, options.format.as_deref(), Some(listener));
+    let entities = options.tags.apply(entities);
+
+    let mut report = ConflictReport::new(OutputLevel::Quiet)
+        .with_gates(run.gates.clone())
+        .with_suppressions(load_suppressions(config), super::today())
+        .with_impact_weights(load_impact_weights(config))
+        .with_session_outputs();
+
+    check_entities(entities, options, run, &mut report, Some(listener));
+
+    let outputs = report.session_outputs().unwrap_or_default();
+    listener.on_event(AnalysisEvent::Finished {
+        summary: outputs.summary.clone(),
+    });
+
+    outputs
+}
@@ -0,0 +1,177 @@
+use std::collections::BTreeSet;
+
+use crate::model::{Entity, EntityRule, EntityRuleTopologyKey, Env, METADATA_TOPOLOGY_KEY};
+
+// Replicas of an entity the nodes of an env can still take
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Headroom {
+    pub entity: String,
+    // Topology the replicas are spread on by a self exclude, `node` without one
+    pub topology: String,
+    pub replicas: usize,
+    // Domains of the topology with nodes able to host a replica
+    pub domains: usize,
+    // Replicas which can still be added, `None` when the rules set no limit: replicas which
+    // are not spread share the nodes able to host them
+    pub headroom: Option<usize>,
+}
+
+fn topology(rule: &EntityRule) -> &str {
+    rule.metadata(METADATA_TOPOLOGY_KEY)
+        .unwrap_or(EntityRuleTopologyKey::Node.as_ref())
+}
+
+// Domain of the nodes of `env` on `topology`: their label of that key, e.g. `zone=a` or
+// `topology.kubernetes.io/zone=a`, and the nodes themselves on `node` or without one
+fn domain<'a>(env: &'a Env, topology: &str) -> &'a str {
+    if topology != EntityRuleTopologyKey::Node.as_ref() {
+        let label = env.labels.iter().find(|label| {
+            label
+                .split_once('=')
+                .is_some_and(|(key, _)| key == topology || key.ends_with(&format!("/{}", topology)))
+        });
+        if let Some(label) = label {
+            return label;
+        }
+    }
+
+    &env.name
+}
+
+// Whether a replica of `entity` on the nodes of `env` keeps every rule: its requires find a
+// target in the domain, its excludes and the `excluders` naming it find none
+fn hosts(entity: &Entity, excluders: &[&EntityRule], env: &Env, envs: &[Env]) -> bool {
+    let name = entity.name.as_ref();
+    let placed = |topology: &str| {
+        let domain = domain(env, topology);
+        envs.iter()
+            .filter(|other| self::domain(other, topology) == domain)
+            .flat_map(|other| other.labels.iter().map(String::as_str))
+            .collect::<BTreeSet<_>>()
+    };
+
+    let requires = entity
+        .requires
+        .iter()
+        .filter(|rule| !rule.is_in_target(name))
+        .all(|rule| {
+            let placed = placed(topology(rule));
+            rule.targets()
+                .into_iter()
+                .any(|target| placed.contains(target.as_ref()))
+        });
+    let excludes = entity
+        .excludes
+        .iter()
+        .filter(|rule| !rule.is_in_target(name))
+        .all(|rule| {
+            let placed = placed(topology(rule));
+            rule.targets()
+                .into_iter()
+                .all(|target| !placed.contains(target.as_ref()))
+        });
+    let excluded = excluders
+        .iter()
+        .any(|rule| placed(topology(rule)).contains(rule.source().as_ref()));
+
+    requires && excludes && !excluded
+}
+
+// Headroom of every entity on the nodes of `envs`, the tightest first. Replicas are the
+// `replicas` of the entity, the maximum of its autoscaler when there is one, or 1.
+pub fn headroom(entities: &[Entity], envs: &[Env]) -> Vec<Headroom> {
+    let mut headroom = entities
+        .iter()
+        .map(|entity| {
+            let name = entity.name.as_ref();
+            let excluders = entities
+                .iter()
+                .filter(|other| other.name != entity.name)
+                .flat_map(|other| other.excludes.iter())
+                .filter(|rule| rule.is_in_target(name))
+                .collect::<Vec<_>>();
+            let spread = entity.excludes.iter().find(|rule| rule.is_in_target(name));
+            let topology = spread
+                .map(topology)
+                .unwrap_or(EntityRuleTopologyKey::Node.as_ref());
+
+            let replicas = entity
+                .rules()
+                .find_map(|rule| rule.replicas())
+                .max(entity.rules().find_map(|rule| rule.max_replicas()))
+                .unwrap_or(1);
+
+            let hosting = envs
+                .iter()
+                .filter(|env| hosts(entity, &excluders, env, envs))
+                .collect::<Vec<_>>();
+            let domains = if topology == EntityRuleTopologyKey::Node.as_ref() {
+                hosting.iter().map(|env| env.capacity()).sum()
+            } else {
+                hosting
+                    .iter()
+                    .map(|env| domain(env, topology))
+                    .collect::<BTreeSet<_>>()
+                    .len()
+            };
+
+            let headroom = match spread {
+                Some(_) => Some(domains.saturating_sub(replicas)),
+                None if domains == 0 => Some(0),
+                None => None,
+            };
+
+            Headroom {
+                entity: name.to_string(),
+                topology: topology.to_string(),
+                replicas,
+                domains,
+                headroom,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    headroom.sort_by(|a, b| {
+        (a.headroom.is_none(), a.headroom, &a.entity).cmp(&(
+            b.headroom.is_none(),
+            b.headroom,
+            &b.entity,
+        ))
+    });
+
+    headroom
+}
+
+/*
+    ENTITY     TOPOLOGY  REPLICAS  DOMAINS  HEADROOM
+    app=db     zone      2         2        0
+    app=web    node      3         5        2
+    app=batch  node      1         4        unlimited
+*/
+pub fn format_headroom(headroom: &[Headroom]) -> String {
+    let width = headroom
+        .iter()
+        .map(|h| h.entity.len())
+        .max()
+        .unwrap_or(0)
+        .max("ENTITY".len());
+
+    let mut output = format!(
+        "{:width$}  {:8}  {:8}  {:7}  HEADROOM\n",
+        "ENTITY", "TOPOLOGY", "REPLICAS", "DOMAINS"
+    );
+    for h in headroom {
+        output.push_str(&format!(
+            "{:width$}  {:8}  {:<8}  {:<7}  {}\n",
+            h.entity,
+            h.topology,
+            h.replicas,
+            h.domains,
+            h.headroom
+                .map(|headroom| headroom.to_string())
+                .unwrap_or_else(|| "unlimited".to_string())
+        ));
+    }
+
+    output
+}
@@ -0,0 +1,83 @@
+use std::path::PathBuf;
+
+use clap::Subcommand;
+use log::info;
+
+use crate::model::{merge_entities, rule_location, DeployIRFormatter, Entity, EntityRuleType};
+
+use super::{collect_inputs, import_layers};
+
+#[derive(Subcommand)]
+pub enum LayersCommands {
+    #[clap(about = "Print the effective model of layered inputs and where its rules come from")]
+    Explain {
+        #[clap(
+            value_name = "PATH",
+            required = true,
+            help = "Files, directories or glob patterns of the layers, base layer first"
+        )]
+        paths: Vec<PathBuf>,
+        #[clap(short, long, value_name = "FORMAT")]
+        format: Option<String>,
+    },
+}
+
+/*
+    The explanation is itself an IR file of the effective model:
+
+    // Layer 1: base.ir
+    // Layer 2: team.ir
+    // team.ir:1: override `app=web require app=cache` replaces `app=web require app=db` (base.ir:3)
+
+    // From team.ir:1
+    app=web require app=cache
+*/
+pub fn explain(paths: &[PathBuf], format: Option<&str>) -> String {
+    let mut explanation = String::new();
+
+    for (idx, path) in collect_inputs(paths).iter().enumerate() {
+        explanation.push_str(&format!("// Layer {}: {}\n", idx + 1, path.display()));
+    }
+
+    let (entities, changes) = import_layers(paths, format);
+    for change in changes.iter() {
+        explanation.push_str(&format!("// {}\n", change));
+    }
+
+    let mut entities = merge_entities(entities, None);
+    entities.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for entity in entities.iter() {
+        for rule in entity.rules() {
+            let mut single = Entity::new(entity.name.as_ref());
+            match rule.r#type() {
+                EntityRuleType::Require => single.add_require(rule.clone()),
+                EntityRuleType::Exclude => single.add_exclude(rule.clone()),
+            }
+
+            explanation.push_str(&format!(
+                "\n// From {}\n",
+                rule_location(rule)
+                    .as_deref()
+                    .unwrap_or("an unknown location")
+            ));
+            explanation.push_str(&DeployIRFormatter::format(&vec![single]));
+        }
+    }
+
+    explanation
+}
+
+pub fn execute(command: LayersCommands) {
+    match command {
+        LayersCommands::Explain { paths, format } => {
+            let explanation = explain(&paths, format.as_deref());
+            if explanation.is_empty() {
+                info!("No layers found");
+                return;
+            }
+
+            print!("{}", explanation);
+        }
+    }
+}
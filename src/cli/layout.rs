@@ -0,0 +1,110 @@
+use std::cell::RefCell;
+
+use serde::Serialize;
+
+use super::{
+    digest::{stamp, STAMPED_KINDS},
+    ReportSink, SourceDigests,
+};
+
+// Manifest of every file written by a run
+pub const INDEX_FILE: &str = "index.yaml";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Artifact {
+    pub path: String,
+    // e.g. dump, conflicts, recommendations, solution or manifest
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Index<'a> {
+    layout: &'a str,
+    artifacts: &'a [Artifact],
+}
+
+// Names the files of a run and records them for `index.yaml`. The files of one domain
+// (e.g. a topology key) go to a subdirectory named after it, the flat layout of older
+// releases suffixes the file names with the domain instead.
+pub struct OutputLayout<'a> {
+    sink: &'a dyn ReportSink,
+    flat: bool,
+    sources: SourceDigests,
+    artifacts: RefCell<Vec<Artifact>>,
+}
+
+impl<'a> OutputLayout<'a> {
+    pub fn new(sink: &'a dyn ReportSink, flat: bool) -> Self {
+        Self {
+            sink,
+            flat,
+            sources: SourceDigests::default(),
+            artifacts: RefCell::new(vec![]),
+        }
+    }
+
+    // Digests stamped on the dumps, conflicts and recommendations
+    pub fn with_sources(mut self, sources: SourceDigests) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    // Path of `name` in the directory of `domain`, `flat_name` in the flat layout
+    pub fn domain_file(&self, domain: &str, name: &str, flat_name: &str) -> String {
+        if self.flat {
+            flat_name.to_string()
+        } else {
+            format!("{}/{}", domain.replace('/', "_"), name)
+        }
+    }
+
+    pub fn write_artifact(
+        &self,
+        path: &str,
+        kind: &str,
+        domain: Option<&str>,
+        content: &str,
+    ) -> anyhow::Result<()> {
+        if STAMPED_KINDS.contains(&kind) {
+            self.sink
+                .write(path, &stamp(&self.sources, path, content))?;
+        } else {
+            self.sink.write(path, content)?;
+        }
+
+        self.artifacts.borrow_mut().push(Artifact {
+            path: path.to_string(),
+            kind: kind.to_string(),
+            domain: domain.map(|d| d.to_string()),
+        });
+
+        Ok(())
+    }
+
+    pub fn write_index(&self) -> anyhow::Result<()> {
+        let artifacts = self.artifacts.borrow();
+        let index = Index {
+            layout: if self.flat { "flat" } else { "domain" },
+            artifacts: &artifacts,
+        };
+
+        self.sink.write(INDEX_FILE, &serde_yaml::to_string(&index)?)
+    }
+}
+
+// Files written through the plain sink interface, e.g. injected manifests
+impl ReportSink for OutputLayout<'_> {
+    fn write(&self, name: &str, content: &str) -> anyhow::Result<()> {
+        self.write_artifact(name, "manifest", None, content)
+    }
+
+    fn location(&self, name: &str) -> String {
+        self.sink.location(name)
+    }
+
+    fn read(&self, name: &str) -> Option<String> {
+        self.sink.read(name)
+    }
+}
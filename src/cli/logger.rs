@@ -0,0 +1,78 @@
+use std::{io::Write, path::PathBuf, sync::Mutex};
+
+use clap::ValueEnum;
+use flexi_logger::{DeferredNow, FileSpec, Logger};
+use log::Record;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    // `2024-01-01 12:00 [INFO] message`
+    #[default]
+    Text,
+    // One JSON object per line with `time`, `level`, `target` and `message`
+    Json,
+}
+
+// Guards the global logger, which can only be set once per process
+static INITIALIZED: Mutex<bool> = Mutex::new(false);
+
+fn text_format(
+    write: &mut dyn Write,
+    now: &mut DeferredNow,
+    record: &Record,
+) -> std::io::Result<()> {
+    let now = now.format("%Y-%m-%d %H:%M");
+
+    write!(write, "{} [{}] {}", now, record.level(), record.args())
+}
+
+fn json_format(
+    write: &mut dyn Write,
+    now: &mut DeferredNow,
+    record: &Record,
+) -> std::io::Result<()> {
+    let line = serde_json::json!({
+        "time": now.format_rfc3339().to_string(),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    });
+
+    write!(write, "{}", line)
+}
+
+// Installs the logger of the binary. The library never calls this itself, so embedders keep
+// their own logger. Returns false when a logger was already set, by a previous call or by
+// the embedding application, in which case the existing one is kept.
+pub fn init_logger(log_dir: Option<PathBuf>, format: LogFormat) -> bool {
+    let mut initialized = INITIALIZED.lock().unwrap();
+    if *initialized {
+        return false;
+    }
+
+    let logger = match Logger::try_with_env_or_str("info") {
+        Ok(logger) => logger,
+        Err(err) => {
+            eprintln!("Invalid log specification: {}", err);
+            return false;
+        }
+    };
+
+    let logger = logger.format(match format {
+        LogFormat::Text => text_format,
+        LogFormat::Json => json_format,
+    });
+
+    let logger = match log_dir {
+        Some(path) => logger
+            .log_to_file(FileSpec::default().directory(path))
+            .write_mode(flexi_logger::WriteMode::BufferAndFlush)
+            .duplicate_to_stderr(flexi_logger::Duplicate::Warn),
+        None => logger,
+    };
+
+    *initialized = logger.start().is_ok();
+    *initialized
+}
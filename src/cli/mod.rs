@@ -1,20 +1,111 @@
 mod annotate;
+mod audit;
+mod budget;
+mod certificate;
+mod compare;
+mod config;
+mod defs;
+mod digest;
+mod disruption;
+mod doctor;
+mod domains;
+mod events;
+mod headroom;
+mod layers;
+mod layout;
+mod logger;
+mod normalize;
+mod order;
+mod output;
+mod passes;
+mod plan;
+mod profile;
+mod quota;
+mod recommend;
+mod report;
+mod server;
+mod session;
+mod sink;
+mod summary;
+mod suppress;
+mod tags;
+mod telemetry;
+#[cfg(feature = "tui")]
+mod tui;
+mod workspace;
 
-pub use annotate::ConflictAnnotater;
-use flexi_logger::FileSpec;
+pub use annotate::{ConflictAnnotater, SourceCache, MAX_ANNOTATED_FILE_BYTES};
+pub use audit::{audit_placements, format_violations, Placement, PlacementViolation};
+pub use budget::BudgetArgs;
+pub use certificate::{
+    certificate_key, Certificate, CertificateArgs, CertificateError, CERTIFICATE_KEY_ENV,
+    CERTIFICATE_VERSION, CONFLICT_FREE,
+};
+pub use compare::{compare_envs, format_comparison, EnvComparison, EnvHint, EnvRule};
+pub use config::{Config, ConfigSection, DEFAULT_CONFIG_FILE};
+pub use defs::{dump_definitions, reverse_lookup, Definition, DefsCommands, RuleCounts};
+pub use digest::{check_artifact_sources, stamp, SourceDigests, StaleSource, STAMPED_KINDS};
+pub use disruption::{
+    check_disruption_budgets, disruption_output, DisruptionConflict, DISRUPTION_SOLVER,
+};
+pub use domains::{domain_candidates, format_candidates, DomainCandidate};
+pub use events::{analyze, AnalysisEvent, EventListener};
+pub use headroom::{format_headroom, headroom, Headroom};
+pub use layers::LayersCommands;
+pub use layout::{Artifact, OutputLayout, INDEX_FILE};
+pub use logger::{init_logger, LogFormat};
+pub use normalize::{load_normalizations, AppliedNormalization, NameNormalization, Normalizations};
+pub use order::{
+    deploy_order, format_deploy_order, DeployOrder, DeployOrderError, DEPLOY_ORDER_FILE,
+};
+pub use output::{format_output, print_output, OutputFormat, StructuredOutput, OUTPUT_VERSION};
+pub use passes::{run_passes, AnalysisPasses};
+pub use plan::{plan_file, PlanChange, PlanSink, PlannedFile};
+pub use profile::{Profile, Profiles};
+pub use quota::{check_pod_quotas, quota_output, QuotaConflict, QUOTA_SOLVER};
+pub use recommend::{format_actions, recommend_actions, ActionKind, RecommendedAction};
+pub use report::{default_gates, load_impact_weights, ConflictReport, OutputLevel};
+pub use server::{Server, ServerConfig, Tenant};
+pub use session::{
+    diff_outputs, Session, SessionConflict, SessionInput, SessionOptions, SessionOutputs,
+    SESSION_VERSION,
+};
+pub use sink::{DirectorySink, ReportSink, SinkArgs, SinkKind, StdoutSink, WebhookSink};
+pub use summary::{DomainResult, DomainSummary, RunSummary, SUMMARY_FILE};
+pub use suppress::{
+    load_suppressions, today, Suppression, SuppressionStatus, Suppressions, SuppressionsCommands,
+};
+pub use tags::TagArgs;
+pub use telemetry::{enable_telemetry, record_solve, write_stats, SolveStats, TelemetryConfig};
+#[cfg(feature = "tui")]
+pub use tui::{run_dashboard, Dashboard, DashboardAction, Pane};
+pub use workspace::{Project, Workspace, WORKSPACE_FILE};
 
-use std::path::PathBuf;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use clap::{Parser, Subcommand};
 use log::{debug, error, info, warn};
 
 use crate::{
-    model::{get_parser, Entity},
+    model::{
+        apply_layers, get_parser, lint_model, load_env_files, merge_entities, DeployIRFormatter,
+        Entity, Env, LayerChange, RuleLifecycle,
+    },
     plugin::{k8s::K8SCommands, yarn::YarnCommands},
-    solver::{self, get_solver, SolverOutput},
+    solver::{
+        self, decode_snapshot, encode_snapshot, get_solver_with, EntityMap, SolverOptions,
+        SolverOutput, SNAPSHOT_EXTENSION,
+    },
     util,
 };
 
+// Name of the definitions artifact written next to the conflict reports
+pub const DEFINITIONS_FILE: &str = "definitions.yaml";
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
@@ -23,13 +114,110 @@ pub struct Cli {
 
     #[clap(short, long)]
     log_dir: Option<PathBuf>,
+
+    #[clap(
+        long,
+        global = true,
+        value_enum,
+        default_value = "text",
+        help = "Format of the log lines"
+    )]
+    log_format: LogFormat,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "PATH",
+        help = "Config file, defaults to ./deployfix.yaml when present"
+    )]
+    config: Option<PathBuf>,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Only warn about the require cycles co-locating their members satisfies, instead of reporting them as conflicts"
+    )]
+    relaxed_cycles: bool,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "N",
+        default_value_t = solver::DEFAULT_MAX_CYCLES,
+        help = "Require cycles the ring solver enumerates per strongly connected component"
+    )]
+    max_cycles: usize,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Fail instead of assuming a default for a missing setting, e.g. the topology key of a rule"
+    )]
+    strict: bool,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "STATE",
+        value_delimiter = ',',
+        default_value = "enforced",
+        help = "Lifecycle states of the rules whose conflicts fail the run: proposed, enforced or deprecated"
+    )]
+    gate: Vec<RuleLifecycle>,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "SECONDS",
+        help = "Seconds z3 may spend on one check before reporting the entity as undetermined"
+    )]
+    solve_timeout: Option<u64>,
+
+    #[clap(
+        long,
+        global = true,
+        value_name = "NAME",
+        help = "Profile of the `profiles` config section filling the options left at their default"
+    )]
+    profile: Option<String>,
+
+    #[clap(
+        long,
+        global = true,
+        help = "Record anonymous statistics of the solves to the destinations of the `telemetry` config section"
+    )]
+    telemetry: bool,
+
+    #[clap(short, long, global = true, help = "Only print the final summary line")]
+    quiet: bool,
+
+    #[clap(
+        short,
+        long,
+        global = true,
+        help = "Print the conflicting rule lists in addition to the annotated snippets"
+    )]
+    verbose: bool,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
     Check {
-        #[clap(value_name = "PATH")]
-        path: PathBuf,
+        #[clap(
+            value_name = "PATH",
+            required_unless_present = "workspace",
+            help = "Files, directories or glob patterns of models to check"
+        )]
+        paths: Vec<PathBuf>,
+        #[clap(
+            long,
+            value_name = "PATH",
+            num_args = 0..=1,
+            default_missing_value = WORKSPACE_FILE,
+            conflicts_with_all = ["paths", "domain", "snapshot"],
+            help = "Check every project of a workspace file, ./deployfix.workspace.yaml by default"
+        )]
+        workspace: Option<PathBuf>,
         #[clap(short, long, value_name = "FORMAT")]
         format: Option<String>,
         #[clap(short, long)]
@@ -38,6 +226,272 @@ pub enum Commands {
         default_domain_key: Option<String>,
         #[clap(short, long, default_value = "true")]
         cycle_check: bool,
+        #[clap(
+            long,
+            help = "Skip the z3 stage when the cheap solvers already report conflicts"
+        )]
+        fast_fail: bool,
+        #[command(flatten)]
+        budget: BudgetArgs,
+        #[command(flatten)]
+        tags: TagArgs,
+        #[clap(
+            long,
+            value_name = "PATH",
+            help = "Write a binary snapshot of the imported model, read back faster than IR"
+        )]
+        snapshot: Option<PathBuf>,
+        #[clap(
+            long,
+            value_name = "FILE",
+            conflicts_with = "workspace",
+            help = "Write the inputs, options, model and results of the run to a JSON session file"
+        )]
+        session_export: Option<PathBuf>,
+        #[clap(
+            long,
+            value_enum,
+            value_name = "FORMAT",
+            conflicts_with = "workspace",
+            help = "Print the conflicts to stdout as json, yaml or sarif instead of annotated snippets"
+        )]
+        output: Option<OutputFormat>,
+        #[command(flatten)]
+        certificate: CertificateArgs,
+    },
+    #[clap(
+        about = "Check that a certificate is signed with the key and that its inputs did not change"
+    )]
+    VerifyCertificate {
+        #[clap(
+            value_name = "FILE",
+            help = "Certificate written by check or k8s go --certificate"
+        )]
+        certificate: PathBuf,
+        #[clap(
+            long,
+            value_name = "FILE",
+            help = "Key the certificate was signed with, $DEPLOYFIX_CERTIFICATE_KEY otherwise"
+        )]
+        certificate_key: Option<PathBuf>,
+    },
+    #[cfg(feature = "tui")]
+    #[clap(
+        about = "Browse the entities, rules and conflicts of models and solve them again on demand"
+    )]
+    Tui {
+        #[clap(
+            value_name = "PATH",
+            required = true,
+            help = "Files, directories or glob patterns of models to browse"
+        )]
+        paths: Vec<PathBuf>,
+        #[clap(short, long, value_name = "FORMAT")]
+        format: Option<String>,
+        #[clap(
+            long,
+            value_name = "FILE",
+            help = "Environment file, may be repeated with later files overriding earlier ones"
+        )]
+        env_file: Vec<PathBuf>,
+        #[clap(short, long, default_value = "true")]
+        cycle_check: bool,
+    },
+    #[clap(
+        about = "Rerun the analysis of a session file and compare it with the recorded results"
+    )]
+    Replay {
+        #[clap(
+            value_name = "FILE",
+            help = "Session file written by check --session-export"
+        )]
+        session: PathBuf,
+    },
+    #[clap(about = "Generate a random model in the deployfix IR for benchmarking")]
+    Gen {
+        #[clap(long, default_value = "1000", help = "Number of entities")]
+        entities: u32,
+        #[clap(
+            long,
+            default_value = "0.01",
+            help = "Fraction of entity pairs connected by a rule"
+        )]
+        density: f64,
+        #[clap(
+            long,
+            default_value = "0.5",
+            help = "Fraction of the generated rules which are exclusions"
+        )]
+        exclude_ratio: f64,
+        #[clap(
+            long,
+            default_value = "0",
+            help = "Number of entities with both self-affinity and self-anti-affinity"
+        )]
+        self_conflicts: u32,
+        #[clap(long, default_value = "0", help = "Seed of the random generator")]
+        seed: u64,
+        #[clap(
+            short,
+            long,
+            help = "Output file, print to stdout when omitted, a binary snapshot when it ends with .snapshot"
+        )]
+        output: Option<PathBuf>,
+    },
+    #[clap(about = "Report the rules and conflicts of one environment missing from the other")]
+    CompareEnvs {
+        #[clap(
+            long,
+            value_name = "PATH",
+            required = true,
+            num_args = 1..,
+            help = "Files, directories or glob patterns of the models of the first environment"
+        )]
+        left: Vec<PathBuf>,
+        #[clap(
+            long,
+            value_name = "PATH",
+            required = true,
+            num_args = 1..,
+            help = "Files, directories or glob patterns of the models of the second environment"
+        )]
+        right: Vec<PathBuf>,
+        #[clap(
+            long,
+            value_name = "FILE",
+            help = "Environment file of the first environment, may be repeated"
+        )]
+        left_env_file: Vec<PathBuf>,
+        #[clap(
+            long,
+            value_name = "FILE",
+            help = "Environment file of the second environment, may be repeated"
+        )]
+        right_env_file: Vec<PathBuf>,
+        #[clap(short, long, value_name = "FORMAT")]
+        format: Option<String>,
+        #[command(flatten)]
+        budget: BudgetArgs,
+    },
+    #[clap(about = "List the metadata keys of the rules a check could be split into domains by")]
+    Domains {
+        #[clap(
+            value_name = "PATH",
+            required = true,
+            help = "Files, directories or glob patterns of models to scan"
+        )]
+        paths: Vec<PathBuf>,
+        #[clap(short, long, value_name = "FORMAT")]
+        format: Option<String>,
+    },
+    #[clap(about = "Report how many more replicas of each entity the nodes of env files can host")]
+    Headroom {
+        #[clap(
+            value_name = "PATH",
+            required = true,
+            help = "Files, directories or glob patterns of models to plan"
+        )]
+        paths: Vec<PathBuf>,
+        #[clap(
+            long,
+            value_name = "FILE",
+            required = true,
+            help = "Environment file, may be repeated with later files overriding earlier ones"
+        )]
+        env_file: Vec<PathBuf>,
+        #[clap(short, long, value_name = "FORMAT")]
+        format: Option<String>,
+    },
+    #[clap(about = "Report the entities the rules of one entity make unschedulable")]
+    BlastRadius {
+        #[clap(value_name = "ENTITY", help = "Entity whose rules are enforced")]
+        entity: String,
+        #[clap(
+            value_name = "PATH",
+            required = true,
+            help = "Files, directories or glob patterns of models, the new policy included"
+        )]
+        paths: Vec<PathBuf>,
+        #[clap(short, long, value_name = "FORMAT")]
+        format: Option<String>,
+        #[clap(short, long, default_value = "true")]
+        cycle_check: bool,
+        #[command(flatten)]
+        budget: BudgetArgs,
+    },
+    #[clap(
+        about = "Write the waves entities can be deployed in, each after the entities it requires"
+    )]
+    DeployOrder {
+        #[clap(
+            value_name = "PATH",
+            required = true,
+            help = "Files, directories or glob patterns of models to order"
+        )]
+        paths: Vec<PathBuf>,
+        #[clap(short, long, value_name = "FORMAT")]
+        format: Option<String>,
+        #[clap(
+            short,
+            long,
+            value_name = "FILE",
+            default_value = DEPLOY_ORDER_FILE,
+            help = "Output file, `-` for stdout"
+        )]
+        output: PathBuf,
+    },
+    #[clap(about = "Report contradictory, redundant and always satisfied rules of a model")]
+    LintModel {
+        #[clap(
+            value_name = "PATH",
+            required = true,
+            help = "Files, directories or glob patterns of models to lint"
+        )]
+        paths: Vec<PathBuf>,
+        #[clap(short, long, value_name = "FORMAT")]
+        format: Option<String>,
+        #[clap(
+            long = "pass",
+            value_name = "FILE",
+            help = "Custom analysis pass compiled to WASM, run after the passes of the config"
+        )]
+        passes: Vec<PathBuf>,
+    },
+    #[clap(about = "Check the installation: z3 backend, solvers and writable directories")]
+    Doctor {
+        #[clap(
+            short,
+            long,
+            value_name = "DIR",
+            help = "Output directories to check for write permission"
+        )]
+        output_dir: Vec<PathBuf>,
+    },
+    #[clap(about = "Look up entity definitions and the rules referencing them")]
+    Defs {
+        #[command(subcommand)]
+        command: DefsCommands,
+    },
+    #[clap(about = "Explain how layered IR inputs override each other")]
+    Layers {
+        #[command(subcommand)]
+        command: LayersCommands,
+    },
+    #[clap(about = "Audit the conflict suppressions of the config file")]
+    Suppressions {
+        #[command(subcommand)]
+        command: SuppressionsCommands,
+    },
+    #[clap(about = "Serve checks over HTTP to the tenants of the `server` config section")]
+    Serve {
+        #[clap(long, default_value = "127.0.0.1:8080", help = "Address to listen on")]
+        listen: String,
+        #[clap(
+            long,
+            value_name = "PATH",
+            help = "Append one JSON line per check request to this file"
+        )]
+        audit_log: Option<PathBuf>,
     },
     K8S {
         #[command(subcommand)]
@@ -49,94 +503,683 @@ pub enum Commands {
     },
 }
 
-fn init_logger(path: Option<PathBuf>) {
-    if let Some(path) = path {
-        // Set env logger format
-        flexi_logger::Logger::try_with_env_or_str("info")
-            .expect("Failed to initialize logger")
-            .log_to_file(FileSpec::default().directory(path))
-            .write_mode(flexi_logger::WriteMode::BufferAndFlush)
-            .duplicate_to_stderr(flexi_logger::Duplicate::Warn)
-            .format(|write, now, record| {
-                let now = now.format("%Y-%m-%d %H:%M");
+pub(crate) fn detect_format(path: &Path, format: Option<&str>) -> Option<String> {
+    let format = match format {
+        Some(f) => f,
+        None => path.extension()?.to_str()?,
+    };
 
-                write!(write, "{} [{}] {}", now, record.level(), record.args())
-            })
-            .start()
-            .expect("Failed to initialize logger");
-    } else {
-        flexi_logger::Logger::try_with_env_or_str("info")
-            .expect("Failed to initialize logger")
-            .format(|write, now, record| {
-                let now = now.format("%Y-%m-%d %H:%M");
+    let format = match format {
+        "ir" => "deployfix",
+        "yml" => "yaml",
+        x => x,
+    };
 
-                write!(write, "{} [{}] {}", now, record.level(), record.args())
-            })
-            .start()
-            .expect("Failed to initialize logger");
+    Some(format.to_string())
+}
+
+// Expands glob patterns and directories into the list of model files to check. The files
+// keep the order of `paths`, which is the layer order, a directory or pattern in name order.
+pub(crate) fn collect_inputs(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut seen = std::collections::HashSet::new();
+
+    paths
+        .iter()
+        .flat_map(|path| {
+            let pattern = path.to_str().unwrap_or_default();
+
+            let mut files = if pattern.contains(['*', '?', '[']) {
+                match glob::glob(pattern) {
+                    Ok(entries) => entries.filter_map(|e| e.ok()).collect::<Vec<_>>(),
+                    Err(err) => {
+                        warn!("Invalid glob pattern {}: {}", pattern, err);
+                        vec![]
+                    }
+                }
+            } else if path.is_dir() {
+                match std::fs::read_dir(path) {
+                    Ok(entries) => entries
+                        .filter_map(|e| e.ok().map(|e| e.path()))
+                        .filter(|p| p.is_file())
+                        .collect::<Vec<_>>(),
+                    Err(err) => {
+                        warn!("Failed to read directory {}: {}", path.display(), err);
+                        vec![]
+                    }
+                }
+            } else {
+                vec![path.clone()]
+            };
+            files.sort();
+
+            files
+        })
+        .filter(|path| seen.insert(path.clone()))
+        .collect()
+}
+
+// Entities of each input stacked as layers, unmerged, with the changes of their directives
+fn import_layers(paths: &[PathBuf], format: Option<&str>) -> (Vec<Entity>, Vec<LayerChange>) {
+    import_layers_with_events(paths, format, None)
+}
+
+fn import_layers_with_events(
+    paths: &[PathBuf],
+    format: Option<&str>,
+    listener: Option<&dyn EventListener>,
+) -> (Vec<Entity>, Vec<LayerChange>) {
+    let parsed = |path: &Path, entities: &[Entity]| {
+        if let Some(listener) = listener {
+            listener.on_event(AnalysisEvent::FileParsed {
+                path: path.display().to_string(),
+                entities: entities.len(),
+            });
+        }
+    };
+
+    // Files found in a directory or by a pattern may be skipped, the ones named may not
+    let skip = |path: &Path, reason: String| {
+        if paths.iter().any(|p| p == path) {
+            error!("{}", reason);
+            std::process::exit(1);
+        }
+        warn!("{}, skipping", reason);
+    };
+
+    let layers = collect_inputs(paths)
+        .into_iter()
+        .filter_map(|path| {
+            let format = match detect_format(&path, format) {
+                Some(format) => format,
+                None => {
+                    skip(
+                        &path,
+                        format!("Unable to detect the format of {}", path.display()),
+                    );
+                    return None;
+                }
+            };
+
+            if format == SNAPSHOT_EXTENSION {
+                let data = std::fs::read(&path).unwrap();
+                let entities = decode_snapshot(&data).unwrap_or_else(|err| {
+                    error!("Failed to read snapshot {}: {}", path.display(), err);
+                    std::process::exit(1);
+                });
+                parsed(&path, &entities);
+
+                return Some(entities);
+            }
+
+            let parser = match get_parser(&format) {
+                Ok(parser) => parser,
+                Err(err) => {
+                    skip(&path, format!("Unable to read {}: {}", path.display(), err));
+                    return None;
+                }
+            };
+
+            debug!("Importing from {} with format {:?}", path.display(), format);
+
+            let data = util::read_source(&path).unwrap();
+            let entities = parser.parse(&data, path.clone().into()).unwrap();
+            parsed(&path, &entities);
+
+            Some(entities)
+        })
+        .collect::<Vec<_>>();
+
+    // Checking nothing would pass, e.g. a directory of files of another extension
+    if !paths.is_empty() && layers.is_empty() {
+        error!(
+            "No model found in {}",
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        std::process::exit(1);
+    }
+
+    apply_layers(layers)
+}
+
+fn import_inputs(paths: &[PathBuf], format: Option<&str>) -> Vec<Entity> {
+    import_inputs_with_events(paths, format, None)
+}
+
+fn import_inputs_with_events(
+    paths: &[PathBuf],
+    format: Option<&str>,
+    listener: Option<&dyn EventListener>,
+) -> Vec<Entity> {
+    let (entities, changes) = import_layers_with_events(paths, format, listener);
+    for change in changes.iter() {
+        if change.replaced.is_empty() {
+            warn!("{}", change);
+        } else {
+            info!("{}", change);
+        }
     }
+
+    merge_entities(entities, None)
 }
 
+// Entry point of the binary, parses the arguments and installs the logger
 pub fn run() {
-    let cli = Cli::parse();
-    init_logger(cli.log_dir);
+    let mut cli = Cli::parse();
+    // The profile may pick the format of the log lines
+    let profile = cli.apply_profile();
+    init_logger(cli.log_dir.clone(), cli.log_format);
+    if let Err(err) = profile {
+        error!("{:#}", err);
+        std::process::exit(1);
+    }
+
+    execute(cli)
+}
+
+impl Cli {
+    // Fills the options left at their default from `--profile`, once
+    fn apply_profile(&mut self) -> anyhow::Result<()> {
+        let Some(name) = self.profile.take() else {
+            return Ok(());
+        };
+
+        let config = Config::load_or_default(self.config.as_deref())?;
+        config.section::<Profiles>()?.get(&name)?.apply(self);
+
+        Ok(())
+    }
+
+    fn run_options(&self, config: &Config) -> anyhow::Result<RunOptions> {
+        let telemetry = if self.telemetry {
+            enable_telemetry(config.section::<TelemetryConfig>()?)
+        } else {
+            None
+        };
+
+        Ok(RunOptions {
+            level: OutputLevel::from_flags(self.quiet, self.verbose),
+            solver: SolverOptions {
+                relaxed_cycles: self.relaxed_cycles,
+                max_cycles: self.max_cycles,
+                timeout: self
+                    .solve_timeout
+                    .filter(|seconds| *seconds > 0)
+                    .map(Duration::from_secs),
+                deadline: None,
+                strict: self.strict,
+            },
+            gates: self.gate.iter().copied().collect(),
+            telemetry,
+        })
+    }
+}
+
+// Global flags of a run, handed to the commands instead of being kept by the process so that
+// embedders running several checks at once do not share them
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    pub level: OutputLevel,
+    pub solver: SolverOptions,
+    // Lifecycle states whose conflicts fail the run
+    pub gates: BTreeSet<RuleLifecycle>,
+    // Destinations of the solve statistics, with `--telemetry`
+    pub telemetry: Option<TelemetryConfig>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            level: OutputLevel::default(),
+            solver: SolverOptions::default(),
+            gates: default_gates(),
+            telemetry: None,
+        }
+    }
+}
+
+impl RunOptions {
+    pub fn record_solve(&self, stats: &SolveStats) {
+        if let Some(telemetry) = self.telemetry.as_ref() {
+            record_solve(telemetry, stats);
+        }
+    }
+}
+
+// Runs a parsed command line without touching the global logger, for embedding
+pub fn execute(mut cli: Cli) {
+    if let Err(err) = cli.apply_profile() {
+        error!("{:#}", err);
+        std::process::exit(1);
+    }
+
+    let config = Config::load_or_default(cli.config.as_deref()).unwrap_or_else(|err| {
+        error!("{:#}", err);
+        std::process::exit(1);
+    });
+    let run = cli.run_options(&config).unwrap_or_else(|err| {
+        error!("{:#}", err);
+        std::process::exit(1);
+    });
 
     match cli.command {
         Some(Commands::Check {
-            path,
+            paths,
+            workspace,
             format,
             domain,
             default_domain_key,
             cycle_check,
+            fast_fail,
+            budget,
+            tags,
+            snapshot,
+            session_export,
+            output,
+            certificate,
         }) => {
-            let format = match format {
-                Some(f) => f,
-                None => path.extension().unwrap().to_str().unwrap().to_string(),
-            };
+            if let Some(workspace) = workspace {
+                if certificate.certificate.is_some() {
+                    warn!("Workspace checks do not write certificates, ignoring --certificate");
+                }
 
-            let format = match format.as_str() {
-                "ir" => "deployfix",
-                x => x,
-            };
+                workspace::check(
+                    &workspace,
+                    cycle_check,
+                    fast_fail,
+                    &budget,
+                    &tags,
+                    &run,
+                    &config,
+                )
+                .unwrap_or_else(|err| {
+                    error!("{:#}", err);
+                    std::process::exit(1);
+                });
 
-            debug!("Importing from {} with format {:?}", path.display(), format);
+                return;
+            }
 
-            let parser = get_parser(&format).unwrap();
-            let data = std::fs::read_to_string(&path).unwrap();
-            let entities = parser.parse(&data, path.into()).unwrap();
+            let entities =
+                load_normalizations(&config).apply(import_inputs(&paths, format.as_deref()));
+            let entities = tags.apply(entities);
             debug!("Imported entities: {:?}", entities);
 
-            let mut no_conflict = true;
+            if let Some(snapshot) = snapshot {
+                std::fs::write(&snapshot, encode_snapshot(&entities))
+                    .expect("Failed to write snapshot");
+                info!("Snapshot written to {}", snapshot.display());
+            }
+
+            let options = SessionOptions {
+                format,
+                domain,
+                default_domain_key,
+                cycle_check,
+                fast_fail,
+                budget,
+                tags,
+                relaxed_cycles: cli.relaxed_cycles,
+                max_cycles: cli.max_cycles,
+            };
+            let today = today();
+
+            // Structured output replaces the annotated snippets on stdout
+            let level = if output.is_some() {
+                OutputLevel::Quiet
+            } else {
+                run.level
+            };
+            let mut report = ConflictReport::new(level)
+                .with_gates(run.gates.clone())
+                .with_suppressions(load_suppressions(&config), today)
+                .with_impact_weights(load_impact_weights(&config));
+            if session_export.is_some() || output.is_some() {
+                report = report.with_session_outputs();
+            }
+
+            let session = session_export.map(|path| {
+                (
+                    path,
+                    Session::new(&paths, options.clone(), &config, today, entities.clone()),
+                )
+            });
 
-            if let Some(domain) = domain {
-                assert!(default_domain_key.is_some());
+            check_entities(entities, &options, &run, &mut report, None);
+
+            if !report.has_conflict() && !report.has_undetermined() {
+                info!("No conflict found");
+            }
 
-                let default_domain_key = default_domain_key.unwrap();
-                let entities = util::split_by_metadata(&entities, &domain, &default_domain_key);
+            if let Some((path, mut session)) = session {
+                session.outputs = report.session_outputs().unwrap_or_default();
+                if let Err(err) = session.write(&path) {
+                    error!("{:#}", err);
+                    std::process::exit(1);
+                }
+                info!("Session written to {}", path.display());
+            }
 
-                for (domain, entities) in entities {
-                    info!("Checking domain {}...", domain);
+            if let Err(err) = certificate.issue("check", &paths, &report) {
+                error!("{:#}", err);
+                std::process::exit(1);
+            }
 
-                    no_conflict &= solve(entities, cycle_check);
+            print_output(&report, output);
+            report.print_summary();
+        }
+        Some(Commands::VerifyCertificate {
+            certificate,
+            certificate_key: key,
+        }) => {
+            let (loaded, key) = match Certificate::load(&certificate)
+                .and_then(|loaded| Ok((loaded, certificate_key(key.as_deref())?)))
+            {
+                Ok(loaded) => loaded,
+                Err(err) => {
+                    error!("{:#}", err);
+                    std::process::exit(1);
                 }
+            };
+            if key.is_none() {
+                warn!(
+                    "No certificate key, pass --certificate-key or set {} to check the signature",
+                    CERTIFICATE_KEY_ENV
+                );
+            }
+
+            let errors = loaded.verify(key.as_deref());
+            for err in errors.iter() {
+                error!("{}: {}", certificate.display(), err);
+            }
+            if !errors.is_empty() {
+                std::process::exit(1);
+            }
+
+            info!(
+                "{}: {} of {} inputs by deployfix {} at {}",
+                certificate.display(),
+                loaded.result,
+                loaded.inputs.len(),
+                loaded.deployfix,
+                loaded.issued_at
+            );
+        }
+        #[cfg(feature = "tui")]
+        Some(Commands::Tui {
+            paths,
+            format,
+            env_file,
+            cycle_check,
+        }) => {
+            let envs = (!env_file.is_empty()).then(|| {
+                load_env_files(&env_file).unwrap_or_else(|err| {
+                    error!("Failed to load env files: {}", err);
+                    std::process::exit(1);
+                })
+            });
+
+            let dashboard = Dashboard::new(paths, format, envs, cycle_check, run.solver);
+            if let Err(err) = run_dashboard(dashboard) {
+                error!("{:#}", err);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Gen {
+            entities,
+            density,
+            exclude_ratio,
+            self_conflicts,
+            seed,
+            output,
+        }) => {
+            let entities =
+                util::generate_entities(entities, density, exclude_ratio, self_conflicts, seed);
+            if let Some(output) = output
+                .as_ref()
+                .filter(|o| o.extension().and_then(|e| e.to_str()) == Some(SNAPSHOT_EXTENSION))
+            {
+                std::fs::write(output, encode_snapshot(&entities))
+                    .expect("Failed to write generated model");
+                info!("Generated model written to {}", output.display());
+                return;
+            }
+
+            let ir = DeployIRFormatter::format(&entities);
+
+            if let Some(output) = output {
+                std::fs::write(&output, ir).expect("Failed to write generated model");
+                info!("Generated model written to {}", output.display());
             } else {
-                no_conflict = solve(entities, cycle_check);
+                print!("{}", ir);
             }
+        }
+        Some(Commands::CompareEnvs {
+            left,
+            right,
+            left_env_file,
+            right_env_file,
+            format,
+            budget,
+        }) => {
+            let normalizations = load_normalizations(&config);
+            let load_envs = |paths: &[PathBuf]| {
+                if paths.is_empty() {
+                    return vec![];
+                }
 
-            if no_conflict {
-                info!("No conflict found");
+                load_env_files(paths).unwrap_or_else(|err| {
+                    error!("Failed to load env files: {}", err);
+                    std::process::exit(1);
+                })
+            };
+            let (left_envs, right_envs) = (load_envs(&left_env_file), load_envs(&right_env_file));
+            let import = |paths: &[PathBuf], envs: &Vec<Env>| {
+                let entities = normalizations.apply(import_inputs(paths, format.as_deref()));
+                let mut report = ConflictReport::new(OutputLevel::Quiet);
+                let envs = (!envs.is_empty()).then_some(envs);
+                solve(
+                    entities.clone(),
+                    envs,
+                    true,
+                    false,
+                    &budget,
+                    &run,
+                    &mut report,
+                );
+
+                (entities, report.conflicting_entities().clone())
+            };
+            let name = |paths: &[PathBuf]| {
+                paths
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+
+            let (left_entities, left_conflicts) = import(&left, &left_envs);
+            let (right_entities, right_conflicts) = import(&right, &right_envs);
+            let comparison = compare_envs(&left_entities, &right_entities)
+                .with_conflicts(&left_conflicts, &right_conflicts)
+                .with_env_hints((&left_entities, &left_envs), (&right_entities, &right_envs));
+
+            if comparison.is_empty() {
+                info!(
+                    "No differences between {} and {}",
+                    name(&left),
+                    name(&right)
+                );
+                return;
+            }
+
+            print!(
+                "{}",
+                format_comparison(&comparison, &name(&left), &name(&right))
+            );
+            std::process::exit(1);
+        }
+        Some(Commands::Domains { paths, format }) => {
+            let entities =
+                load_normalizations(&config).apply(import_inputs(&paths, format.as_deref()));
+            let candidates = domain_candidates(&entities);
+            if candidates.is_empty() {
+                info!("No metadata keys found in {} entities", entities.len());
+                return;
+            }
+
+            print!("{}", format_candidates(&candidates));
+        }
+        Some(Commands::Headroom {
+            paths,
+            env_file,
+            format,
+        }) => {
+            let entities =
+                load_normalizations(&config).apply(import_inputs(&paths, format.as_deref()));
+            let envs = load_env_files(&env_file).unwrap_or_else(|err| {
+                error!("Failed to load env files: {}", err);
+                std::process::exit(1);
+            });
+
+            print!("{}", format_headroom(&headroom(&entities, &envs)));
+        }
+        Some(Commands::BlastRadius {
+            entity,
+            paths,
+            format,
+            cycle_check,
+            budget,
+        }) => {
+            let entities =
+                load_normalizations(&config).apply(import_inputs(&paths, format.as_deref()));
+            let radius = solver::blast_radius(&entities, &entity, |entities| {
+                let entity_map: EntityMap = entities.try_into().unwrap();
+                budget.check(&entity_map.problem_size(), "narrowing the input paths");
+
+                run_solvers(&entity_map, None, cycle_check, false, &run.solver)
+            })
+            .unwrap_or_else(|err| {
+                error!("{:#}", err);
+                std::process::exit(1);
+            });
+
+            print!("{}", radius);
+            if !radius.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::DeployOrder {
+            paths,
+            format,
+            output,
+        }) => {
+            let entities =
+                load_normalizations(&config).apply(import_inputs(&paths, format.as_deref()));
+            let order = deploy_order(&entities).unwrap_or_else(|err| {
+                error!("{}", err);
+                if !err.blocked.is_empty() {
+                    error!("Blocked by the cycles: {}", err.blocked.join(", "));
+                }
+                std::process::exit(1);
+            });
+
+            if output.as_os_str() == "-" {
+                print!("{}", format_deploy_order(&order));
+                return;
+            }
+
+            std::fs::write(&output, format_deploy_order(&order))
+                .expect("Failed to write deployment order");
+            info!(
+                "Deployment order of {} waves written to {}",
+                order.waves.len(),
+                output.display()
+            );
+        }
+        Some(Commands::LintModel {
+            paths,
+            format,
+            passes,
+        }) => {
+            let entities =
+                load_normalizations(&config).apply(import_inputs(&paths, format.as_deref()));
+            let findings = lint_model(&entities);
+            let pass_findings = run_passes(&config, &passes, &entities).unwrap_or_else(|err| {
+                error!("{:#}", err);
+                std::process::exit(1);
+            });
+
+            for finding in findings.iter() {
+                warn!("{}", finding);
+            }
+            for (_, finding) in pass_findings.iter() {
+                warn!("{}", finding);
+            }
+
+            let total = findings.len() + pass_findings.len();
+            if total > 0 {
+                error!(
+                    "deployfix lint: {} findings across {} entities",
+                    total,
+                    findings
+                        .iter()
+                        .map(|f| &f.entity)
+                        .chain(pass_findings.iter().map(|(entity, _)| entity))
+                        .collect::<std::collections::BTreeSet<_>>()
+                        .len()
+                );
+                std::process::exit(1);
+            }
+
+            info!("deployfix lint: no findings in {} entities", entities.len());
+        }
+        Some(Commands::Doctor { output_dir }) => {
+            let dirs = cli
+                .log_dir
+                .into_iter()
+                .chain(output_dir)
+                .collect::<Vec<_>>();
+
+            if !doctor::doctor(&dirs) {
+                error!("deployfix doctor found problems");
+                std::process::exit(1);
+            }
+
+            info!("deployfix doctor found no problems");
+        }
+        Some(Commands::Replay { session }) => session::replay(&session, &run),
+        Some(Commands::Defs { command }) => defs::execute(command),
+        Some(Commands::Layers { command }) => layers::execute(command),
+        Some(Commands::Suppressions { command }) => suppress::execute(command, &config),
+        Some(Commands::Serve { listen, audit_log }) => {
+            let server = config
+                .section::<ServerConfig>()
+                .and_then(|section| Server::new(section, audit_log.as_deref()))
+                .map(|server| server.with_solver_options(run.solver))
+                .unwrap_or_else(|err| {
+                    error!("{:#}", err);
+                    std::process::exit(1);
+                });
+
+            if let Err(err) = server.serve(&listen) {
+                error!("{:#}", err);
+                std::process::exit(1);
             }
         }
         Some(Commands::K8S { command }) => {
             if let Some(command) = command {
-                crate::plugin::k8s::execute(command)
+                crate::plugin::k8s::execute(command, &run, &config)
             } else {
                 warn!("No command specified")
             }
         }
         Some(Commands::Yarn { command }) => {
             if let Some(command) = command {
-                crate::plugin::yarn::execute(command)
+                crate::plugin::yarn::execute(command, &run, &config)
             } else {
                 warn!("No command specified")
             }
@@ -147,42 +1190,172 @@ pub fn run() {
     }
 }
 
-fn solve(entities: Vec<Entity>, cycle_check: bool) -> bool {
-    let entity_map = entities.try_into().unwrap();
+// Checks the model as a whole, or each domain of `--domain` apart, with the cycle options of
+// `options` over the ones of `run`. The listener is told about every solve and the conflicts
+// it adds to `report`, which must record its session outputs.
+fn check_entities(
+    entities: Vec<Entity>,
+    options: &SessionOptions,
+    run: &RunOptions,
+    report: &mut ConflictReport,
+    listener: Option<&dyn EventListener>,
+) {
+    let (cycle_check, fast_fail, budget) =
+        (options.cycle_check, options.fast_fail, &options.budget);
+    let run = &RunOptions {
+        solver: SolverOptions {
+            relaxed_cycles: options.relaxed_cycles,
+            max_cycles: options.max_cycles,
+            ..run.solver
+        },
+        ..run.clone()
+    };
 
-    let result = if cycle_check {
-        let ring_solver = get_solver("ring").unwrap();
-        let ring_result = ring_solver.solve(&entity_map);
-        debug!("Ring Solver Result: {:?}", ring_result);
+    let domains = match options.domain.as_deref() {
+        Some(domain) => {
+            assert!(options.default_domain_key.is_some());
 
-        let solver = get_solver("z3").unwrap();
-        let result = solver.solve(&entity_map);
+            let default_domain_key = options.default_domain_key.as_deref().unwrap();
+            util::split_by_metadata(&entities, domain, default_domain_key, run.solver.strict)
+                .unwrap_or_else(|err| {
+                    error!("{:#}", err);
+                    std::process::exit(1);
+                })
+                .into_iter()
+                .map(|(domain, entities)| (Some(domain), entities))
+                .collect()
+        }
+        None => vec![(None, entities)],
+    };
 
-        debug!("Z3 Solver Result: {:?}", result);
+    for (domain, entities) in domains {
+        if let Some(domain) = domain.as_deref() {
+            info!("Checking domain {}...", domain);
+        }
 
-        ring_result.merge(result)
-    } else {
-        let solver = get_solver("z3").unwrap();
-        let result = solver.solve(&entity_map);
+        let Some(listener) = listener else {
+            solve(entities, None, cycle_check, fast_fail, budget, run, report);
+            continue;
+        };
 
-        debug!("Z3 Solver Result: {:?}", result);
+        listener.on_event(AnalysisEvent::DomainStarted {
+            domain,
+            entities: entities.len(),
+        });
 
-        result
-    };
+        let reported = report.recorded_conflicts().len();
+        solve(entities, None, cycle_check, fast_fail, budget, run, report);
+
+        for conflict in report.recorded_conflicts()[reported..].iter() {
+            listener.on_event(AnalysisEvent::ConflictFound(conflict.clone()));
+
+            for recommendation in conflict.recommendations.iter() {
+                listener.on_event(AnalysisEvent::RecommendationReady {
+                    entity: conflict.entity.clone(),
+                    rule: conflict.rule.clone(),
+                    recommendation: recommendation.clone(),
+                });
+            }
+        }
+    }
+}
+
+fn solve(
+    entities: Vec<Entity>,
+    envs: Option<&Vec<Env>>,
+    cycle_check: bool,
+    fast_fail: bool,
+    budget: &BudgetArgs,
+    run: &RunOptions,
+    report: &mut ConflictReport,
+) -> bool {
+    let entity_map: EntityMap = entities.try_into().unwrap();
+    budget.check(
+        &entity_map.problem_size(),
+        "checking one domain at a time with --domain or narrowing the input paths",
+    );
 
-    if let SolverOutput::Conflict(conflicts) = result {
-        let conflicts_annotations = conflicts
-            .into_iter()
-            .flat_map(|(k, v)| v.into_iter().map(move |v| (k.clone(), v)))
-            .map(|(name, rule)| ConflictAnnotater::new(name.as_str(), &rule).annotate())
-            .collect::<Vec<_>>();
+    let started = std::time::Instant::now();
+    let result = run_solvers(&entity_map, envs, cycle_check, fast_fail, &run.solver);
+    run.record_solve(&SolveStats::new(
+        "check",
+        &entity_map.problem_size(),
+        started.elapsed(),
+        result.get_unscheduable().map(|e| e.len()).unwrap_or(0),
+    ));
 
-        let conflicts = conflicts_annotations.join("\n\n");
+    let (result, undetermined) = result.take_undetermined();
+    if let Some(undetermined) = undetermined {
+        report.add_undetermined(&undetermined);
+    }
+    let (result, soft) = result.take_soft();
+    report.add_soft(&soft);
 
-        error!("{}", conflicts);
+    if let SolverOutput::Conflict(conflicts, solvers) = result {
+        report.add(&conflicts, &solvers, &entity_map.entities);
 
         false
     } else {
         true
     }
 }
+
+// Runs z3 with the cheap solvers picked by the flags, on the whole map, z3 placing the entities
+// on the nodes of `envs` when given
+fn run_solvers(
+    entity_map: &EntityMap,
+    envs: Option<&Vec<Env>>,
+    cycle_check: bool,
+    fast_fail: bool,
+    options: &SolverOptions,
+) -> SolverOutput {
+    let z3 = || {
+        let solver = get_solver_with("z3", options).unwrap();
+        if let Some(envs) = envs {
+            solver.set_envs(envs.clone());
+        }
+
+        solver
+    };
+
+    if fast_fail {
+        // References to undefined entities are conflicts of this stage, like `--reject-unknown`
+        let mut result = get_solver_with("structural", options)
+            .unwrap()
+            .solve(entity_map);
+        result = result.merge(
+            get_solver_with("unknown", options)
+                .unwrap()
+                .solve(entity_map),
+        );
+        if cycle_check {
+            result = result.merge(get_solver_with("ring", options).unwrap().solve(entity_map));
+        }
+
+        if result.is_conflict() {
+            info!("Cheap solvers reported conflicts, skipping z3");
+            result
+        } else {
+            let result = z3().solve(entity_map);
+            debug!("Z3 Solver Result: {:?}", result);
+
+            result
+        }
+    } else if cycle_check {
+        let ring_solver = get_solver_with("ring", options).unwrap();
+        let ring_result = ring_solver.solve(entity_map);
+        debug!("Ring Solver Result: {:?}", ring_result);
+
+        let result = z3().solve(entity_map);
+
+        debug!("Z3 Solver Result: {:?}", result);
+
+        ring_result.merge(result)
+    } else {
+        let result = z3().solve(entity_map);
+
+        debug!("Z3 Solver Result: {:?}", result);
+
+        result
+    }
+}
@@ -0,0 +1,169 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Display,
+};
+
+use log::{error, info};
+use regex::Regex;
+use serde::{de, Deserialize, Deserializer};
+
+use crate::model::{merge_entities, Entity, EntityName};
+
+use super::{Config, ConfigSection};
+
+// A rename rule, `replace` may refer to the groups of `pattern` as `$1` or `$name`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NameNormalization {
+    #[serde(deserialize_with = "deserialize_pattern")]
+    pub pattern: Regex,
+    pub replace: String,
+}
+
+fn deserialize_pattern<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+
+    Regex::new(&pattern).map_err(de::Error::custom)
+}
+
+// Entity names of other teams renamed at import so that their models align, e.g.
+//
+//   normalize:
+//     - pattern: '^(app\.kubernetes\.io/name|service)=(.+)$'
+//       replace: app=$2
+//
+// The first pattern matching a name renames it, entities renamed to the same name are merged.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Normalizations(pub Vec<NameNormalization>);
+
+impl ConfigSection for Normalizations {
+    const NAME: &'static str = "normalize";
+}
+
+// A name renamed by a normalization
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AppliedNormalization {
+    pub from: EntityName,
+    pub to: EntityName,
+    pub pattern: String,
+}
+
+impl Display for AppliedNormalization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Normalized {} to {} by `{}`",
+            self.from.as_ref(),
+            self.to.as_ref(),
+            self.pattern
+        )
+    }
+}
+
+impl Normalizations {
+    fn rename(&self, name: &EntityName) -> Option<AppliedNormalization> {
+        let normalization = self
+            .0
+            .iter()
+            .find(|normalization| normalization.pattern.is_match(name.as_ref()))?;
+        let to = normalization
+            .pattern
+            .replace(name.as_ref(), normalization.replace.as_str());
+
+        (to != name.as_ref()).then(|| AppliedNormalization {
+            from: name.clone(),
+            to: to.into_owned().into(),
+            pattern: normalization.pattern.to_string(),
+        })
+    }
+
+    // Renames the entities and the targets of their rules, returns the names renamed
+    pub fn normalize(&self, entities: Vec<Entity>) -> (Vec<Entity>, Vec<AppliedNormalization>) {
+        if self.0.is_empty() {
+            return (entities, vec![]);
+        }
+
+        let names = entities
+            .iter()
+            .flat_map(|entity| {
+                std::iter::once(&entity.name).chain(
+                    entity
+                        .rules()
+                        .flat_map(|rule| std::iter::once(rule.source()).chain(rule.targets())),
+                )
+            })
+            .collect::<BTreeSet<_>>();
+        let renames = names
+            .into_iter()
+            .filter_map(|name| self.rename(name).map(|applied| (name.clone(), applied)))
+            .collect::<BTreeMap<_, _>>();
+
+        if renames.is_empty() {
+            return (entities, vec![]);
+        }
+
+        let rename = |name: &EntityName| {
+            renames
+                .get(name)
+                .map(|applied| applied.to.clone())
+                .unwrap_or_else(|| name.clone())
+        };
+        let entities = entities
+            .into_iter()
+            .map(|entity| Entity {
+                name: rename(&entity.name),
+                requires: entity
+                    .requires
+                    .into_iter()
+                    .map(|rule| rule.renamed(rename))
+                    .collect(),
+                excludes: entity
+                    .excludes
+                    .into_iter()
+                    .map(|rule| rule.renamed(rename))
+                    .collect(),
+                ..entity
+            })
+            .collect();
+
+        (
+            merge_entities(entities, None),
+            renames.into_values().collect(),
+        )
+    }
+
+    // Normalizes the entities and logs every name renamed
+    pub fn apply(&self, entities: Vec<Entity>) -> Vec<Entity> {
+        self.apply_with_renames(entities).0
+    }
+
+    // As `apply`, also returning the new name of every name renamed
+    pub fn apply_with_renames(
+        &self,
+        entities: Vec<Entity>,
+    ) -> (Vec<Entity>, HashMap<String, String>) {
+        let (entities, applied) = self.normalize(entities);
+        for normalization in applied.iter() {
+            info!("{}", normalization);
+        }
+        if !applied.is_empty() {
+            info!("Normalized {} entity names", applied.len());
+        }
+
+        let renames = applied
+            .into_iter()
+            .map(|applied| (applied.from.0, applied.to.0))
+            .collect();
+
+        (entities, renames)
+    }
+}
+
+// Loads the `normalize` section, exiting on an invalid one like the plugin sections
+pub fn load_normalizations(config: &Config) -> Normalizations {
+    config.section().unwrap_or_else(|err| {
+        error!("{:#}", err);
+        std::process::exit(1);
+    })
+}
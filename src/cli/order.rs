@@ -0,0 +1,129 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use petgraph::{algo::tarjan_scc, graphmap::DiGraphMap};
+use serde::Serialize;
+
+use crate::model::Entity;
+
+// Deployment order artifact written by `deploy-order` and `k8s go`
+pub const DEPLOY_ORDER_FILE: &str = "deploy-order.yaml";
+
+/*
+    Entities in deployment waves, each one after every entity it requires. The members of a
+    wave do not require each other and may be rolled out in parallel:
+
+    waves:
+    - - app=db
+    - - app=web
+      - app=worker
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeployOrder {
+    pub waves: Vec<Vec<String>>,
+}
+
+// Entities without a deployment order: the members of each require cycle, and the entities
+// requiring them directly or not
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "No deployment order, require cycles between {} block {} entities",
+    format_cycles(.cycles),
+    .blocked.len()
+)]
+pub struct DeployOrderError {
+    pub cycles: Vec<Vec<String>>,
+    pub blocked: Vec<String>,
+}
+
+fn format_cycles(cycles: &[Vec<String>]) -> String {
+    cycles
+        .iter()
+        .map(|cycle| format!("[{}]", cycle.join(", ")))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Orders the entities and the targets they require. Every target of a multi rule is deployed
+// first as any of them may be the one placed with the entity; self requires are ignored.
+pub fn deploy_order(entities: &[Entity]) -> Result<DeployOrder, DeployOrderError> {
+    let mut requires: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for entity in entities {
+        requires.entry(entity.name.as_ref()).or_default();
+
+        for rule in entity.requires.iter() {
+            for target in rule.targets() {
+                requires.entry(target.as_ref()).or_default();
+
+                if target != rule.source() {
+                    requires
+                        .entry(rule.source().as_ref())
+                        .or_default()
+                        .insert(target.as_ref());
+                }
+            }
+        }
+    }
+
+    let mut waves = vec![];
+    let mut deployed: BTreeSet<&str> = BTreeSet::new();
+    loop {
+        let wave = requires
+            .iter()
+            .filter(|(name, targets)| {
+                !deployed.contains(*name) && targets.iter().all(|t| deployed.contains(t))
+            })
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        if wave.is_empty() {
+            break;
+        }
+
+        deployed.extend(wave.iter().copied());
+        waves.push(wave.into_iter().map(str::to_string).collect());
+    }
+
+    if deployed.len() == requires.len() {
+        return Ok(DeployOrder { waves });
+    }
+
+    let mut graph = DiGraphMap::<&str, ()>::new();
+    for (name, targets) in requires.iter() {
+        if deployed.contains(name) {
+            continue;
+        }
+
+        graph.add_node(*name);
+        for target in targets.iter().filter(|t| !deployed.contains(*t)) {
+            graph.add_edge(*name, *target, ());
+        }
+    }
+
+    let mut cycles = tarjan_scc(&graph)
+        .into_iter()
+        .filter(|component| component.len() > 1)
+        .map(|component| {
+            let mut cycle = component
+                .into_iter()
+                .map(str::to_string)
+                .collect::<Vec<_>>();
+            cycle.sort();
+            cycle
+        })
+        .collect::<Vec<_>>();
+    cycles.sort();
+
+    let blocked = graph
+        .nodes()
+        .filter(|name| !cycles.iter().flatten().any(|member| member == name))
+        .map(str::to_string)
+        .collect::<BTreeSet<_>>();
+
+    Err(DeployOrderError {
+        cycles,
+        blocked: blocked.into_iter().collect(),
+    })
+}
+
+pub fn format_deploy_order(order: &DeployOrder) -> String {
+    serde_yaml::to_string(order).unwrap()
+}
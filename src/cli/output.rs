@@ -0,0 +1,132 @@
+use clap::ValueEnum;
+use log::error;
+use serde::Serialize;
+use serde_json::json;
+
+use super::{ConflictReport, SessionConflict, SessionOutputs};
+
+// Version of the structured output schema, bumped when a field changes meaning or goes away
+pub const OUTPUT_VERSION: u32 = 1;
+
+// Machine readable result of `check` and `k8s go`, printed to stdout with `--output`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Json,
+    Yaml,
+    // SARIF 2.1.0, read by code scanning tools
+    Sarif,
+}
+
+/*
+    {
+      "version": 1,
+      "conflicts": [{
+        "entity": "app=web", "rule": "app=web exclude app=db", "location": "web.yaml:30",
+        "rule_type": "exclude", "topology": "node", "file": "web.yaml", "line": 30,
+        "solvers": ["z3"], "impact": 12.0, "recommendations": []
+      }],
+      "undetermined": {},
+      "summary": "deployfix summary: 1 conflicts across 1 entities in 1 files"
+    }
+*/
+#[derive(Debug, Clone, Serialize)]
+pub struct StructuredOutput<'a> {
+    pub version: u32,
+    #[serde(flatten)]
+    pub outputs: &'a SessionOutputs,
+}
+
+fn sarif_result(conflict: &SessionConflict) -> serde_json::Value {
+    let mut result = json!({
+        "ruleId": "conflict",
+        "level": "error",
+        "message": {
+            "text": format!("{} is unschedulable: {}", conflict.entity, conflict.rule),
+        },
+        "properties": {
+            "entity": conflict.entity,
+            "rule": conflict.rule,
+            "ruleType": conflict.rule_type,
+            "topology": conflict.topology,
+            "solvers": conflict.solvers,
+            "impact": conflict.impact,
+        },
+    });
+
+    if let Some(file) = conflict.file.as_deref() {
+        result["locations"] = json!([{
+            "physicalLocation": {
+                "artifactLocation": { "uri": file },
+                "region": { "startLine": conflict.line.unwrap_or(1) },
+            },
+        }]);
+    }
+
+    result
+}
+
+fn sarif(outputs: &SessionOutputs) -> serde_json::Value {
+    let results = outputs
+        .conflicts
+        .iter()
+        .map(sarif_result)
+        .chain(outputs.undetermined.iter().map(|(entity, reason)| {
+            json!({
+                "ruleId": "undetermined",
+                "level": "warning",
+                "message": { "text": format!("{} is undetermined: {}", entity, reason) },
+                "properties": { "entity": entity },
+            })
+        }))
+        .collect::<Vec<_>>();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "deployfix",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": [
+                        {
+                            "id": "conflict",
+                            "shortDescription": { "text": "Rule making its entity unschedulable" },
+                        },
+                        {
+                            "id": "undetermined",
+                            "shortDescription": { "text": "Entity a solver gave up on" },
+                        },
+                    ],
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+pub fn format_output(outputs: &SessionOutputs, format: OutputFormat) -> anyhow::Result<String> {
+    let output = StructuredOutput {
+        version: OUTPUT_VERSION,
+        outputs,
+    };
+
+    Ok(match format {
+        OutputFormat::Json => serde_json::to_string_pretty(&output)? + "\n",
+        OutputFormat::Yaml => serde_yaml::to_string(&output)?,
+        OutputFormat::Sarif => serde_json::to_string_pretty(&sarif(outputs))? + "\n",
+    })
+}
+
+// Prints what `report` recorded, it must be built `with_session_outputs`
+pub fn print_output(report: &ConflictReport, format: Option<OutputFormat>) {
+    let Some(format) = format else {
+        return;
+    };
+
+    let outputs = report.session_outputs().unwrap_or_default();
+    match format_output(&outputs, format) {
+        Ok(output) => print!("{}", output),
+        Err(err) => error!("Failed to format the output: {:#}", err),
+    }
+}
@@ -0,0 +1,50 @@
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::model::{Entity, EntityName};
+
+use super::{Config, ConfigSection};
+
+// Custom analysis passes run by `lint-model` after the built-in lints, e.g.
+//
+//   passes:
+//     - passes/naming.wasm
+//     - passes/zones.wat
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct AnalysisPasses(pub Vec<PathBuf>);
+
+impl ConfigSection for AnalysisPasses {
+    const NAME: &'static str = "passes";
+}
+
+// Findings of the passes of the config followed by `extra`, with the entity of each
+#[cfg(feature = "wasm")]
+pub fn run_passes(
+    config: &Config,
+    extra: &[PathBuf],
+    entities: &[Entity],
+) -> anyhow::Result<Vec<(EntityName, String)>> {
+    let passes = config.section::<AnalysisPasses>()?.0;
+    let paths = passes.iter().chain(extra).cloned().collect::<Vec<_>>();
+
+    let findings = crate::plugin::wasm::run_passes(&paths, entities)?;
+    Ok(findings
+        .into_iter()
+        .map(|finding| (finding.entity.clone(), finding.to_string()))
+        .collect())
+}
+
+#[cfg(not(feature = "wasm"))]
+pub fn run_passes(
+    config: &Config,
+    extra: &[PathBuf],
+    _entities: &[Entity],
+) -> anyhow::Result<Vec<(EntityName, String)>> {
+    if !config.section::<AnalysisPasses>()?.0.is_empty() || !extra.is_empty() {
+        anyhow::bail!("Analysis passes need deployfix built with the `wasm` feature");
+    }
+
+    Ok(vec![])
+}
@@ -0,0 +1,257 @@
+use std::{fmt::Display, io::Write, path::Path};
+
+use super::{DirectorySink, ReportSink};
+
+// Lines of context around the changes of an update
+const CONTEXT_LINES: usize = 2;
+// Beyond this many comparisons the changed lines are listed as removed then added
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanChange {
+    Create,
+    Update,
+    Unchanged,
+}
+
+impl PlanChange {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Create => "create",
+            Self::Update => "update",
+            Self::Unchanged => "unchanged",
+        }
+    }
+}
+
+// What writing one file would do, with the diff against its current content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedFile {
+    pub name: String,
+    pub change: PlanChange,
+    pub added: usize,
+    pub removed: usize,
+    // Unified diff hunks, every line of a created file is added
+    pub diff: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+impl DiffLine<'_> {
+    fn format(&self) -> String {
+        match self {
+            Self::Same(line) => format!(" {}", line),
+            Self::Removed(line) => format!("-{}", line),
+            Self::Added(line) => format!("+{}", line),
+        }
+    }
+}
+
+// Longest common subsequence of the lines, after the common prefix and suffix are set aside
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<'a>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_changed = &old[prefix..old.len() - suffix];
+    let new_changed = &new[prefix..new.len() - suffix];
+
+    let mut lines = old[..prefix]
+        .iter()
+        .map(|line| DiffLine::Same(line))
+        .collect::<Vec<_>>();
+
+    let (n, m) = (old_changed.len(), new_changed.len());
+    if n * m > MAX_DIFF_CELLS {
+        lines.extend(old_changed.iter().map(|line| DiffLine::Removed(line)));
+        lines.extend(new_changed.iter().map(|line| DiffLine::Added(line)));
+    } else {
+        let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i][j] = if old_changed[i] == new_changed[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && old_changed[i] == new_changed[j] {
+                lines.push(DiffLine::Same(old_changed[i]));
+                i += 1;
+                j += 1;
+            } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+                lines.push(DiffLine::Removed(old_changed[i]));
+                i += 1;
+            } else {
+                lines.push(DiffLine::Added(new_changed[j]));
+                j += 1;
+            }
+        }
+    }
+
+    lines.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|line| DiffLine::Same(line)),
+    );
+    lines
+}
+
+// Groups the changed lines into hunks headed by `@@ -start,len +start,len @@`
+fn hunks(lines: &[DiffLine]) -> Vec<String> {
+    let changes = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, DiffLine::Same(_)))
+        .map(|(idx, _)| idx)
+        .collect::<Vec<_>>();
+
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    for idx in changes {
+        let start = idx.saturating_sub(CONTEXT_LINES);
+        let end = (idx + CONTEXT_LINES + 1).min(lines.len());
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    let mut ret = vec![];
+    for (start, end) in ranges {
+        let count = |range: &[DiffLine], removed: bool| {
+            range
+                .iter()
+                .filter(|line| match line {
+                    DiffLine::Same(_) => true,
+                    DiffLine::Removed(_) => removed,
+                    DiffLine::Added(_) => !removed,
+                })
+                .count()
+        };
+        let (old_start, new_start) = (count(&lines[..start], true), count(&lines[..start], false));
+        let (old_len, new_len) = (
+            count(&lines[start..end], true),
+            count(&lines[start..end], false),
+        );
+
+        ret.push(format!(
+            "@@ -{},{} +{},{} @@",
+            old_start + 1,
+            old_len,
+            new_start + 1,
+            new_len
+        ));
+        ret.extend(lines[start..end].iter().map(DiffLine::format));
+    }
+
+    ret
+}
+
+pub fn plan_file(name: &str, current: Option<&str>, content: &str) -> PlannedFile {
+    let new = content.lines().collect::<Vec<_>>();
+
+    let Some(current) = current else {
+        return PlannedFile {
+            name: name.to_string(),
+            change: PlanChange::Create,
+            added: new.len(),
+            removed: 0,
+            diff: new.iter().map(|line| format!("+{}", line)).collect(),
+        };
+    };
+
+    let old = current.lines().collect::<Vec<_>>();
+    let lines = diff_lines(&old, &new);
+    let added = lines
+        .iter()
+        .filter(|line| matches!(line, DiffLine::Added(_)))
+        .count();
+    let removed = lines
+        .iter()
+        .filter(|line| matches!(line, DiffLine::Removed(_)))
+        .count();
+
+    PlannedFile {
+        name: name.to_string(),
+        change: if added + removed == 0 {
+            PlanChange::Unchanged
+        } else {
+            PlanChange::Update
+        },
+        added,
+        removed,
+        diff: hunks(&lines),
+    }
+}
+
+/*
+    update node/conflicts.yaml (+1 -1)
+    @@ -3,3 +3,3 @@
+     - entity: app=web
+    -  rule: app=web exclude app=db
+    +  rule: app=web exclude app=api
+       location: web.yaml:30
+*/
+impl Display for PlannedFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.change {
+            PlanChange::Unchanged => writeln!(f, "unchanged {}", self.name)?,
+            change => writeln!(
+                f,
+                "{} {} (+{} -{})",
+                change.as_str(),
+                self.name,
+                self.added,
+                self.removed
+            )?,
+        }
+
+        for line in self.diff.iter() {
+            writeln!(f, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Prints what a run would write to a directory instead of writing it, with `--dry-run`
+pub struct PlanSink {
+    root: DirectorySink,
+}
+
+impl PlanSink {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: DirectorySink::new(root),
+        }
+    }
+}
+
+impl ReportSink for PlanSink {
+    fn write(&self, name: &str, content: &str) -> anyhow::Result<()> {
+        let planned = plan_file(name, self.root.read(name).as_deref(), content);
+
+        write!(std::io::stdout().lock(), "{}", planned)?;
+        Ok(())
+    }
+
+    fn location(&self, name: &str) -> String {
+        format!("{} (dry run)", self.root.location(name))
+    }
+
+    fn read(&self, name: &str) -> Option<String> {
+        self.root.read(name)
+    }
+}
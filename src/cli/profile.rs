@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use crate::{model::RuleLifecycle, solver::DEFAULT_MAX_CYCLES};
+
+use super::{Cli, Commands, ConfigSection, LogFormat, TagArgs};
+
+// Options of a run bundled under a name, picked with `--profile`. Every option is optional, a
+// profile only fills the options left at their default on the command line.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    // Solvers of `check`
+    pub cycle_check: Option<bool>,
+    pub fast_fail: Option<bool>,
+    pub relaxed_cycles: Option<bool>,
+    pub max_cycles: Option<usize>,
+    // Strictness
+    pub strict: Option<bool>,
+    pub gate: Vec<RuleLifecycle>,
+    // Rule families checked by `check`
+    pub only_tags: Vec<String>,
+    pub skip_tags: Vec<String>,
+    // Output
+    pub log_format: Option<LogFormat>,
+    pub quiet: Option<bool>,
+    pub verbose: Option<bool>,
+    // Limits, the timeout in seconds per z3 check
+    pub solve_timeout: Option<u64>,
+    pub max_variables: Option<usize>,
+    pub max_clauses: Option<usize>,
+}
+
+// The `profiles` config section, e.g.
+//
+//   profiles:
+//     ci-fast:
+//       fast_fail: true
+//       solve_timeout: 30
+//     security:
+//       only_tags: [security]
+//       strict: true
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Profiles(pub BTreeMap<String, Profile>);
+
+impl ConfigSection for Profiles {
+    const NAME: &'static str = "profiles";
+}
+
+impl Profiles {
+    pub fn get(&self, name: &str) -> anyhow::Result<&Profile> {
+        self.0.get(name).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unknown profile {}, the `profiles` config section has: {}",
+                name,
+                self.0.keys().cloned().collect::<Vec<_>>().join(", ")
+            )
+        })
+    }
+}
+
+impl Profile {
+    pub(super) fn apply(&self, cli: &mut Cli) {
+        cli.relaxed_cycles |= self.relaxed_cycles.unwrap_or_default();
+        if cli.max_cycles == DEFAULT_MAX_CYCLES {
+            cli.max_cycles = self.max_cycles.unwrap_or(cli.max_cycles);
+        }
+        cli.strict |= self.strict.unwrap_or_default();
+        if cli.gate == [RuleLifecycle::Enforced] && !self.gate.is_empty() {
+            cli.gate = self.gate.clone();
+        }
+        if cli.log_format == LogFormat::default() {
+            cli.log_format = self.log_format.unwrap_or(cli.log_format);
+        }
+        cli.quiet |= self.quiet.unwrap_or_default();
+        cli.verbose |= self.verbose.unwrap_or_default();
+        cli.solve_timeout = cli.solve_timeout.or(self.solve_timeout);
+
+        if let Some(Commands::Check {
+            cycle_check,
+            fast_fail,
+            budget,
+            tags,
+            ..
+        }) = &mut cli.command
+        {
+            *cycle_check = self.cycle_check.unwrap_or(*cycle_check);
+            *fast_fail |= self.fast_fail.unwrap_or_default();
+            *budget = budget
+                .clone()
+                .or_limits(self.max_variables, self.max_clauses);
+            if tags.is_empty() {
+                *tags = TagArgs {
+                    only_tags: self.only_tags.clone(),
+                    skip_tags: self.skip_tags.clone(),
+                };
+            }
+        }
+    }
+}
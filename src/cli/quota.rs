@@ -0,0 +1,115 @@
+use std::collections::{BTreeMap, HashMap};
+
+use log::warn;
+
+use crate::{
+    model::{Entity, EntityRule, Env},
+    solver::SolverOutput,
+};
+
+use super::headroom;
+
+// Solver the conflicts of pod quotas are reported under
+pub const QUOTA_SOLVER: &str = "quota";
+
+// A namespace whose workloads need more pods than its ResourceQuota allows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaConflict {
+    pub namespace: String,
+    pub quota: usize,
+    // Pods of the workloads of the namespace at their replicas, or the maximum of their autoscaler
+    pub pods: usize,
+    // The workloads with the first rule carrying the quota
+    pub entities: BTreeMap<String, EntityRule>,
+    // Whether the nodes of the envs could host every pod, the quota alone then limits them.
+    // Without envs only the quota is checked.
+    pub quota_only: bool,
+}
+
+/*
+    Pods of the entities of each namespace with a quota summed and checked against it. A
+    namespace over its quota has pods the API server rejects whatever their affinities, with
+    `envs` the conflict tells whether the affinities would have hosted them.
+*/
+pub fn check_pod_quotas(entities: &[Entity], envs: &[Env]) -> Vec<QuotaConflict> {
+    let fits = if envs.is_empty() {
+        HashMap::new()
+    } else {
+        headroom(entities, envs)
+            .into_iter()
+            .map(|h| {
+                let fits = h.domains > 0 && (h.headroom.is_none() || h.domains >= h.replicas);
+                (h.entity, fits)
+            })
+            .collect()
+    };
+
+    let mut namespaces: BTreeMap<&str, QuotaConflict> = BTreeMap::new();
+    for entity in entities {
+        let name = entity.name.as_ref();
+        let Some(rule) = entity.rules().find(|rule| rule.pod_quota().is_some()) else {
+            continue;
+        };
+        let namespace = rule.namespace().unwrap_or("default");
+        let pods = entity
+            .rules()
+            .find_map(EntityRule::replicas)
+            .max(entity.rules().find_map(EntityRule::max_replicas))
+            .unwrap_or(1);
+
+        let conflict = namespaces
+            .entry(namespace)
+            .or_insert_with(|| QuotaConflict {
+                namespace: namespace.to_string(),
+                quota: rule.pod_quota().unwrap_or_default(),
+                pods: 0,
+                entities: BTreeMap::new(),
+                quota_only: true,
+            });
+        conflict.pods += pods;
+        conflict.entities.insert(name.to_string(), rule.clone());
+        conflict.quota_only &= fits.get(name).copied().unwrap_or(true);
+    }
+
+    namespaces
+        .into_values()
+        .filter(|conflict| conflict.pods > conflict.quota)
+        .collect()
+}
+
+// The conflicts as a solver reports them, under `QUOTA_SOLVER`
+pub fn quota_output(conflicts: &[QuotaConflict]) -> SolverOutput {
+    if conflicts.is_empty() {
+        return SolverOutput::Ok;
+    }
+
+    for conflict in conflicts {
+        let entities = conflict
+            .entities
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(", ");
+        if conflict.quota_only {
+            warn!(
+                "Namespace {} allows {} pods, {} need {}: its quota, not an affinity, limits them",
+                conflict.namespace, conflict.quota, entities, conflict.pods
+            );
+        } else {
+            warn!(
+                "Namespace {} allows {} pods, {} need {}, and the nodes cannot host them all either",
+                conflict.namespace, conflict.quota, entities, conflict.pods
+            );
+        }
+    }
+
+    SolverOutput::Conflict(
+        conflicts
+            .iter()
+            .flat_map(|conflict| conflict.entities.iter())
+            .map(|(entity, rule)| (entity.clone(), vec![rule.clone()]))
+            .collect(),
+        HashMap::new(),
+    )
+    .with_solver(QUOTA_SOLVER)
+}
@@ -0,0 +1,162 @@
+use std::collections::{BTreeSet, HashMap};
+
+use serde::Serialize;
+
+use crate::model::{
+    rule_location, rule_text, Entity, EntityRule, EntityRuleTopologyKey, EntityRuleType,
+    METADATA_TOPOLOGY_KEY,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ActionKind {
+    // Drop the rule, what the solution manifests of `k8s go` do
+    RemoveRule,
+    // Point the rule at `to` instead of its unknown target `from`
+    RewriteTarget,
+    // Move the rule from topology `from` to `to`
+    ChangeTopology,
+}
+
+// A change of one recommended rule, with what an external tool needs to apply or review it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RecommendedAction {
+    pub action: ActionKind,
+    pub entity: String,
+    pub rule: String,
+    pub location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    pub reason: String,
+}
+
+// The single entity named like the unknown `target` under another label key, e.g. `app=db`
+// for `app.kubernetes.io/name=db`
+fn renamed_target<'a>(entities: &HashMap<&str, &'a Entity>, target: &str) -> Option<&'a str> {
+    if entities.contains_key(target) {
+        return None;
+    }
+
+    let value = target.rsplit_once('=').map(|(_, value)| value)?;
+    let mut candidates = entities
+        .values()
+        .copied()
+        .filter(|entity| entity.name.as_ref().rsplit_once('=').map(|(_, v)| v) == Some(value))
+        .map(|entity| entity.name.as_ref());
+
+    match (candidates.next(), candidates.next()) {
+        (Some(candidate), None) => Some(candidate),
+        _ => None,
+    }
+}
+
+// An exclude of a target its source also requires on the same zone or rack: both hold once
+// the exclude is narrowed to nodes
+fn narrowed_topology(entities: &HashMap<&str, &Entity>, rule: &EntityRule) -> Option<String> {
+    if rule.r#type() != EntityRuleType::Exclude {
+        return None;
+    }
+
+    let topology = rule.metadata(METADATA_TOPOLOGY_KEY)?;
+    if topology == EntityRuleTopologyKey::Node.as_ref() {
+        return None;
+    }
+
+    let source = entities.get(rule.source().as_ref())?;
+    let required = rule.targets().into_iter().any(|target| {
+        source.requires.iter().any(|require| {
+            require.is_in_target(target.as_ref())
+                && require.metadata(METADATA_TOPOLOGY_KEY) == Some(topology)
+        })
+    });
+
+    required.then(|| topology.to_string())
+}
+
+// One action per recommended rule, the least invasive one found. `conflicts` are the
+// unschedulable entities the rules were recommended for.
+pub fn recommend_actions(
+    entities: &[Entity],
+    conflicts: &HashMap<String, Vec<EntityRule>>,
+    rules: &[EntityRule],
+) -> Vec<RecommendedAction> {
+    let by_name = entities
+        .iter()
+        .map(|entity| (entity.name.as_ref(), entity))
+        .collect::<HashMap<_, _>>();
+
+    rules
+        .iter()
+        .map(|rule| {
+            let recommended =
+                |action, from: Option<String>, to: Option<String>, reason| RecommendedAction {
+                    action,
+                    entity: rule.source().as_ref().to_string(),
+                    rule: rule_text(rule),
+                    location: rule_location(rule),
+                    from,
+                    to,
+                    reason,
+                };
+
+            let renamed = match rule {
+                EntityRule::Mono { target, .. } => renamed_target(&by_name, target.as_ref())
+                    .map(|renamed| (target.as_ref(), renamed)),
+                EntityRule::Multi { .. } => None,
+            };
+            if let Some((target, renamed)) = renamed {
+                return recommended(
+                    ActionKind::RewriteTarget,
+                    Some(target.to_string()),
+                    Some(renamed.to_string()),
+                    format!("{} is not an entity of the model, {} is", target, renamed),
+                );
+            }
+
+            if let Some(topology) = narrowed_topology(&by_name, rule) {
+                return recommended(
+                    ActionKind::ChangeTopology,
+                    Some(topology.clone()),
+                    Some(EntityRuleTopologyKey::Node.to_string()),
+                    format!(
+                        "{} also requires its target on the same {}",
+                        rule.source().as_ref(),
+                        topology
+                    ),
+                );
+            }
+
+            let unschedulable = conflicts
+                .iter()
+                .filter(|(_, rules)| rules.contains(rule))
+                .map(|(name, _)| name.as_str())
+                .collect::<BTreeSet<_>>();
+            let reason = match rule.desc() {
+                Some(desc) => desc.to_string(),
+                None => format!(
+                    "makes {} unschedulable",
+                    unschedulable.into_iter().collect::<Vec<_>>().join(", ")
+                ),
+            };
+
+            recommended(ActionKind::RemoveRule, None, None, reason)
+        })
+        .collect()
+}
+
+/*
+    recommendations.yaml, one entry per recommended rule:
+
+    - action: change-topology
+      entity: app=web
+      rule: app=web exclude app=db
+      location: web.yaml:30
+      from: zone
+      to: node
+      reason: app=web also requires its target on the same zone
+*/
+pub fn format_actions(actions: &[RecommendedAction]) -> String {
+    serde_yaml::to_string(actions).unwrap()
+}
@@ -0,0 +1,614 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use chrono::NaiveDate;
+use log::{debug, error, info, warn};
+
+use crate::{
+    model::{rule_location, Entity, EntityRule, RuleLifecycle, METADATA_TOPOLOGY_KEY},
+    solver::{
+        cluster_conflicts, diagnose_multi_rule, explain_solver, impact_scores, ConflictSolvers,
+        ImpactScore, ImpactWeights, Undetermined,
+    },
+};
+
+use super::{
+    Config, ConfigSection, ConflictAnnotater, SessionConflict, SessionOutputs, SourceCache,
+    Suppression, SuppressionStatus, Suppressions,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputLevel {
+    // Only the final summary line
+    Quiet,
+    // Annotated snippets of every conflicting rule
+    #[default]
+    Normal,
+    // Annotated snippets followed by the raw rule list of each entity
+    Verbose,
+}
+
+impl OutputLevel {
+    pub fn from_flags(quiet: bool, verbose: bool) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Self::Quiet,
+            (false, true) => Self::Verbose,
+            (false, false) => Self::Normal,
+        }
+    }
+}
+
+// Accumulates the conflicts reported across all checked domains so that a single
+// summary line can be printed at the end of a run.
+#[derive(Debug, Default)]
+pub struct ConflictReport {
+    level: OutputLevel,
+    conflicts: usize,
+    entities: BTreeSet<String>,
+    files: BTreeSet<String>,
+    // Entities a solver gave up on, neither schedulable nor in conflict
+    undetermined: BTreeSet<String>,
+    suppressions: Vec<Suppression>,
+    today: Option<NaiveDate>,
+    // Number of conflicts matched by each suppression
+    suppressed: Vec<usize>,
+    // Conflicts are reported from the highest impact down
+    weights: ImpactWeights,
+    // Files annotated so far, shared by the conflicts of every domain
+    sources: SourceCache,
+    // Entities whose conflicts were already reported elsewhere, e.g. by their workspace project
+    reported: BTreeSet<String>,
+    // What was reported, kept for `check --session-export`
+    outputs: Option<SessionOutputs>,
+    // Lifecycle states whose conflicts count, the others are only warned about
+    gates: BTreeSet<RuleLifecycle>,
+    // Conflicting rules left out of the count by `gates`, per lifecycle of their conflict
+    ungated: BTreeMap<RuleLifecycle, usize>,
+    // Soft rules given up by schedulable entities, they never fail the run
+    unmet_preferences: usize,
+}
+
+// Only enforced rules fail the run unless `--gate` says otherwise
+pub fn default_gates() -> BTreeSet<RuleLifecycle> {
+    BTreeSet::from([RuleLifecycle::Enforced])
+}
+
+// A conflict goes away with its least enforced rule, e.g. a deprecated rule in the conflict of
+// enforced ones makes it a conflict of a deprecated rule
+fn conflict_lifecycle(rules: &[EntityRule]) -> RuleLifecycle {
+    rules
+        .iter()
+        .map(EntityRule::lifecycle)
+        .min()
+        .unwrap_or_default()
+}
+
+impl ConfigSection for ImpactWeights {
+    const NAME: &'static str = "impact";
+}
+
+pub fn load_impact_weights(config: &Config) -> ImpactWeights {
+    config.section().unwrap_or_else(|err| {
+        error!("{:#}", err);
+        std::process::exit(1);
+    })
+}
+
+// Conflicting entities from the highest impact down, ties in name order
+fn by_impact<'a>(
+    conflicts: &'a HashMap<String, Vec<EntityRule>>,
+    scores: &HashMap<String, ImpactScore>,
+) -> Vec<(&'a String, &'a Vec<EntityRule>)> {
+    let mut conflicts = conflicts.iter().collect::<Vec<_>>();
+    conflicts.sort_by(|(a, _), (b, _)| {
+        scores[*b]
+            .score
+            .total_cmp(&scores[*a].score)
+            .then_with(|| a.cmp(b))
+    });
+
+    conflicts
+}
+
+impl ConflictReport {
+    pub fn new(level: OutputLevel) -> Self {
+        Self {
+            level,
+            gates: default_gates(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_gates(mut self, gates: BTreeSet<RuleLifecycle>) -> Self {
+        self.gates = gates;
+        self
+    }
+
+    // Conflicts matched by an unexpired suppression are left out of the report
+    pub fn with_suppressions(mut self, suppressions: Suppressions, today: NaiveDate) -> Self {
+        for suppression in suppressions.0.iter().filter(|s| s.is_expired(today)) {
+            warn!(
+                "Suppression of {} expired on {}, its conflicts are reported again",
+                suppression.entity,
+                suppression.expires.unwrap()
+            );
+        }
+
+        self.suppressed = vec![0; suppressions.0.len()];
+        self.suppressions = suppressions.0;
+        self.today = Some(today);
+        self
+    }
+
+    pub fn with_impact_weights(mut self, weights: ImpactWeights) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    pub fn with_reported(mut self, reported: BTreeSet<String>) -> Self {
+        self.reported = reported;
+        self
+    }
+
+    pub fn with_session_outputs(mut self) -> Self {
+        self.outputs = Some(SessionOutputs::default());
+        self
+    }
+
+    // Reported conflicts and undetermined entities, when recorded
+    pub fn session_outputs(&self) -> Option<SessionOutputs> {
+        let mut outputs = self.outputs.clone()?;
+        outputs
+            .conflicts
+            .sort_by(|a, b| (&a.entity, &a.rule).cmp(&(&b.entity, &b.rule)));
+        outputs.summary = self.summary();
+
+        Some(outputs)
+    }
+
+    // Recorded conflicts in the order they were reported
+    pub fn recorded_conflicts(&self) -> &[SessionConflict] {
+        self.outputs
+            .as_ref()
+            .map(|outputs| outputs.conflicts.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn conflicting_entities(&self) -> &BTreeSet<String> {
+        &self.entities
+    }
+
+    pub fn suppression_statuses(&self) -> Vec<(&Suppression, SuppressionStatus)> {
+        self.suppressions
+            .iter()
+            .zip(self.suppressed.iter())
+            .map(|(suppression, count)| {
+                let status = match self.today {
+                    Some(today) if suppression.is_expired(today) => SuppressionStatus::Expired,
+                    _ if *count == 0 => SuppressionStatus::Unused,
+                    _ => SuppressionStatus::Active,
+                };
+
+                (suppression, status)
+            })
+            .collect()
+    }
+
+    fn suppress(
+        &mut self,
+        conflicts: &HashMap<String, Vec<EntityRule>>,
+    ) -> HashMap<String, Vec<EntityRule>> {
+        let mut kept = HashMap::new();
+        let mut suppressed = 0;
+
+        for (name, rules) in conflicts.iter() {
+            let rules = rules
+                .iter()
+                .filter(|rule| {
+                    let matched = self.suppressions.iter().position(|s| {
+                        !self.today.is_some_and(|today| s.is_expired(today))
+                            && s.matches(name, rule)
+                    });
+
+                    if let Some(i) = matched {
+                        self.suppressed[i] += 1;
+                        suppressed += 1;
+                    }
+
+                    matched.is_none()
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if !rules.is_empty() {
+                kept.insert(name.clone(), rules);
+            }
+        }
+
+        if suppressed > 0 {
+            info!("Suppressed {} conflicts", suppressed);
+        }
+
+        kept
+    }
+
+    pub fn level(&self) -> OutputLevel {
+        self.level
+    }
+
+    pub fn has_conflict(&self) -> bool {
+        self.conflicts > 0
+    }
+
+    pub fn has_undetermined(&self) -> bool {
+        !self.undetermined.is_empty()
+    }
+
+    // Preferences the solvers had to give up, per entity, as split off by `take_soft`
+    pub fn add_soft(&mut self, conflicts: &HashMap<String, Vec<EntityRule>>) {
+        for (name, rules) in conflicts.iter().collect::<BTreeMap<_, _>>() {
+            self.unmet_preferences += rules.len();
+
+            if self.level == OutputLevel::Quiet {
+                continue;
+            }
+
+            let described = rules
+                .iter()
+                .map(|r| {
+                    let location = rule_location(r)
+                        .map(|location| format!(" at {}", location))
+                        .unwrap_or_default();
+                    format!("  {}{}", r.describe(), location)
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            warn!(
+                "{} is schedulable but cannot meet all its preferences:\n{}",
+                name, described
+            );
+        }
+    }
+
+    pub fn add_undetermined(&mut self, undetermined: &Undetermined) {
+        for (name, reason) in undetermined.entities.iter() {
+            self.undetermined.insert(name.clone());
+            if let Some(outputs) = self.outputs.as_mut() {
+                outputs.undetermined.insert(name.clone(), reason.clone());
+            }
+
+            if self.level != OutputLevel::Quiet {
+                warn!("Undetermined entity: {} (solver gave up: {})", name, reason);
+            }
+        }
+
+        if self.level == OutputLevel::Verbose && !undetermined.statistics.is_empty() {
+            let statistics = undetermined
+                .statistics
+                .iter()
+                .map(|(key, value)| format!("  {}: {}", key, value))
+                .collect::<Vec<_>>()
+                .join("\n");
+            warn!("Solver statistics when giving up:\n{}", statistics);
+        }
+    }
+
+    // `entities` is the checked model, used to explain the alternatives of multi rules
+    pub fn add(
+        &mut self,
+        conflicts: &HashMap<String, Vec<EntityRule>>,
+        solvers: &ConflictSolvers,
+        entities: &[Entity],
+    ) {
+        let mut conflicts = self.suppress(conflicts);
+        conflicts.retain(|name, _| !self.reported.contains(name));
+
+        let (conflicts, ungated): (HashMap<_, _>, HashMap<_, _>) = conflicts
+            .into_iter()
+            .partition(|(_, rules)| self.gates.contains(&conflict_lifecycle(rules)));
+        self.add_ungated(&ungated);
+
+        let conflicts = &conflicts;
+        if conflicts.is_empty() {
+            return;
+        }
+
+        for (name, rules) in conflicts.iter() {
+            self.entities.insert(name.clone());
+            self.conflicts += rules.len();
+
+            for rule in rules {
+                if let Some(file) = rule.meta_file().or(rule.file()) {
+                    self.files.insert(file.to_string());
+                }
+            }
+        }
+
+        let scores = impact_scores(entities, conflicts.keys(), &self.weights);
+        if let Some(outputs) = self.outputs.as_mut() {
+            Self::record(outputs, conflicts, solvers, entities, &scores);
+        }
+
+        match self.level {
+            OutputLevel::Quiet => {}
+            OutputLevel::Normal => {
+                Self::print_root_causes(conflicts);
+                Self::print_groups(conflicts);
+                Self::print_annotations(conflicts, false, entities, &scores, &self.sources);
+            }
+            OutputLevel::Verbose => {
+                Self::print_root_causes(conflicts);
+                Self::print_groups(conflicts);
+                Self::print_annotations(conflicts, true, entities, &scores, &self.sources);
+                Self::print_rules(conflicts, solvers, &scores);
+            }
+        }
+    }
+
+    // Conflicts of the states left out of `gates`: those of proposed rules are listed in an
+    // advisory section, the others are warned about one entity at a time
+    fn add_ungated(&mut self, conflicts: &HashMap<String, Vec<EntityRule>>) {
+        let mut advisory = vec![];
+
+        for (name, rules) in conflicts.iter().collect::<BTreeMap<_, _>>() {
+            let lifecycle = conflict_lifecycle(rules);
+            *self.ungated.entry(lifecycle).or_default() += rules.len();
+
+            if self.level == OutputLevel::Quiet {
+                continue;
+            }
+
+            let described = rules
+                .iter()
+                .map(|r| format!("  {} ({})", r.describe(), r.lifecycle()))
+                .collect::<Vec<_>>()
+                .join("\n");
+            match lifecycle {
+                RuleLifecycle::Proposed => advisory.push(format!("{}:\n{}", name, described)),
+                lifecycle => warn!(
+                    "{} is unschedulable because of {} rules, not failing the run:\n{}",
+                    name, lifecycle, described
+                ),
+            }
+        }
+
+        if !advisory.is_empty() {
+            warn!(
+                "Advisory, entities the proposed rules would make unschedulable once enforced:\n{}",
+                advisory.join("\n")
+            );
+        }
+    }
+
+    fn record(
+        outputs: &mut SessionOutputs,
+        conflicts: &HashMap<String, Vec<EntityRule>>,
+        solvers: &ConflictSolvers,
+        entities: &[Entity],
+        scores: &HashMap<String, ImpactScore>,
+    ) {
+        for (name, rules) in conflicts.iter() {
+            for rule in rules {
+                outputs.conflicts.push(SessionConflict {
+                    entity: name.clone(),
+                    rule: rule.to_string(),
+                    location: rule_location(rule),
+                    rule_type: Some(rule.r#type().to_string()),
+                    topology: rule.metadata(METADATA_TOPOLOGY_KEY).map(String::from),
+                    file: rule.file().or(rule.meta_file()).map(String::from),
+                    line: rule.line().or(rule.meta_line()),
+                    solvers: solvers.get(name).cloned().unwrap_or_default(),
+                    impact: scores[name].score,
+                    desc: rule.desc().map(String::from),
+                    recommendations: diagnose_multi_rule(entities, conflicts, rule)
+                        .into_iter()
+                        .map(|(target, diagnosis)| format!("alternative {}: {}", target, diagnosis))
+                        .collect(),
+                });
+            }
+        }
+    }
+
+    // Leads the report with the rules behind the conflicts of several entities, the most
+    // frequent rules of each cluster of conflicts sharing rules
+    fn print_root_causes(conflicts: &HashMap<String, Vec<EntityRule>>) {
+        let mut causes = cluster_conflicts(conflicts)
+            .into_iter()
+            .flat_map(|cluster| {
+                let most = cluster.roots.first().map(|(_, count)| *count);
+                let size = cluster.entities.len();
+
+                cluster
+                    .roots
+                    .into_iter()
+                    .take_while(move |(_, count)| Some(*count) == most)
+                    .map(move |(rule, count)| (rule, count, size))
+            })
+            .collect::<Vec<_>>();
+        if causes.is_empty() {
+            return;
+        }
+        causes.sort_by(|(a, a_count, _), (b, b_count, _)| {
+            b_count.cmp(a_count).then_with(|| a.cmp(b))
+        });
+
+        let causes = causes
+            .iter()
+            .map(|(rule, count, size)| {
+                format!(
+                    "  {} {}: in the conflicts of {} of {} entities",
+                    rule.source().as_ref(),
+                    rule,
+                    count,
+                    size
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        error!("Root causes:\n{}", causes);
+    }
+
+    // One line per colocation group instead of one snippet per pairwise rule
+    fn print_groups(conflicts: &HashMap<String, Vec<EntityRule>>) {
+        let groups = conflicts
+            .values()
+            .flatten()
+            .filter_map(|r| r.group())
+            .collect::<BTreeSet<_>>();
+
+        for group in groups {
+            let members = conflicts
+                .iter()
+                .filter(|(_, rules)| rules.iter().any(|r| r.group() == Some(group)))
+                .map(|(name, _)| name.as_str())
+                .collect::<BTreeSet<_>>();
+
+            let causes = conflicts
+                .iter()
+                .filter(|(name, _)| members.contains(name.as_str()))
+                .flat_map(|(_, rules)| rules.iter())
+                .filter(|r| r.group() != Some(group))
+                .map(|r| r.to_string())
+                .collect::<BTreeSet<_>>();
+
+            if causes.is_empty() {
+                error!("Group {} cannot be colocated", group);
+            } else {
+                error!(
+                    "Group {} cannot be colocated because of {}",
+                    group,
+                    causes.into_iter().collect::<Vec<_>>().join(", ")
+                );
+            }
+        }
+    }
+
+    fn print_annotations(
+        conflicts: &HashMap<String, Vec<EntityRule>>,
+        with_groups: bool,
+        entities: &[Entity],
+        scores: &HashMap<String, ImpactScore>,
+        sources: &SourceCache,
+    ) {
+        let conflicts_annotations = by_impact(conflicts, scores)
+            .into_iter()
+            .flat_map(|(k, v)| v.iter().map(move |v| (k, v)))
+            .filter(|(_, rule)| with_groups || rule.group().is_none())
+            .map(|(name, rule)| {
+                let impact = &scores[name];
+                let notes = std::iter::once(format!(
+                    "impact {:.1}: {} priority, {} replicas, {} dependents",
+                    impact.score,
+                    impact.priority.as_str(),
+                    impact.replicas,
+                    impact.dependents
+                ))
+                .chain(rule.desc().map(|desc| format!("intent: {}", desc)))
+                .chain(rule.is_scale_out().then(|| {
+                    format!(
+                        "only at scale-out: {} replicas fit, the autoscaler maximum of {} does not",
+                        rule.min_replicas().unwrap_or(1),
+                        rule.max_replicas().unwrap_or_default()
+                    )
+                }))
+                .chain(
+                    rule.pod()
+                        .map(|pod| format!("declared by pod {} of {}", pod, name)),
+                )
+                .chain(
+                    diagnose_multi_rule(entities, conflicts, rule)
+                        .into_iter()
+                        .map(|(target, diagnosis)| {
+                            format!("alternative {}: {}", target, diagnosis)
+                        }),
+                )
+                .collect();
+
+                ConflictAnnotater::new_with_cache(name.as_str(), rule, sources)
+                    .with_notes(notes)
+                    .annotate()
+            })
+            .collect::<Vec<_>>();
+
+        if !conflicts_annotations.is_empty() {
+            error!("{}", conflicts_annotations.join("\n\n"));
+        }
+    }
+
+    fn print_rules(
+        conflicts: &HashMap<String, Vec<EntityRule>>,
+        solvers: &ConflictSolvers,
+        scores: &HashMap<String, ImpactScore>,
+    ) {
+        for (name, rules) in by_impact(conflicts, scores) {
+            let described = rules
+                .iter()
+                .map(|r| format!("  {}", r.describe()))
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            let reasons = solvers
+                .get(name)
+                .map(|solvers| {
+                    solvers
+                        .iter()
+                        .map(|s| format!("{}: {}", s, explain_solver(s)))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                })
+                .unwrap_or_else(|| "unknown".to_string());
+
+            error!(
+                "Conflicting rules of {} ({}, impact {:.1}):\n{}",
+                name, reasons, scores[name].score, described
+            );
+            // Raw form with the metadata, for debugging the import
+            for rule in rules {
+                debug!("  {}: {}", name, rule);
+            }
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        format!("deployfix summary: {}", self.totals())
+    }
+
+    // Counts of the summary line, without its prefix
+    pub fn totals(&self) -> String {
+        let summary = format!(
+            "{} conflicts across {} entities in {} files",
+            self.conflicts,
+            self.entities.len(),
+            self.files.len()
+        );
+
+        let summary = match self.suppressed.iter().sum::<usize>() {
+            0 => summary,
+            suppressed => format!("{}, {} suppressed", summary, suppressed),
+        };
+
+        let summary = self
+            .ungated
+            .iter()
+            .fold(summary, |summary, (lifecycle, count)| {
+                format!("{}, {} from {} rules", summary, count, lifecycle)
+            });
+
+        let summary = match self.unmet_preferences {
+            0 => summary,
+            unmet => format!("{}, {} unmet preferences", summary, unmet),
+        };
+
+        match self.undetermined.len() {
+            0 => summary,
+            undetermined => format!("{}, {} undetermined", summary, undetermined),
+        }
+    }
+
+    pub fn print_summary(&self) {
+        if self.has_conflict() || self.has_undetermined() {
+            error!("{}", self.summary());
+        } else {
+            info!("{}", self.summary());
+        }
+    }
+}
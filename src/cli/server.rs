@@ -0,0 +1,576 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    future::Future,
+    io::Write,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    model::{apply_layers, get_parser, merge_entities, Entity, EntitySource},
+    solver::{get_solver_with, EntityMap, SolverOptions, SolverOutput},
+    util::{
+        self,
+        http::{self, Request},
+    },
+};
+
+use super::ConfigSection;
+
+// A team using the server, e.g.
+//
+//   server:
+//     tenants:
+//       - name: payments
+//         token: 2b7e151628aed2a6
+//         max_entities: 5000
+//         max_models: 20
+//         max_solve_seconds: 30
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tenant {
+    pub name: String,
+    // Sent as `Authorization: Bearer <token>`
+    pub token: String,
+    // Largest model the tenant may upload
+    pub max_entities: Option<usize>,
+    // Models the tenant may keep at once, replacing one of them is always allowed
+    pub max_models: Option<usize>,
+    // Checks running longer are answered with 503, z3 gives up on the solve at the same time
+    pub max_solve_seconds: Option<u64>,
+}
+
+// `server` section of the config file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    #[serde(default)]
+    pub tenants: Vec<Tenant>,
+    // Solves running at once, defaults to the number of CPUs
+    pub max_concurrent_solves: Option<usize>,
+    // Checks waiting for a free solve slot, more are answered with 503 (default: 64)
+    pub max_queued_solves: Option<usize>,
+    // Time given to the requests in flight when shutting down (default: 30)
+    pub shutdown_grace_seconds: Option<u64>,
+}
+
+impl ConfigSection for ServerConfig {
+    const NAME: &'static str = "server";
+}
+
+// One line of the audit log, written for every check request including rejected ones
+#[derive(Debug, Serialize)]
+struct AuditRecord<'a> {
+    time: String,
+    tenant: Option<&'a str>,
+    model: &'a str,
+    status: u16,
+    entities: Option<usize>,
+    conflicts: Option<usize>,
+    elapsed_ms: u128,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    running: usize,
+    waiting: usize,
+}
+
+// Bounds the solves running at once and the checks waiting for one to finish
+struct SolveQueue {
+    slots: usize,
+    capacity: usize,
+    state: Mutex<QueueState>,
+    freed: Condvar,
+}
+
+// A running solve, frees its slot when dropped
+struct SolvePermit(Arc<SolveQueue>);
+
+impl Drop for SolvePermit {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().running -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+impl SolveQueue {
+    fn acquire(self: &Arc<Self>, timeout: Option<Duration>) -> Result<SolvePermit, String> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.state.lock().unwrap();
+
+        if state.running >= self.slots {
+            if state.waiting >= self.capacity {
+                return Err(format!(
+                    "Solver queue is full, {} checks are waiting",
+                    state.waiting
+                ));
+            }
+
+            state.waiting += 1;
+            while state.running >= self.slots {
+                state = match deadline {
+                    Some(deadline) => {
+                        let left = deadline.saturating_duration_since(Instant::now());
+                        let (mut state, result) = self.freed.wait_timeout(state, left).unwrap();
+                        if result.timed_out() && state.running >= self.slots {
+                            state.waiting -= 1;
+                            return Err("Solve time quota exceeded while queued".to_string());
+                        }
+
+                        state
+                    }
+                    None => self.freed.wait(state).unwrap(),
+                };
+            }
+            state.waiting -= 1;
+        }
+
+        state.running += 1;
+        Ok(SolvePermit(self.clone()))
+    }
+}
+
+/*
+    Models live in memory, in one namespace per tenant:
+
+    GET    /models              models of the tenant
+    PUT    /models/<name>       upload a model in the deployfix IR
+    DELETE /models/<name>
+    POST   /models/<name>/check conflicts of the model
+
+    and two endpoints without authentication for load balancers and orchestrators:
+
+    GET    /healthz             200 while the process serves requests
+    GET    /readyz              200 when it accepts checks, 503 while the solve queue is full
+                                or the server shuts down
+*/
+pub struct Server {
+    tenants: Vec<Tenant>,
+    models: Mutex<HashMap<String, BTreeMap<String, Vec<Entity>>>>,
+    audit: Mutex<Option<File>>,
+    queue: Arc<SolveQueue>,
+    grace: Duration,
+    shutting_down: AtomicBool,
+    // Options of every solve, the flags of `serve`
+    solver: SolverOptions,
+}
+
+fn error(message: impl ToString) -> serde_json::Value {
+    json!({ "error": message.to_string() })
+}
+
+fn solve(
+    entities: Vec<Entity>,
+    queue: &Arc<SolveQueue>,
+    timeout: Option<Duration>,
+    options: SolverOptions,
+) -> Result<SolverOutput, String> {
+    let started = Instant::now();
+    let permit = queue.acquire(timeout)?;
+    // The solve stops with the quota and frees its slot, instead of running on unanswered
+    let options = SolverOptions {
+        deadline: timeout.map(|timeout| started + timeout),
+        ..options
+    };
+
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        let _permit = permit;
+        let result = EntityMap::try_from(entities)
+            .map(|entity_map| {
+                let ring_result = get_solver_with("ring", &options)
+                    .unwrap()
+                    .solve(&entity_map);
+                ring_result.merge(get_solver_with("z3", &options).unwrap().solve(&entity_map))
+            })
+            .map_err(|e| e.to_string());
+
+        let _ = sender.send(result);
+    });
+
+    match timeout {
+        // The time spent queued counts towards the quota
+        Some(timeout) => receiver
+            .recv_timeout(timeout.saturating_sub(started.elapsed()))
+            .map_err(|_| {
+                format!(
+                    "Solve time quota of {}s exceeded",
+                    timeout.as_secs_f64().round()
+                )
+            })?,
+        None => receiver.recv().map_err(|e| e.to_string())?,
+    }
+}
+
+impl Server {
+    pub fn new(config: ServerConfig, audit_log: Option<&Path>) -> anyhow::Result<Self> {
+        if config.tenants.is_empty() {
+            anyhow::bail!("No tenants in the `server` section of the config");
+        }
+
+        let audit = audit_log
+            .map(|path| {
+                File::options()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open audit log {}", path.display()))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            tenants: config.tenants,
+            models: Mutex::new(HashMap::new()),
+            audit: Mutex::new(audit),
+            queue: Arc::new(SolveQueue {
+                slots: config
+                    .max_concurrent_solves
+                    .unwrap_or_else(num_cpus::get)
+                    .max(1),
+                capacity: config.max_queued_solves.unwrap_or(64),
+                state: Mutex::new(QueueState::default()),
+                freed: Condvar::new(),
+            }),
+            grace: Duration::from_secs(config.shutdown_grace_seconds.unwrap_or(30)),
+            shutting_down: AtomicBool::new(false),
+            solver: SolverOptions::default(),
+        })
+    }
+
+    pub fn with_solver_options(mut self, options: SolverOptions) -> Self {
+        self.solver = options;
+        self
+    }
+
+    fn audit(&self, record: AuditRecord) {
+        info!(
+            "check {} by {}: {}",
+            record.model,
+            record.tenant.unwrap_or("-"),
+            record.status
+        );
+
+        if let Some(audit) = self.audit.lock().unwrap().as_mut() {
+            let line = serde_json::to_string(&record).unwrap();
+            if let Err(err) = writeln!(audit, "{}", line) {
+                warn!("Failed to write audit log: {}", err);
+            }
+        }
+    }
+
+    fn health(&self, path: &str) -> Option<(u16, serde_json::Value)> {
+        let state = self.queue.state.lock().unwrap();
+        let body = |status: &str| {
+            json!({
+                "status": status,
+                "running_solves": state.running,
+                "queued_solves": state.waiting,
+            })
+        };
+
+        match path {
+            "/healthz" => Some((200, body("ok"))),
+            "/readyz" if self.shutting_down.load(Ordering::SeqCst) => {
+                Some((503, body("shutting down")))
+            }
+            "/readyz" if state.waiting >= self.queue.capacity => Some((503, body("busy"))),
+            "/readyz" => Some((200, body("ready"))),
+            _ => None,
+        }
+    }
+
+    // Blocks while the model is solved, called from the blocking pool by `serve`
+    pub fn handle(&self, request: &Request) -> (u16, serde_json::Value) {
+        let started = Instant::now();
+
+        if request.method == "GET" {
+            if let Some(response) = self.health(&request.path) {
+                return response;
+            }
+        }
+
+        let tenant = request
+            .bearer_token()
+            .and_then(|token| {
+                self.tenants
+                    .iter()
+                    .find(|t| util::same_secret(&t.token, token))
+            })
+            .cloned();
+
+        let segments = request
+            .path
+            .trim_matches('/')
+            .split('/')
+            .collect::<Vec<_>>();
+
+        let (status, body, entities, conflicts) = match (&tenant, segments.as_slice()) {
+            (None, _) => (401, error("Missing or unknown API token"), None, None),
+            (Some(tenant), ["models"]) if request.method == "GET" => {
+                let models = self.models.lock().unwrap();
+                let models = models
+                    .get(&tenant.name)
+                    .into_iter()
+                    .flatten()
+                    .map(|(name, entities)| json!({ "name": name, "entities": entities.len() }))
+                    .collect::<Vec<_>>();
+
+                (200, json!({ "models": models }), None, None)
+            }
+            (Some(tenant), ["models", name]) if request.method == "PUT" => {
+                let (status, body) = self.upload(tenant, name, &request.body);
+                (status, body, None, None)
+            }
+            (Some(tenant), ["models", name]) if request.method == "DELETE" => {
+                match self
+                    .models
+                    .lock()
+                    .unwrap()
+                    .get_mut(&tenant.name)
+                    .and_then(|models| models.remove(*name))
+                {
+                    Some(_) => (200, json!({ "model": name }), None, None),
+                    None => (404, error(format!("No model {}", name)), None, None),
+                }
+            }
+            (Some(tenant), ["models", name, "check"]) if request.method == "POST" => {
+                self.check(tenant, name)
+            }
+            (Some(_), ["models", ..]) => (405, error("Method not allowed"), None, None),
+            (Some(_), _) => (404, error("Not found"), None, None),
+        };
+
+        if let ["models", name, "check"] = segments.as_slice() {
+            self.audit(AuditRecord {
+                time: chrono::Local::now().to_rfc3339(),
+                tenant: tenant.as_ref().map(|t| t.name.as_str()),
+                model: name,
+                status,
+                entities,
+                conflicts,
+                elapsed_ms: started.elapsed().as_millis(),
+            });
+        }
+
+        (status, body)
+    }
+
+    fn upload(&self, tenant: &Tenant, name: &str, data: &str) -> (u16, serde_json::Value) {
+        let entities = match get_parser("deployfix")
+            .and_then(|parser| parser.parse(data, EntitySource::Unknown))
+        {
+            // A single layer, `remove` directives have nothing to apply to
+            Ok(entities) => merge_entities(apply_layers(vec![entities]).0, None),
+            Err(err) => return (400, error(err)),
+        };
+
+        if let Some(max) = tenant.max_entities.filter(|max| entities.len() > *max) {
+            return (
+                413,
+                error(format!(
+                    "Model has {} entities, the quota of {} is {}",
+                    entities.len(),
+                    tenant.name,
+                    max
+                )),
+            );
+        }
+
+        let count = entities.len();
+        let mut models = self.models.lock().unwrap();
+        let models = models.entry(tenant.name.clone()).or_default();
+        if let Some(max) = tenant
+            .max_models
+            .filter(|max| !models.contains_key(name) && models.len() >= *max)
+        {
+            return (
+                413,
+                error(format!(
+                    "{} already has {} models, the quota, delete one first",
+                    tenant.name, max
+                )),
+            );
+        }
+
+        models.insert(name.to_string(), entities);
+
+        (200, json!({ "model": name, "entities": count }))
+    }
+
+    fn check(
+        &self,
+        tenant: &Tenant,
+        name: &str,
+    ) -> (u16, serde_json::Value, Option<usize>, Option<usize>) {
+        let models = self
+            .models
+            .lock()
+            .unwrap()
+            .get(&tenant.name)
+            .and_then(|m| m.get(name))
+            .cloned();
+        let entities = match models {
+            Some(entities) => entities,
+            None => return (404, error(format!("No model {}", name)), None, None),
+        };
+        let count = entities.len();
+
+        let timeout = tenant.max_solve_seconds.map(Duration::from_secs);
+        let (output, undetermined) = match solve(entities, &self.queue, timeout, self.solver) {
+            Ok(output) => output.take_undetermined(),
+            Err(err) => return (503, error(err), Some(count), None),
+        };
+
+        let conflicts = output.get_conflict_rules().unwrap_or_default();
+        let total = conflicts.values().map(|rules| rules.len()).sum();
+        let conflicts = conflicts
+            .into_iter()
+            .map(|(name, rules)| {
+                (
+                    name,
+                    rules.iter().map(|r| r.to_string()).collect::<Vec<_>>(),
+                )
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let mut body = json!({ "model": name, "conflicts": conflicts });
+        // Entities the solver gave up on, neither schedulable nor in conflict
+        if let Some(undetermined) = undetermined {
+            body["undetermined"] = json!(undetermined);
+        }
+
+        (200, body, Some(count), Some(total))
+    }
+
+    // Serves until SIGINT or SIGTERM, on a runtime of its own so that the CLI stays blocking
+    pub fn serve(self, listen: &str) -> anyhow::Result<()> {
+        let runtime = tokio::runtime::Runtime::new().context("Failed to start the runtime")?;
+
+        let result = runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind(listen)
+                .await
+                .with_context(|| format!("Failed to listen on {}", listen))?;
+            info!(
+                "Serving {} tenants on http://{}",
+                self.tenants.len(),
+                listen
+            );
+
+            Arc::new(self)
+                .serve_until(listener, shutdown_signal())
+                .await
+        });
+
+        // Solves still running after the grace period are not waited for
+        runtime.shutdown_background();
+
+        result
+    }
+
+    // Handles connections concurrently until `shutdown` completes, then stops accepting and
+    // waits up to the grace period for the requests in flight
+    pub async fn serve_until(
+        self: Arc<Self>,
+        listener: tokio::net::TcpListener,
+        shutdown: impl Future<Output = ()>,
+    ) -> anyhow::Result<()> {
+        let mut connections = tokio::task::JoinSet::new();
+        tokio::pin!(shutdown);
+
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+                accepted = listener.accept() => {
+                    let mut stream = match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(err) => {
+                            warn!("Failed to accept connection: {}", err);
+                            continue;
+                        }
+                    };
+
+                    let server = self.clone();
+                    connections.spawn(async move {
+                        let request = tokio::time::timeout(
+                            Duration::from_secs(30),
+                            http::read_request(&mut stream),
+                        )
+                        .await
+                        .unwrap_or_else(|_| {
+                            Err(anyhow::anyhow!("Timed out reading the request").into())
+                        });
+
+                        let (status, body) = match request {
+                            Ok(request) => tokio::task::spawn_blocking(move || server.handle(&request))
+                                .await
+                                .unwrap_or_else(|err| (503, error(err))),
+                            Err(err) => (err.status(), error(err)),
+                        };
+
+                        if let Err(err) =
+                            http::write_response(&mut stream, status, &body.to_string()).await
+                        {
+                            warn!("Failed to write response: {}", err);
+                        }
+                    });
+                }
+            }
+        }
+
+        self.shutting_down.store(true, Ordering::SeqCst);
+        info!(
+            "Shutting down, waiting up to {}s for {} requests",
+            self.grace.as_secs(),
+            connections.len()
+        );
+
+        let drained = tokio::time::timeout(self.grace, async {
+            while connections.join_next().await.is_some() {}
+        })
+        .await;
+        if drained.is_err() {
+            warn!(
+                "Dropped {} requests still running after the grace period",
+                connections.len()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(err) => {
+                warn!("Failed to listen for SIGTERM: {}", err);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
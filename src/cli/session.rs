@@ -0,0 +1,279 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use chrono::NaiveDate;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{model::Entity, solver};
+
+use super::{
+    check_entities, collect_inputs, digest::digest, load_impact_weights, load_suppressions,
+    BudgetArgs, Config, ConflictReport, RunOptions, TagArgs,
+};
+
+// Version of the session format, sessions of another version are rejected
+pub const SESSION_VERSION: u32 = 1;
+
+/*
+    Everything needed to reproduce a `check` run, written by `check --session-export` as a
+    single JSON file and rerun by `deployfix replay`:
+
+    {
+      "version": 1,
+      "deployfix": "0.1.0",
+      "inputs": [{ "path": "model.ir", "digest": "9f86d081884c7d65..." }],
+      "options": { "format": null, "domain": null, ..., "relaxed_cycles": false },
+      "today": "2024-01-31",
+      "config": { "impact": { "priority": 10 } },
+      "entities": [ ... ],
+      "outputs": { "conflicts": [ ... ], "undetermined": {}, "summary": "..." }
+    }
+
+    Replays solve the recorded entities, the inputs are only compared with their digests.
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub version: u32,
+    // Release which recorded the session
+    pub deployfix: String,
+    pub inputs: Vec<SessionInput>,
+    pub options: SessionOptions,
+    // Day the suppressions were checked against
+    pub today: NaiveDate,
+    pub config: Config,
+    pub entities: Vec<Entity>,
+    pub outputs: SessionOutputs,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionInput {
+    pub path: String,
+    pub digest: String,
+}
+
+// Flags of the recorded `check`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionOptions {
+    pub format: Option<String>,
+    pub domain: Option<String>,
+    pub default_domain_key: Option<String>,
+    pub cycle_check: bool,
+    pub fast_fail: bool,
+    pub budget: BudgetArgs,
+    // Already applied to the recorded entities
+    #[serde(default)]
+    pub tags: TagArgs,
+    // Sessions recorded before the option checked with strict cycles
+    #[serde(default)]
+    pub relaxed_cycles: bool,
+    #[serde(default = "default_max_cycles")]
+    pub max_cycles: usize,
+}
+
+fn default_max_cycles() -> usize {
+    solver::DEFAULT_MAX_CYCLES
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionOutputs {
+    // In entity then rule order
+    pub conflicts: Vec<SessionConflict>,
+    pub undetermined: BTreeMap<String, String>,
+    pub summary: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionConflict {
+    pub entity: String,
+    pub rule: String,
+    pub location: Option<String>,
+    // `require` or `exclude`, the topology it applies in and where it is declared, missing from
+    // the sessions of older releases
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topology: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    pub solvers: BTreeSet<String>,
+    pub impact: f64,
+    // Why the rule exists, from its `desc` metadata
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub desc: Option<String>,
+    // Alternatives of a multi rule which would schedule the entity
+    pub recommendations: Vec<String>,
+}
+
+impl Session {
+    pub fn new(
+        paths: &[PathBuf],
+        options: SessionOptions,
+        config: &Config,
+        today: NaiveDate,
+        mut entities: Vec<Entity>,
+    ) -> Self {
+        // Canonical order, the same model is always written the same way
+        entities.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let inputs = collect_inputs(paths)
+            .into_iter()
+            .filter_map(|path| {
+                let digest = digest(&path)
+                    .map_err(|err| warn!("Failed to digest {}: {}", path.display(), err))
+                    .ok()?;
+
+                Some(SessionInput {
+                    path: path.display().to_string(),
+                    digest,
+                })
+            })
+            .collect();
+
+        Self {
+            version: SESSION_VERSION,
+            deployfix: env!("CARGO_PKG_VERSION").to_string(),
+            inputs,
+            options,
+            today,
+            config: config.clone(),
+            entities,
+            outputs: SessionOutputs::default(),
+        }
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read session {}", path.display()))?;
+        let session: Self = serde_json::from_str(&data)
+            .with_context(|| format!("Invalid session {}", path.display()))?;
+
+        if session.version != SESSION_VERSION {
+            anyhow::bail!(
+                "Session {} has version {}, this release reads version {}",
+                path.display(),
+                session.version,
+                SESSION_VERSION
+            );
+        }
+
+        Ok(session)
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let data = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, data + "\n")
+            .with_context(|| format!("Failed to write session {}", path.display()))
+    }
+
+    // Report of the recorded run, with the suppressions and weights of the recorded config
+    pub fn report(&self, run: &RunOptions) -> ConflictReport {
+        ConflictReport::new(run.level)
+            .with_gates(run.gates.clone())
+            .with_suppressions(load_suppressions(&self.config), self.today)
+            .with_impact_weights(load_impact_weights(&self.config))
+            .with_session_outputs()
+    }
+
+    // Solves the recorded entities again with the recorded options, the others taken from `run`
+    pub fn replay(&self, run: &RunOptions) -> SessionOutputs {
+        if self.deployfix != env!("CARGO_PKG_VERSION") {
+            warn!(
+                "Session recorded by deployfix {}, replaying with {}",
+                self.deployfix,
+                env!("CARGO_PKG_VERSION")
+            );
+        }
+
+        for input in self.inputs.iter() {
+            match digest(Path::new(&input.path)) {
+                Ok(digest) if digest == input.digest => {}
+                Ok(_) => warn!("{} changed since the session was recorded", input.path),
+                Err(_) => info!(
+                    "{} is not available, replaying its recorded model",
+                    input.path
+                ),
+            }
+        }
+
+        let mut report = self.report(run);
+        check_entities(self.entities.clone(), &self.options, run, &mut report, None);
+        report.print_summary();
+
+        report.session_outputs().unwrap_or_default()
+    }
+}
+
+// Lines describing how `replayed` differs from `recorded`, empty when they match
+pub fn diff_outputs(recorded: &SessionOutputs, replayed: &SessionOutputs) -> Vec<String> {
+    let mut diff = vec![];
+
+    let key = |c: &SessionConflict| (c.entity.clone(), c.rule.clone());
+    let (recorded_keys, replayed_keys) = (
+        recorded.conflicts.iter().map(key).collect::<BTreeSet<_>>(),
+        replayed.conflicts.iter().map(key).collect::<BTreeSet<_>>(),
+    );
+
+    for conflict in recorded.conflicts.iter() {
+        if !replayed_keys.contains(&key(conflict)) {
+            diff.push(format!(
+                "- {}: {} is no longer reported",
+                conflict.entity, conflict.rule
+            ));
+        }
+    }
+    for conflict in replayed.conflicts.iter() {
+        if !recorded_keys.contains(&key(conflict)) {
+            diff.push(format!(
+                "+ {}: {} is newly reported",
+                conflict.entity, conflict.rule
+            ));
+        }
+    }
+
+    for (entity, reason) in recorded.undetermined.iter() {
+        if !replayed.undetermined.contains_key(entity) {
+            diff.push(format!(
+                "- {} is no longer undetermined ({})",
+                entity, reason
+            ));
+        }
+    }
+    for (entity, reason) in replayed.undetermined.iter() {
+        if !recorded.undetermined.contains_key(entity) {
+            diff.push(format!("+ {} is newly undetermined ({})", entity, reason));
+        }
+    }
+
+    diff
+}
+
+pub fn replay(path: &Path, run: &RunOptions) {
+    let session = Session::load(path).unwrap_or_else(|err| {
+        error!("{:#}", err);
+        std::process::exit(1);
+    });
+
+    info!(
+        "Replaying {} entities of session {}...",
+        session.entities.len(),
+        path.display()
+    );
+
+    let outputs = session.replay(run);
+    let diff = diff_outputs(&session.outputs, &outputs);
+    if !diff.is_empty() {
+        error!(
+            "Replay differs from the recorded session:\n{}",
+            diff.join("\n")
+        );
+        std::process::exit(1);
+    }
+
+    info!("Replay matches the recorded session");
+}
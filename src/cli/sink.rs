@@ -0,0 +1,168 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::{Args, ValueEnum};
+
+use crate::util::http::{self, HttpUrl};
+
+use super::PlanSink;
+
+// Destination of the files produced by a run, e.g. `dump.ir`, `conflicts-node.yaml` or the
+// injected manifests. `name` is always relative to the root of the sink.
+pub trait ReportSink {
+    fn write(&self, name: &str, content: &str) -> anyhow::Result<()>;
+
+    // Human readable location of `name`, used in logs
+    fn location(&self, name: &str) -> String;
+
+    // Current content of `name`, for sinks that can read back what they hold
+    fn read(&self, _name: &str) -> Option<String> {
+        None
+    }
+}
+
+pub struct DirectorySink {
+    root: PathBuf,
+}
+
+impl DirectorySink {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+}
+
+impl ReportSink for DirectorySink {
+    fn write(&self, name: &str, content: &str) -> anyhow::Result<()> {
+        let path = self.root.join(name);
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create dir {}", parent.display()))?;
+        }
+
+        std::fs::write(&path, content)
+            .with_context(|| format!("Failed to write file {}", path.display()))
+    }
+
+    fn location(&self, name: &str) -> String {
+        self.root.join(name).display().to_string()
+    }
+
+    fn read(&self, name: &str) -> Option<String> {
+        std::fs::read_to_string(self.root.join(name)).ok()
+    }
+}
+
+pub struct StdoutSink;
+
+impl ReportSink for StdoutSink {
+    fn write(&self, name: &str, content: &str) -> anyhow::Result<()> {
+        let mut stdout = std::io::stdout().lock();
+
+        writeln!(stdout, "# ---- {} ----", name)?;
+        write!(stdout, "{}", content)?;
+        if !content.ends_with('\n') {
+            writeln!(stdout)?;
+        }
+
+        Ok(())
+    }
+
+    fn location(&self, name: &str) -> String {
+        format!("stdout ({})", name)
+    }
+}
+
+// POSTs every file to a plain http:// endpoint, the file name is sent in the
+// `X-Deployfix-Name` header
+pub struct WebhookSink {
+    url: HttpUrl,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            url: HttpUrl::parse(url)?,
+        })
+    }
+}
+
+impl ReportSink for WebhookSink {
+    fn write(&self, name: &str, content: &str) -> anyhow::Result<()> {
+        let status = http::post(
+            &self.url,
+            &[
+                ("Content-Type", "text/plain; charset=utf-8"),
+                ("X-Deployfix-Name", name),
+            ],
+            content,
+        )?;
+
+        if !(200..300).contains(&status) {
+            anyhow::bail!("Webhook rejected {} with status {}", name, status);
+        }
+
+        Ok(())
+    }
+
+    fn location(&self, name: &str) -> String {
+        format!("{} ({})", self.url, name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum SinkKind {
+    #[default]
+    Dir,
+    Stdout,
+    Webhook,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SinkArgs {
+    #[clap(
+        long,
+        value_enum,
+        default_value = "dir",
+        help = "Where to write the output files"
+    )]
+    sink: SinkKind,
+    #[clap(long, help = "URL receiving the output files when --sink=webhook")]
+    webhook_url: Option<String>,
+    #[clap(
+        long,
+        help = "Print how the output files would differ from the ones in the output directory instead of writing them"
+    )]
+    dry_run: bool,
+}
+
+impl SinkArgs {
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    // `root` is the directory used by the `dir` sink, and the one a dry run is planned against
+    pub fn build(&self, root: &Path) -> anyhow::Result<Box<dyn ReportSink>> {
+        if self.dry_run {
+            return Ok(Box::new(PlanSink::new(root)));
+        }
+
+        match self.sink {
+            SinkKind::Dir => Ok(Box::new(DirectorySink::new(root))),
+            SinkKind::Stdout => Ok(Box::new(StdoutSink)),
+            SinkKind::Webhook => {
+                let url = self
+                    .webhook_url
+                    .as_deref()
+                    .context("--webhook-url is required when --sink=webhook")?;
+
+                Ok(Box::new(WebhookSink::new(url)?))
+            }
+        }
+    }
+}
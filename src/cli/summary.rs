@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+// Per-domain results written by `k8s go`
+pub const SUMMARY_FILE: &str = "summary.yaml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DomainResult {
+    Ok,
+    Conflict,
+    // A solver gave up on some entities of the domain
+    Undetermined,
+    // Not checked, either turned off by `--topology-solvers` or after an earlier conflict
+    Skipped,
+}
+
+impl DomainResult {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Conflict => "conflict",
+            Self::Undetermined => "undetermined",
+            Self::Skipped => "skipped",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DomainSummary {
+    pub topology: String,
+    pub entities: usize,
+    // Entities reported conflicting in this domain and not in an earlier one
+    pub conflicts: usize,
+    pub duration_ms: u64,
+    pub result: DomainResult,
+}
+
+impl DomainSummary {
+    pub fn new(
+        topology: &str,
+        entities: usize,
+        conflicts: usize,
+        undetermined: bool,
+        duration: Duration,
+    ) -> Self {
+        let result = match (conflicts, undetermined) {
+            (0, false) => DomainResult::Ok,
+            (0, true) => DomainResult::Undetermined,
+            _ => DomainResult::Conflict,
+        };
+
+        Self {
+            topology: topology.to_string(),
+            entities,
+            conflicts,
+            duration_ms: duration.as_millis() as u64,
+            result,
+        }
+    }
+
+    pub fn skipped(topology: &str, entities: usize) -> Self {
+        Self {
+            topology: topology.to_string(),
+            entities,
+            conflicts: 0,
+            duration_ms: 0,
+            result: DomainResult::Skipped,
+        }
+    }
+}
+
+/*
+    Results of every domain of a run, in the order they were checked:
+
+    domains:
+    - topology: kubernetes.io/hostname
+      entities: 12
+      conflicts: 2
+      duration_ms: 840
+      result: conflict
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub domains: Vec<DomainSummary>,
+}
+
+impl RunSummary {
+    pub fn push(&mut self, domain: DomainSummary) {
+        self.domains.push(domain);
+    }
+
+    pub fn to_yaml(&self) -> String {
+        serde_yaml::to_string(self).unwrap()
+    }
+
+    // Aligned table of the domains, one line each under a header
+    pub fn format_table(&self) -> String {
+        let header = ["TOPOLOGY", "ENTITIES", "CONFLICTS", "DURATION", "RESULT"];
+        let rows = self
+            .domains
+            .iter()
+            .map(|domain| {
+                [
+                    domain.topology.clone(),
+                    domain.entities.to_string(),
+                    domain.conflicts.to_string(),
+                    format!("{}ms", domain.duration_ms),
+                    domain.result.as_str().to_string(),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let mut widths = header.map(str::len);
+        for row in rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        std::iter::once(header.map(str::to_string))
+            .chain(rows)
+            .map(|row| {
+                let line = row
+                    .iter()
+                    .zip(widths.iter())
+                    .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                    .collect::<Vec<_>>()
+                    .join("  ");
+                line.trim_end().to_string() + "\n"
+            })
+            .collect()
+    }
+}
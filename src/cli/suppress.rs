@@ -0,0 +1,153 @@
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+use clap::Subcommand;
+use log::{error, info, warn};
+use serde::Deserialize;
+
+use crate::model::EntityRule;
+
+use super::{import_inputs, BudgetArgs, Config, ConfigSection, ConflictReport, OutputLevel};
+
+// A conflict accepted on purpose, e.g.
+//
+//   suppressions:
+//     - entity: app=app1
+//       target: app=app2
+//       expires: 2024-06-30
+//       reason: migration in progress
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Suppression {
+    // Entity whose conflicts are suppressed
+    pub entity: String,
+    // Only suppress the rules of `entity` naming this target
+    pub target: Option<String>,
+    // Last day the suppression applies
+    pub expires: Option<NaiveDate>,
+    pub reason: Option<String>,
+}
+
+impl Suppression {
+    pub fn is_expired(&self, today: NaiveDate) -> bool {
+        self.expires.is_some_and(|expires| expires < today)
+    }
+
+    pub fn matches(&self, entity: &str, rule: &EntityRule) -> bool {
+        self.entity == entity
+            && self
+                .target
+                .as_deref()
+                .is_none_or(|target| rule.is_in_target(target))
+    }
+}
+
+// `suppressions` section of the config file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Suppressions(pub Vec<Suppression>);
+
+impl ConfigSection for Suppressions {
+    const NAME: &'static str = "suppressions";
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuppressionStatus {
+    // Matched a conflict of the run
+    Active,
+    // Past its expiry date, the conflicts it matches are reported again
+    Expired,
+    // Matched no conflict, the rule was probably fixed or renamed
+    Unused,
+}
+
+impl AsRef<str> for SuppressionStatus {
+    fn as_ref(&self) -> &str {
+        match self {
+            SuppressionStatus::Active => "active",
+            SuppressionStatus::Expired => "expired",
+            SuppressionStatus::Unused => "unused",
+        }
+    }
+}
+
+pub fn today() -> NaiveDate {
+    chrono::Local::now().date_naive()
+}
+
+// Loads the `suppressions` section, exiting on an invalid one like the plugin sections
+pub fn load_suppressions(config: &Config) -> Suppressions {
+    config.section().unwrap_or_else(|err| {
+        error!("{:#}", err);
+        std::process::exit(1);
+    })
+}
+
+#[derive(Subcommand)]
+pub enum SuppressionsCommands {
+    #[clap(about = "Check the models and report active, expired and unused suppressions")]
+    List {
+        #[clap(
+            value_name = "PATH",
+            required = true,
+            help = "Files, directories or glob patterns of models to check"
+        )]
+        paths: Vec<PathBuf>,
+        #[clap(short, long, value_name = "FORMAT")]
+        format: Option<String>,
+        #[clap(long, help = "Enable cycle check")]
+        cycle_check: bool,
+        #[command(flatten)]
+        budget: BudgetArgs,
+    },
+}
+
+pub fn execute(command: SuppressionsCommands, config: &Config) {
+    match command {
+        SuppressionsCommands::List {
+            paths,
+            format,
+            cycle_check,
+            budget,
+        } => {
+            let suppressions = load_suppressions(config);
+            if suppressions.0.is_empty() {
+                info!("No suppressions configured");
+                return;
+            }
+
+            let entities = import_inputs(&paths, format.as_deref());
+            let mut report =
+                ConflictReport::new(OutputLevel::Quiet).with_suppressions(suppressions, today());
+            super::solve(entities, None, cycle_check, false, &budget, &mut report);
+
+            let statuses = report.suppression_statuses();
+            for (suppression, status) in statuses.iter() {
+                println!(
+                    "{:<8} {} {}{}{}",
+                    status.as_ref(),
+                    suppression.entity,
+                    suppression.target.as_deref().unwrap_or("*"),
+                    suppression
+                        .expires
+                        .map(|d| format!(" expires {}", d))
+                        .unwrap_or_default(),
+                    suppression
+                        .reason
+                        .as_deref()
+                        .map(|r| format!(" # {}", r))
+                        .unwrap_or_default(),
+                );
+            }
+
+            let stale = statuses
+                .iter()
+                .filter(|(_, status)| *status != SuppressionStatus::Active)
+                .count();
+            if stale > 0 {
+                warn!("{} suppressions are expired or unused", stale);
+                std::process::exit(1);
+            }
+        }
+    }
+}
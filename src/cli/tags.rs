@@ -0,0 +1,46 @@
+use clap::Args;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::model::{filter_rules_by_tags, Entity};
+
+// Rule families to check, from the `tags` metadata of the rules
+#[derive(Debug, Clone, Default, Args, Serialize, Deserialize)]
+pub struct TagArgs {
+    #[clap(
+        long,
+        value_name = "TAG",
+        value_delimiter = ',',
+        help = "Only check the rules with one of these tags"
+    )]
+    pub only_tags: Vec<String>,
+    #[clap(
+        long,
+        value_name = "TAG",
+        value_delimiter = ',',
+        help = "Do not check the rules with one of these tags"
+    )]
+    pub skip_tags: Vec<String>,
+}
+
+impl TagArgs {
+    pub fn is_empty(&self) -> bool {
+        self.only_tags.is_empty() && self.skip_tags.is_empty()
+    }
+
+    pub fn apply(&self, mut entities: Vec<Entity>) -> Vec<Entity> {
+        if self.is_empty() {
+            return entities;
+        }
+
+        let total = entities.iter().map(|e| e.rules_len()).sum::<usize>();
+        let dropped = filter_rules_by_tags(&mut entities, &self.only_tags, &self.skip_tags);
+        info!(
+            "Checking {} of {} rules selected by their tags",
+            total - dropped,
+            total
+        );
+
+        entities
+    }
+}
@@ -0,0 +1,99 @@
+use std::{fs::OpenOptions, io::Write, path::PathBuf, time::Duration};
+
+use anyhow::Context;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    solver::ProblemSize,
+    util::http::{self, HttpUrl},
+};
+
+use super::ConfigSection;
+
+// Where the statistics of the solves go with `--telemetry`, e.g.
+//
+//   telemetry:
+//     file: /var/lib/deployfix/stats.jsonl
+//     endpoint: http://stats.example.com/deployfix
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelemetryConfig {
+    // Appended with one JSON line per solve
+    pub file: Option<PathBuf>,
+    // Receives each line as an HTTP POST
+    pub endpoint: Option<String>,
+}
+
+impl ConfigSection for TelemetryConfig {
+    const NAME: &'static str = "telemetry";
+}
+
+// Statistics of one solve, without any name or path of the model
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SolveStats {
+    pub time: String,
+    pub version: String,
+    pub command: String,
+    pub entities: usize,
+    pub rules: usize,
+    pub clauses: usize,
+    pub solve_ms: u128,
+    pub conflicts: usize,
+}
+
+impl SolveStats {
+    pub fn new(command: &str, size: &ProblemSize, elapsed: Duration, conflicts: usize) -> Self {
+        Self {
+            time: chrono::Utc::now().to_rfc3339(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            command: command.to_string(),
+            entities: size.entities,
+            rules: size.rules,
+            clauses: size.clauses,
+            solve_ms: elapsed.as_millis(),
+            conflicts,
+        }
+    }
+}
+
+// Destinations of the statistics, none when the config gives neither a file nor an endpoint.
+// Nothing is recorded without `--telemetry`, whatever the config says.
+pub fn enable_telemetry(config: TelemetryConfig) -> Option<TelemetryConfig> {
+    if config.file.is_none() && config.endpoint.is_none() {
+        warn!("--telemetry needs a `file` or an `endpoint` in the `telemetry` config section, nothing is recorded");
+        return None;
+    }
+
+    Some(config)
+}
+
+// Statistics are best effort, failing to record them only warns
+pub fn record_solve(config: &TelemetryConfig, stats: &SolveStats) {
+    if let Err(err) = write_stats(config, stats) {
+        warn!("Failed to record telemetry: {:#}", err);
+    }
+}
+
+pub fn write_stats(config: &TelemetryConfig, stats: &SolveStats) -> anyhow::Result<()> {
+    let line = serde_json::to_string(stats)?;
+
+    if let Some(path) = &config.file {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        writeln!(file, "{}", line)?;
+    }
+
+    if let Some(endpoint) = &config.endpoint {
+        let url = HttpUrl::parse(endpoint)?;
+        let status = http::post(&url, &[("Content-Type", "application/json")], &line)?;
+        if !(200..300).contains(&status) {
+            anyhow::bail!("{} rejected the statistics with status {}", url, status);
+        }
+    }
+
+    Ok(())
+}
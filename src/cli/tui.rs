@@ -0,0 +1,381 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    widgets::{Block, List, ListState, Paragraph, Wrap},
+    Frame,
+};
+
+use crate::{
+    model::{rule_location, Entity, EntityRule, Env},
+    solver::{EntityMap, SolverOptions, SolverOutput},
+};
+
+use super::{
+    import_inputs, run_solvers, ConflictAnnotater, ConflictReport, OutputLevel, SourceCache,
+};
+
+const HELP: &str = "up/down move, tab switch pane, r re-solve, q quit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pane {
+    Entities,
+    Rules,
+    Conflicts,
+}
+
+impl Pane {
+    fn index(self) -> usize {
+        match self {
+            Self::Entities => 0,
+            Self::Rules => 1,
+            Self::Conflicts => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DashboardAction {
+    Up,
+    Down,
+    NextPane,
+    PreviousPane,
+    // Reads the inputs again and solves them, picking up the files edited meanwhile
+    Resolve,
+    Quit,
+}
+
+/*
+    State of `deployfix-cli tui`: the entities of the inputs, the rules of the selected entity
+    and the conflicts of the last solve, previewed as annotated snippets. The terminal only
+    draws it, actions drive it, so it is usable without a terminal.
+*/
+pub struct Dashboard {
+    paths: Vec<PathBuf>,
+    format: Option<String>,
+    envs: Option<Vec<Env>>,
+    cycle_check: bool,
+    options: SolverOptions,
+    pub entities: Vec<Entity>,
+    pub conflicts: BTreeMap<String, Vec<EntityRule>>,
+    pub pane: Pane,
+    // Selected line of each pane
+    selected: [usize; 3],
+    sources: SourceCache,
+    // Summary line of the last solve, or why it failed
+    pub status: String,
+}
+
+impl Dashboard {
+    pub fn new(
+        paths: Vec<PathBuf>,
+        format: Option<String>,
+        envs: Option<Vec<Env>>,
+        cycle_check: bool,
+        options: SolverOptions,
+    ) -> Self {
+        let mut dashboard = Self {
+            paths,
+            format,
+            envs,
+            cycle_check,
+            options,
+            entities: vec![],
+            conflicts: BTreeMap::new(),
+            pane: Pane::Entities,
+            selected: [0; 3],
+            sources: SourceCache::new(),
+            status: String::new(),
+        };
+        dashboard.resolve();
+
+        dashboard
+    }
+
+    pub fn resolve(&mut self) {
+        let mut entities = import_inputs(&self.paths, self.format.as_deref());
+        entities.sort_by(|a, b| a.name.cmp(&b.name));
+        // Annotated files may have been edited since the last solve
+        self.sources = SourceCache::new();
+
+        let entity_map: EntityMap = match (&entities).try_into() {
+            Ok(entity_map) => entity_map,
+            Err(err) => {
+                self.status = format!("Failed to solve: {}", err);
+                self.entities = entities;
+                self.conflicts.clear();
+                self.clamp();
+                return;
+            }
+        };
+
+        let result = run_solvers(
+            &entity_map,
+            self.envs.as_ref(),
+            self.cycle_check,
+            false,
+            &self.options,
+        );
+        let (result, undetermined) = result.take_undetermined();
+        let (result, soft) = result.take_soft();
+
+        let mut report = ConflictReport::new(OutputLevel::Quiet);
+        if let Some(undetermined) = undetermined {
+            report.add_undetermined(&undetermined);
+        }
+        report.add_soft(&soft);
+        self.conflicts = match result {
+            SolverOutput::Conflict(conflicts, solvers) => {
+                report.add(&conflicts, &solvers, &entities);
+                conflicts.into_iter().collect()
+            }
+            _ => BTreeMap::new(),
+        };
+
+        self.status = report.summary();
+        self.entities = entities;
+        self.clamp();
+    }
+
+    // Rules of the selected entity
+    pub fn rules(&self) -> Vec<&EntityRule> {
+        self.entities
+            .get(self.selected[Pane::Entities.index()])
+            .map(|entity| entity.rules().collect())
+            .unwrap_or_default()
+    }
+
+    // Every conflicting rule with its entity, in entity order
+    pub fn conflict_rules(&self) -> Vec<(&str, &EntityRule)> {
+        self.conflicts
+            .iter()
+            .flat_map(|(name, rules)| rules.iter().map(move |rule| (name.as_str(), rule)))
+            .collect()
+    }
+
+    fn len(&self, pane: Pane) -> usize {
+        match pane {
+            Pane::Entities => self.entities.len(),
+            Pane::Rules => self.rules().len(),
+            Pane::Conflicts => self.conflict_rules().len(),
+        }
+    }
+
+    // Keeps the selections within their panes, e.g. after a solve with fewer conflicts
+    fn clamp(&mut self) {
+        for pane in [Pane::Entities, Pane::Rules, Pane::Conflicts] {
+            let len = self.len(pane);
+            let selected = &mut self.selected[pane.index()];
+            *selected = (*selected).min(len.saturating_sub(1));
+        }
+    }
+
+    pub fn selected(&self, pane: Pane) -> usize {
+        self.selected[pane.index()]
+    }
+
+    // Returns false once the dashboard is closed
+    pub fn apply(&mut self, action: DashboardAction) -> bool {
+        let index = self.pane.index();
+
+        match action {
+            DashboardAction::Up => self.selected[index] = self.selected[index].saturating_sub(1),
+            DashboardAction::Down => self.selected[index] += 1,
+            DashboardAction::NextPane => {
+                self.pane = match self.pane {
+                    Pane::Entities => Pane::Rules,
+                    Pane::Rules => Pane::Conflicts,
+                    Pane::Conflicts => Pane::Entities,
+                }
+            }
+            DashboardAction::PreviousPane => {
+                self.pane = match self.pane {
+                    Pane::Entities => Pane::Conflicts,
+                    Pane::Rules => Pane::Entities,
+                    Pane::Conflicts => Pane::Rules,
+                }
+            }
+            DashboardAction::Resolve => self.resolve(),
+            DashboardAction::Quit => return false,
+        }
+
+        // The rules follow the selected entity
+        if self.pane == Pane::Entities
+            && matches!(action, DashboardAction::Up | DashboardAction::Down)
+        {
+            self.selected[Pane::Rules.index()] = 0;
+        }
+        self.clamp();
+
+        true
+    }
+
+    // Annotated snippet of the selected conflict or rule, a short account of an entity
+    pub fn preview(&self) -> String {
+        let annotate = |name: &str, rule: &EntityRule| {
+            ConflictAnnotater::new_with_cache(name, rule, &self.sources).annotate_plain()
+        };
+
+        match self.pane {
+            Pane::Conflicts => self
+                .conflict_rules()
+                .get(self.selected(Pane::Conflicts))
+                .map(|(name, rule)| annotate(name, rule))
+                .unwrap_or_else(|| "No conflict".to_string()),
+            Pane::Rules => self
+                .rules()
+                .get(self.selected(Pane::Rules))
+                .map(|rule| {
+                    let location = rule_location(rule).unwrap_or_else(|| "unknown".to_string());
+                    format!("{}\n\nDeclared at {}", rule.describe(), location)
+                })
+                .unwrap_or_else(|| "No rule".to_string()),
+            Pane::Entities => self
+                .entities
+                .get(self.selected(Pane::Entities))
+                .map(|entity| {
+                    let name = entity.name.as_ref();
+                    let conflicts = self.conflicts.get(name).map_or(0, |rules| rules.len());
+                    format!(
+                        "{}: {} requires, {} excludes, {} conflicting rules",
+                        name,
+                        entity.requires.len(),
+                        entity.excludes.len(),
+                        conflicts
+                    )
+                })
+                .unwrap_or_else(|| "No entity".to_string()),
+        }
+    }
+
+    fn render_list(
+        &self,
+        frame: &mut Frame,
+        area: Rect,
+        pane: Pane,
+        title: &str,
+        items: Vec<String>,
+    ) {
+        let border = match self.pane == pane {
+            true => Style::new().fg(Color::Yellow),
+            false => Style::new(),
+        };
+        let mut state =
+            ListState::default().with_selected((!items.is_empty()).then(|| self.selected(pane)));
+        let list = List::new(items)
+            .block(Block::bordered().title(title).border_style(border))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+
+    pub fn render(&self, frame: &mut Frame) {
+        let [panes, preview, status] = Layout::vertical([
+            Constraint::Percentage(50),
+            Constraint::Min(5),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [entities, rules, conflicts] =
+            Layout::horizontal([Constraint::Ratio(1, 3); 3]).areas(panes);
+
+        let entity_items = self
+            .entities
+            .iter()
+            .map(|entity| {
+                let name = entity.name.as_ref();
+                match self.conflicts.contains_key(name) {
+                    true => format!("! {}", name),
+                    false => format!("  {}", name),
+                }
+            })
+            .collect();
+        self.render_list(frame, entities, Pane::Entities, "Entities", entity_items);
+
+        let rule_items = self.rules().iter().map(|rule| rule_text(rule)).collect();
+        self.render_list(frame, rules, Pane::Rules, "Rules", rule_items);
+
+        let conflict_items = self
+            .conflict_rules()
+            .iter()
+            .map(|(name, rule)| format!("{}: {}", name, rule_text(rule)))
+            .collect();
+        self.render_list(
+            frame,
+            conflicts,
+            Pane::Conflicts,
+            "Conflicts",
+            conflict_items,
+        );
+
+        frame.render_widget(
+            Paragraph::new(self.preview())
+                .block(Block::bordered().title("Preview"))
+                .wrap(Wrap { trim: false }),
+            preview,
+        );
+        frame.render_widget(
+            Paragraph::new(format!("{} | {}", self.status, HELP)),
+            status,
+        );
+    }
+}
+
+// The rule as written in the IR, without its metadata
+fn rule_text(rule: &EntityRule) -> String {
+    let targets = rule
+        .targets()
+        .iter()
+        .map(|target| target.as_ref())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    format!("{} {} {}", rule.source().as_ref(), rule.r#type(), targets)
+}
+
+fn action(code: KeyCode) -> Option<DashboardAction> {
+    match code {
+        KeyCode::Up | KeyCode::Char('k') => Some(DashboardAction::Up),
+        KeyCode::Down | KeyCode::Char('j') => Some(DashboardAction::Down),
+        KeyCode::Tab | KeyCode::Right | KeyCode::Char('l') => Some(DashboardAction::NextPane),
+        KeyCode::BackTab | KeyCode::Left | KeyCode::Char('h') => {
+            Some(DashboardAction::PreviousPane)
+        }
+        KeyCode::Char('r') => Some(DashboardAction::Resolve),
+        KeyCode::Char('q') | KeyCode::Esc => Some(DashboardAction::Quit),
+        _ => None,
+    }
+}
+
+// Draws the dashboard until it is closed, the terminal is restored whatever happens
+pub fn run_dashboard(mut dashboard: Dashboard) -> anyhow::Result<()> {
+    // Log lines would be drawn over the dashboard
+    let level = log::max_level();
+    log::set_max_level(log::LevelFilter::Off);
+    let mut terminal = ratatui::init();
+
+    let result = (|| -> anyhow::Result<()> {
+        loop {
+            terminal.draw(|frame| dashboard.render(frame))?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if let Some(action) = action(key.code) {
+                if !dashboard.apply(action) {
+                    return Ok(());
+                }
+            }
+        }
+    })();
+
+    ratatui::restore();
+    log::set_max_level(level);
+    result
+}
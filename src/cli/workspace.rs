@@ -0,0 +1,218 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use log::{error, info};
+use serde::Deserialize;
+
+use crate::{
+    model::{merge_entities, Entity},
+    plugin::{k8s, yarn},
+    util,
+};
+
+use super::{
+    import_inputs, load_impact_weights, load_normalizations, load_suppressions, today, BudgetArgs,
+    Config, ConflictReport, RunOptions, TagArgs,
+};
+
+// Workspace file read by `check --workspace` when no path is given
+pub const WORKSPACE_FILE: &str = "deployfix.workspace.yaml";
+
+/*
+    Several projects checked together, paths are relative to the workspace file:
+
+    projects:
+      - name: payments
+        paths: [payments/]
+        format: k8s              # k8s, yarn or a `check` input format, detected when omitted
+        domain_key: tenant       # optional, checks each value of this rule metadata apart
+        default_domain: shared   # domain of the rules without the key (default: default)
+      - name: search
+        paths: [search/model.ir]
+    global:                      # policies spanning projects, checked once with all of them
+      - policies/
+*/
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Workspace {
+    pub projects: Vec<Project>,
+    #[serde(default)]
+    pub global: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Project {
+    pub name: String,
+    pub paths: Vec<PathBuf>,
+    pub format: Option<String>,
+    pub domain_key: Option<String>,
+    #[serde(default = "default_domain")]
+    pub default_domain: String,
+}
+
+fn default_domain() -> String {
+    "default".to_string()
+}
+
+impl Workspace {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read workspace {}", path.display()))?;
+        let mut workspace: Self = serde_yaml::from_str(&data)
+            .with_context(|| format!("Invalid workspace {}", path.display()))?;
+
+        if workspace.projects.is_empty() {
+            anyhow::bail!("Workspace {} lists no project", path.display());
+        }
+
+        let mut names = BTreeSet::new();
+        for project in workspace.projects.iter() {
+            if !names.insert(project.name.as_str()) {
+                anyhow::bail!(
+                    "Project {} is listed twice in workspace {}",
+                    project.name,
+                    path.display()
+                );
+            }
+        }
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        for project in workspace.projects.iter_mut() {
+            project.paths = project.paths.iter().map(|p| base.join(p)).collect();
+        }
+        workspace.global = workspace.global.iter().map(|p| base.join(p)).collect();
+
+        Ok(workspace)
+    }
+}
+
+impl Project {
+    fn import(&self, config: &Config) -> Vec<Entity> {
+        let entities = match self.format.as_deref() {
+            Some("k8s") => k8s::import_manifests(&self.paths, config),
+            Some("yarn") => yarn::import_specs(&self.paths),
+            format => import_inputs(&self.paths, format),
+        };
+
+        load_normalizations(config).apply(entities)
+    }
+}
+
+// Checks every project on its own, then the global policies once with the entities of all
+// projects. Conflicts a project already reported are not reported again by the global check.
+pub fn check(
+    path: &Path,
+    cycle_check: bool,
+    fast_fail: bool,
+    budget: &BudgetArgs,
+    tags: &TagArgs,
+    run: &RunOptions,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let workspace = Workspace::load(path)?;
+    let (suppressions, weights) = (load_suppressions(config), load_impact_weights(config));
+    let new_report = || {
+        ConflictReport::new(run.level)
+            .with_gates(run.gates.clone())
+            .with_suppressions(suppressions.clone(), today())
+            .with_impact_weights(weights)
+    };
+
+    let mut reports = vec![];
+    let mut all = vec![];
+    let mut reported = BTreeSet::new();
+
+    for project in workspace.projects.iter() {
+        info!("Checking project {}...", project.name);
+
+        let entities = tags.apply(project.import(config));
+        let mut report = new_report();
+
+        if let Some(domain_key) = project.domain_key.as_deref() {
+            let domains = util::split_by_metadata(
+                &entities,
+                domain_key,
+                &project.default_domain,
+                run.solver.strict,
+            )?;
+            for (domain, entities) in domains {
+                info!("Checking domain {} of project {}...", domain, project.name);
+
+                super::solve(
+                    entities,
+                    None,
+                    cycle_check,
+                    fast_fail,
+                    budget,
+                    run,
+                    &mut report,
+                );
+            }
+        } else {
+            super::solve(
+                entities.clone(),
+                None,
+                cycle_check,
+                fast_fail,
+                budget,
+                run,
+                &mut report,
+            );
+        }
+
+        reported.extend(report.conflicting_entities().iter().cloned());
+        all.extend(entities);
+        reports.push((project.name.clone(), report));
+    }
+
+    if !workspace.global.is_empty() {
+        info!(
+            "Checking global policies across {} projects...",
+            workspace.projects.len()
+        );
+
+        let global = load_normalizations(config).apply(import_inputs(&workspace.global, None));
+        let global = tags.apply(global);
+        let entities = merge_entities(all.into_iter().chain(global).collect(), None);
+
+        let mut report = new_report().with_reported(reported);
+        super::solve(
+            entities,
+            None,
+            cycle_check,
+            fast_fail,
+            budget,
+            run,
+            &mut report,
+        );
+        reports.push(("global".to_string(), report));
+    }
+
+    let mut failed = 0;
+    for (name, report) in reports.iter() {
+        if report.has_conflict() || report.has_undetermined() {
+            failed += 1;
+            error!("{}: {}", name, report.totals());
+        } else {
+            info!("{}: {}", name, report.totals());
+        }
+    }
+
+    let summary = format!(
+        "deployfix summary: {} of {} checks failed in workspace {}",
+        failed,
+        reports.len(),
+        path.display()
+    );
+    if failed > 0 {
+        error!("{}", summary);
+    } else {
+        info!("{}", summary);
+    }
+
+    Ok(())
+}
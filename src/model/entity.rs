@@ -1,11 +1,12 @@
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
     path::PathBuf,
 };
 
+use log::info;
 use serde::{Deserialize, Serialize};
 
-use super::rule::EntityRule;
+use super::rule::{EntityRule, RuleDedupKey, METADATA_PROVENANCE_KEY};
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 #[serde(transparent)]
@@ -17,10 +18,15 @@ pub enum EntitySource {
     Unknown,
 }
 
+pub const CRITICAL_PRIORITY_VALUE: i32 = 1_000_000;
+
+// Ordered from the highest priority down
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum EntityPriority {
     Critical,
+    High,
     Default,
+    Low,
 }
 
 impl Default for EntityPriority {
@@ -33,6 +39,8 @@ impl From<&str> for EntityPriority {
     fn from(val: &str) -> Self {
         match val {
             "critical" => Self::Critical,
+            "high" => Self::High,
+            "low" => Self::Low,
             _ => Self::Default,
         }
     }
@@ -42,7 +50,20 @@ impl EntityPriority {
     pub fn as_str(&self) -> &str {
         match self {
             Self::Critical => "critical",
+            Self::High => "high",
             Self::Default => "default",
+            Self::Low => "low",
+        }
+    }
+
+    // Tier of a k8s PriorityClass value: system classes and values from 1,000,000 up are
+    // critical, other positive values high and negative values low
+    pub fn from_value(value: i32) -> Self {
+        match value {
+            v if v >= CRITICAL_PRIORITY_VALUE => Self::Critical,
+            v if v > 0 => Self::High,
+            0 => Self::Default,
+            _ => Self::Low,
         }
     }
 
@@ -132,6 +153,22 @@ impl Entity {
         self.excludes.insert(rule);
     }
 
+    // Moves every rule `lines` lines and `bytes` bytes down its file
+    pub fn shift_rules(&mut self, lines: usize, bytes: usize) {
+        let shift = |rules: BTreeSet<EntityRule>| {
+            rules
+                .into_iter()
+                .map(|mut rule| {
+                    rule.shift(lines, bytes);
+                    rule
+                })
+                .collect()
+        };
+
+        self.requires = shift(std::mem::take(&mut self.requires));
+        self.excludes = shift(std::mem::take(&mut self.excludes));
+    }
+
     pub fn set_source(&mut self, source: EntitySource) {
         self.source = source;
     }
@@ -181,7 +218,12 @@ pub fn merge_entities(
             e.requires.extend(entity.requires);
             e.excludes.extend(entity.excludes);
 
-            if entity.source != e.source {
+            // Entities only named by rules of another manifest (e.g. a NetworkPolicy) have no
+            // source of their own, the declaring one wins
+            if e.source == EntitySource::Unknown && entity.source != EntitySource::Unknown {
+                e.source = entity.source;
+                e.priority = entity.priority;
+            } else if entity.source != e.source {
                 if let Some(merge_source) = merge_source {
                     merge_source(&mut e.source, entity.source);
                 }
@@ -191,7 +233,129 @@ pub fn merge_entities(
         }
     }
 
-    map.into_values().collect()
+    let (entities, duplicates) = dedup_entity_rules(map.into_values().collect());
+
+    for duplicate in duplicates.iter() {
+        info!(
+            "Collapsed duplicate rule `{}` of {} declared at {}",
+            duplicate.rule,
+            duplicate.entity.as_ref(),
+            duplicate.locations.join(", ")
+        );
+    }
+
+    entities
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DuplicateRule {
+    pub entity: EntityName,
+    pub rule: String,
+    pub locations: Vec<String>,
+}
+
+// `file:line` declaring the rule, from its rule source or else its metadata
+pub fn rule_location(rule: &EntityRule) -> Option<String> {
+    match (rule.file(), rule.line()) {
+        (Some(file), Some(line)) => Some(format!("{}:{}", file, line)),
+        _ => match (rule.meta_file(), rule.meta_line()) {
+            (Some(file), Some(line)) => Some(format!("{}:{}", file, line)),
+            _ => None,
+        },
+    }
+}
+
+fn dedup_rule_set(
+    name: &EntityName,
+    rules: BTreeSet<EntityRule>,
+    duplicates: &mut Vec<DuplicateRule>,
+) -> BTreeSet<EntityRule> {
+    let mut groups: BTreeMap<RuleDedupKey, Vec<EntityRule>> = BTreeMap::new();
+
+    for rule in rules {
+        groups.entry(rule.dedup_key()).or_default().push(rule);
+    }
+
+    groups
+        .into_values()
+        .map(|mut group| {
+            let mut survivor = group.remove(0);
+
+            if group.is_empty() {
+                return survivor;
+            }
+
+            // Keep every known declaration site, including the ones recorded by a previous merge
+            let locations = std::iter::once(&survivor)
+                .chain(group.iter())
+                .flat_map(|rule| {
+                    let mut locations = rule
+                        .metadata(METADATA_PROVENANCE_KEY)
+                        .map(|e| e.split(',').map(|e| e.to_string()).collect::<Vec<_>>())
+                        .unwrap_or_default();
+                    locations.extend(rule_location(rule));
+
+                    locations
+                })
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>();
+
+            survivor.add_metadata(METADATA_PROVENANCE_KEY.to_string(), locations.join(","));
+
+            duplicates.push(DuplicateRule {
+                entity: name.clone(),
+                rule: survivor.to_string(),
+                locations,
+            });
+
+            survivor
+        })
+        .collect()
+}
+
+// Collapses rules sharing the same (source, targets, type), which typically happens when
+// both a manifest and an IR file declare the same constraint. The surviving rule records
+// every declaration site in its `provenance` metadata.
+pub fn dedup_entity_rules(entities: Vec<Entity>) -> (Vec<Entity>, Vec<DuplicateRule>) {
+    let mut duplicates = vec![];
+
+    let entities = entities
+        .into_iter()
+        .map(|mut entity| {
+            let requires = std::mem::take(&mut entity.requires);
+            let excludes = std::mem::take(&mut entity.excludes);
+
+            entity.requires = dedup_rule_set(&entity.name, requires, &mut duplicates);
+            entity.excludes = dedup_rule_set(&entity.name, excludes, &mut duplicates);
+
+            entity
+        })
+        .collect();
+
+    (entities, duplicates)
+}
+
+// Keeps the rules with one of the `only` tags (every rule when empty) and none of the `skip`
+// tags. Entities left without rules are kept, they are still scheduled. Returns the number of
+// rules dropped.
+pub fn filter_rules_by_tags(entities: &mut [Entity], only: &[String], skip: &[String]) -> usize {
+    let selected = |rule: &EntityRule| {
+        let tags = rule.tags();
+
+        (only.is_empty() || tags.iter().any(|tag| only.iter().any(|t| t == tag)))
+            && !tags.iter().any(|tag| skip.iter().any(|t| t == tag))
+    };
+
+    let mut dropped = 0;
+    for entity in entities.iter_mut() {
+        let len = entity.rules_len();
+        entity.requires.retain(|rule| selected(rule));
+        entity.excludes.retain(|rule| selected(rule));
+        dropped += len - entity.rules_len();
+    }
+
+    dropped
 }
 
 impl Default for EntitySource {
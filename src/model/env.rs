@@ -1,9 +1,22 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+
+use crate::util::read_source;
 
 #[derive(Debug, thiserror::Error)]
 pub enum EnvParseError {
     #[error("Empty env data")]
     EmptyData,
+    #[error("Invalid env data: {0}")]
+    InvalidData(String),
+    #[error("Failed to read env file {0}: {1}")]
+    Read(String, String),
+    #[error("Env file {0} extends itself")]
+    CyclicExtends(String),
 }
 
 #[derive(Debug, Clone)]
@@ -11,6 +24,18 @@ pub struct Env {
     pub name: String,
     pub labels: Vec<String>,
     pub duplicate_names: Vec<String>,
+    // Number of nodes of the names declared as node pools, the other names are single nodes
+    pub pool_sizes: HashMap<String, usize>,
+}
+
+impl Env {
+    // Number of nodes with the labels of this env
+    pub fn capacity(&self) -> usize {
+        std::iter::once(&self.name)
+            .chain(self.duplicate_names.iter())
+            .map(|name| self.pool_sizes.get(name).copied().unwrap_or(1))
+            .sum()
+    }
 }
 
 pub trait EnvParser {
@@ -19,64 +44,200 @@ pub trait EnvParser {
 
 pub struct DefaultEnvParser {}
 
+// Key of the lines pulling in another env file
+const EXTENDS_KEY: &str = "extends:";
+// Key of the lines declaring a pool of identical nodes, `pool: name count labels`
+const POOL_KEY: &str = "pool:";
+
+// Labels and pool size of a node, `None` for a single node
+type Node = (Vec<String>, Option<usize>);
+
+fn parse_labels(labels: Option<&str>) -> Vec<String> {
+    let mut labels: Vec<String> = labels
+        .unwrap_or_default()
+        .split(';')
+        .filter_map(|s| {
+            if s.is_empty() {
+                None
+            } else {
+                Some(s.to_string())
+            }
+        })
+        .collect();
+    labels.sort();
+
+    labels
+}
+
+// Splits env data into the files it extends and the nodes it defines, in order
+fn parse_nodes(data: &str) -> Result<(Vec<String>, Vec<(String, Node)>), EnvParseError> {
+    let mut extends = vec![];
+    let mut nodes = vec![];
+
+    for line in data.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        if let Some(path) = line.trim().strip_prefix(EXTENDS_KEY) {
+            extends.push(path.trim().to_string());
+            continue;
+        }
+
+        if let Some(pool) = line.trim().strip_prefix(POOL_KEY) {
+            let parts = pool.split_whitespace().collect::<Vec<_>>();
+            let count = parts
+                .get(1)
+                .and_then(|count| count.parse::<usize>().ok())
+                .filter(|count| *count > 0)
+                .ok_or_else(|| {
+                    EnvParseError::InvalidData(format!(
+                        "Invalid pool `{}`, expected `{} name count labels`",
+                        line.trim(),
+                        POOL_KEY
+                    ))
+                })?;
+
+            nodes.push((
+                parts[0].to_string(),
+                (parse_labels(parts.get(2).copied()), Some(count)),
+            ));
+            continue;
+        }
+
+        let parts = line.split_whitespace().collect::<Vec<_>>();
+        let env_name = parts[0].to_string();
+
+        nodes.push((env_name, (parse_labels(parts.get(1).copied()), None)));
+    }
+
+    Ok((extends, nodes))
+}
+
 impl EnvParser for DefaultEnvParser {
     // format:
+    // extends: base.env
     // env_name app=app1;app=app2;app=app3;node=high-performance-node;
+    // pool: pool_name 8 node=gpu;zone=a;
     fn parse(&self, data: &str) -> Result<Vec<Env>, EnvParseError> {
-        let envs = data
-            .lines()
-            .filter_map(|line| {
-                if line.is_empty() {
-                    return None;
-                }
-
-                let parts = line.split_whitespace().collect::<Vec<_>>();
-                let env_name = parts[0].to_string();
-
-                let labels = if parts.len() < 2 {
-                    vec![]
-                } else {
-                    let mut labels: Vec<String> = parts[1]
-                        .split(';')
-                        .filter_map(|s| {
-                            if s.is_empty() {
-                                None
-                            } else {
-                                Some(s.to_string())
-                            }
-                        })
-                        .collect();
-                    labels.sort();
-
-                    labels
-                };
-
-                Some((env_name, labels))
-            })
-            .collect::<HashMap<String, Vec<String>>>();
-
-        // group by label groups
-        let mut seen_envs: HashMap<Vec<String>, Env> = HashMap::new();
-
-        for (name, labels) in envs {
-            if seen_envs.contains_key(&labels) {
-                let env = seen_envs.get_mut(&labels).unwrap();
-                env.duplicate_names.push(name);
-            } else {
-                let env = Env {
-                    name,
-                    labels: labels.clone(),
-                    duplicate_names: vec![],
-                };
-                seen_envs.insert(labels, env);
-            }
+        let (extends, nodes) = parse_nodes(data)?;
+
+        if !extends.is_empty() {
+            warn!(
+                "Ignoring `{}` {:?}, only env files can extend others",
+                EXTENDS_KEY, extends
+            );
         }
 
-        let envs: Vec<Env> = seen_envs.into_iter().map(|(_, v)| v).collect();
-        if envs.is_empty() {
-            return Err(EnvParseError::EmptyData);
+        group_node_pools(nodes.into_iter().collect())
+    }
+}
+
+// Reads the env files in order, a node defined again by a later file replaces the earlier
+// definition. The files named by `extends:` lines, relative to the extending file, are read
+// before the nodes of the extending file.
+pub fn load_env_files(paths: &[PathBuf]) -> Result<Vec<Env>, EnvParseError> {
+    let mut nodes = HashMap::new();
+
+    for path in paths {
+        load_env_file(path, &mut vec![], &mut nodes)?;
+    }
+
+    group_node_pools(nodes)
+}
+
+fn load_env_file(
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    nodes: &mut HashMap<String, Node>,
+) -> Result<(), EnvParseError> {
+    let data = read_source(path)
+        .map_err(|e| EnvParseError::Read(path.display().to_string(), e.to_string()))?;
+
+    let canonical = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    if stack.contains(&canonical) {
+        return Err(EnvParseError::CyclicExtends(path.display().to_string()));
+    }
+
+    let (extends, own_nodes) = parse_nodes(&data)?;
+
+    stack.push(canonical);
+    for parent in extends {
+        let parent = match path.parent() {
+            Some(dir) => dir.join(parent),
+            None => PathBuf::from(parent),
+        };
+
+        load_env_file(&parent, stack, nodes)?;
+    }
+    stack.pop();
+
+    for (name, node) in own_nodes {
+        if nodes.insert(name.clone(), node).is_some() {
+            debug!("Env {} overridden by {}", name, path.display());
         }
+    }
 
-        Ok(envs)
+    Ok(())
+}
+
+// Merges nodes with the same (sorted) labels into a single env
+pub fn group_envs(envs: HashMap<String, Vec<String>>) -> Result<Vec<Env>, EnvParseError> {
+    group_node_pools(
+        envs.into_iter()
+            .map(|(name, labels)| (name, (labels, None)))
+            .collect(),
+    )
+}
+
+fn group_node_pools(nodes: HashMap<String, Node>) -> Result<Vec<Env>, EnvParseError> {
+    // group by label groups
+    let mut seen_envs: HashMap<Vec<String>, Env> = HashMap::new();
+
+    for (name, (labels, pool_size)) in nodes {
+        let env = seen_envs.entry(labels.clone()).or_insert_with(|| Env {
+            name: name.clone(),
+            labels,
+            duplicate_names: vec![],
+            pool_sizes: HashMap::new(),
+        });
+
+        if env.name != name {
+            env.duplicate_names.push(name.clone());
+        }
+        if let Some(pool_size) = pool_size {
+            env.pool_sizes.insert(name, pool_size);
+        }
     }
+
+    let envs: Vec<Env> = seen_envs.into_iter().map(|(_, v)| v).collect();
+    if envs.is_empty() {
+        return Err(EnvParseError::EmptyData);
+    }
+
+    Ok(envs)
+}
+
+// Writes envs back in the format read by `DefaultEnvParser`, one line per node
+pub fn format_envs(envs: &[Env]) -> String {
+    let mut lines = envs
+        .iter()
+        .flat_map(|env| {
+            let labels = env
+                .labels
+                .iter()
+                .map(|l| format!("{};", l))
+                .collect::<String>();
+
+            std::iter::once(&env.name)
+                .chain(env.duplicate_names.iter())
+                .map(move |name| match env.pool_sizes.get(name) {
+                    Some(size) => format!("{} {} {} {}", POOL_KEY, name, size, labels),
+                    None => format!("{} {}", name, labels),
+                })
+        })
+        .collect::<Vec<_>>();
+    lines.sort();
+
+    lines.join("\n") + "\n"
 }
@@ -1,6 +1,81 @@
 use std::fmt::Display;
 
-use super::{Entity, EntityRule, EntityRuleMetadata};
+use thiserror::Error;
+
+use super::{Entity, EntityRule, EntityRuleMetadata, METADATA_FILE_KEY, METADATA_LINE_KEY};
+
+#[derive(Debug, Error)]
+pub enum FormatterError {
+    #[error("Rule `{0}` does not survive a round-trip through the IR: {1}")]
+    NotRoundTrippable(String, String),
+}
+
+// File and line first, then the other keys in order
+fn metadata_entries(metadata: &EntityRuleMetadata) -> Vec<(&str, String)> {
+    let mut entries = vec![];
+
+    if let Some(file) = metadata.file() {
+        entries.push((METADATA_FILE_KEY, file.to_string()));
+    }
+    if let Some(line) = metadata.line() {
+        entries.push((METADATA_LINE_KEY, line.to_string()));
+    }
+    if let Some(map) = metadata.get_metadata() {
+        entries.extend(map.iter().map(|(k, v)| (k.as_str(), v.clone())));
+    }
+
+    entries
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("empty entity name".to_string());
+    }
+
+    // `group NAME = ...` lines declare colocation groups
+    if name == "group" {
+        return Err("`group` is a keyword of the IR".to_string());
+    }
+
+    if name.contains(|ch: char| ch.is_whitespace() || ch == ',' || ch == ';') || name.contains("//")
+    {
+        return Err(format!("entity name `{}` contains a separator", name));
+    }
+
+    Ok(())
+}
+
+fn check_metadata(metadata: &EntityRuleMetadata) -> Result<(), String> {
+    let entries = metadata_entries(metadata);
+    if entries.is_empty() {
+        return Err("empty metadata is read back as no metadata".to_string());
+    }
+
+    for (key, value) in entries.iter() {
+        if key.is_empty() || key.contains(['=', ';']) || key.starts_with(char::is_whitespace) {
+            return Err(format!("invalid metadata key `{}`", key));
+        }
+
+        if value.contains(';') || value.starts_with(char::is_whitespace) {
+            return Err(format!(
+                "invalid value `{}` of metadata key `{}`",
+                value, key
+            ));
+        }
+    }
+
+    if let Some(map) = metadata.get_metadata() {
+        for key in map.keys() {
+            if key.eq_ignore_ascii_case(METADATA_FILE_KEY)
+                || key.eq_ignore_ascii_case(METADATA_LINE_KEY)
+            {
+                return Err(format!("metadata key `{}` is reserved", key));
+            }
+        }
+    }
+
+    Ok(())
+}
 
 pub struct DeployIRFormatter<'a>(&'a Vec<Entity>);
 
@@ -30,17 +105,16 @@ impl<'a> DeployIRFormatter<'a> {
         metadata: &EntityRuleMetadata,
         f: &mut std::fmt::Formatter<'_>,
     ) -> std::fmt::Result {
-        write!(
-            f,
-            "// File={};Line={};",
-            metadata.file().unwrap_or("unknown"),
-            metadata.line().unwrap_or(0)
-        )?;
-
-        if let Some(metadata) = metadata.get_metadata() {
-            for (key, value) in metadata.iter() {
-                write!(f, "{}={};", key, value)?;
-            }
+        let entries = metadata_entries(metadata);
+
+        // Nothing to write, the parser reads a bare rule back as a rule without metadata
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        write!(f, "// ")?;
+        for (key, value) in entries {
+            write!(f, "{}={};", key, value)?;
         }
 
         Ok(())
@@ -119,4 +193,41 @@ impl<'a> DeployIRFormatter<'a> {
 
         format!("{}", formatter)
     }
+
+    // Checks that parsing the formatted rule gives the same rule back, up to its rule source
+    pub fn check_round_trip(rule: &EntityRule) -> Result<(), FormatterError> {
+        let fail = |reason: String| FormatterError::NotRoundTrippable(rule.to_string(), reason);
+
+        check_name(rule.source().as_ref()).map_err(fail)?;
+
+        match rule {
+            EntityRule::Mono { target, .. } => check_name(target.as_ref()).map_err(fail)?,
+            EntityRule::Multi { targets, .. } => {
+                if targets.len() < 2 {
+                    return Err(fail(
+                        "a single target is read back as a mono rule".to_string(),
+                    ));
+                }
+
+                for target in targets {
+                    check_name(target.as_ref()).map_err(fail)?;
+                }
+            }
+        }
+
+        if let Some(metadata) = rule.metadata_ref() {
+            check_metadata(metadata).map_err(fail)?;
+        }
+
+        Ok(())
+    }
+
+    // Strict mode: fails instead of writing rules which would be read back differently
+    pub fn format_strict(entities: &'a Vec<Entity>) -> Result<String, FormatterError> {
+        for rule in entities.iter().flat_map(Entity::rules) {
+            Self::check_round_trip(rule)?;
+        }
+
+        Ok(Self::format(entities))
+    }
 }
@@ -0,0 +1,194 @@
+use std::{collections::BTreeSet, fmt::Display};
+
+use log::warn;
+use serde::Serialize;
+
+use super::{
+    lint::rule_text, rule_location, Entity, EntityRule, EntityRuleType, METADATA_LAYER_KEY,
+};
+
+/*
+    Layered IR inputs, e.g. a base policy shared by all teams and the overrides of one team.
+    Inputs are stacked in the order they are given and a later layer can change the rules of
+    the earlier ones with a directive in front of a rule:
+
+    override app=web require app=cache   // every earlier `app=web require` rule is replaced
+    remove app=web exclude app=batch     // the earlier `app=web exclude app=batch` is dropped
+    remove app=web exclude *             // every earlier `app=web exclude` rule is dropped
+
+    Directives never apply to the rules of their own layer.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LayerDirective {
+    Override,
+    Remove,
+}
+
+impl LayerDirective {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "override" => Some(Self::Override),
+            "remove" => Some(Self::Remove),
+            _ => None,
+        }
+    }
+
+    // Whether the directive `rule` applies to `other`, a rule of an earlier layer
+    fn matches(&self, rule: &EntityRule, other: &EntityRule) -> bool {
+        if other.source() != rule.source() || other.r#type() != rule.r#type() {
+            return false;
+        }
+
+        match self {
+            Self::Override => true,
+            Self::Remove => rule.is_in_target("*") || other.targets() == rule.targets(),
+        }
+    }
+}
+
+impl Display for LayerDirective {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Override => write!(f, "override"),
+            Self::Remove => write!(f, "remove"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LayeredRule {
+    pub rule: String,
+    pub location: Option<String>,
+}
+
+impl From<&EntityRule> for LayeredRule {
+    fn from(rule: &EntityRule) -> Self {
+        Self {
+            rule: rule_text(rule),
+            location: rule_location(rule),
+        }
+    }
+}
+
+// A directive of a later layer and the rules of the earlier layers it replaced or removed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LayerChange {
+    pub directive: LayerDirective,
+    pub rule: LayeredRule,
+    pub replaced: Vec<LayeredRule>,
+}
+
+impl Display for LayerChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} `{}`",
+            self.rule.location.as_deref().unwrap_or("unknown"),
+            self.directive,
+            self.rule.rule
+        )?;
+
+        if self.replaced.is_empty() {
+            return write!(f, " matches no rule of an earlier layer");
+        }
+
+        let replaced = self
+            .replaced
+            .iter()
+            .map(|r| {
+                format!(
+                    "`{}` ({})",
+                    r.rule,
+                    r.location.as_deref().unwrap_or("unknown")
+                )
+            })
+            .collect::<Vec<_>>();
+
+        match self.directive {
+            LayerDirective::Override => write!(f, " replaces {}", replaced.join(", ")),
+            LayerDirective::Remove => write!(f, " removes {}", replaced.join(", ")),
+        }
+    }
+}
+
+fn rules_of(entity: &mut Entity, r#type: EntityRuleType) -> &mut BTreeSet<EntityRule> {
+    match r#type {
+        EntityRuleType::Require => &mut entity.requires,
+        EntityRuleType::Exclude => &mut entity.excludes,
+    }
+}
+
+// Stacks the entities of each input in order, applying the directives of every layer to the
+// layers before it. The entities are returned unmerged, with the changes in directive order.
+pub fn apply_layers(layers: Vec<Vec<Entity>>) -> (Vec<Entity>, Vec<LayerChange>) {
+    let mut stacked: Vec<Entity> = vec![];
+    let mut changes = vec![];
+
+    for mut layer in layers {
+        let mut directives = vec![];
+        for (idx, entity) in layer.iter_mut().enumerate() {
+            for r#type in [EntityRuleType::Require, EntityRuleType::Exclude] {
+                let rules = rules_of(entity, r#type);
+                let (found, rest) = std::mem::take(rules)
+                    .into_iter()
+                    .partition::<BTreeSet<_>, _>(|rule| rule.layer().is_some());
+
+                *rules = rest;
+                directives.extend(found.into_iter().map(|rule| (idx, rule)));
+            }
+        }
+        directives.sort_by_key(|(_, rule)| rule.line());
+
+        // Entities only named by directives are not declared by this layer
+        let declared = layer
+            .iter()
+            .map(|entity| entity.rules_len() > 0)
+            .collect::<Vec<_>>();
+
+        for (idx, mut rule) in directives {
+            let value = rule.remove_metadata(METADATA_LAYER_KEY).unwrap_or_default();
+            let Some(directive) = LayerDirective::parse(&value) else {
+                warn!(
+                    "Ignoring unknown layer directive `{}` of `{}`",
+                    value,
+                    rule_text(&rule)
+                );
+                rules_of(&mut layer[idx], rule.r#type()).insert(rule);
+                continue;
+            };
+
+            let mut replaced = vec![];
+            for earlier in stacked.iter_mut().filter(|e| &e.name == rule.source()) {
+                rules_of(earlier, rule.r#type()).retain(|other| {
+                    if directive.matches(&rule, other) {
+                        replaced.push(LayeredRule::from(other));
+                        false
+                    } else {
+                        true
+                    }
+                });
+            }
+
+            changes.push(LayerChange {
+                directive,
+                rule: LayeredRule::from(&rule),
+                replaced,
+            });
+
+            if directive == LayerDirective::Override {
+                rules_of(&mut layer[idx], rule.r#type()).insert(rule);
+            }
+        }
+
+        stacked.extend(
+            layer
+                .into_iter()
+                .zip(declared)
+                .filter(|(entity, declared)| *declared || entity.rules_len() > 0)
+                .map(|(entity, _)| entity),
+        );
+    }
+
+    (stacked, changes)
+}
@@ -0,0 +1,185 @@
+use std::{collections::BTreeSet, fmt::Display};
+
+use serde::Serialize;
+
+use super::{rule_location, Entity, EntityName, EntityRule, METADATA_PROVENANCE_KEY};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LintKind {
+    // The entity requires and excludes the same target, a conflict the solvers will report
+    Contradictory,
+    // The same rule is declared in more than one place
+    Redundant,
+    // A multi require whose alternatives include those of another require of the entity
+    Subsumed,
+    // A require of the entity itself, always satisfied
+    SelfRequire,
+}
+
+impl Display for LintKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Contradictory => write!(f, "contradictory"),
+            Self::Redundant => write!(f, "redundant"),
+            Self::Subsumed => write!(f, "subsumed"),
+            Self::SelfRequire => write!(f, "self-require"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct LintFinding {
+    pub entity: EntityName,
+    pub kind: LintKind,
+    pub rule: String,
+    pub location: Option<String>,
+    // How to fix it
+    pub hint: String,
+}
+
+impl Display for LintFinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} rule `{}`\n  hint: {}",
+            self.location.as_deref().unwrap_or("unknown"),
+            self.kind,
+            self.rule,
+            self.hint
+        )
+    }
+}
+
+// The rule as written in the IR, without its metadata
+pub(crate) fn rule_text(rule: &EntityRule) -> String {
+    let targets = rule
+        .targets()
+        .into_iter()
+        .map(|t| t.as_ref())
+        .collect::<Vec<_>>();
+
+    format!(
+        "{} {} {}",
+        rule.source().as_ref(),
+        rule.r#type(),
+        targets.join(", ")
+    )
+}
+
+fn finding(entity: &Entity, kind: LintKind, rule: &EntityRule, hint: String) -> LintFinding {
+    LintFinding {
+        entity: entity.name.clone(),
+        kind,
+        rule: rule_text(rule),
+        location: rule_location(rule),
+        hint,
+    }
+}
+
+fn lint_entity(entity: &Entity, findings: &mut Vec<LintFinding>) {
+    let name = entity.name.as_ref();
+
+    for rule in entity.rules() {
+        // Duplicates were collapsed by the merge, which recorded every declaration site
+        let locations = rule
+            .metadata(METADATA_PROVENANCE_KEY)
+            .map(|locations| locations.split(',').collect::<Vec<_>>())
+            .unwrap_or_default();
+        if locations.len() > 1 {
+            findings.push(finding(
+                entity,
+                LintKind::Redundant,
+                rule,
+                format!(
+                    "declared at {}, keep one and remove the others",
+                    locations.join(", ")
+                ),
+            ));
+        }
+    }
+
+    for rule in entity.requires.iter() {
+        if let EntityRule::Mono { target, .. } = rule {
+            let excluded = entity.excludes.iter().find(
+                |exclude| matches!(exclude, EntityRule::Mono { target: t, .. } if t == target),
+            );
+            if let Some(exclude) = excluded {
+                findings.push(finding(
+                    entity,
+                    LintKind::Contradictory,
+                    rule,
+                    format!(
+                        "`{}` excludes the same target, keep only one of them",
+                        rule_text(exclude)
+                    ),
+                ));
+                continue;
+            }
+        }
+
+        if rule.is_in_target(name) {
+            let hint = match rule {
+                EntityRule::Mono { .. } => "remove it, an entity is always placed with itself",
+                EntityRule::Multi { .. } => {
+                    "remove the rule, the entity itself is one of its alternatives"
+                }
+            };
+            findings.push(finding(
+                entity,
+                LintKind::SelfRequire,
+                rule,
+                hint.to_string(),
+            ));
+            continue;
+        }
+
+        let targets = rule
+            .targets()
+            .into_iter()
+            .map(|t| t.as_ref())
+            .collect::<BTreeSet<_>>();
+
+        // The rule with the fewest alternatives is reported once for the whole set
+        let narrower = entity
+            .requires
+            .iter()
+            .filter(|other| !other.is_in_target(name))
+            .map(|other| {
+                (
+                    other,
+                    other
+                        .targets()
+                        .into_iter()
+                        .map(|t| t.as_ref())
+                        .collect::<BTreeSet<_>>(),
+                )
+            })
+            .filter(|(_, other)| other.len() < targets.len() && other.is_subset(&targets))
+            .min_by_key(|(_, other)| other.len());
+        if let Some((other, _)) = narrower {
+            findings.push(finding(
+                entity,
+                LintKind::Subsumed,
+                rule,
+                format!(
+                    "remove it, `{}` already requires a subset of its targets",
+                    rule_text(other)
+                ),
+            ));
+        }
+    }
+}
+
+// Flags rules which are contradictory, redundant or always satisfied, cheaply and before any
+// solver runs. `entities` are expected to be merged, which collapses duplicate rules.
+pub fn lint_model(entities: &[Entity]) -> Vec<LintFinding> {
+    let mut findings = vec![];
+
+    for entity in entities {
+        lint_entity(entity, &mut findings);
+    }
+    findings.sort();
+
+    findings
+}
@@ -1,13 +1,36 @@
 mod entity;
 mod env;
 mod formatter;
+mod layer;
+mod lint;
 mod parser;
 mod rule;
+mod selector;
 mod topology;
+mod window;
 
-pub use entity::{merge_entities, Entity, EntityName, EntityPriority, EntitySource};
-pub use env::{DefaultEnvParser, Env, EnvParseError, EnvParser};
-pub use formatter::DeployIRFormatter;
-pub use parser::get_parser;
-pub use rule::{EntityRule, EntityRuleMetadata, EntityRuleSource, EntityRuleType};
+pub use entity::{
+    dedup_entity_rules, filter_rules_by_tags, merge_entities, rule_location, DuplicateRule, Entity,
+    EntityName, EntityPriority, EntitySource, CRITICAL_PRIORITY_VALUE,
+};
+pub use env::{
+    format_envs, group_envs, load_env_files, DefaultEnvParser, Env, EnvParseError, EnvParser,
+};
+pub use formatter::{DeployIRFormatter, FormatterError};
+pub use layer::{apply_layers, LayerChange, LayerDirective, LayeredRule};
+pub(crate) use lint::rule_text;
+pub use lint::{lint_model, LintFinding, LintKind};
+pub use parser::{get_parser, ParserError};
+pub use rule::{
+    EntityRule, EntityRuleMetadata, EntityRuleSource, EntityRuleType, RuleDedupKey, RuleLifecycle,
+    SourceSpan, METADATA_ANNOTATION_KEY, METADATA_DESC_KEY, METADATA_FILE_KEY,
+    METADATA_GENERATED_KEY, METADATA_GROUP_KEY, METADATA_HINT_KEY, METADATA_LAYER_KEY,
+    METADATA_LIFECYCLE_KEY, METADATA_LINE_KEY, METADATA_MAX_REPLICAS_KEY,
+    METADATA_MAX_UNAVAILABLE_KEY, METADATA_MIN_AVAILABLE_KEY, METADATA_MIN_REPLICAS_KEY,
+    METADATA_NAMESPACE_KEY, METADATA_OWNER_KEY, METADATA_POD_KEY, METADATA_POD_QUOTA_KEY,
+    METADATA_PROVENANCE_KEY, METADATA_REPLICAS_KEY, METADATA_SCALE_OUT_KEY, METADATA_SCOPE_KEY,
+    METADATA_TAGS_KEY, METADATA_WEIGHT_KEY, METADATA_WINDOW_KEY,
+};
+pub use selector::{LabelRequirement, Selector, SelectorOperator};
 pub use topology::{EntityRuleTopologyKey, METADATA_TOPOLOGY_KEY};
+pub use window::TimeWindows;
@@ -3,11 +3,11 @@ use std::{
     num::NonZeroUsize,
 };
 
-use log::error;
+use log::{error, warn};
 use nom::{
     branch::alt,
     bytes::complete::{tag, take_until, take_while},
-    character::complete::{char, multispace0},
+    character::complete::{char, multispace0, multispace1},
     combinator::{map, opt},
     multi::{separated_list0, separated_list1},
     sequence::{delimited, preceded, tuple},
@@ -19,7 +19,7 @@ use crate::util;
 
 use super::{
     Entity, EntityName, EntityRule, EntityRuleMetadata, EntityRuleSource, EntityRuleType,
-    EntitySource,
+    EntitySource, METADATA_FILE_KEY, METADATA_GROUP_KEY, METADATA_LAYER_KEY, METADATA_LINE_KEY,
 };
 
 #[derive(Debug, Error)]
@@ -32,6 +32,8 @@ pub enum ParserError {
     YamlError(#[from] serde_yaml::Error),
     #[error("DeployIR error: {0}")]
     DeployIRError(String),
+    #[error("CSV error: {0}")]
+    CsvError(String),
     #[error("Unknown error: {0}")]
     CustomError(String),
 }
@@ -44,6 +46,7 @@ pub struct JsonParser;
 pub struct YamlParser;
 pub struct DeployIRParser;
 pub struct NomDeployIRParser;
+pub struct CsvParser;
 
 impl JsonParser {
     pub fn new() -> Self {
@@ -108,19 +111,13 @@ impl DeployIRParser {
             })
             .collect::<BTreeMap<String, String>>();
 
-        let file = map
-            .get("File")
-            .map(|e| e.to_string())
+        let file = take_metadata_key(&mut map, METADATA_FILE_KEY)
             .or_else(|| Some(default_file.to_string()));
 
-        let line = map
-            .get("Line")
-            .map(|e| e.parse().unwrap())
+        let line = take_metadata_key(&mut map, METADATA_LINE_KEY)
+            .and_then(|e| parse_metadata_line(&e))
             .or_else(|| NonZeroUsize::new(default_line));
 
-        map.remove("File");
-        map.remove("Line");
-
         if !map.is_empty() {
             Ok(EntityRuleMetadata::new(file, line, Some(map)))
         } else {
@@ -224,11 +221,31 @@ impl Parser for DeployIRParser {
     }
 }
 
+// Removes `key` from the metadata, ignoring its case: older IR files use `file`/`line`
+fn take_metadata_key(metadata: &mut BTreeMap<String, String>, key: &str) -> Option<String> {
+    let found = metadata
+        .keys()
+        .find(|k| k.eq_ignore_ascii_case(key))
+        .cloned()?;
+
+    metadata.remove(&found)
+}
+
+fn parse_metadata_line(value: &str) -> Option<NonZeroUsize> {
+    let line = value.parse().ok();
+    if line.is_none() {
+        warn!("Ignoring invalid line number `{}` in rule metadata", value);
+    }
+
+    line
+}
+
 pub fn get_parser(format: &str) -> Result<Box<dyn Parser>, ParserError> {
     match format {
         "json" => Ok(Box::new(JsonParser::new())),
         "yaml" => Ok(Box::new(YamlParser::new())),
         "deployfix" => Ok(Box::new(NomDeployIRParser::new())),
+        "csv" => Ok(Box::new(CsvParser::new())),
         _ => Err(ParserError::CustomError(format!(
             "Unknown format: {}",
             format
@@ -272,6 +289,45 @@ impl NomDeployIRParser {
         Ok((rest, names.into_iter().collect()))
     }
 
+    fn parse_label_value(line: &str) -> IResult<&str, String> {
+        let (rest, value) = preceded(
+            multispace0,
+            take_while(|ch: char| ch != ',' && ch != ')' && !ch.is_whitespace()),
+        )(line)?;
+
+        let (rest, _) = multispace0(rest)?;
+
+        Ok((rest, value.to_string()))
+    }
+
+    // `key in (v1, v2)` or `key notin (v1, v2)`, mirroring Kubernetes matchExpressions.
+    // Targets are expanded into `key=value` names like the ones imported from K8s.
+    fn parse_label_expression(line: &str) -> IResult<&str, (bool, String, BTreeSet<String>)> {
+        let (rest, (key, _, inverse, values)) = tuple((
+            preceded(
+                multispace0,
+                take_while(|ch: char| ch != ',' && !ch.is_whitespace()),
+            ),
+            multispace1,
+            alt((map(tag("notin"), |_| true), map(tag("in"), |_| false))),
+            preceded(
+                multispace0,
+                delimited(
+                    char('('),
+                    separated_list1(char(','), Self::parse_label_value),
+                    char(')'),
+                ),
+            ),
+        ))(line)?;
+
+        let targets = values
+            .into_iter()
+            .map(|value| format!("{}={}", key, value))
+            .collect();
+
+        Ok((rest, (inverse, key.to_string(), targets)))
+    }
+
     fn parse_metadata_entry(line: &str) -> IResult<&str, (String, String)> {
         let (rest, (key, _, value)) = tuple((
             preceded(multispace0, take_until("=")),
@@ -303,8 +359,9 @@ impl NomDeployIRParser {
             None => return Ok((rest, None)),
         };
 
-        let file = metadata.remove("file").map(|e| e.to_string());
-        let line = metadata.remove("line").map(|e| e.parse().unwrap());
+        let file = take_metadata_key(&mut metadata, METADATA_FILE_KEY);
+        let line = take_metadata_key(&mut metadata, METADATA_LINE_KEY)
+            .and_then(|e| parse_metadata_line(&e));
 
         let map = if metadata.is_empty() {
             None
@@ -326,13 +383,37 @@ impl NomDeployIRParser {
         source: &EntitySource,
         line_num: usize,
     ) -> IResult<&'a str, EntityRule> {
-        let (rest, (name, op, target, metadata)) = tuple((
+        let (rest, (name, mut op, (expression, target), mut metadata)) = tuple((
             preceded(multispace0, Self::parse_entity_name),
             preceded(multispace0, Self::parse_op),
-            preceded(multispace0, Self::parse_target_entities),
+            preceded(
+                multispace0,
+                alt((
+                    map(Self::parse_label_expression, |(inverse, key, targets)| {
+                        (Some((inverse, key)), targets)
+                    }),
+                    map(Self::parse_target_entities, |targets| (None, targets)),
+                )),
+            ),
             preceded(multispace0, Self::parse_metadata),
         ))(line)?;
 
+        // Same as K8s import, `notin` is turned into the inverse rule type with an `In` operator
+        if let Some((inverse, key)) = expression {
+            let metadata =
+                metadata.get_or_insert_with(|| EntityRuleMetadata::new(None, None, None));
+            metadata.add_metadata("key".into(), key);
+            metadata.add_metadata("operator".into(), "In".into());
+
+            if inverse {
+                metadata.add_metadata("inverse".into(), "true".into());
+                op = match op {
+                    EntityRuleType::Require => EntityRuleType::Exclude,
+                    EntityRuleType::Exclude => EntityRuleType::Require,
+                };
+            }
+        }
+
         let source = EntityRuleSource::File(source.as_ref().to_string(), line_num);
         let rule = match target.len() {
             0 => unreachable!(),
@@ -349,6 +430,77 @@ impl NomDeployIRParser {
 
         Ok((rest, rule))
     }
+
+    // `group payments = app=api, app=db, app=cache` expands to pairwise require rules between
+    // all members, each tagged with the group name
+    fn parse_group<'a>(
+        line: &'a str,
+        source: &EntitySource,
+        line_num: usize,
+    ) -> IResult<&'a str, Vec<EntityRule>> {
+        let (rest, (_, group, _, members, metadata)) = tuple((
+            preceded(multispace0, tag("group")),
+            preceded(
+                multispace1,
+                take_while(|ch: char| ch != '=' && !ch.is_whitespace()),
+            ),
+            preceded(multispace0, char('=')),
+            preceded(multispace0, Self::parse_target_entities),
+            preceded(multispace0, Self::parse_metadata),
+        ))(line)?;
+
+        let mut metadata = metadata.unwrap_or_else(|| EntityRuleMetadata::new(None, None, None));
+        metadata.add_metadata(METADATA_GROUP_KEY.into(), group.to_string());
+
+        let source = EntityRuleSource::File(source.as_ref().to_string(), line_num);
+        let rules = members
+            .iter()
+            .flat_map(|a| members.iter().filter(move |b| *b != a).map(move |b| (a, b)))
+            .map(|(a, b)| {
+                EntityRule::mono(
+                    EntityName(a.clone()),
+                    EntityName(b.clone()),
+                    EntityRuleType::Require,
+                    source.clone(),
+                    Some(metadata.clone()),
+                )
+            })
+            .collect();
+
+        Ok((rest, rules))
+    }
+
+    // `override a require b` replaces the rules of `a` of the same type from the earlier
+    // layers, `remove a require b` drops the matching one and `remove a require *` all of them
+    fn parse_directive<'a>(
+        line: &'a str,
+        source: &EntitySource,
+        line_num: usize,
+    ) -> IResult<&'a str, Vec<EntityRule>> {
+        let (rest, (directive, mut rule)) = tuple((
+            preceded(multispace0, alt((tag("override"), tag("remove")))),
+            preceded(multispace1, |line| Self::parse_rule(line, source, line_num)),
+        ))(line)?;
+
+        rule.add_metadata(METADATA_LAYER_KEY.into(), directive.to_string());
+
+        Ok((rest, vec![rule]))
+    }
+
+    fn parse_line<'a>(
+        line: &'a str,
+        source: &EntitySource,
+        line_num: usize,
+    ) -> IResult<&'a str, Vec<EntityRule>> {
+        alt((
+            |line| Self::parse_group(line, source, line_num),
+            |line| Self::parse_directive(line, source, line_num),
+            map(
+                |line| Self::parse_rule(line, source, line_num),
+                |rule| vec![rule],
+            ),
+        ))(line)
+    }
 }
 
 impl Parser for NomDeployIRParser {
@@ -356,7 +508,12 @@ impl Parser for NomDeployIRParser {
         let rules = data
             .lines()
             .enumerate()
-            .map(|(idx, line)| (idx, Self::parse_rule(line, &source, idx + 1)))
+            // Blank lines and lines starting with `//` are comments
+            .filter(|(_, line)| {
+                let line = line.trim_start();
+                !line.is_empty() && !line.starts_with("//")
+            })
+            .map(|(idx, line)| (idx, Self::parse_line(line, &source, idx + 1)))
             .collect::<Vec<_>>();
 
         let errs = rules
@@ -373,13 +530,13 @@ impl Parser for NomDeployIRParser {
 
         let rules = rules
             .into_iter()
-            .filter_map(|(i, r)| r.ok().map(|(res, rule)| (i, res, rule)))
-            .map(|(i, rest, rule)| {
+            .filter_map(|(i, r)| r.ok().map(|(res, rules)| (i, res, rules)))
+            .flat_map(|(i, rest, rules)| {
                 if !rest.is_empty() {
                     error!("Line {}: Unparsed: {}", i + 1, rest);
                 }
 
-                rule
+                rules
             })
             .collect::<Vec<_>>();
 
@@ -388,3 +545,193 @@ impl Parser for NomDeployIRParser {
         Ok(entities)
     }
 }
+
+impl CsvParser {
+    pub fn new() -> Self {
+        Self
+    }
+
+    // Fields of each record with the line it starts on. Quoted fields may hold commas and line
+    // breaks, `""` being a quote.
+    fn records(data: &str) -> Result<Vec<(usize, Vec<String>)>, ParserError> {
+        let mut records = vec![];
+        let mut record = vec![];
+        let mut field = String::new();
+        let (mut line, mut start) = (1, 1);
+        let mut quoted = false;
+        let mut chars = data.chars().peekable();
+
+        while let Some(ch) = chars.next() {
+            match (quoted, ch) {
+                (true, '"') if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                (true, '"') => quoted = false,
+                (false, '"') if field.trim().is_empty() => {
+                    field.clear();
+                    quoted = true;
+                }
+                (false, ',') => record.push(std::mem::take(&mut field)),
+                (false, '\r') if chars.peek() == Some(&'\n') => {}
+                (false, '\n') => {
+                    record.push(std::mem::take(&mut field));
+                    records.push((start, std::mem::take(&mut record)));
+                    line += 1;
+                    start = line;
+                }
+                (_, ch) => {
+                    if ch == '\n' {
+                        line += 1;
+                    }
+                    field.push(ch);
+                }
+            }
+        }
+
+        if quoted {
+            return Err(ParserError::CsvError(format!(
+                "Line {}: Unterminated quoted field",
+                start
+            )));
+        }
+        if !field.is_empty() || !record.is_empty() {
+            record.push(field);
+            records.push((start, record));
+        }
+
+        // Spreadsheet exports often end with blank rows
+        records.retain(|(_, record)| record.iter().any(|field| !field.trim().is_empty()));
+
+        Ok(records)
+    }
+
+    // `header` names the metadata columns, without it metadata fields are `key=value`
+    fn parse_record(
+        record: &[String],
+        header: Option<&[String]>,
+        source: &EntitySource,
+        line: usize,
+    ) -> Result<EntityRule, String> {
+        let field = |idx: usize| record.get(idx).map(|f| f.trim()).unwrap_or_default();
+
+        let name = field(0);
+        if name.is_empty() {
+            return Err("Missing source".to_string());
+        }
+        let r#type = match field(1).to_ascii_lowercase().as_str() {
+            "require" => EntityRuleType::Require,
+            "exclude" => EntityRuleType::Exclude,
+            relation => {
+                return Err(format!(
+                    "Invalid relation `{}`, expected require or exclude",
+                    relation
+                ))
+            }
+        };
+        let targets = field(2)
+            .split([',', ';'])
+            .map(str::trim)
+            .filter(|target| !target.is_empty())
+            .map(|target| EntityName(target.to_string()))
+            .collect::<BTreeSet<_>>();
+        if targets.is_empty() {
+            return Err("Missing target".to_string());
+        }
+
+        let mut metadata = BTreeMap::new();
+        for (idx, value) in record.iter().enumerate().skip(3) {
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+
+            let key = header
+                .and_then(|header| header.get(idx - 3))
+                .filter(|key| !key.is_empty());
+            let (key, value) = match key {
+                Some(key) => (key.clone(), value.to_string()),
+                None => match value.split_once('=') {
+                    Some((key, value)) => (key.trim().to_string(), value.trim().to_string()),
+                    None => return Err(format!("Metadata `{}` is not key=value", value)),
+                },
+            };
+            metadata.insert(key, value);
+        }
+
+        let file = take_metadata_key(&mut metadata, METADATA_FILE_KEY);
+        let meta_line = take_metadata_key(&mut metadata, METADATA_LINE_KEY)
+            .and_then(|e| parse_metadata_line(&e));
+        let metadata = match (file, meta_line, metadata.is_empty()) {
+            (None, None, true) => None,
+            (file, meta_line, empty) => Some(EntityRuleMetadata::new(
+                file,
+                meta_line,
+                (!empty).then_some(metadata),
+            )),
+        };
+
+        let name = EntityName(name.to_string());
+        let source = EntityRuleSource::File(source.as_ref().to_string(), line);
+        let rule = match targets.len() {
+            1 => {
+                let target = targets.into_iter().next().unwrap();
+                EntityRule::mono(name, target, r#type, source, metadata)
+            }
+            _ => EntityRule::multi(name, targets, r#type, source, metadata),
+        };
+
+        Ok(rule)
+    }
+}
+
+impl Parser for CsvParser {
+    /*
+        Inventories exported from spreadsheets, one rule per row. Targets are separated by
+        `,` in a quoted field or by `;`, and a header row starting with `source` names the
+        metadata columns:
+
+        source,relation,target,topology,owner
+        app=web,require,app=db,zone,payments
+        app=web,exclude,"app=batch,app=etl",,
+
+        Without a header every metadata field is `key=value`:
+
+        app=web,require,app=db,topology=zone,owner=payments
+    */
+    fn parse(&self, data: &str, source: EntitySource) -> Result<Vec<Entity>, ParserError> {
+        let mut records = Self::records(data)?.into_iter().peekable();
+
+        let header = match records.peek() {
+            Some((_, record))
+                if record
+                    .first()
+                    .is_some_and(|field| field.trim().eq_ignore_ascii_case("source")) =>
+            {
+                records.next().map(|(_, record)| {
+                    record
+                        .into_iter()
+                        .skip(3)
+                        .map(|key| key.trim().to_string())
+                        .collect::<Vec<_>>()
+                })
+            }
+            _ => None,
+        };
+
+        let mut rules = vec![];
+        let mut errs = vec![];
+        for (line, record) in records {
+            match Self::parse_record(&record, header.as_deref(), &source, line) {
+                Ok(rule) => rules.push(rule),
+                Err(err) => errs.push(format!("Line {}: {}", line, err)),
+            }
+        }
+
+        if !errs.is_empty() {
+            return Err(ParserError::CsvError(errs.join("\n")));
+        }
+
+        Ok(util::rule_set_to_entity_set(rules))
+    }
+}
@@ -7,14 +7,91 @@ use std::{
 use log::debug;
 use serde::{Deserialize, Serialize};
 
-use super::{EntityName, EntityRuleTopologyKey, METADATA_TOPOLOGY_KEY};
+use super::{rule_location, EntityName, EntityRuleTopologyKey, METADATA_TOPOLOGY_KEY};
+
+// Keys of the file and line a rule was declared at in the `// File=...;Line=...;` comment of
+// the IR, matched case-insensitively when parsing
+pub static METADATA_FILE_KEY: &str = "File";
+pub static METADATA_LINE_KEY: &str = "Line";
+pub static METADATA_PROVENANCE_KEY: &str = "provenance";
+// Name of the metadata key (e.g. `tenant`) an exclusion is scoped by: the rule only applies
+// when the source and the target share the same value for that key.
+pub static METADATA_SCOPE_KEY: &str = "scope_key";
+// Weight (1-100) of a soft rule, injected as a `preferredDuringScheduling...` term
+pub static METADATA_WEIGHT_KEY: &str = "weight";
+// Name of the colocation group (`group payments = a, b, c` in IR) a rule was expanded from
+pub static METADATA_GROUP_KEY: &str = "group";
+// Set to `true` on rules deployfix injected into a manifest, as opposed to rules written by users
+pub static METADATA_GENERATED_KEY: &str = "generated";
+// Daily time windows the source entity runs in (see `TimeWindows`), e.g. `01:00-03:00`
+pub static METADATA_WINDOW_KEY: &str = "window";
+// Number of pods of the source entity, spread over distinct nodes by a self anti-affinity
+pub static METADATA_REPLICAS_KEY: &str = "replicas";
+// Bounds of the replicas of the source entity set by a HorizontalPodAutoscaler. Capacity checks
+// spread the maximum, the worst case of a scale-out, instead of `replicas`.
+pub static METADATA_MIN_REPLICAS_KEY: &str = "min_replicas";
+pub static METADATA_MAX_REPLICAS_KEY: &str = "max_replicas";
+// Pods of the source entity a PodDisruptionBudget keeps available, or lets be unavailable at
+// once, as a number or a percentage of the replicas (e.g. `2` or `50%`)
+pub static METADATA_MIN_AVAILABLE_KEY: &str = "min_available";
+pub static METADATA_MAX_UNAVAILABLE_KEY: &str = "max_unavailable";
+// Set to `true` by the solvers on the rules of a conflict that only appears once the source
+// entity scales out past its minimum replicas
+pub static METADATA_SCALE_OUT_KEY: &str = "scale_out";
+// Origin of a dependency hint (e.g. `network`): a require inferred from how entities talk to
+// each other rather than declared as an affinity. Hints only feed the ring solver.
+pub static METADATA_HINT_KEY: &str = "hint";
+// Directive (`override` or `remove`) of a rule written in a later IR layer, applied to the
+// rules of the earlier layers when the inputs are stacked (see `apply_layers`)
+pub static METADATA_LAYER_KEY: &str = "layer";
+// Comma separated families a rule belongs to (e.g. `security,capacity`), selected with the
+// `--only-tags` and `--skip-tags` flags of `check`
+pub static METADATA_TAGS_KEY: &str = "tags";
+// Name of the pod a rule was read from when a workload (e.g. the Deployment of its ReplicaSet)
+// owns the pod, the rule then belongs to the entity of the owner
+pub static METADATA_POD_KEY: &str = "pod";
+// Why the rule exists (`desc="keeps the cache next to its readers"`), shown next to the rule
+// in conflict reports
+pub static METADATA_DESC_KEY: &str = "desc";
+// Rollout state of a rule (see `RuleLifecycle`), `enforced` when missing
+pub static METADATA_LIFECYCLE_KEY: &str = "lifecycle";
+// Team or person answering for the source entity (`owner=payments`), listed with the entity in
+// `definitions.yaml`
+pub static METADATA_OWNER_KEY: &str = "owner";
+// Namespace of the source entity, `default` when missing
+pub static METADATA_NAMESPACE_KEY: &str = "namespace";
+// Pods a ResourceQuota lets the namespace of the source entity run, its other workloads included
+pub static METADATA_POD_QUOTA_KEY: &str = "pod_quota";
+// Annotation of a resource (e.g. of a custom resource) a rule was read from, such rules are
+// checked but never written back to the resource
+pub static METADATA_ANNOTATION_KEY: &str = "annotation";
+
+// Source, targets, type, the topology, scope, weight and window metadata, and the lifecycle
+pub type RuleDedupKey = (
+    EntityName,
+    Vec<EntityName>,
+    EntityRuleType,
+    [Option<String>; 4],
+    RuleLifecycle,
+);
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum EntityRuleSource {
     File(String, usize),
+    // A term of a structured file, e.g. an affinity term of a manifest
+    Span(String, SourceSpan),
     Unknown,
 }
 
+// Where a term starts, 1-based like the editors show it, and the bytes it covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct SourceSpan {
+    pub line: usize,
+    pub column: usize,
+    pub index: usize,
+    pub len: usize,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum EntityRuleType {
     Require,
@@ -78,6 +155,17 @@ impl EntityRuleMetadata {
             self.metadata = Some(metadata);
         }
     }
+
+    pub fn remove_metadata(&mut self, key: &str) -> Option<String> {
+        let metadata = self.metadata.as_mut()?;
+        let value = metadata.remove(key);
+
+        if metadata.is_empty() {
+            self.metadata = None;
+        }
+
+        value
+    }
 }
 
 impl Display for EntityRuleMetadata {
@@ -111,6 +199,9 @@ impl Display for EntityRuleSource {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             EntityRuleSource::File(path, line) => write!(f, "{}:{}", path, line),
+            EntityRuleSource::Span(path, span) => {
+                write!(f, "{}:{}:{}", path, span.line, span.column)
+            }
             EntityRuleSource::Unknown => write!(f, "unknown"),
         }
     }
@@ -123,7 +214,9 @@ impl EntityRuleSource {
 
     pub fn file(&self) -> Option<&str> {
         match self {
-            EntityRuleSource::File(path, _) => Some(path.as_str()),
+            EntityRuleSource::File(path, _) | EntityRuleSource::Span(path, _) => {
+                Some(path.as_str())
+            }
             EntityRuleSource::Unknown => None,
         }
     }
@@ -131,9 +224,93 @@ impl EntityRuleSource {
     pub fn line(&self) -> Option<usize> {
         match self {
             EntityRuleSource::File(_, line) => Some(*line),
+            EntityRuleSource::Span(_, span) => Some(span.line),
             EntityRuleSource::Unknown => None,
         }
     }
+
+    pub fn column(&self) -> Option<usize> {
+        match self {
+            EntityRuleSource::Span(_, span) => Some(span.column),
+            _ => None,
+        }
+    }
+
+    // Byte range of the term in its file
+    pub fn range(&self) -> Option<(usize, usize)> {
+        match self {
+            EntityRuleSource::Span(_, span) => Some((span.index, span.index + span.len)),
+            _ => None,
+        }
+    }
+
+    // The same place `lines` lines and `bytes` bytes further down its file
+    pub fn shifted(self, lines: usize, bytes: usize) -> Self {
+        match self {
+            EntityRuleSource::File(path, line) => EntityRuleSource::File(path, line + lines),
+            EntityRuleSource::Span(path, span) => EntityRuleSource::Span(
+                path,
+                SourceSpan {
+                    line: span.line + lines,
+                    index: span.index + bytes,
+                    ..span
+                },
+            ),
+            EntityRuleSource::Unknown => EntityRuleSource::Unknown,
+        }
+    }
+}
+
+/*
+    Rollout state of a placement policy:
+
+    proposed    checked and reported apart as advisory, not enforced yet
+    enforced    the default, its conflicts fail the run
+    deprecated  on its way out, its conflicts are only warned about
+
+    The states whose conflicts fail the run are picked with `--gate`.
+*/
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleLifecycle {
+    Deprecated,
+    Proposed,
+    #[default]
+    Enforced,
+}
+
+impl RuleLifecycle {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Proposed => "proposed",
+            Self::Enforced => "enforced",
+            Self::Deprecated => "deprecated",
+        }
+    }
+}
+
+impl Display for RuleLifecycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::str::FromStr for RuleLifecycle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "proposed" => Ok(Self::Proposed),
+            "enforced" => Ok(Self::Enforced),
+            "deprecated" => Ok(Self::Deprecated),
+            _ => Err(format!(
+                "Unknown lifecycle {}, expected proposed, enforced or deprecated",
+                s
+            )),
+        }
+    }
 }
 
 impl Display for EntityRuleType {
@@ -217,6 +394,28 @@ impl EntityRule {
         }
     }
 
+    // Moves the rule `lines` lines and `bytes` bytes down its file, e.g. from a document of a
+    // manifest to the whole manifest
+    pub fn shift(&mut self, lines: usize, bytes: usize) {
+        let (rule_source, metadata) = match self {
+            Self::Mono {
+                rule_source,
+                metadata,
+                ..
+            } => (rule_source, metadata),
+            Self::Multi {
+                rule_source,
+                metadata,
+                ..
+            } => (rule_source, metadata),
+        };
+
+        *rule_source = std::mem::take(rule_source).shifted(lines, bytes);
+        if let Some(metadata) = metadata {
+            metadata.line = metadata.line.map(|line| line.saturating_add(lines));
+        }
+    }
+
     pub fn set_rule_source(&mut self, new_source: EntityRuleSource) {
         match self {
             Self::Mono {
@@ -255,45 +454,31 @@ impl EntityRule {
         }
     }
 
-    pub fn file(&self) -> Option<&str> {
+    pub fn rule_source(&self) -> &EntityRuleSource {
         match self {
-            Self::Mono {
-                rule_source: source,
-                ..
-            } => match source {
-                EntityRuleSource::File(path, _) => Some(path.as_str()),
-                EntityRuleSource::Unknown => None,
-            },
-            Self::Multi {
-                rule_source: source,
-                ..
-            } => match source {
-                EntityRuleSource::File(path, _) => Some(path.as_str()),
-                EntityRuleSource::Unknown => None,
-            },
+            Self::Mono { rule_source, .. } => rule_source,
+            Self::Multi { rule_source, .. } => rule_source,
         }
     }
 
+    pub fn file(&self) -> Option<&str> {
+        self.rule_source().file()
+    }
+
     pub fn line(&self) -> Option<usize> {
-        match self {
-            Self::Mono {
-                rule_source: source,
-                ..
-            } => match source {
-                EntityRuleSource::File(_, line) => Some(*line),
-                EntityRuleSource::Unknown => None,
-            },
-            Self::Multi {
-                rule_source: source,
-                ..
-            } => match source {
-                EntityRuleSource::File(_, line) => Some(*line),
-                EntityRuleSource::Unknown => None,
-            },
-        }
+        self.rule_source().line()
     }
 
+    pub fn column(&self) -> Option<usize> {
+        self.rule_source().column()
+    }
+
+    // Byte range of the rule in its file, from its span or its `index` and `len` metadata
     pub fn range(&self) -> Option<(usize, usize)> {
+        if let Some(range) = self.rule_source().range() {
+            return Some(range);
+        }
+
         let start = self.metadata("index").map(|e| e.parse().unwrap_or(0usize));
         let len = self.metadata("len").map(|e| e.parse().unwrap_or(0usize));
 
@@ -304,6 +489,13 @@ impl EntityRule {
         }
     }
 
+    pub fn metadata_ref(&self) -> Option<&EntityRuleMetadata> {
+        match self {
+            Self::Mono { metadata, .. } => metadata.as_ref(),
+            Self::Multi { metadata, .. } => metadata.as_ref(),
+        }
+    }
+
     pub fn metadata(&self, key: &str) -> Option<&str> {
         match self {
             Self::Mono { metadata, .. } => metadata
@@ -317,6 +509,139 @@ impl EntityRule {
         }
     }
 
+    pub fn add_metadata(&mut self, key: String, value: String) {
+        let metadata = match self {
+            Self::Mono { metadata, .. } => metadata,
+            Self::Multi { metadata, .. } => metadata,
+        };
+
+        metadata
+            .get_or_insert_with(EntityRuleMetadata::default)
+            .add_metadata(key, value);
+    }
+
+    pub fn remove_metadata(&mut self, key: &str) -> Option<String> {
+        match self {
+            Self::Mono { metadata, .. } => metadata.as_mut()?.remove_metadata(key),
+            Self::Multi { metadata, .. } => metadata.as_mut()?.remove_metadata(key),
+        }
+    }
+
+    // Identity of a rule regardless of where it was declared, used to detect duplicates that
+    // only differ by their source location or descriptive metadata. The metadata changing what
+    // the rule constrains, its topology, scope, weight, window and lifecycle, is part of it.
+    pub fn dedup_key(&self) -> RuleDedupKey {
+        (
+            self.source().clone(),
+            self.targets().into_iter().cloned().collect(),
+            self.r#type(),
+            [
+                METADATA_TOPOLOGY_KEY,
+                METADATA_SCOPE_KEY,
+                METADATA_WEIGHT_KEY,
+                METADATA_WINDOW_KEY,
+            ]
+            .map(|key| self.metadata(key).map(str::to_string)),
+            self.lifecycle(),
+        )
+    }
+
+    pub fn scope_key(&self) -> Option<&str> {
+        self.metadata(METADATA_SCOPE_KEY)
+    }
+
+    pub fn weight(&self) -> Option<&str> {
+        self.metadata(METADATA_WEIGHT_KEY)
+    }
+
+    pub fn group(&self) -> Option<&str> {
+        self.metadata(METADATA_GROUP_KEY)
+    }
+
+    pub fn window(&self) -> Option<&str> {
+        self.metadata(METADATA_WINDOW_KEY)
+    }
+
+    pub fn replicas(&self) -> Option<usize> {
+        self.metadata(METADATA_REPLICAS_KEY)
+            .and_then(|replicas| replicas.parse().ok())
+    }
+
+    pub fn min_replicas(&self) -> Option<usize> {
+        self.metadata(METADATA_MIN_REPLICAS_KEY)
+            .and_then(|replicas| replicas.parse().ok())
+    }
+
+    pub fn max_replicas(&self) -> Option<usize> {
+        self.metadata(METADATA_MAX_REPLICAS_KEY)
+            .and_then(|replicas| replicas.parse().ok())
+    }
+
+    pub fn is_scale_out(&self) -> bool {
+        self.metadata(METADATA_SCALE_OUT_KEY) == Some("true")
+    }
+
+    pub fn layer(&self) -> Option<&str> {
+        self.metadata(METADATA_LAYER_KEY)
+    }
+
+    pub fn hint(&self) -> Option<&str> {
+        self.metadata(METADATA_HINT_KEY)
+    }
+
+    pub fn pod(&self) -> Option<&str> {
+        self.metadata(METADATA_POD_KEY)
+    }
+
+    pub fn desc(&self) -> Option<&str> {
+        self.metadata(METADATA_DESC_KEY)
+            .map(|desc| desc.trim().trim_matches('"').trim())
+            .filter(|desc| !desc.is_empty())
+    }
+
+    pub fn owner(&self) -> Option<&str> {
+        self.metadata(METADATA_OWNER_KEY)
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.metadata(METADATA_NAMESPACE_KEY)
+    }
+
+    pub fn pod_quota(&self) -> Option<usize> {
+        self.metadata(METADATA_POD_QUOTA_KEY)
+            .and_then(|quota| quota.parse().ok())
+    }
+
+    pub fn annotation(&self) -> Option<&str> {
+        self.metadata(METADATA_ANNOTATION_KEY)
+    }
+
+    pub fn tags(&self) -> Vec<&str> {
+        self.metadata(METADATA_TAGS_KEY)
+            .map(|tags| {
+                tags.split(',')
+                    .map(|tag| tag.trim())
+                    .filter(|tag| !tag.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // Unknown states count as enforced, a typo never weakens a rule
+    pub fn lifecycle(&self) -> RuleLifecycle {
+        self.metadata(METADATA_LIFECYCLE_KEY)
+            .and_then(|lifecycle| lifecycle.parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn is_soft(&self) -> bool {
+        self.weight().is_some()
+    }
+
+    pub fn is_generated(&self) -> bool {
+        self.metadata(METADATA_GENERATED_KEY) == Some("true")
+    }
+
     pub fn r#type(&self) -> EntityRuleType {
         match self {
             Self::Mono { r#type, .. } => r#type.clone(),
@@ -355,10 +680,79 @@ impl EntityRule {
 
     pub fn is_in_target(&self, target: &str) -> bool {
         match self {
-            Self::Mono { target, .. } => target == target,
+            Self::Mono { target: t, .. } => t.0 == target,
             Self::Multi { targets, .. } => targets.contains(&EntityName(target.to_string())),
         }
     }
+
+    /*
+        The rule in plain words for people reading a report, without the metadata of the
+        `Display` form:
+
+        app=frontend must not be co-located with app=db on the same node (from deploy/frontend.yaml:42)
+    */
+    pub fn describe(&self) -> String {
+        let targets = self
+            .targets()
+            .into_iter()
+            .map(|t| t.as_ref())
+            .collect::<Vec<_>>();
+        let modal = if self.is_soft() { "should" } else { "must" };
+        let relation = match (self.r#type(), targets.len()) {
+            (EntityRuleType::Require, 1) => format!("{} be placed with", modal),
+            (EntityRuleType::Require, _) => format!("{} be placed with one of", modal),
+            (EntityRuleType::Exclude, 1) => format!("{} not be co-located with", modal),
+            (EntityRuleType::Exclude, _) => format!("{} not be co-located with any of", modal),
+        };
+        let topology = self
+            .metadata(METADATA_TOPOLOGY_KEY)
+            .unwrap_or(EntityRuleTopologyKey::Node.as_ref());
+
+        let mut text = format!(
+            "{} {} {} on the same {}",
+            self.source().as_ref(),
+            relation,
+            targets.join(", "),
+            topology
+        );
+        if let Some(location) = rule_location(self) {
+            text.push_str(&format!(" (from {})", location));
+        }
+
+        text
+    }
+
+    // The rule with its source and targets passed through `rename`
+    pub fn renamed(self, rename: impl Fn(&EntityName) -> EntityName) -> Self {
+        match self {
+            Self::Mono {
+                source,
+                target,
+                r#type,
+                rule_source,
+                metadata,
+            } => Self::Mono {
+                source: rename(&source),
+                target: rename(&target),
+                r#type,
+                rule_source,
+                metadata,
+            },
+            Self::Multi {
+                source,
+                targets,
+                r#type,
+                rule_source,
+                metadata,
+            } => Self::Multi {
+                source: rename(&source),
+                targets: targets.iter().map(&rename).collect(),
+                r#type,
+                rule_source,
+                metadata,
+            },
+        }
+    }
 }
 
 impl Display for EntityRule {
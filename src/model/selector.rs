@@ -0,0 +1,178 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
+
+/*
+    Label selectors as sets of pods, e.g. `app in (a, b), tier=db, !legacy` matches the pods
+    labelled `app` a or b and `tier` db, without a `legacy` label. Rules name entities, which
+    only match a selector when their labels do: `app in (a, b)` and `app=a` select the same
+    pods although the names differ, while `app=a, tier=db` does not select `app=a` pods of
+    another tier.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SelectorOperator {
+    In(BTreeSet<String>),
+    NotIn(BTreeSet<String>),
+    Exists,
+    DoesNotExist,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LabelRequirement {
+    pub key: String,
+    pub operator: SelectorOperator,
+}
+
+// Requirements which all hold, an empty selector matches every pod
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selector {
+    requirements: Vec<LabelRequirement>,
+}
+
+// What the requirements of both selectors ask of the value of one key
+#[derive(Default)]
+struct KeyConstraint {
+    allowed: Option<BTreeSet<String>>,
+    forbidden: BTreeSet<String>,
+    exists: bool,
+    absent: bool,
+}
+
+impl KeyConstraint {
+    fn add(&mut self, operator: &SelectorOperator) {
+        match operator {
+            SelectorOperator::In(values) => {
+                self.allowed = Some(match self.allowed.take() {
+                    Some(allowed) => allowed.intersection(values).cloned().collect(),
+                    None => values.clone(),
+                });
+                self.exists = true;
+            }
+            SelectorOperator::NotIn(values) => self.forbidden.extend(values.iter().cloned()),
+            SelectorOperator::Exists => self.exists = true,
+            SelectorOperator::DoesNotExist => self.absent = true,
+        }
+    }
+
+    // Whether some value, or the absence of the label, satisfies the constraint
+    fn is_satisfiable(&self) -> bool {
+        if self.absent {
+            return !self.exists;
+        }
+
+        match &self.allowed {
+            Some(allowed) => allowed.difference(&self.forbidden).next().is_some(),
+            None => true,
+        }
+    }
+}
+
+impl SelectorOperator {
+    // From a Kubernetes `matchExpressions` operator and its values
+    pub fn parse(operator: &str, values: &[String]) -> Result<Self, String> {
+        let values = values.iter().cloned().collect();
+
+        match operator {
+            "In" => Ok(Self::In(values)),
+            "NotIn" => Ok(Self::NotIn(values)),
+            "Exists" => Ok(Self::Exists),
+            "DoesNotExist" => Ok(Self::DoesNotExist),
+            operator => Err(format!("Unknown selector operator `{}`", operator)),
+        }
+    }
+}
+
+impl Selector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Selector of the pods carrying all `labels`
+    pub fn from_labels(labels: &BTreeMap<String, String>) -> Self {
+        labels.iter().fold(Self::new(), |selector, (key, value)| {
+            selector.with(key, SelectorOperator::In([value.clone()].into()))
+        })
+    }
+
+    pub fn with(mut self, key: &str, operator: SelectorOperator) -> Self {
+        self.requirements.push(LabelRequirement {
+            key: key.to_string(),
+            operator,
+        });
+        self
+    }
+
+    pub fn requirements(&self) -> &[LabelRequirement] {
+        &self.requirements
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requirements.is_empty()
+    }
+
+    // Whether a pod with exactly these labels is selected
+    pub fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        self.requirements.iter().all(|requirement| {
+            match (&requirement.operator, labels.get(&requirement.key)) {
+                (SelectorOperator::In(values), Some(value)) => values.contains(value),
+                (SelectorOperator::In(_), None) => false,
+                (SelectorOperator::NotIn(values), Some(value)) => !values.contains(value),
+                (SelectorOperator::NotIn(_), None) => true,
+                (SelectorOperator::Exists, label) => label.is_some(),
+                (SelectorOperator::DoesNotExist, label) => label.is_none(),
+            }
+        })
+    }
+
+    // Whether some pod can be selected by both selectors, labels neither selector mentions
+    // being free
+    pub fn overlaps(&self, other: &Selector) -> bool {
+        let mut constraints: BTreeMap<&str, KeyConstraint> = BTreeMap::new();
+
+        for requirement in self.requirements.iter().chain(other.requirements.iter()) {
+            constraints
+                .entry(requirement.key.as_str())
+                .or_default()
+                .add(&requirement.operator);
+        }
+
+        constraints
+            .values()
+            .all(|constraint| constraint.is_satisfiable())
+    }
+}
+
+impl Display for LabelRequirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |values: &BTreeSet<String>| {
+            values
+                .iter()
+                .map(|v| v.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        match &self.operator {
+            SelectorOperator::In(values) if values.len() == 1 => {
+                write!(f, "{}={}", self.key, join(values))
+            }
+            SelectorOperator::In(values) => write!(f, "{} in ({})", self.key, join(values)),
+            SelectorOperator::NotIn(values) => write!(f, "{} notin ({})", self.key, join(values)),
+            SelectorOperator::Exists => write!(f, "{}", self.key),
+            SelectorOperator::DoesNotExist => write!(f, "!{}", self.key),
+        }
+    }
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let requirements = self
+            .requirements
+            .iter()
+            .map(|r| r.to_string())
+            .collect::<Vec<_>>();
+
+        write!(f, "{}", requirements.join(", "))
+    }
+}
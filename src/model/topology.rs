@@ -3,6 +3,7 @@ use serde::{Deserialize, Serialize};
 pub static METADATA_TOPOLOGY_KEY: &str = "topology";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum EntityRuleTopologyKey {
     Zone,
     Rack,
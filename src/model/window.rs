@@ -0,0 +1,201 @@
+use std::{collections::BTreeSet, fmt::Display};
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/*
+    Daily time windows an entity runs in, e.g. a nightly batch job. Entities whose windows
+    do not overlap never run at the same time, so an exclusion between them cannot conflict.
+
+    In the IR a window is declared in the metadata of the entity's rules, as a list of
+    `HH:MM-HH:MM` ranges in the cluster's time zone (the end may wrap past midnight):
+
+    app=backup exclude app=report // window=01:00-03:00,13:00-13:30
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeWindows {
+    // Sorted, disjoint, half-open ranges of minutes within the day
+    ranges: Vec<(u32, u32)>,
+}
+
+fn parse_time(value: &str) -> Result<u32, String> {
+    let (hour, minute) = value
+        .trim()
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid time `{}`, expected HH:MM", value))?;
+
+    match (hour.parse::<u32>(), minute.parse::<u32>()) {
+        (Ok(hour), Ok(minute)) if minute < 60 && hour * 60 + minute <= MINUTES_PER_DAY => {
+            Ok(hour * 60 + minute)
+        }
+        _ => Err(format!("Invalid time `{}`, expected HH:MM", value)),
+    }
+}
+
+// Values of one cron field, supporting `*`, lists, ranges and steps
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<BTreeSet<u32>, String> {
+    let mut values = BTreeSet::new();
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("Invalid step in cron field `{}`", field))?,
+            ),
+            None => (part, 1),
+        };
+
+        let parse = |value: &str| {
+            value
+                .parse::<u32>()
+                .ok()
+                .filter(|value| (min..=max).contains(value))
+                .ok_or_else(|| format!("Invalid value `{}` in cron field `{}`", value, field))
+        };
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (parse(start)?, parse(end)?),
+                // `5/15` starts at 5 and runs to the end of the range
+                None if step > 1 => (parse(range)?, max),
+                None => {
+                    let value = parse(range)?;
+                    (value, value)
+                }
+            },
+        };
+
+        values.extend((start..=end).step_by(step as usize));
+    }
+
+    Ok(values)
+}
+
+impl TimeWindows {
+    pub fn all_day() -> Self {
+        Self {
+            ranges: vec![(0, MINUTES_PER_DAY)],
+        }
+    }
+
+    // Builds the windows from `(start, duration)` pairs in minutes, splitting the ranges
+    // running past midnight and merging the overlapping ones
+    fn from_runs(runs: impl IntoIterator<Item = (u32, u32)>) -> Self {
+        let mut ranges = vec![];
+
+        for (start, duration) in runs {
+            if duration >= MINUTES_PER_DAY {
+                return Self::all_day();
+            }
+
+            let end = start + duration;
+            if end > MINUTES_PER_DAY {
+                ranges.push((start, MINUTES_PER_DAY));
+                ranges.push((0, end - MINUTES_PER_DAY));
+            } else {
+                ranges.push((start, end));
+            }
+        }
+        ranges.sort();
+
+        let ranges = ranges
+            .into_iter()
+            .fold(Vec::<(u32, u32)>::new(), |mut acc, (start, end)| {
+                match acc.last_mut() {
+                    Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                    _ => acc.push((start, end)),
+                }
+
+                acc
+            });
+
+        Self { ranges }
+    }
+
+    // Parses `HH:MM-HH:MM[,HH:MM-HH:MM...]`, a range ending at its start covers the whole day
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let runs = value
+            .split(',')
+            .map(|range| {
+                let (start, end) = range
+                    .split_once('-')
+                    .ok_or_else(|| format!("Invalid window `{}`, expected HH:MM-HH:MM", range))?;
+
+                let (start, end) = (parse_time(start)? % MINUTES_PER_DAY, parse_time(end)?);
+                let duration = match (end + MINUTES_PER_DAY - start) % MINUTES_PER_DAY {
+                    0 => MINUTES_PER_DAY,
+                    duration => duration,
+                };
+
+                Ok((start, duration))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Self::from_runs(runs))
+    }
+
+    // Windows of the runs of a cron schedule (`minute hour day month weekday`), each lasting
+    // `duration` minutes. Only the minute and hour fields are used, a job running on some days
+    // only is assumed to run every day.
+    pub fn from_cron(schedule: &str, duration: u32) -> Result<Self, String> {
+        let schedule = match schedule.trim() {
+            "@yearly" | "@annually" | "@monthly" | "@weekly" | "@daily" | "@midnight" => {
+                "0 0 * * *"
+            }
+            "@hourly" => "0 * * * *",
+            schedule => schedule,
+        };
+
+        let fields = schedule.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return Err(format!(
+                "Invalid cron schedule `{}`, expected 5 fields",
+                schedule
+            ));
+        }
+
+        let minutes = parse_cron_field(fields[0], 0, 59)?;
+        let hours = parse_cron_field(fields[1], 0, 23)?;
+
+        let duration = duration.max(1);
+        let runs = hours.iter().flat_map(|hour| {
+            minutes
+                .iter()
+                .map(move |minute| (hour * 60 + minute, duration))
+        });
+
+        Ok(Self::from_runs(runs))
+    }
+
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.ranges.iter().any(|(start, end)| {
+            other
+                .ranges
+                .iter()
+                .any(|(other_start, other_end)| start < other_end && other_start < end)
+        })
+    }
+}
+
+impl Display for TimeWindows {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ranges = self
+            .ranges
+            .iter()
+            .map(|(start, end)| {
+                format!(
+                    "{:02}:{:02}-{:02}:{:02}",
+                    start / 60,
+                    start % 60,
+                    end / 60,
+                    end % 60
+                )
+            })
+            .collect::<Vec<_>>();
+
+        write!(f, "{}", ranges.join(","))
+    }
+}
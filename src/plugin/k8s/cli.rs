@@ -3,17 +3,33 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use anyhow::Context;
 use clap::Subcommand;
 use log::{debug, error, info, warn};
 
 use crate::{
-    cli::ConflictAnnotater,
+    cli::{
+        check_artifact_sources, check_disruption_budgets, check_pod_quotas, deploy_order,
+        disruption_output, dump_definitions, format_actions, format_deploy_order,
+        load_impact_weights, load_normalizations, load_suppressions, print_output, quota_output,
+        recommend_actions, today, BudgetArgs, CertificateArgs, Config, ConflictReport,
+        DomainResult, DomainSummary, OutputFormat, OutputLayout, OutputLevel, ReportSink,
+        RunOptions, RunSummary, SinkArgs, SolveStats, SourceDigests, DEFINITIONS_FILE,
+        DEPLOY_ORDER_FILE, SUMMARY_FILE,
+    },
     model::{
-        get_parser, merge_entities, DeployIRFormatter, Entity, EntityPriority, EntityRule,
-        EntitySource, EnvParser,
+        format_envs, load_env_files, rule_text, DeployIRFormatter, Entity, EntityPriority,
+        EntityRule, EntityRuleTopologyKey, EntitySource, Env, EnvParseError, METADATA_TOPOLOGY_KEY,
+    },
+    plugin::{
+        self,
+        k8s::{DeprecatedTopologyKey, K8sConfig, K8sPlugin, K8sVersion},
+        unique_inputs, DeployPlugin, InjectOptions,
+    },
+    solver::{
+        self, conflict_slices, decode_snapshot, encode_snapshot, get_solver_with, ConflictSolvers,
+        EntityMap, ImpactScore, SolveCache, SolverOptions, SolverOutput, CACHE_DIR,
+        SNAPSHOT_EXTENSION, SOLVER_NAMES,
     },
-    solver::{get_solver, SolverOutput},
     util,
 };
 
@@ -44,12 +60,108 @@ pub enum K8SCommands {
     Import {
         #[clap(value_name = "PATH", help = "Paths to K8s files")]
         paths: Vec<PathBuf>,
+        #[clap(
+            long,
+            value_name = "VERSION",
+            help = "Kubernetes version of the cluster, e.g. 1.24, warns about fields it does not serve"
+        )]
+        k8s_version: Option<K8sVersion>,
+        #[command(flatten)]
+        sink: SinkArgs,
     },
     Inject {
         #[clap(value_name = "OUTPUT", help = "Output K8s directory")]
         output_dir: PathBuf,
         #[clap(value_name = "PATH", help = "Paths to deployfix files")]
         paths: Vec<PathBuf>,
+        #[clap(
+            long,
+            value_name = "PATTERN",
+            value_delimiter = ',',
+            help = "Only inject the entities matching these glob patterns, e.g. app=web or 'pay*'"
+        )]
+        only: Vec<String>,
+        #[clap(
+            long,
+            help = "Leave the manifests the injection would not change untouched"
+        )]
+        skip_unchanged: bool,
+        #[clap(
+            long,
+            help = "Only warn about deployfix files computed from manifests changed since"
+        )]
+        allow_stale: bool,
+        #[clap(
+            long,
+            value_name = "VERSION",
+            help = "Kubernetes version of the cluster, e.g. 1.24, warns about fields it does not serve"
+        )]
+        k8s_version: Option<K8sVersion>,
+        #[command(flatten)]
+        sink: SinkArgs,
+    },
+    #[clap(
+        about = "Check the affinities of a new service against the manifests and print its affinity block"
+    )]
+    Scaffold {
+        #[clap(
+            long,
+            value_name = "ENTITY",
+            help = "Entity of the new service, e.g. app=new"
+        )]
+        name: String,
+        #[clap(
+            long,
+            value_name = "ENTITY",
+            help = "Entity the service is placed with, e.g. app=db, may be repeated"
+        )]
+        require: Vec<String>,
+        #[clap(
+            long,
+            value_name = "ENTITY",
+            help = "Entity the service is kept apart from, e.g. app=legacy, may be repeated"
+        )]
+        exclude: Vec<String>,
+        #[clap(
+            long,
+            value_name = "TOPOLOGY",
+            default_value = "node",
+            value_parser = ["node", "zone", "rack"],
+            help = "Scheduling domain of the rules"
+        )]
+        topology: String,
+        #[clap(
+            value_name = "PATH",
+            help = "Paths to the K8s files of the existing services"
+        )]
+        paths: Vec<PathBuf>,
+        #[clap(
+            long,
+            value_name = "VERSION",
+            help = "Kubernetes version of the cluster, e.g. 1.24, warns about fields it does not serve"
+        )]
+        k8s_version: Option<K8sVersion>,
+    },
+    #[clap(
+        about = "Check one changed manifest against the cached model of a directory, printing only its conflicts"
+    )]
+    CheckOne {
+        #[clap(value_name = "FILE", help = "Changed K8s manifest")]
+        file: PathBuf,
+        #[clap(
+            long,
+            value_name = "DIR",
+            help = "K8s and deployfix files of the model, e.g. the manifests of the repository or a dump of the cluster"
+        )]
+        against: PathBuf,
+        #[clap(long, help = "Enable cycle check", default_value = "false")]
+        cycle_check: bool,
+        #[clap(
+            long,
+            value_name = "VERSION",
+            help = "Kubernetes version of the cluster, e.g. 1.24, warns about fields it does not serve"
+        )]
+        k8s_version: Option<K8sVersion>,
     },
     Go {
         #[clap(value_name = "SOURCE_DIR", help = "Path to K8s files")]
@@ -65,68 +177,175 @@ pub enum K8SCommands {
             default_value = "false"
         )]
         recommend: bool,
+        #[clap(long, help = "Recommand policy to use [default: HighPriorityFirst]")]
+        recommend_policy: Option<RecommendPolicy>,
         #[clap(
             long,
-            help = "Recommand policy to use",
-            default_value = "HighPriorityFirst"
+            help = "Environment file, may be repeated with later files overriding earlier ones"
         )]
-        recommend_policy: RecommendPolicy,
-        #[clap(long, help = "Enviroment file")]
-        env_file: Option<PathBuf>,
+        env_file: Vec<PathBuf>,
         #[clap(long, help = "Enable cycle check", default_value = "false")]
         cycle_check: bool,
         #[clap(long, help = "Reject unknown entities", default_value = "false")]
         reject_unknown: bool,
+        #[clap(
+            long,
+            help = "Skip the z3 stage when the cheap solvers already report conflicts"
+        )]
+        fast_fail: bool,
+        #[clap(
+            long,
+            help = "Check every topology even after one has conflicts, instead of skipping the rest"
+        )]
+        keep_going: bool,
+        #[clap(
+            long,
+            help = "Write the flat `conflicts-<topology>.yaml` layout of older releases instead of one directory per topology"
+        )]
+        flat_output: bool,
+        #[clap(
+            long,
+            help = "List only the `file:line` of the conflicting rules in conflicts.yaml, as older releases did"
+        )]
+        legacy_conflicts_format: bool,
+        #[clap(
+            long,
+            help = "Solve every topology again instead of reusing the results of unchanged components"
+        )]
+        no_cache: bool,
+        #[clap(
+            long,
+            value_name = "TOPOLOGY=SOLVERS",
+            num_args = 1..,
+            help = "Solvers to run on a topology, e.g. node=z3,ring zone=ring, none to skip it"
+        )]
+        topology_solvers: Vec<String>,
+        #[clap(
+            long,
+            value_name = "VERSION",
+            help = "Kubernetes version of the cluster, e.g. 1.24, warns about fields it does not serve"
+        )]
+        k8s_version: Option<K8sVersion>,
+        #[command(flatten)]
+        sink: SinkArgs,
+        #[command(flatten)]
+        budget: BudgetArgs,
+        #[clap(
+            long,
+            value_enum,
+            value_name = "FORMAT",
+            help = "Print the conflicts to stdout as json, yaml or sarif instead of annotated snippets"
+        )]
+        output: Option<OutputFormat>,
+        #[command(flatten)]
+        certificate: CertificateArgs,
     },
 }
 
-fn dump_recommendation_to_file(recommendations: &[EntityRule], output: &Path) {
-    let recommendations = recommendations
-        .iter()
-        .map(|rule| {
-            let file = rule.file().unwrap_or("Unknown");
-            let line = rule.line().unwrap_or(0);
-
-            format!("{}:{}", file, line)
-        })
-        .collect::<Vec<_>>();
-
-    let recommendations = serde_yaml::to_string(&recommendations).unwrap();
-    let target_file = output.join("recommendations.yaml");
-
-    if target_file.exists() {
-        std::fs::remove_file(&target_file).expect("Failed to remove old recommendations file");
+// Manifests still using deprecated topology labels, written by `k8s go` when there are any
+pub const TOPOLOGY_MIGRATION_FILE: &str = "topology-migration.yaml";
 
+// Warns about each deprecated topology label, the labels are read as their replacement anyway
+fn warn_deprecated_topology_keys(deprecated: &[DeprecatedTopologyKey]) {
+    for key in deprecated {
         warn!(
-            "Removed old recommendations file {} before writing new one",
-            target_file.display()
+            "{}:{}: {} is deprecated, read as {}",
+            key.file, key.line, key.key, key.replacement
         );
     }
+}
+
+fn dump_topology_migration(deprecated: &[DeprecatedTopologyKey], layout: &OutputLayout) {
+    if deprecated.is_empty() {
+        return;
+    }
+
+    let manifests = deprecated
+        .iter()
+        .map(|key| key.file.as_str())
+        .collect::<BTreeSet<_>>();
+    warn!(
+        "{} deprecated topology labels in {} manifests, listed in {}",
+        deprecated.len(),
+        manifests.len(),
+        TOPOLOGY_MIGRATION_FILE
+    );
+
+    let report = serde_yaml::to_string(deprecated).expect("Failed to format topology migration");
+    layout
+        .write_artifact(TOPOLOGY_MIGRATION_FILE, "topology-migration", None, &report)
+        .expect("Failed to write topology migration");
+}
 
-    std::fs::write(&target_file, recommendations).expect("Failed to write recommendations to file");
-    info!("Dumped recommendations to {}", target_file.display());
+fn dump_recommendation_to_file(
+    recommendations: &[EntityRule],
+    entities: &[Entity],
+    conflicts: &HashMap<String, Vec<EntityRule>>,
+    layout: &OutputLayout,
+    domain: &str,
+) {
+    let recommendations = format_actions(&recommend_actions(entities, conflicts, recommendations));
+    let target_file = layout.domain_file(domain, "recommendations.yaml", "recommendations.yaml");
+
+    layout
+        .write_artifact(
+            &target_file,
+            "recommendations",
+            Some(domain),
+            &recommendations,
+        )
+        .expect("Failed to write recommendations");
+    info!(
+        "Dumped recommendations to {}",
+        layout.location(&target_file)
+    );
 }
 
 fn dump_conflicts_to_file(
     conflicts: &HashMap<String, Vec<EntityRule>>,
-    output: &Path,
-    topology: &str,
+    solvers: &ConflictSolvers,
+    scores: &HashMap<String, ImpactScore>,
+    layout: &OutputLayout,
+    domain: &str,
+    legacy: bool,
 ) {
     /*
-       Format:
-       UnscheableEntities:
-           - A:
-               - FileName:Line
-           - B
-               - FileName:Line
-               - FileName:Line
-           - C
-               - FileName:Line
+       Format, from the highest impact down:
+       unscheduable_entities:
+       - name: app=web
+         conflicts:
+         - location: web.yaml:12
+           type: require
+           targets: [app=db]
+           topology: kubernetes.io/hostname
+           rule: app=web require app=db
+         solvers: [z3]
+         impact: ...
+
+       The legacy format lists the `FileName:Line` of each rule under `conflicts` instead.
     */
+    #[derive(serde::Serialize)]
+    struct ConflictRule {
+        location: String,
+        r#type: String,
+        targets: Vec<String>,
+        topology: String,
+        rule: String,
+    }
+
+    #[derive(serde::Serialize)]
+    #[serde(untagged)]
+    enum ConflictEntry {
+        Legacy(String),
+        Rule(ConflictRule),
+    }
+
     #[derive(serde::Serialize)]
     struct Conflict {
         name: String,
-        conflicts: Vec<String>,
+        conflicts: Vec<ConflictEntry>,
+        solvers: Vec<String>,
+        impact: Option<ImpactScore>,
     }
 
     #[derive(serde::Serialize)]
@@ -134,7 +353,7 @@ fn dump_conflicts_to_file(
         unscheduable_entities: Vec<Conflict>,
     }
 
-    let conflicts = conflicts
+    let mut conflicts = conflicts
         .iter()
         .collect::<BTreeMap<_, _>>()
         .into_iter()
@@ -144,62 +363,154 @@ fn dump_conflicts_to_file(
                 .map(|rule| {
                     let file = rule.file().unwrap_or("Unknown");
                     let line = rule.line().unwrap_or(0);
+                    let location = format!("{}:{}", file, line);
+
+                    if legacy {
+                        return ConflictEntry::Legacy(location);
+                    }
 
-                    format!("{}:{}", file, line)
+                    ConflictEntry::Rule(ConflictRule {
+                        location,
+                        r#type: rule.r#type().to_string(),
+                        targets: rule
+                            .targets()
+                            .into_iter()
+                            .map(|target| target.as_ref().to_string())
+                            .collect(),
+                        topology: rule
+                            .metadata(METADATA_TOPOLOGY_KEY)
+                            .unwrap_or(domain)
+                            .to_string(),
+                        rule: rule_text(rule),
+                    })
                 })
                 .collect();
 
+            let solvers = solvers
+                .get(name)
+                .map(|e| e.iter().cloned().collect())
+                .unwrap_or_default();
+
             Conflict {
                 name: name.clone(),
                 conflicts,
+                solvers,
+                impact: scores.get(name).cloned(),
             }
         })
-        .collect();
+        .collect::<Vec<_>>();
+    conflicts.sort_by(|a, b| {
+        let score = |c: &Conflict| c.impact.as_ref().map(|i| i.score).unwrap_or_default();
+        score(b).total_cmp(&score(a))
+    });
 
     let conflicts = ConflictFile {
         unscheduable_entities: conflicts,
     };
 
     let conflicts = serde_yaml::to_string(&conflicts).unwrap();
-    let target_file = output.join(format!("conflicts-{}.yaml", topology));
+    // Older releases named the flat file after the last segment of the topology key
+    let base_topo_key = domain.rsplit('/').next().unwrap_or(domain);
+    let target_file = layout.domain_file(
+        domain,
+        "conflicts.yaml",
+        &format!("conflicts-{}.yaml", base_topo_key),
+    );
 
-    if target_file.exists() {
-        std::fs::remove_file(&target_file).expect("Failed to remove old conflicts file");
+    layout
+        .write_artifact(&target_file, "conflicts", Some(domain), &conflicts)
+        .expect("Failed to write conflicts");
+    info!("Dumped conflicts to {}", layout.location(&target_file));
+}
 
-        warn!(
-            "Removed old conflicts file {} before writing new one",
-            target_file.display()
+// One standalone IR file per group of conflicting entities, to hand each team its own slice
+fn dump_repros_to_file(
+    conflicts: &HashMap<String, Vec<EntityRule>>,
+    solvers: &ConflictSolvers,
+    entities: &[Entity],
+    layout: &OutputLayout,
+    domain: &str,
+) {
+    for (i, slice) in conflict_slices(entities, conflicts, solvers)
+        .iter()
+        .enumerate()
+    {
+        let name = slice.entities[0]
+            .name
+            .as_ref()
+            .replace(|ch: char| !ch.is_ascii_alphanumeric() && ch != '-', "_");
+        let file = format!("repro/{}-{}.ir", i + 1, name);
+        let base_topo_key = domain.rsplit('/').next().unwrap_or(domain);
+        let target_file = layout.domain_file(
+            domain,
+            &file,
+            &format!("repro-{}/{}-{}.ir", base_topo_key, i + 1, name),
         );
+
+        layout
+            .write_artifact(&target_file, "repro", Some(domain), &slice.to_ir())
+            .expect("Failed to write conflict reproduction");
+        info!("Dumped reproduction to {}", layout.location(&target_file));
     }
+}
 
-    std::fs::write(&target_file, conflicts).expect("Failed to write conflicts to file");
-    info!("Dumped conflicts to {}", target_file.display());
+// Merged entities of the manifests under `paths`, as read by `go`
+pub fn import_manifests(paths: &[PathBuf], config: &Config) -> Vec<Entity> {
+    let config: K8sConfig = config.section().unwrap_or_else(|err| {
+        error!("{:#}", err);
+        std::process::exit(1);
+    });
+    let mut k8s = K8sPlugin::new(config);
+    let paths = unique_inputs(paths, |path| k8s.accepts(path));
+    k8s.load_priority_classes(&paths);
+    k8s.load_services(&paths);
+    k8s.load_workloads(&paths);
+    k8s.load_autoscalers(&paths);
+    k8s.load_disruption_budgets(&paths);
+    k8s.load_resource_quotas(&paths);
+
+    plugin::merge::<K8sPlugin>(plugin::extract_entities(&k8s, &paths))
 }
 
-pub fn execute(command: K8SCommands) {
-    match command {
-        K8SCommands::Import { paths } => {
-            let entities = paths
-                .iter()
-                .filter_map(|path| {
-                    debug!("Importing from {}", path.display());
+pub fn execute(command: K8SCommands, run: &RunOptions, config: &Config) {
+    let suppressions = load_suppressions(config);
+    let impact_weights = load_impact_weights(config);
+    let normalizations = load_normalizations(config);
+    let mut config: K8sConfig = config.section().unwrap_or_else(|err| {
+        error!("{:#}", err);
+        std::process::exit(1);
+    });
+    let k8s_version = match &command {
+        K8SCommands::Import { k8s_version, .. }
+        | K8SCommands::Inject { k8s_version, .. }
+        | K8SCommands::Scaffold { k8s_version, .. }
+        | K8SCommands::CheckOne { k8s_version, .. }
+        | K8SCommands::Go { k8s_version, .. } => *k8s_version,
+    };
+    config.version = k8s_version.or(config.version);
 
-                    let entity = crate::plugin::k8s::K8sPlugin::extract_entity_from_path(path);
+    match command {
+        K8SCommands::Import {
+            paths,
+            k8s_version: _,
+            sink,
+        } => {
+            let sink = sink.build(Path::new(".")).unwrap_or_else(|err| {
+                error!("{:#}", err);
+                std::process::exit(1);
+            });
 
-                    match entity {
-                        Ok(entity) => {
-                            debug!("Imported entity {:?} from {}", entity, path.display());
+            let mut k8s = K8sPlugin::new(config.clone());
+            let paths = unique_inputs(&paths, |path| k8s.accepts(path));
+            k8s.load_priority_classes(&paths);
+            k8s.load_services(&paths);
+            k8s.load_workloads(&paths);
+            k8s.load_autoscalers(&paths);
+            k8s.load_disruption_budgets(&paths);
+            k8s.load_resource_quotas(&paths);
+            warn_deprecated_topology_keys(&k8s.deprecated_topology_keys(&paths));
 
-                            Some(entity)
-                        }
-                        Err(err) => {
-                            warn!("Failed to extract entity from {}: {}", path.display(), err);
-                            None
-                        }
-                    }
-                })
-                .flatten()
-                .collect::<Vec<_>>();
+            let entities = normalizations.apply(plugin::extract_entities(&k8s, &paths));
 
             match entities.is_empty() {
                 true => {
@@ -209,43 +520,249 @@ pub fn execute(command: K8SCommands) {
                 false => {}
             }
 
-            let output = DeployIRFormatter::format(&entities);
+            let output =
+                plugin::format_import(&k8s, &entities, run.solver.strict).unwrap_or_else(|err| {
+                    error!("{}", err);
+                    std::process::exit(1);
+                });
             info!("{}", output);
 
-            std::fs::write("output.ir", output).unwrap();
+            sink.write("output.ir", &output)
+                .expect("Failed to write output.ir");
         }
-        K8SCommands::Inject { output_dir, paths } => {
-            let entities = paths
+        K8SCommands::Inject {
+            output_dir,
+            paths,
+            only,
+            skip_unchanged,
+            allow_stale,
+            k8s_version: _,
+            sink,
+        } => {
+            let paths = unique_inputs(&paths, |path| {
+                path.extension().is_some_and(|ext| ext == "ir")
+            });
+            check_artifact_sources(&paths, allow_stale);
+            let sink = sink.build(&output_dir).unwrap_or_else(|err| {
+                error!("{:#}", err);
+                std::process::exit(1);
+            });
+
+            let only = only
                 .iter()
-                .flat_map(|path| {
-                    debug!("Importing from {}", path.display());
-
-                    get_parser("deployfix")
-                        .unwrap()
-                        .parse(
-                            &std::fs::read_to_string(path).unwrap(),
-                            crate::model::EntitySource::File(path.to_str().unwrap().to_string()),
-                        )
-                        .expect("Failed to parse deployfix file")
-                })
-                .collect::<Vec<_>>();
+                .map(|pattern| glob::Pattern::new(pattern))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap_or_else(|err| {
+                    error!("Invalid --only pattern: {}", err);
+                    std::process::exit(1);
+                });
+            let options = InjectOptions {
+                only,
+                skip_unchanged,
+            };
 
-            let entities = merge_entities(
-                entities,
-                Some(|a, b| match (a, b) {
-                    (EntitySource::File(a), EntitySource::File(b)) => {
-                        if !a.ends_with(".yaml") {
-                            warn!("Replacing {} with {}", a, b);
-                            *a = b;
-                        }
-                    }
-                    _ => {}
-                }),
-            );
+            let entities = plugin::merge::<K8sPlugin>(plugin::read_ir_entities(&paths));
 
             debug!("Imported entities: {:?}", entities);
 
-            inject(entities, &output_dir)
+            let k8s = K8sPlugin::new(config).with_strict(run.solver.strict);
+            inject(&k8s, entities, sink.as_ref(), &options)
+        }
+        K8SCommands::Scaffold {
+            name,
+            require,
+            exclude,
+            topology,
+            paths,
+            k8s_version: _,
+        } => {
+            let mut k8s = K8sPlugin::new(config.clone()).with_strict(run.solver.strict);
+            let entity = k8s
+                .scaffold_entity(
+                    &name,
+                    &require,
+                    &exclude,
+                    EntityRuleTopologyKey::from(topology.as_str()),
+                )
+                .unwrap_or_else(|err| {
+                    error!("{:#}", err);
+                    std::process::exit(1);
+                });
+            if entity.requires.is_empty() && entity.excludes.is_empty() {
+                error!("Nothing to scaffold, give at least one --require or --exclude");
+                std::process::exit(1);
+            }
+
+            let paths = unique_inputs(&paths, |path| k8s.accepts(path));
+            k8s.load_priority_classes(&paths);
+            k8s.load_services(&paths);
+            k8s.load_workloads(&paths);
+            k8s.load_autoscalers(&paths);
+            k8s.load_disruption_budgets(&paths);
+            k8s.load_resource_quotas(&paths);
+
+            let existing = plugin::extract_entities(&k8s, &paths);
+            if existing.iter().any(|e| e.name == entity.name) {
+                warn!(
+                    "{} already exists, checking its rules together with the requested ones",
+                    name
+                );
+            }
+            let entities = plugin::merge::<K8sPlugin>(
+                existing
+                    .into_iter()
+                    .chain(std::iter::once(entity.clone()))
+                    .collect(),
+            );
+
+            let mut report = ConflictReport::new(run.level).with_gates(run.gates.clone());
+            for (key, entities) in split_entities_by_topo_key(&entities, run.solver.strict) {
+                let entity_map: EntityMap = (&entities).try_into().unwrap();
+                let (result, _) =
+                    solve_topology(&entity_map, None, false, false, false, &run.solver);
+
+                let (result, undetermined) = result.take_undetermined();
+                if let Some(undetermined) = undetermined {
+                    report.add_undetermined(&undetermined);
+                }
+                let (result, soft) = result.take_soft();
+                report.add_soft(&soft);
+
+                if let SolverOutput::Conflict(conflicts, solvers) = result {
+                    // Conflicts the new service takes no part in are already in the manifests
+                    let (conflicts, existing): (HashMap<_, _>, HashMap<_, _>) =
+                        conflicts.into_iter().partition(|(name, rules)| {
+                            name == entity.name.as_ref()
+                                || rules.iter().any(|rule| rule.source() == &entity.name)
+                        });
+                    if !existing.is_empty() {
+                        warn!(
+                            "The manifests already have {} conflicting entities in topology {}, see `k8s go`",
+                            existing.len(),
+                            key
+                        );
+                    }
+
+                    report.add(&conflicts, &solvers, &entities);
+                }
+            }
+
+            if report.has_conflict() {
+                error!(
+                    "The requested rules of {} conflict with the manifests",
+                    name
+                );
+                report.print_summary();
+                std::process::exit(1);
+            }
+
+            if report.has_undetermined() {
+                error!("Some entities could not be checked, aborting");
+                report.print_summary();
+                std::process::exit(1);
+            }
+
+            let snippet = k8s.scaffold_affinity(entity).unwrap_or_else(|err| {
+                error!("{:#}", err);
+                std::process::exit(1);
+            });
+            info!("No conflicts found, add to the pod spec of {}:", name);
+            print!("{}", snippet);
+        }
+        K8SCommands::CheckOne {
+            file,
+            against,
+            cycle_check,
+            k8s_version: _,
+        } => {
+            let changed = file.canonicalize().unwrap_or_else(|err| {
+                error!("Failed to read {}: {}", file.display(), err);
+                std::process::exit(1);
+            });
+            let is_changed = |path: &str| {
+                Path::new(path)
+                    .canonicalize()
+                    .is_ok_and(|path| path == changed)
+            };
+
+            // The manifest replaces the entities read from its previous version
+            let model = cached_model(&against, &config, &is_changed)
+                .into_iter()
+                .filter(|e| !matches!(&e.source, EntitySource::File(path) if is_changed(path)));
+
+            let mut k8s = K8sPlugin::new(config.clone());
+            k8s.load_priority_classes(std::slice::from_ref(&file));
+            k8s.load_services(std::slice::from_ref(&file));
+            k8s.load_workloads(std::slice::from_ref(&file));
+            k8s.load_autoscalers(std::slice::from_ref(&file));
+            k8s.load_disruption_budgets(std::slice::from_ref(&file));
+            k8s.load_resource_quotas(std::slice::from_ref(&file));
+
+            let manifest = plugin::extract_entities(&k8s, std::slice::from_ref(&file));
+            if manifest.is_empty() {
+                warn!("No entities found in {}", file.display());
+                return;
+            }
+            let names = manifest
+                .iter()
+                .map(|e| e.name.as_ref().to_string())
+                .collect::<HashSet<_>>();
+
+            let entities = plugin::merge::<K8sPlugin>(model.chain(manifest).collect());
+            let mut report = ConflictReport::new(run.level)
+                .with_gates(run.gates.clone())
+                .with_suppressions(suppressions, today());
+
+            for (key, entities) in split_entities_by_topo_key(&entities, run.solver.strict) {
+                // Only the entities linked to the manifest by a rule can conflict because of it
+                let entities = solver::components(&entities)
+                    .into_iter()
+                    .filter(|component| component.iter().any(|e| names.contains(e.name.as_ref())))
+                    .flatten()
+                    .collect::<Vec<_>>();
+                if entities.is_empty() {
+                    continue;
+                }
+                debug!("Checking {} entities in topology {}", entities.len(), key);
+
+                let entity_map: EntityMap = (&entities).try_into().unwrap();
+                let (result, _) =
+                    solve_topology(&entity_map, None, cycle_check, false, false, &run.solver);
+
+                let (result, undetermined) = result.take_undetermined();
+                if let Some(undetermined) = undetermined {
+                    report.add_undetermined(&undetermined);
+                }
+                let (result, soft) = result.take_soft();
+                report.add_soft(&soft);
+
+                if let SolverOutput::Conflict(conflicts, solvers) = result {
+                    let conflicts = conflicts
+                        .into_iter()
+                        .filter(|(name, rules)| {
+                            names.contains(name)
+                                || rules
+                                    .iter()
+                                    .any(|rule| rule.file().is_some_and(&is_changed))
+                        })
+                        .collect::<HashMap<_, _>>();
+
+                    report.add(&conflicts, &solvers, &entities);
+                }
+            }
+
+            if report.has_conflict() {
+                error!(
+                    "{} conflicts with the model of {}",
+                    file.display(),
+                    against.display()
+                );
+                report.print_summary();
+                std::process::exit(1);
+            }
+
+            info!("No conflicts involving {}", file.display());
+            report.print_summary();
         }
         K8SCommands::Go {
             source_dir,
@@ -256,142 +773,226 @@ pub fn execute(command: K8SCommands) {
             env_file,
             cycle_check,
             reject_unknown,
+            fast_fail,
+            keep_going,
+            flat_output,
+            legacy_conflicts_format,
+            no_cache,
+            topology_solvers,
+            k8s_version: _,
+            sink,
+            budget,
+            output,
+            certificate,
         } => {
-            let k8s_entities = std::fs::read_dir(&source_dir)
-                .with_context(|| {
-                    format!(
-                        "Failed to read source directory: {}",
-                        source_dir.display().to_string()
-                    )
-                })
-                .unwrap()
-                .filter_map(|entry| {
-                    let entry = entry.ok()?;
-                    let file_name = entry.file_name().to_str().unwrap().to_string();
-                    let file_path = &entry.path();
-
-                    if file_name.ends_with(".yaml") {
-                        let entity =
-                            crate::plugin::k8s::K8sPlugin::extract_entity_from_path(file_path);
-
-                        match entity {
-                            Ok(entity) => return Some(entity),
-                            Err(err) => {
-                                warn!("Failed to extract entity from {}: {}", file_name, err);
-                                return None;
-                            }
-                        }
-                    }
+            let dry_run = sink.is_dry_run();
+            let sink = sink.build(&output_dir).unwrap_or_else(|err| {
+                error!("{:#}", err);
+                std::process::exit(1);
+            });
+            let manifests = unique_inputs(&[source_dir], |path| K8sPlugin::default().accepts(path));
+            let ir_files = unique_inputs(&[inject_dir], |path| {
+                path.extension().is_some_and(|ext| ext == "ir")
+            });
+            let sources = manifests
+                .iter()
+                .chain(ir_files.iter())
+                .cloned()
+                .collect::<Vec<_>>();
+            let layout = OutputLayout::new(sink.as_ref(), flat_output || config.flat_output)
+                .with_sources(SourceDigests::of(&sources));
 
-                    None
-                })
-                .flatten();
-
-            let deployfix_entities = std::fs::read_dir(inject_dir);
-            let deployfix_entities = match deployfix_entities {
-                Ok(deployfix_entities) => deployfix_entities.into_iter().collect::<Vec<_>>(),
-                Err(err) => {
-                    warn!("Failed to read inject directory: {}", err);
-                    vec![]
-                }
+            let env_files = if env_file.is_empty() {
+                config.env_file.clone().into_iter().collect()
+            } else {
+                env_file
             };
-
-            let deployfix_entities = deployfix_entities
-                .into_iter()
-                .filter_map(|entry| {
-                    let entry = entry.ok()?;
-                    let file_name = entry.file_name().to_str().unwrap().to_string();
-                    let file_path = &entry.path();
-
-                    if file_name.ends_with(".ir") {
-                        let entities = get_parser("deployfix")
-                            .unwrap()
-                            .parse(
-                                &std::fs::read_to_string(file_path).unwrap(),
-                                crate::model::EntitySource::File(
-                                    file_path.to_str().unwrap().to_string(),
-                                ),
-                            )
-                            .unwrap();
-
-                        return Some(entities);
-                    }
-
-                    None
-                })
-                .flatten()
-                .collect::<Vec<_>>();
+            let cycle_check = cycle_check || config.cycle_check;
+            let reject_unknown = reject_unknown || config.reject_unknown;
+            let legacy_conflicts_format = legacy_conflicts_format || config.legacy_conflicts_format;
+            let fast_fail = fast_fail || config.fast_fail;
+            let keep_going = keep_going || config.keep_going;
+            let topology_solvers = if topology_solvers.is_empty() {
+                config.topology_solvers.clone()
+            } else {
+                parse_topology_solvers(&topology_solvers)
+            };
+            check_topology_solvers(&topology_solvers).unwrap_or_else(|err| {
+                error!("{:#}", err);
+                std::process::exit(1);
+            });
+            let recommend_policy = recommend_policy
+                .or(config
+                    .recommend_policy
+                    .as_deref()
+                    .map(RecommendPolicy::from))
+                .unwrap_or_default();
+
+            let mut k8s = K8sPlugin::new(config.clone()).with_strict(run.solver.strict);
+            k8s.load_priority_classes(&manifests);
+            k8s.load_services(&manifests);
+            k8s.load_workloads(&manifests);
+            k8s.load_autoscalers(&manifests);
+            k8s.load_disruption_budgets(&manifests);
+            k8s.load_resource_quotas(&manifests);
+            let deprecated = k8s.deprecated_topology_keys(&manifests);
+
+            let k8s_entities = plugin::extract_entities(&k8s, &manifests);
+            let deployfix_entities = plugin::read_ir_entities(&ir_files);
 
             let has_injected_flag = !deployfix_entities.is_empty();
 
-            let entities = k8s_entities.chain(deployfix_entities).collect::<Vec<_>>();
-            let entities = merge_entities(
-                entities,
-                Some(|a, b| match (a, b) {
-                    (EntitySource::File(a), EntitySource::File(b)) => {
-                        if !a.ends_with(".yaml") {
-                            warn!("Replacing {} with {}", a, b);
-                            *a = b;
-                        }
-                    }
-                    _ => {}
-                }),
+            let (entities, renames) = normalizations.apply_with_renames(
+                k8s_entities
+                    .into_iter()
+                    .chain(deployfix_entities)
+                    .collect::<Vec<_>>(),
             );
+            let entities = plugin::merge::<K8sPlugin>(entities);
+            let k8s = k8s.with_renames(renames);
 
             debug!("Imported Entities {:?}", entities);
 
             // Dump entities
             let output = DeployIRFormatter::format(&entities);
-            std::fs::create_dir_all(&output_dir).unwrap();
-            std::fs::write(output_dir.join("dump.ir"), output).unwrap();
+            layout
+                .write_artifact("dump.ir", "dump", None, &output)
+                .unwrap();
 
-            let definitions = dump_definitions(&entities);
-            std::fs::write(output_dir.join("definitions.yaml"), definitions).unwrap();
+            let definitions = dump_definitions(&entities).unwrap_or_else(|err| {
+                error!("{:#}", err);
+                std::process::exit(1);
+            });
+            layout
+                .write_artifact(DEFINITIONS_FILE, "definitions", None, &definitions)
+                .unwrap();
+            dump_topology_migration(&deprecated, &layout);
+
+            match deploy_order(&entities) {
+                Ok(order) => layout
+                    .write_artifact(
+                        DEPLOY_ORDER_FILE,
+                        "deploy-order",
+                        None,
+                        &format_deploy_order(&order),
+                    )
+                    .unwrap(),
+                Err(err) => warn!("{}", err),
+            }
 
             // Split entities by different topologyKeys
-            let topology_split_entities = split_entities_by_topo_key(&entities);
+            let topology_split_entities = split_entities_by_topo_key(&entities, run.solver.strict);
 
-            let envs = if let Some(env_file) = env_file {
-                let env_data = std::fs::read_to_string(env_file).unwrap();
-                let env_parser = crate::model::DefaultEnvParser {};
-                env_parser.parse(&env_data).ok()
-            } else {
+            let envs = if env_files.is_empty() {
                 None
+            } else {
+                match load_env_files(&env_files) {
+                    Ok(envs) => Some(envs),
+                    Err(EnvParseError::EmptyData) => None,
+                    Err(err) => {
+                        error!("Failed to load env files: {}", err);
+                        std::process::exit(1);
+                    }
+                }
+            };
+
+            // Structured output replaces the annotated snippets on stdout
+            let level = if output.is_some() {
+                OutputLevel::Quiet
+            } else {
+                run.level
+            };
+            let mut report = ConflictReport::new(level)
+                .with_gates(run.gates.clone())
+                .with_suppressions(suppressions, today())
+                .with_impact_weights(impact_weights);
+            if output.is_some() {
+                report = report.with_session_outputs();
+            }
+            // Results of the previous run in the same output directory
+            let cache = |key: &str| {
+                if no_cache {
+                    SolveCache::default()
+                } else {
+                    SolveCache::load(&output_dir, key)
+                }
             };
 
-            let mut has_conflict = false;
+            let mut summary = RunSummary::default();
+            let mut stopped = false;
             for (key, entities) in topology_split_entities {
+                let solvers = topology_solvers.get(&key);
+                if stopped || solvers.is_some_and(|solvers| solvers.is_empty()) {
+                    info!("Skipping topology: {}", key);
+                    summary.push(DomainSummary::skipped(&key, entities.len()));
+                    continue;
+                }
                 info!("Checking topology: {}", key);
 
-                let entity_map = (&entities).try_into().unwrap();
-
-                std::fs::write(
-                    output_dir.join(format!("dump-{key}.yaml")),
-                    serde_yaml::to_string(&entity_map).unwrap(),
-                )
-                .unwrap();
+                let entity_map: EntityMap = (&entities).try_into().unwrap();
+                budget.check(
+                    &entity_map.problem_size(),
+                    "splitting the deployments into smaller source directories",
+                );
 
-                let result = {
-                    let z3_solver = get_solver("z3").unwrap();
-                    if let Some(envs) = &envs {
-                        z3_solver.set_envs(envs.clone());
-                    }
+                layout
+                    .write_artifact(
+                        &layout.domain_file(&key, "dump.yaml", &format!("dump-{key}.yaml")),
+                        "dump",
+                        Some(&key),
+                        &serde_yaml::to_string(&entity_map).unwrap(),
+                    )
+                    .unwrap();
 
-                    let mut result = z3_solver.solve(&entity_map);
-                    if cycle_check {
-                        let ring_solver = get_solver("ring").unwrap();
-                        let ring_result = ring_solver.solve(&entity_map);
+                let components = if envs.is_some() {
+                    // Env labels link every entity, the topology is solved as a whole
+                    vec![entities.clone()]
+                } else {
+                    solver::components(&entities)
+                };
+                let options = format!(
+                    "cycle_check={} relaxed_cycles={} reject_unknown={} fast_fail={} envs={} solvers={}",
+                    cycle_check,
+                    run.solver.relaxed_cycles,
+                    reject_unknown,
+                    fast_fail,
+                    envs.as_deref().map(format_envs).unwrap_or_default(),
+                    solvers.map(|solvers| solvers.join(",")).unwrap_or_default()
+                );
 
-                        result = result.merge(ring_result);
+                let started = std::time::Instant::now();
+                let mut complete = true;
+                let (result, solved) = cache(&key).solve(components, &options, |entities| {
+                    let entity_map: EntityMap = (&entities).try_into().unwrap();
+                    if let Some(solvers) = solvers {
+                        return solve_with(&entity_map, envs.as_ref(), solvers, &run.solver);
                     }
-                    if reject_unknown {
-                        let unknown_solver = get_solver("unknown").unwrap();
-                        let unknown_result = unknown_solver.solve(&entity_map);
 
-                        result = result.merge(unknown_result);
-                    }
+                    let (result, z3_skipped) = solve_topology(
+                        &entity_map,
+                        envs.as_ref(),
+                        cycle_check,
+                        reject_unknown,
+                        fast_fail,
+                        &run.solver,
+                    );
+                    complete = !z3_skipped;
+
                     result
-                };
+                });
+                run.record_solve(&SolveStats::new(
+                    "k8s go",
+                    &entity_map.problem_size(),
+                    started.elapsed(),
+                    result.get_unscheduable().map(|e| e.len()).unwrap_or(0),
+                ));
+
+                // Skipping z3 leaves the other components unchecked, a dry run writes nothing
+                if complete && !no_cache && !dry_run {
+                    if let Err(err) = solved.save(&output_dir, &key) {
+                        warn!("{:#}", err);
+                    }
+                }
 
                 // let result = if cycle_check {
                 //     let ring_solver = get_solver("ring").unwrap();
@@ -406,79 +1007,103 @@ pub fn execute(command: K8SCommands) {
                 //     z3_solver.solve(&entity_map)
                 // };
 
-                if let SolverOutput::Conflict(conflicts) = result {
+                let (result, undetermined) = result.take_undetermined();
+                let has_undetermined = undetermined.is_some();
+                if let Some(undetermined) = undetermined {
+                    report.add_undetermined(&undetermined);
+                }
+                let (result, soft) = result.take_soft();
+                report.add_soft(&soft);
+
+                let count = entities.len();
+                let reported = report.conflicting_entities().len();
+                if let SolverOutput::Conflict(conflicts, solvers) = result {
+                    report.add(&conflicts, &solvers, &entities);
+                    let scores =
+                        solver::impact_scores(&entities, conflicts.keys(), &impact_weights);
+
                     {
                         if recommend {
-                            let recommendations = match recommend_policy {
-                                RecommendPolicy::HighPriorityFirst => {
-                                    let priority_map = conflicts
-                                        .keys()
-                                        .into_iter()
-                                        .map(|e| {
-                                            (
-                                                e,
-                                                entity_map
-                                                    .entities
-                                                    .iter()
-                                                    .find(|x| x.name.0.as_str() == e)
-                                                    .unwrap()
-                                                    .priority
-                                                    .clone(),
-                                            )
-                                        })
-                                        .collect::<HashMap<_, _>>();
-
-                                    recommend_policy_high_priority_first(&priority_map, &conflicts)
-                                }
-                                RecommendPolicy::All => recommend_policy_all(&conflicts),
-                            };
-
-                            let recommendations = if recommendations.is_empty() {
-                                warn!("No recommendations found for high priority first, using default strategy");
-
-                                recommend_policy_all(&conflicts)
-                            } else {
-                                recommendations
-                            };
-
-                            dump_recommendation_to_file(&recommendations, &output_dir);
-
-                            let output_solution_dir = output_dir.join("solution");
+                            let recommendations =
+                                recommend_rules(recommend_policy, &entity_map.entities, &conflicts);
+
+                            dump_recommendation_to_file(
+                                &recommendations,
+                                &entity_map.entities,
+                                &conflicts,
+                                &layout,
+                                &key,
+                            );
 
                             remove_rules_from_entities(
+                                &k8s,
                                 entities,
                                 &recommendations,
-                                &output_solution_dir,
+                                &layout,
+                                &key,
                             );
                         }
                     }
 
-                    {
-                        let base_topo_key = if key.contains('/') {
-                            key.split('/').last().unwrap()
-                        } else {
-                            key.as_str()
-                        };
-
-                        dump_conflicts_to_file(&conflicts, &output_dir, base_topo_key);
-                    }
+                    dump_conflicts_to_file(
+                        &conflicts,
+                        &solvers,
+                        &scores,
+                        &layout,
+                        &key,
+                        legacy_conflicts_format,
+                    );
+                    dump_repros_to_file(&conflicts, &solvers, &entity_map.entities, &layout, &key);
+                }
 
-                    let conflicts_annotations = conflicts
-                        .into_iter()
-                        .flat_map(|(k, v)| v.into_iter().map(move |v| (k.clone(), v)))
-                        .map(|(name, rule)| ConflictAnnotater::new(name.as_str(), &rule).annotate())
-                        .collect::<Vec<_>>();
+                let domain = DomainSummary::new(
+                    &key,
+                    count,
+                    report.conflicting_entities().len() - reported,
+                    has_undetermined,
+                    started.elapsed(),
+                );
+                if domain.result == DomainResult::Conflict && !keep_going {
+                    warn!(
+                        "Conflicts found in topology {}, skipping the remaining topologies, pass --keep-going to check them",
+                        key
+                    );
+                    stopped = true;
+                }
+                summary.push(domain);
+            }
 
-                    let conflicts_output = conflicts_annotations.join("\n\n");
+            // Disruption budgets and quotas hold across topologies, they are checked once on all
+            // entities
+            let disruptions =
+                check_disruption_budgets(&entities, envs.as_deref().unwrap_or_default());
+            if let SolverOutput::Conflict(conflicts, solvers) = disruption_output(&disruptions) {
+                report.add(&conflicts, &solvers, &entities);
+            }
+            let quotas = check_pod_quotas(&entities, envs.as_deref().unwrap_or_default());
+            if let SolverOutput::Conflict(conflicts, solvers) = quota_output(&quotas) {
+                report.add(&conflicts, &solvers, &entities);
+            }
 
-                    error!("{}", conflicts_output);
+            info!("Topology summary:\n{}", summary.format_table().trim_end());
+            layout
+                .write_artifact(SUMMARY_FILE, "summary", None, &summary.to_yaml())
+                .unwrap();
 
-                    has_conflict = true;
-                }
+            if report.has_conflict() {
+                layout.write_index().expect("Failed to write index");
+                error!("Conflicts found, aborting");
+                print_output(&report, output);
+                report.print_summary();
+                std::process::exit(1);
             }
 
-            if has_conflict {
-                error!("Conflicts found, aborting");
+            // Injecting needs every entity to be checked
+            if report.has_undetermined() {
+                layout.write_index().expect("Failed to write index");
+                error!("Some entities could not be checked, aborting");
+                print_output(&report, output);
+                report.print_summary();
                 std::process::exit(1);
             }
 
@@ -488,62 +1113,304 @@ pub fn execute(command: K8SCommands) {
                 info!("No injected entities found, aborting");
             } else {
                 info!("Injecting entities");
-                inject(entities, &output_dir);
+                inject(&k8s, entities, &layout, &InjectOptions::default());
+            }
+
+            layout.write_index().expect("Failed to write index");
+            // The manifests and rules checked, the injected files are derived from them
+            if let Err(err) = certificate.issue("k8s go", &sources, &report) {
+                error!("{:#}", err);
+                std::process::exit(1);
             }
+            print_output(&report, output);
+            report.print_summary();
         }
     }
 }
 
-fn inject(entities: Vec<Entity>, output_dir: &Path) {
-    let mapping = crate::plugin::k8s::K8sPlugin::scan_entity_file_mapping(&entities)
-        .expect("Failed to scan entity file mapping");
-    let pods = crate::plugin::k8s::K8sPlugin::inject_entities(entities, &mapping)
-        .expect("Failed to inject entities");
+// Manifests and deployfix files under `dir`, unmerged so that the entities of one file can be
+// replaced. Kept as a snapshot in the cache directory of `dir` along with the digests of its
+// sources, and read again when one of them changed, the checked manifest `is_changed` aside.
+fn cached_model(dir: &Path, config: &K8sConfig, is_changed: impl Fn(&str) -> bool) -> Vec<Entity> {
+    let is_ir = |path: &Path| path.extension().is_some_and(|ext| ext == "ir");
+    let sources = unique_inputs(&[dir.to_path_buf()], |path| {
+        K8sPlugin::default().accepts(path) || is_ir(path)
+    });
+    let digests = SourceDigests::of(&sources);
+
+    let cache_dir = match dir.is_dir() {
+        true => dir.join(CACHE_DIR),
+        false => dir.parent().unwrap_or(Path::new(".")).join(CACHE_DIR),
+    };
+    let snapshot = cache_dir.join(format!("model.{}", SNAPSHOT_EXTENSION));
+    let recorded = cache_dir.join("model.sources");
 
-    for (base_name, spec) in pods {
-        // let output = serde_yaml::to_string(&pod).unwrap();
-        // let name = pod.metadata.name.unwrap();
-        // let name = format!("app={}", name);
-        // let path = mapping.get(&base_name).unwrap();
-        // let file_name = path.file_name().unwrap();
-        let output_path = output_dir.join(base_name);
-
-        std::fs::create_dir_all(output_path.parent().unwrap()).expect("Failed to create dir");
-        std::fs::write(output_path, spec).expect("Failed to write file");
+    let unchanged = |mut digests: SourceDigests| {
+        digests.0.retain(|path, _| !is_changed(path));
+        digests
+    };
+    let fresh = std::fs::read_to_string(&recorded).is_ok_and(|content| {
+        unchanged(SourceDigests::parse(&content)) == unchanged(digests.clone())
+    });
+    if let Some(entities) = fresh
+        .then(|| std::fs::read(&snapshot).ok())
+        .flatten()
+        .and_then(|data| decode_snapshot(&data).ok())
+    {
+        debug!("Using the cached model of {}", dir.display());
+        return entities;
+    }
+
+    info!("Building the model of {}", dir.display());
+    let (ir_files, manifests): (Vec<_>, Vec<_>) =
+        sources.iter().cloned().partition(|path| is_ir(path));
+    let mut k8s = K8sPlugin::new(config.clone());
+    k8s.load_priority_classes(&manifests);
+    k8s.load_services(&manifests);
+    k8s.load_workloads(&manifests);
+    k8s.load_autoscalers(&manifests);
+    k8s.load_disruption_budgets(&manifests);
+    k8s.load_resource_quotas(&manifests);
+
+    let entities = plugin::extract_entities(&k8s, &manifests)
+        .into_iter()
+        .chain(plugin::read_ir_entities(&ir_files))
+        .collect::<Vec<_>>();
+
+    let written = std::fs::create_dir_all(&cache_dir)
+        .and_then(|_| std::fs::write(&snapshot, encode_snapshot(&entities)))
+        .and_then(|_| std::fs::write(&recorded, digests.header("#")));
+    if let Err(err) = written {
+        warn!(
+            "Failed to cache the model in {}: {}",
+            cache_dir.display(),
+            err
+        );
+    }
+
+    entities
+}
+
+// Runs the enabled solvers on one topology, returns whether z3 was skipped by `fast_fail`
+fn solve_topology(
+    entity_map: &EntityMap,
+    envs: Option<&Vec<Env>>,
+    cycle_check: bool,
+    reject_unknown: bool,
+    fast_fail: bool,
+    options: &SolverOptions,
+) -> (SolverOutput, bool) {
+    if fast_fail {
+        let mut result = get_solver_with("structural", options)
+            .unwrap()
+            .solve(entity_map);
+        if cycle_check {
+            let ring_solver = get_solver_with("ring", options).unwrap();
+            result = result.merge(ring_solver.solve(entity_map));
+        }
+        if reject_unknown {
+            let unknown_solver = get_solver_with("unknown", options).unwrap();
+            result = result.merge(unknown_solver.solve(entity_map));
+        }
+
+        if result.is_conflict() {
+            info!("Cheap solvers reported conflicts, skipping z3");
+            return (result, true);
+        }
+
+        let z3_solver = get_solver_with("z3", options).unwrap();
+        if let Some(envs) = envs {
+            z3_solver.set_envs(envs.clone());
+        }
+
+        (z3_solver.solve(entity_map), false)
+    } else {
+        let z3_solver = get_solver_with("z3", options).unwrap();
+        if let Some(envs) = envs {
+            z3_solver.set_envs(envs.clone());
+        }
+
+        let mut result = z3_solver.solve(entity_map);
+        if cycle_check {
+            let ring_solver = get_solver_with("ring", options).unwrap();
+            let ring_result = ring_solver.solve(entity_map);
+
+            result = result.merge(ring_result);
+        }
+        if reject_unknown {
+            let unknown_solver = get_solver_with("unknown", options).unwrap();
+            let unknown_result = unknown_solver.solve(entity_map);
+
+            result = result.merge(unknown_result);
+        }
+
+        (result, false)
+    }
+}
+
+// Runs the solvers configured for a topology, in the given order
+fn solve_with(
+    entity_map: &EntityMap,
+    envs: Option<&Vec<Env>>,
+    solvers: &[String],
+    options: &SolverOptions,
+) -> SolverOutput {
+    solvers.iter().fold(SolverOutput::Ok, |result, name| {
+        let solver = get_solver_with(name, options).unwrap();
+        if let Some(envs) = envs {
+            solver.set_envs(envs.clone());
+        }
+
+        result.merge(solver.solve(entity_map))
+    })
+}
+
+// `node=z3,ring zone=ring` of `--topology-solvers`, `none` leaving the topology unchecked
+fn parse_topology_solvers(specs: &[String]) -> BTreeMap<String, Vec<String>> {
+    specs
+        .iter()
+        .map(|spec| {
+            let (topology, solvers) = spec.split_once('=').unwrap_or_else(|| {
+                error!(
+                    "Invalid --topology-solvers `{}`, expecting TOPOLOGY=SOLVERS",
+                    spec
+                );
+                std::process::exit(1);
+            });
+            let solvers = solvers
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty() && *name != "none")
+                .map(String::from)
+                .collect();
+
+            (topology.trim().to_string(), solvers)
+        })
+        .collect()
+}
+
+fn check_topology_solvers(topology_solvers: &BTreeMap<String, Vec<String>>) -> anyhow::Result<()> {
+    for (topology, solvers) in topology_solvers {
+        if !["node", "zone", "rack"].contains(&topology.as_str()) {
+            anyhow::bail!(
+                "Unknown topology `{}` in the topology solvers, expecting node, zone or rack",
+                topology
+            );
+        }
+
+        if let Some(name) = solvers
+            .iter()
+            .find(|name| !SOLVER_NAMES.contains(&name.as_str()))
+        {
+            anyhow::bail!(
+                "Unknown solver `{}` for topology {}, expecting one of {}",
+                name,
+                topology,
+                SOLVER_NAMES.join(", ")
+            );
+        }
     }
+
+    Ok(())
+}
+
+fn inject(k8s: &K8sPlugin, entities: Vec<Entity>, sink: &dyn ReportSink, options: &InjectOptions) {
+    plugin::inject(k8s, entities, sink, options).expect("Failed to inject entities");
 }
 
-fn remove_rules_from_entities(entities: Vec<Entity>, rules: &[EntityRule], output_dir: &Path) {
+fn remove_rules_from_entities(
+    k8s: &K8sPlugin,
+    entities: Vec<Entity>,
+    rules: &[EntityRule],
+    layout: &OutputLayout,
+    domain: &str,
+) {
     let mapping = crate::plugin::k8s::K8sPlugin::scan_entity_file_mapping(&entities)
         .expect("Failed to scan entity file mapping");
-    let pods = crate::plugin::k8s::K8sPlugin::remove_rules_from_entities(entities, rules, &mapping)
+    let pods = k8s
+        .remove_rules_from_entities(entities, rules, &mapping)
         .expect("Failed to remove entities");
 
     for (base_name, spec) in pods {
-        let output_path = output_dir.join(base_name);
+        let name = layout.domain_file(
+            domain,
+            &format!("solution/{}", base_name),
+            &format!("solution/{}", base_name),
+        );
 
-        std::fs::create_dir_all(output_path.parent().unwrap()).expect("Failed to create dir");
-        std::fs::write(output_path, spec).expect("Failed to write file");
+        layout
+            .write_artifact(&name, "solution", Some(domain), &spec)
+            .expect("Failed to write file");
     }
 }
 
-fn split_entities_by_topo_key(entities: &[Entity]) -> HashMap<String, Vec<Entity>> {
-    util::split_by_metadata(entities, "topology", "node")
+fn split_entities_by_topo_key(entities: &[Entity], strict: bool) -> HashMap<String, Vec<Entity>> {
+    util::split_by_metadata(entities, "topology", "node", strict).unwrap_or_else(|err| {
+        error!("{:#}", err);
+        std::process::exit(1);
+    })
+}
+
+/*
+    Rules to change so the conflicting entities become schedulable, picked by `policy`. The
+    rules of the highest priority tier in conflict go first, the fewest rules covering every
+    conflict when no tier stands out.
+*/
+pub(crate) fn recommend_rules(
+    policy: RecommendPolicy,
+    entities: &[Entity],
+    conflicts: &HashMap<String, Vec<EntityRule>>,
+) -> Vec<EntityRule> {
+    let recommendations = match policy {
+        RecommendPolicy::HighPriorityFirst => {
+            let priority_map = conflicts
+                .keys()
+                .map(|e| {
+                    let priority = entities
+                        .iter()
+                        .find(|x| x.name.0.as_str() == e)
+                        .map(|x| x.priority.clone())
+                        .unwrap_or_default();
+
+                    (e, priority)
+                })
+                .collect::<HashMap<_, _>>();
+
+            recommend_policy_high_priority_first(&priority_map, conflicts)
+        }
+        RecommendPolicy::All => recommend_policy_all(conflicts),
+    };
+
+    let recommendations = if recommendations.is_empty() {
+        warn!("No recommendations found for high priority first, using default strategy");
+
+        recommend_policy_all(conflicts)
+    } else {
+        recommendations
+    };
+
+    // Network hints are not in any manifest, the affinities they conflict with have to change
+    // instead
+    recommendations
+        .into_iter()
+        .filter(|rule| rule.hint().is_none())
+        .collect()
 }
 
 fn recommend_policy_high_priority_first(
     priority_map: &HashMap<&String, EntityPriority>,
     conflicts: &HashMap<String, Vec<EntityRule>>,
 ) -> Vec<EntityRule> {
+    // Rules of the highest tier in conflict go first, nothing stands out when that is the
+    // default tier
+    let top = match priority_map.values().min() {
+        Some(top) if *top < EntityPriority::Default => top,
+        _ => return vec![],
+    };
+
     let critical_apps = priority_map
         .iter()
-        .filter_map(|(k, v)| {
-            if *v == EntityPriority::Critical {
-                Some(k.as_str())
-            } else {
-                None
-            }
-        })
+        .filter_map(|(k, v)| if v == top { Some(k.as_str()) } else { None })
         .collect::<HashSet<_>>();
 
     let critical_conflicts = conflicts
@@ -561,6 +1428,14 @@ fn recommend_policy_high_priority_first(
         .cloned()
         .collect::<Vec<_>>();
 
+    // Rules deployfix injected itself are changed before the user's own rules
+    if critical_conflicts.iter().any(|r| r.is_generated()) {
+        return critical_conflicts
+            .into_iter()
+            .filter(|r| r.is_generated())
+            .collect();
+    }
+
     return critical_conflicts;
 }
 
@@ -588,7 +1463,12 @@ fn recommend_policy_all(conflicts: &HashMap<String, Vec<EntityRule>>) -> Vec<Ent
         .into_iter()
         .collect::<Vec<_>>();
 
-    rule_count.sort_by(|a, b| b.1.cmp(&a.1));
+    // Rules deployfix injected itself go first, they are cheaper to change than user rules
+    rule_count.sort_by(|a, b| {
+        b.0.is_generated()
+            .cmp(&a.0.is_generated())
+            .then(b.1.cmp(&a.1))
+    });
 
     debug!("Conflict order: {:?}", rule_count);
 
@@ -613,132 +1493,3 @@ fn recommend_policy_all(conflicts: &HashMap<String, Vec<EntityRule>>) -> Vec<Ent
 
     rules
 }
-
-enum DefinitionEntry {
-    Source {
-        name: String,
-        file: String,
-    },
-    Reference {
-        name: String,
-        file: String,
-        line: usize,
-    },
-}
-
-fn dump_definition(entity: &Entity) -> Vec<DefinitionEntry> {
-    let name = entity.name.0.clone();
-    let source = entity.source.as_ref().to_string();
-
-    let mut ret = vec![DefinitionEntry::Source { name, file: source }];
-
-    for rule in entity.rules() {
-        match rule {
-            EntityRule::Mono {
-                source,
-                target,
-                r#type,
-                rule_source,
-                metadata,
-            } => {
-                let name = target.0.clone();
-                let file = rule_source.file().unwrap_or("unknown").to_string();
-                let line = rule_source.line().unwrap_or(0);
-
-                ret.push(DefinitionEntry::Reference { name, file, line });
-            }
-            EntityRule::Multi {
-                source,
-                targets,
-                r#type,
-                rule_source,
-                metadata,
-            } => {
-                for target in targets {
-                    let name = target.0.clone();
-                    let file = rule_source.file().unwrap_or("unknown").to_string();
-                    let line = rule_source.line().unwrap_or(0);
-
-                    ret.push(DefinitionEntry::Reference { name, file, line });
-                }
-            }
-        }
-    }
-
-    ret
-}
-
-#[derive(serde::Serialize)]
-struct Definition {
-    name: String,
-    source: String,
-    references: Vec<String>,
-}
-
-fn dump_definitions(entities: &[Entity]) -> String {
-    let definitions = entities
-        .iter()
-        .map(|e| dump_definition(e))
-        .flatten()
-        .collect::<Vec<_>>();
-
-    let (sources, references) = definitions.into_iter().fold(
-        (Vec::new(), Vec::new()),
-        |(mut sources, mut references), e| {
-            if matches!(e, DefinitionEntry::Source { .. }) {
-                sources.push(e);
-            } else {
-                references.push(e);
-            }
-
-            (sources, references)
-        },
-    );
-
-    let mut definitions =
-        sources
-            .into_iter()
-            .fold(HashMap::<String, Definition>::new(), |mut acc, e| {
-                if let DefinitionEntry::Source { name, file } = e {
-                    let d = Definition {
-                        name: name.clone(),
-                        source: file,
-                        references: vec![],
-                    };
-
-                    if acc.contains_key(&name) {
-                        panic!("Duplicate definition found: {}", name);
-                    } else {
-                        acc.insert(name, d);
-                    }
-
-                    acc
-                } else {
-                    unreachable!()
-                }
-            });
-
-    for e in references {
-        if let DefinitionEntry::Reference { name, file, line } = e {
-            if let Some(d) = definitions.get_mut(&name) {
-                d.references.push(format!("{}:{}", file, line));
-            } else {
-                definitions.insert(
-                    name.clone(),
-                    Definition {
-                        name: name.clone(),
-                        source: "unknown".to_string(),
-                        references: vec![format!("{}:{}", file, line)],
-                    },
-                );
-            }
-        } else {
-            unreachable!()
-        }
-    }
-
-    let sources = definitions.into_iter().map(|e| e.1).collect::<Vec<_>>();
-    let sources = serde_yaml::to_string(&sources).unwrap();
-
-    sources
-}
@@ -0,0 +1,114 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use serde::Deserialize;
+
+use crate::{cli::ConfigSection, model::EntityRuleTopologyKey};
+
+use super::{plugin::RACK_TOPOLOGY_LABEL, version::K8sVersion};
+
+// `k8s` section of the config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct K8sConfig {
+    // Node label of the rack topology
+    pub rack_label: String,
+    // Kinds of manifests rules are read from: deployment, statefulset, daemonset, replicaset,
+    // pod, job, cronjob, node, and the opt-in service and networkpolicy, which only add
+    // dependency hints
+    pub resource_types: Vec<String>,
+    // Kubernetes version the manifests are deployed to, unsupported fields are warned about
+    // and left out of the injected terms. Every field is used without one.
+    pub version: Option<K8sVersion>,
+    // Run time assumed for the jobs of a CronJob without `activeDeadlineSeconds`
+    pub job_duration_minutes: u32,
+    // Defaults of the `go` flags, a flag given on the command line always wins
+    pub env_file: Option<PathBuf>,
+    pub cycle_check: bool,
+    pub reject_unknown: bool,
+    pub fast_fail: bool,
+    pub keep_going: bool,
+    pub flat_output: bool,
+    pub legacy_conflicts_format: bool,
+    pub recommend_policy: Option<String>,
+    // Solvers run on each topology (node, zone or rack) instead of the ones picked by the
+    // flags above, an empty list skips the topology
+    pub topology_solvers: BTreeMap<String, Vec<String>>,
+    // Deprecated topology labels read and injected as their replacement, besides the built-in
+    // `failure-domain.beta.kubernetes.io` zone and region, e.g. `example.com/old-rack: example.com/rack`
+    pub topology_aliases: BTreeMap<String, String>,
+    // Rules read from the annotations of resources of any kind, by annotation key
+    pub annotation_rules: BTreeMap<String, AnnotationRule>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnotationRuleType {
+    Require,
+    Exclude,
+}
+
+// A rule between the resource annotated and each comma separated entity of the annotation, e.g.
+//
+//   annotation_rules:
+//     mycompany.io/colocate-with:
+//       rule: require
+//     mycompany.io/spread-from:
+//       rule: exclude
+//       target: tier={}
+//       topology: zone
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnnotationRule {
+    pub rule: AnnotationRuleType,
+    // Entity of each value, `{}` is replaced by the value
+    #[serde(default = "AnnotationRule::default_target")]
+    pub target: String,
+    // node, zone or rack, anything else fails the config
+    #[serde(default = "AnnotationRule::default_topology")]
+    pub topology: EntityRuleTopologyKey,
+}
+
+impl AnnotationRule {
+    fn default_target() -> String {
+        "app={}".into()
+    }
+
+    fn default_topology() -> EntityRuleTopologyKey {
+        EntityRuleTopologyKey::Node
+    }
+}
+
+impl Default for K8sConfig {
+    fn default() -> Self {
+        Self {
+            rack_label: RACK_TOPOLOGY_LABEL.to_string(),
+            resource_types: vec![
+                "deployment".into(),
+                "statefulset".into(),
+                "daemonset".into(),
+                "replicaset".into(),
+                "pod".into(),
+                "job".into(),
+                "cronjob".into(),
+                "node".into(),
+            ],
+            version: None,
+            job_duration_minutes: 60,
+            env_file: None,
+            cycle_check: false,
+            reject_unknown: false,
+            fast_fail: false,
+            keep_going: false,
+            flat_output: false,
+            legacy_conflicts_format: false,
+            recommend_policy: None,
+            topology_solvers: BTreeMap::new(),
+            topology_aliases: BTreeMap::new(),
+            annotation_rules: BTreeMap::new(),
+        }
+    }
+}
+
+impl ConfigSection for K8sConfig {
+    const NAME: &'static str = "k8s";
+}
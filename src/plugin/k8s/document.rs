@@ -0,0 +1,90 @@
+use std::path::Path;
+
+use crate::util;
+
+/*
+    A document of a manifest holding several resources separated by `---`. The spans read from
+    its text are moved by `lines_before` and `offset` to be the lines and offsets of the whole
+    file.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document<'a> {
+    // As written in the file, without its separator
+    pub text: &'a str,
+    // Lines and bytes of the file before the document
+    pub lines_before: usize,
+    pub offset: usize,
+}
+
+impl Document<'_> {
+    // First line of the document in the file
+    pub fn line(&self) -> usize {
+        self.lines_before + 1
+    }
+}
+
+fn is_separator(line: &str) -> bool {
+    line.strip_prefix("---").is_some_and(|rest| {
+        let rest = rest.trim();
+        rest.is_empty() || rest.starts_with('#')
+    })
+}
+
+// Documents made only of comments and blank lines (e.g. before a leading `---`) are left out
+fn is_blank(text: &str) -> bool {
+    text.lines().all(|line| {
+        let line = line.trim();
+        line.is_empty() || line.starts_with('#')
+    })
+}
+
+// Documents of a manifest, a file without separators is a single document
+pub fn documents(data: &str) -> Vec<Document<'_>> {
+    let mut bounds = vec![];
+    let (mut start, mut start_line) = (0, 0);
+    let mut offset = 0;
+
+    for (index, line) in data.split_inclusive('\n').enumerate() {
+        if is_separator(line) {
+            bounds.push((start, offset, start_line));
+            start = offset + line.len();
+            start_line = index + 1;
+        }
+        offset += line.len();
+    }
+    bounds.push((start, data.len(), start_line));
+
+    bounds
+        .into_iter()
+        .map(|(start, end, lines_before)| Document {
+            text: &data[start..end],
+            lines_before,
+            offset: start,
+        })
+        .filter(|document| !is_blank(document.text))
+        .collect()
+}
+
+pub fn read_documents(path: &Path) -> std::io::Result<Vec<String>> {
+    let data = util::read_source(path)?;
+
+    Ok(documents(&data)
+        .into_iter()
+        .map(|document| document.text.to_string())
+        .collect())
+}
+
+// Puts rewritten documents back into one manifest
+pub fn join_documents(documents: &[String]) -> String {
+    documents
+        .iter()
+        .map(|document| {
+            if document.ends_with('\n') {
+                document.clone()
+            } else {
+                format!("{}\n", document)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("---\n")
+}
@@ -1,5 +1,11 @@
 mod cli;
+mod config;
+mod document;
 mod plugin;
+mod version;
 
-pub use cli::{execute, K8SCommands};
-pub use plugin::K8sPlugin;
+pub(crate) use cli::recommend_rules;
+pub use cli::{execute, import_manifests, K8SCommands, RecommendPolicy};
+pub use config::K8sConfig;
+pub use plugin::{DeprecatedTopologyKey, K8sPlugin};
+pub use version::K8sVersion;
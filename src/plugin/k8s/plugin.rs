@@ -1,128 +1,1829 @@
 use std::{
     collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    ffi::OsStr,
     num::NonZeroUsize,
     path::{Path, PathBuf},
 };
 
-use crate::model::{
-    Entity, EntityName, EntityPriority, EntityRule, EntityRuleMetadata, EntityRuleSource,
-    EntityRuleTopologyKey, EntityRuleType, EntitySource, METADATA_TOPOLOGY_KEY,
+use crate::{
+    model::{
+        rule_text, Entity, EntityName, EntityPriority, EntityRule, EntityRuleMetadata,
+        EntityRuleSource, EntityRuleTopologyKey, EntityRuleType, EntitySource, Selector,
+        SelectorOperator, SourceSpan, TimeWindows, METADATA_ANNOTATION_KEY, METADATA_DESC_KEY,
+        METADATA_GENERATED_KEY, METADATA_HINT_KEY, METADATA_MAX_REPLICAS_KEY,
+        METADATA_MAX_UNAVAILABLE_KEY, METADATA_MIN_AVAILABLE_KEY, METADATA_MIN_REPLICAS_KEY,
+        METADATA_NAMESPACE_KEY, METADATA_OWNER_KEY, METADATA_POD_KEY, METADATA_POD_QUOTA_KEY,
+        METADATA_REPLICAS_KEY, METADATA_TOPOLOGY_KEY, METADATA_WEIGHT_KEY, METADATA_WINDOW_KEY,
+    },
+    plugin::{self, DeployPlugin},
+    util,
+};
+
+use super::{
+    config::{AnnotationRuleType, K8sConfig},
+    document::{documents, join_documents, read_documents, Document},
+    version::{AFFINITY_TERM_FIELDS, CRON_JOB_V1, TOPOLOGY_LABELS},
 };
 use anyhow::Context;
 use k8s_openapi::{
     api::{
-        apps::v1::Deployment,
+        apps::v1::{DaemonSet, Deployment, ReplicaSet, StatefulSet},
+        autoscaling::{
+            v1::HorizontalPodAutoscaler as HorizontalPodAutoscalerV1, v2::HorizontalPodAutoscaler,
+        },
+        batch::v1::{CronJob, Job},
         core::v1::{
-            Node, NodeAffinity, NodeSelectorRequirement, NodeSelectorTerm, Pod, PodAffinity,
-            PodAffinityTerm, PodAntiAffinity, PodSpec,
+            Affinity, Namespace, Node, NodeAffinity, NodeSelectorRequirement, NodeSelectorTerm,
+            Pod, PodAffinity, PodAffinityTerm, PodAntiAffinity, PodSpec, ResourceQuota, Service,
+            WeightedPodAffinityTerm,
         },
+        networking::v1::{NetworkPolicy, NetworkPolicyPeer},
+        policy::v1::PodDisruptionBudget,
+        scheduling::v1::PriorityClass,
+    },
+    apimachinery::pkg::{
+        apis::meta::v1::{LabelSelector, LabelSelectorRequirement, ObjectMeta},
+        util::intstr::IntOrString,
     },
-    apimachinery::pkg::apis::meta::v1::{LabelSelector, LabelSelectorRequirement},
 };
 use log::{debug, warn};
+use serde::{Deserialize, Serialize};
 
 use serde_yaml::Spanned;
 
-pub const METADATA_RESOURCE_TYPE_KEY: &str = "resource_type";
+pub const METADATA_RESOURCE_TYPE_KEY: &str = "resource_type";
+// JSON list of the signatures of the rules deployfix injected into a manifest, so that they
+// are told apart from the user's own terms when the manifest is imported again
+pub const GENERATED_RULES_ANNOTATION: &str = "deployfix.io/generated-rules";
+// Daily windows (`HH:MM-HH:MM,...`) a workload runs in, overrides the schedule of a CronJob
+pub const WINDOW_ANNOTATION: &str = "deployfix.io/window";
+// Why the affinity terms of a workload exist, carried to its rules as their `desc` metadata
+pub const DESC_ANNOTATION: &str = "deployfix.io/desc";
+// Team answering for a workload, carried to its rules as their `owner` metadata
+pub const OWNER_ANNOTATION: &str = "deployfix.io/owner";
+// Fields of an affinity term deployfix does not model (e.g. `namespaceSelector`, `matchLabels`
+// or `matchFields`), kept as a JSON object with `%` and `;` percent-encoded and put back
+// into the term on injection
+pub const METADATA_PASSTHROUGH_KEY: &str = "passthrough";
+// Label selector of an affinity term resolved against the labels of the workloads, when it
+// does not name them with a single `app` expression (e.g. `tier=db, app in (a, b)`)
+pub const METADATA_SELECTOR_KEY: &str = "selector";
+// `type` of the rules read from the `nodeSelector` and `nodeName` of a pod spec, written back
+// there instead of into its node affinity
+pub const NODE_SELECTOR_TYPE: &str = "nodeSelector";
+pub const NODE_NAME_TYPE: &str = "nodeName";
+
+// Kind and metadata of any manifest, e.g. its namespace
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    kind: String,
+    #[serde(default)]
+    metadata: ObjectMeta,
+}
+
+pub const HOSTNAME_TOPOLOGY_LABEL: &str = "kubernetes.io/hostname";
+pub const ZONE_TOPOLOGY_LABEL: &str = "topology.kubernetes.io/zone";
+pub const RACK_TOPOLOGY_LABEL: &str = "topology.kubernetes.io/rack";
+pub const REGION_TOPOLOGY_LABEL: &str = "topology.kubernetes.io/region";
+// Zone and region labels of the nodes of Kubernetes 1.16 and older
+pub const LEGACY_ZONE_TOPOLOGY_LABEL: &str = "failure-domain.beta.kubernetes.io/zone";
+pub const LEGACY_REGION_TOPOLOGY_LABEL: &str = "failure-domain.beta.kubernetes.io/region";
+// Deprecated topology labels read as their replacement, besides the `topology_aliases` config
+pub const DEPRECATED_TOPOLOGY_LABELS: &[(&str, &str)] = &[
+    (LEGACY_ZONE_TOPOLOGY_LABEL, ZONE_TOPOLOGY_LABEL),
+    (LEGACY_REGION_TOPOLOGY_LABEL, REGION_TOPOLOGY_LABEL),
+];
+
+// A deprecated topology label still written in a manifest, listed by the migration report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeprecatedTopologyKey {
+    pub file: String,
+    pub line: usize,
+    pub key: String,
+    pub replacement: String,
+}
+
+// Value of `METADATA_HINT_KEY` on the requires derived from Services and NetworkPolicies
+pub const NETWORK_HINT: &str = "network";
+
+// Reads the manifest of a path, from disk or from the manifests given in memory
+type ManifestReader<'a> = dyn Fn(&Path) -> anyhow::Result<String> + 'a;
+
+// Priority classes are resolved against the PriorityClass manifests found by
+// `load_priority_classes`, besides the built-in system classes
+#[derive(Debug, Default)]
+pub struct K8sPlugin {
+    priority_classes: HashMap<String, i32>,
+    // Value of the class marked `globalDefault`, used by pods without a class
+    default_priority: Option<i32>,
+    // Entities selected by each Service found by `load_services`
+    services: HashMap<String, Vec<String>>,
+    // Pod labels of each workload entity found by `load_workloads`
+    workloads: BTreeMap<String, BTreeMap<String, String>>,
+    // Minimum and maximum replicas of each workload entity scaled by one of the
+    // HorizontalPodAutoscalers found by `load_autoscalers`
+    autoscalers: HashMap<String, (Option<i32>, i32)>,
+    // Metadata key and value of the budget of each workload entity selected by one of the
+    // PodDisruptionBudgets found by `load_disruption_budgets`
+    disruption_budgets: HashMap<String, (&'static str, String)>,
+    // Namespaces declared by the Namespace objects found by `load_resource_quotas`, and the
+    // tightest pod count of the ResourceQuotas of each namespace
+    namespaces: BTreeSet<String>,
+    pod_quotas: HashMap<String, usize>,
+    config: K8sConfig,
+    // Rules injected without a setting fail instead of taking its default, see `with_strict`
+    strict: bool,
+    // Entity names the workloads were normalized to, see `with_renames`
+    renames: HashMap<String, String>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum ResourceType {
+    Pod,
+    Deployment,
+    StatefulSet,
+    DaemonSet,
+    ReplicaSet,
+    Node,
+    Job,
+    CronJob,
+    Service,
+    NetworkPolicy,
+}
+
+impl DeployPlugin for K8sPlugin {
+    const FILE_EXTENSION: &'static str = "yaml";
+
+    fn extract_entities(&self, path: &Path) -> anyhow::Result<Vec<Entity>> {
+        self.extract_entity_from_path(path)
+    }
+
+    fn inject_entities(&self, entities: Vec<Entity>) -> anyhow::Result<Vec<(String, String)>> {
+        let mapping = Self::scan_entity_file_mapping(&entities)?;
+
+        self.inject_entities_into(entities, &mapping)
+    }
+
+    // Manifests are compared document by document as YAML, a hand-written file and its
+    // injected copy differ in formatting only
+    fn same_content(&self, a: &str, b: &str) -> bool {
+        let values = |data: &str| {
+            documents(data)
+                .iter()
+                .map(|document| serde_yaml::from_str::<serde_yaml::Value>(document.text))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        match (values(a), values(b)) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => a == b,
+        }
+    }
+}
+
+impl AsRef<str> for ResourceType {
+    fn as_ref(&self) -> &str {
+        match self {
+            Self::Pod => "pod",
+            Self::Deployment => "deployment",
+            Self::StatefulSet => "statefulset",
+            Self::DaemonSet => "daemonset",
+            Self::ReplicaSet => "replicaset",
+            Self::Node => "node",
+            Self::Job => "job",
+            Self::CronJob => "cronjob",
+            Self::Service => "service",
+            Self::NetworkPolicy => "networkpolicy",
+        }
+    }
+}
+
+impl TryFrom<&str> for ResourceType {
+    type Error = &'static str;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "pod" => Ok(Self::Pod),
+            "deployment" => Ok(Self::Deployment),
+            "statefulset" => Ok(Self::StatefulSet),
+            "daemonset" => Ok(Self::DaemonSet),
+            "replicaset" => Ok(Self::ReplicaSet),
+            "job" => Ok(Self::Job),
+            "cronjob" => Ok(Self::CronJob),
+            "service" => Ok(Self::Service),
+            "networkpolicy" => Ok(Self::NetworkPolicy),
+            _ => Err("unknown resource type"),
+        }
+    }
+}
+
+// Operators of a match expression modeled as rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExpressionOperator {
+    In,
+    NotIn,
+}
+
+impl ExpressionOperator {
+    // Exists, DoesNotExist, Gt and Lt have no rule to be modeled as, the expression is skipped
+    fn supported(operator: &str, expr: &impl std::fmt::Debug) -> Option<Self> {
+        match operator {
+            "In" => Some(Self::In),
+            "NotIn" => Some(Self::NotIn),
+            _ => {
+                warn!(
+                    "Operator `{}` is not supported yet, skipping {:?}",
+                    operator, expr
+                );
+                None
+            }
+        }
+    }
+}
+
+impl K8sPlugin {
+    // Plugin reading and writing manifests with the `k8s` config section, `default` uses the
+    // defaults of the section
+    pub fn new(config: K8sConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    // Fails the injects of rules missing a topology key, key or operator
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    // Names entities were renamed to after extraction, by their extracted name, so that the
+    // documents of a manifest still find their entity when rewritten
+    pub fn with_renames(mut self, renames: HashMap<String, String>) -> Self {
+        self.renames = renames;
+        self
+    }
+
+    fn enabled(&self, resource_type: ResourceType) -> bool {
+        self.config
+            .resource_types
+            .iter()
+            .any(|t| t == resource_type.as_ref())
+    }
+
+    fn scans(&self, resource_type: ResourceType, path: &Path) -> bool {
+        let scans = self.enabled(resource_type);
+
+        if !scans {
+            debug!(
+                "Skipping {} of type {}",
+                path.display(),
+                resource_type.as_ref()
+            );
+        }
+
+        scans
+    }
+
+    // Fields of affinity terms the target Kubernetes version does not serve, none without one
+    fn unsupported_term_fields(&self) -> Vec<&'static str> {
+        match self.config.version {
+            Some(version) => version
+                .unsupported(AFFINITY_TERM_FIELDS)
+                .into_iter()
+                .map(|field| field.name)
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    // Warns about the fields of a manifest the target Kubernetes version does not serve, the
+    // API server of that version would drop them or reject the manifest
+    fn check_version(&self, document: &Document, path: &Path) {
+        let Some(version) = self.config.version else {
+            return;
+        };
+        let Ok(value) = serde_yaml::from_str::<serde_yaml::Value>(document.text) else {
+            return;
+        };
+
+        // Keys used anywhere under the pod (anti-)affinity of the manifest
+        fn affinity_keys<'a>(
+            value: &'a serde_yaml::Value,
+            inside: bool,
+            keys: &mut BTreeSet<&'a str>,
+        ) {
+            match value {
+                serde_yaml::Value::Mapping(mapping) => {
+                    for (key, value) in mapping.iter() {
+                        let key = key.as_str().unwrap_or_default();
+                        if inside {
+                            keys.insert(key);
+                        }
+
+                        let inside = inside || key == "podAffinity" || key == "podAntiAffinity";
+                        affinity_keys(value, inside, keys);
+                    }
+                }
+                serde_yaml::Value::Sequence(sequence) => {
+                    for value in sequence.iter() {
+                        affinity_keys(value, inside, keys);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut keys = BTreeSet::new();
+        affinity_keys(&value, false, &mut keys);
+
+        let mut unsupported = version
+            .unsupported(AFFINITY_TERM_FIELDS)
+            .into_iter()
+            .filter(|field| keys.contains(field.name))
+            .collect::<Vec<_>>();
+
+        let kind = value.get("kind").and_then(|kind| kind.as_str());
+        let api_version = value.get("apiVersion").and_then(|v| v.as_str());
+        if kind == Some("CronJob")
+            && api_version == Some(CRON_JOB_V1.name)
+            && !version.supports(&CRON_JOB_V1)
+        {
+            unsupported.push(&CRON_JOB_V1);
+        }
+
+        for field in unsupported {
+            warn!(
+                "{}:{}: {}, the target is {}",
+                path.display(),
+                document.lines_before + Self::line_of(document.text, field.name),
+                field,
+                version
+            );
+        }
+    }
+
+    // Topology key of a generated term as served by the target Kubernetes version
+    fn versioned_topology_key<'a>(&self, topology_key: &'a str) -> &'a str {
+        let legacy = self
+            .config
+            .version
+            .is_some_and(|version| !version.supports(&TOPOLOGY_LABELS));
+
+        match topology_key {
+            ZONE_TOPOLOGY_LABEL if legacy => LEGACY_ZONE_TOPOLOGY_LABEL,
+            REGION_TOPOLOGY_LABEL if legacy => LEGACY_REGION_TOPOLOGY_LABEL,
+            topology_key => topology_key,
+        }
+    }
+
+    // Replacement of a deprecated topology label, from the config first
+    fn topology_alias(&self, label: &str) -> Option<&str> {
+        self.config
+            .topology_aliases
+            .get(label)
+            .map(String::as_str)
+            .or_else(|| {
+                DEPRECATED_TOPOLOGY_LABELS
+                    .iter()
+                    .find(|(deprecated, _)| *deprecated == label)
+                    .map(|(_, replacement)| *replacement)
+            })
+    }
+
+    // Topology label as read and written by the plugin, deprecated ones are replaced
+    fn canonical_topology_key<'a>(&'a self, label: &'a str) -> &'a str {
+        self.topology_alias(label).unwrap_or(label)
+    }
+
+    /*
+        Deprecated topology labels written in the manifests under `paths`, anywhere (topology
+        keys, node affinities or node labels), in file then line order. Each one is read as its
+        replacement, the report tells which manifests still need migrating.
+    */
+    pub fn deprecated_topology_keys(&self, paths: &[PathBuf]) -> Vec<DeprecatedTopologyKey> {
+        let files = plugin::expand_paths(paths, |path| self.accepts(path));
+        // The beta labels are the only ones served by a target older than their replacement
+        let legacy = self
+            .config
+            .version
+            .is_some_and(|version| !version.supports(&TOPOLOGY_LABELS));
+        let aliases = DEPRECATED_TOPOLOGY_LABELS
+            .iter()
+            .filter(|_| !legacy)
+            .map(|(deprecated, _)| *deprecated)
+            .chain(self.config.topology_aliases.keys().map(String::as_str))
+            .collect::<BTreeSet<_>>();
+
+        let mut found = vec![];
+        for path in files {
+            let Ok(data) = util::read_source(&path) else {
+                continue;
+            };
+
+            for (index, line) in data.lines().enumerate() {
+                // Comments may name the deprecated labels they replaced
+                if line.trim_start().starts_with('#') {
+                    continue;
+                }
+
+                for key in aliases.iter().filter(|key| line.contains(*key)) {
+                    found.push(DeprecatedTopologyKey {
+                        file: path.display().to_string(),
+                        line: index + 1,
+                        key: key.to_string(),
+                        replacement: self.canonical_topology_key(key).to_string(),
+                    });
+                }
+            }
+        }
+
+        found
+    }
+
+    // Collects the PriorityClass manifests under `paths`, directories are scanned one level deep
+    pub fn load_priority_classes(&mut self, paths: &[PathBuf]) {
+        let files = plugin::expand_paths(paths, |path| self.accepts(path));
+
+        for path in files {
+            for data in read_documents(&path).unwrap_or_default() {
+                let Ok(class) = serde_yaml::from_str::<PriorityClass>(&data) else {
+                    continue;
+                };
+
+                let name = match class.metadata.name {
+                    Some(name) => name,
+                    None => {
+                        warn!(
+                            "Ignoring PriorityClass without a name in {}",
+                            path.display()
+                        );
+                        continue;
+                    }
+                };
+
+                debug!(
+                    "PriorityClass {}={} from {}",
+                    name,
+                    class.value,
+                    path.display()
+                );
+                if class.global_default == Some(true) {
+                    if let Some(previous) = self.default_priority {
+                        warn!(
+                            "Several global default PriorityClasses, {} replaces value {}",
+                            name, previous
+                        );
+                    }
+                    self.default_priority = Some(class.value);
+                }
+                self.priority_classes.insert(name, class.value);
+            }
+        }
+    }
+
+    // Collects the HorizontalPodAutoscaler manifests under `paths` (autoscaling/v2 or v1), the
+    // workloads they scale carry their replica bounds
+    pub fn load_autoscalers(&mut self, paths: &[PathBuf]) {
+        let files = plugin::expand_paths(paths, |path| self.accepts(path));
+
+        for path in files {
+            for data in read_documents(&path).unwrap_or_default() {
+                let (name, target, min_replicas, max_replicas) = if let Ok(hpa) =
+                    serde_yaml::from_str::<HorizontalPodAutoscaler>(&data)
+                {
+                    let Some(spec) = hpa.spec else { continue };
+                    (
+                        hpa.metadata.name,
+                        spec.scale_target_ref.name,
+                        spec.min_replicas,
+                        spec.max_replicas,
+                    )
+                } else if let Ok(hpa) = serde_yaml::from_str::<HorizontalPodAutoscalerV1>(&data) {
+                    let Some(spec) = hpa.spec else { continue };
+                    (
+                        hpa.metadata.name,
+                        spec.scale_target_ref.name,
+                        spec.min_replicas,
+                        spec.max_replicas,
+                    )
+                } else {
+                    continue;
+                };
+                let name = name.unwrap_or_else(|| target.clone());
+
+                if min_replicas.is_some_and(|min_replicas| min_replicas > max_replicas) {
+                    warn!(
+                    "Ignoring HorizontalPodAutoscaler {} with minReplicas above maxReplicas in {}",
+                    name,
+                    path.display()
+                );
+                    continue;
+                }
+
+                debug!(
+                    "HorizontalPodAutoscaler {} scales {} to {}-{} replicas from {}",
+                    name,
+                    target,
+                    min_replicas.unwrap_or(1),
+                    max_replicas,
+                    path.display()
+                );
+                self.autoscalers
+                    .insert(format!("app={}", target), (min_replicas, max_replicas));
+            }
+        }
+    }
+
+    // Collects the PodDisruptionBudget manifests under `paths`, the workloads they select carry
+    // their `minAvailable` or `maxUnavailable`. Selectors are resolved against the workloads
+    // found by `load_workloads`.
+    pub fn load_disruption_budgets(&mut self, paths: &[PathBuf]) {
+        let files = plugin::expand_paths(paths, |path| self.accepts(path));
+
+        for path in files {
+            for data in read_documents(&path).unwrap_or_default() {
+                let Ok(pdb) = serde_yaml::from_str::<PodDisruptionBudget>(&data) else {
+                    continue;
+                };
+                let name = pdb.metadata.name.unwrap_or_default();
+                let Some(spec) = pdb.spec else { continue };
+
+                let value = |value: IntOrString| match value {
+                    IntOrString::Int(value) => value.to_string(),
+                    IntOrString::String(value) => value,
+                };
+                let budget = match (spec.min_available, spec.max_unavailable) {
+                    (Some(min_available), None) => {
+                        (METADATA_MIN_AVAILABLE_KEY, value(min_available))
+                    }
+                    (None, Some(max_unavailable)) => {
+                        (METADATA_MAX_UNAVAILABLE_KEY, value(max_unavailable))
+                    }
+                    _ => {
+                        warn!(
+                        "Ignoring PodDisruptionBudget {} without exactly one of minAvailable and maxUnavailable in {}",
+                        name,
+                        path.display()
+                    );
+                        continue;
+                    }
+                };
+
+                let selector = match spec.selector.as_ref().map(Self::term_selector).transpose() {
+                    Ok(Some(selector)) => selector,
+                    Ok(None) => continue,
+                    Err(err) => {
+                        warn!(
+                            "Ignoring PodDisruptionBudget {} in {}: {}",
+                            name,
+                            path.display(),
+                            err
+                        );
+                        continue;
+                    }
+                };
+                let selected = self
+                    .workloads
+                    .iter()
+                    .filter(|(_, labels)| selector.matches(labels))
+                    .map(|(workload, _)| workload.clone())
+                    .collect::<Vec<_>>();
+                if selected.is_empty() {
+                    debug!("PodDisruptionBudget {} selects no workload", name);
+                    continue;
+                }
+
+                for workload in selected {
+                    debug!(
+                        "PodDisruptionBudget {} sets {}={} on {} from {}",
+                        name,
+                        budget.0,
+                        budget.1,
+                        workload,
+                        path.display()
+                    );
+                    self.disruption_budgets.insert(workload, budget.clone());
+                }
+            }
+        }
+    }
+
+    // Collects the Namespace and ResourceQuota manifests under `paths`, the workloads of a
+    // namespace with a `pods` (or `count/pods`) quota carry the pods it allows
+    pub fn load_resource_quotas(&mut self, paths: &[PathBuf]) {
+        let files = plugin::expand_paths(paths, |path| self.accepts(path));
+        let mut quotas = vec![];
+
+        for path in files {
+            for data in read_documents(&path).unwrap_or_default() {
+                if let Ok(namespace) = serde_yaml::from_str::<Namespace>(&data) {
+                    self.namespaces.extend(namespace.metadata.name);
+                    continue;
+                }
+
+                let Ok(quota) = serde_yaml::from_str::<ResourceQuota>(&data) else {
+                    continue;
+                };
+                let name = quota.metadata.name.unwrap_or_default();
+                let namespace = quota
+                    .metadata
+                    .namespace
+                    .unwrap_or_else(|| "default".to_string());
+                let hard = quota.spec.and_then(|spec| spec.hard).unwrap_or_default();
+                let Some(pods) = hard.get("pods").or(hard.get("count/pods")) else {
+                    continue;
+                };
+
+                match pods.0.parse::<usize>() {
+                    Ok(pods) => quotas.push((name, namespace, pods, path.clone())),
+                    Err(_) => warn!(
+                        "Ignoring ResourceQuota {} with a pod count of {} in {}",
+                        name,
+                        pods.0,
+                        path.display()
+                    ),
+                }
+            }
+        }
+
+        for (name, namespace, pods, path) in quotas {
+            if !self.namespaces.is_empty() && !self.namespaces.contains(&namespace) {
+                warn!(
+                    "ResourceQuota {} in {} limits namespace {}, which no Namespace declares",
+                    name,
+                    path.display(),
+                    namespace
+                );
+            }
+
+            debug!(
+                "ResourceQuota {} allows {} pods in namespace {} from {}",
+                name,
+                pods,
+                namespace,
+                path.display()
+            );
+            let quota = self.pod_quotas.entry(namespace).or_insert(pods);
+            *quota = (*quota).min(pods);
+        }
+    }
+
+    // Collects the Service manifests under `paths`, when services are among the scanned
+    // resource types. Workloads naming a service in their environment get a network hint
+    // to the entities it selects.
+    pub fn load_services(&mut self, paths: &[PathBuf]) {
+        if !self.enabled(ResourceType::Service) {
+            return;
+        }
+
+        let files = plugin::expand_paths(paths, |path| self.accepts(path));
+
+        for path in files {
+            for data in read_documents(&path).unwrap_or_default() {
+                let Ok(service) = serde_yaml::from_str::<Service>(&data) else {
+                    continue;
+                };
+
+                let name = match service.metadata.name {
+                    Some(name) => name,
+                    None => {
+                        warn!("Ignoring Service without a name in {}", path.display());
+                        continue;
+                    }
+                };
+                // Services without a selector front endpoints outside the cluster
+                let selector = match service.spec.and_then(|spec| spec.selector) {
+                    Some(selector) if !selector.is_empty() => selector,
+                    _ => continue,
+                };
+
+                let backends = selector
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect::<Vec<_>>();
+                debug!(
+                    "Service {} selects {} from {}",
+                    name,
+                    backends.join(", "),
+                    path.display()
+                );
+                self.services.insert(name, backends);
+            }
+        }
+    }
+
+    // Entity name and pod labels of a workload manifest, owned pods counting as their owner
+    fn workload_labels(data: &str) -> Option<(String, BTreeMap<String, String>)> {
+        let (name, metadata) = if let Ok(deployment) = serde_yaml::from_str::<Deployment>(data) {
+            let metadata = deployment.spec?.template.metadata?;
+            (
+                deployment.metadata.name.or(metadata.name.clone())?,
+                metadata,
+            )
+        } else if let Ok(stateful_set) = serde_yaml::from_str::<StatefulSet>(data) {
+            (
+                stateful_set.metadata.name?,
+                stateful_set.spec?.template.metadata?,
+            )
+        } else if let Ok(daemon_set) = serde_yaml::from_str::<DaemonSet>(data) {
+            (
+                daemon_set.metadata.name?,
+                daemon_set.spec?.template.metadata?,
+            )
+        } else if let Ok(replica_set) = serde_yaml::from_str::<ReplicaSet>(data) {
+            (
+                replica_set.metadata.name?,
+                replica_set.spec?.template?.metadata?,
+            )
+        } else if let Ok(pod) = serde_yaml::from_str::<Pod>(data) {
+            let name = Self::pod_owner(&pod.metadata).or(pod.metadata.name.clone())?;
+            (name, pod.metadata)
+        } else if let Ok(job) = serde_yaml::from_str::<Job>(data) {
+            (job.metadata.name?, job.spec?.template.metadata?)
+        } else if let Ok(cron_job) = serde_yaml::from_str::<CronJob>(data) {
+            let job = cron_job.spec?.job_template.spec?;
+            (cron_job.metadata.name?, job.template.metadata?)
+        } else {
+            return None;
+        };
+
+        let labels = metadata
+            .labels
+            .map(|labels| labels.value)
+            .unwrap_or_default();
+        Some((format!("app={}", name), labels))
+    }
+
+    // Collects the pod labels of the workloads under `paths`, which the selectors of affinity
+    // terms are resolved against
+    pub fn load_workloads(&mut self, paths: &[PathBuf]) {
+        let files = plugin::expand_paths(paths, |path| self.accepts(path));
+
+        for path in files {
+            for data in read_documents(&path).unwrap_or_default() {
+                let Some((name, labels)) = Self::workload_labels(&data) else {
+                    continue;
+                };
+
+                debug!("Workload {} has labels {:?}", name, labels);
+                self.workloads.entry(name).or_default().extend(labels);
+            }
+        }
+    }
+
+    fn term_selector(label_selector: &LabelSelector) -> anyhow::Result<Selector> {
+        let selector = label_selector.match_labels.iter().flatten().fold(
+            Selector::new(),
+            |selector, (key, value)| {
+                selector.with(key, SelectorOperator::In([value.clone()].into()))
+            },
+        );
+
+        label_selector
+            .match_expressions
+            .iter()
+            .flatten()
+            .try_fold(selector, |selector, expr| {
+                let values = expr.values.as_deref().unwrap_or_default();
+                let operator =
+                    SelectorOperator::parse(&expr.operator, values).map_err(anyhow::Error::msg)?;
+
+                Ok(selector.with(&expr.key, operator))
+            })
+    }
+
+    // Workloads whose pods the selector of a term matches. Terms naming their targets with a
+    // single `app` expression are modelled as is, and so are those matching no known workload.
+    fn selected_workloads(
+        &self,
+        label_selector: &LabelSelector,
+    ) -> anyhow::Result<Option<(Selector, BTreeSet<EntityName>)>> {
+        let by_name = label_selector
+            .match_labels
+            .as_ref()
+            .is_none_or(|labels| labels.is_empty())
+            && matches!(
+                label_selector.match_expressions.as_deref(),
+                Some([expr]) if expr.key == "app" && (expr.operator == "In" || expr.operator == "NotIn")
+            );
+        if by_name || self.workloads.is_empty() {
+            return Ok(None);
+        }
+
+        let selector = Self::term_selector(label_selector)?;
+        let targets = self
+            .workloads
+            .iter()
+            .filter(|(_, labels)| selector.matches(labels))
+            .map(|(name, _)| EntityName(name.clone()))
+            .collect::<BTreeSet<_>>();
+
+        if targets.is_empty() {
+            debug!("Selector {} matches no workload", selector);
+            return Ok(None);
+        }
+
+        debug!("Selector {} matches {:?}", selector, targets);
+        Ok(Some((selector, targets)))
+    }
+
+    // Rule of a term resolved by `selected_workloads`, injected back as an `app In` term
+    fn selector_rule(
+        &self,
+        entity: &Entity,
+        term: &PodAffinityTerm,
+        (selector, targets): (Selector, BTreeSet<EntityName>),
+        r#type: EntityRuleType,
+        rule_source: EntityRuleSource,
+        mut metadata: EntityRuleMetadata,
+    ) -> EntityRule {
+        // The resolved targets replace the whole selector
+        metadata.remove_metadata(METADATA_PASSTHROUGH_KEY);
+        if let Some(fields) = self.passthrough_fields(term, &["topologyKey", "labelSelector"]) {
+            metadata.add_metadata(METADATA_PASSTHROUGH_KEY.into(), fields);
+        }
+        metadata.add_metadata(METADATA_SELECTOR_KEY.into(), selector.to_string());
+        metadata.add_metadata("key".into(), "app".into());
+        metadata.add_metadata("operator".into(), "In".into());
+
+        match targets.len() {
+            1 => EntityRule::mono(
+                entity.name.clone(),
+                targets.into_iter().next().unwrap(),
+                r#type,
+                rule_source,
+                Some(metadata),
+            ),
+            _ => EntityRule::multi(
+                entity.name.clone(),
+                targets,
+                r#type,
+                rule_source,
+                Some(metadata),
+            ),
+        }
+    }
+
+    // Entities matched by a pod selector, the `In` expressions taken as alternatives.
+    // Empty selectors match every pod and are not turned into hints.
+    fn selector_entities(selector: &LabelSelector) -> Vec<String> {
+        let labels = selector
+            .match_labels
+            .iter()
+            .flatten()
+            .map(|(key, value)| format!("{}={}", key, value));
+        let expressions = selector
+            .match_expressions
+            .iter()
+            .flatten()
+            .filter(|expr| expr.operator == "In")
+            .flat_map(|expr| {
+                expr.values
+                    .iter()
+                    .flatten()
+                    .map(|value| format!("{}={}", expr.key, value))
+            });
+
+        labels
+            .chain(expressions)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    // First line of the manifest containing `needle`, hints have no span of their own
+    fn line_of(data: &str, needle: &str) -> usize {
+        data.lines()
+            .position(|line| line.contains(needle))
+            .map(|index| index + 1)
+            .unwrap_or(1)
+    }
+
+    // First line after line `from` containing `needle`, `from` when there is none
+    fn line_after(data: &str, from: usize, needle: &str) -> usize {
+        data.lines()
+            .enumerate()
+            .skip(from)
+            .find(|(_, line)| line.contains(needle))
+            .map(|(index, _)| index + 1)
+            .unwrap_or(from)
+    }
+
+    fn hint_rule(
+        source: &str,
+        targets: &[String],
+        resource_type: ResourceType,
+        path: &Path,
+        line: usize,
+    ) -> EntityRule {
+        let metadata = EntityRuleMetadata::new(
+            Some(path.display().to_string()),
+            NonZeroUsize::new(line),
+            Some(
+                vec![
+                    (METADATA_HINT_KEY.to_string(), NETWORK_HINT.to_string()),
+                    (
+                        METADATA_TOPOLOGY_KEY.to_string(),
+                        EntityRuleTopologyKey::Node.to_string(),
+                    ),
+                    (
+                        METADATA_RESOURCE_TYPE_KEY.to_string(),
+                        resource_type.as_ref().to_string(),
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+        );
+        let rule_source = EntityRuleSource::File(path.display().to_string(), line);
+
+        match targets {
+            [target] => EntityRule::mono(
+                source.into(),
+                target.clone().into(),
+                EntityRuleType::Require,
+                rule_source,
+                Some(metadata),
+            ),
+            targets => EntityRule::multi(
+                source.into(),
+                targets.iter().map(|t| t.clone().into()).collect(),
+                EntityRuleType::Require,
+                rule_source,
+                Some(metadata),
+            ),
+        }
+    }
+
+    // Requires of the pods talking to the pods of the policy: its ingress peers require them,
+    // and they require their egress peers. Peers selecting several entities are alternatives.
+    fn extract_entities_from_network_policy(
+        policy: &NetworkPolicy,
+        data: &str,
+        path: &Path,
+    ) -> Vec<Entity> {
+        let Some(spec) = policy.spec.as_ref() else {
+            return vec![];
+        };
+
+        let selected = Self::selector_entities(&spec.pod_selector);
+        let peers = |peers: Option<&Vec<NetworkPolicyPeer>>| {
+            peers
+                .into_iter()
+                .flatten()
+                .filter_map(|peer| peer.pod_selector.as_ref())
+                .map(Self::selector_entities)
+                .filter(|peer| !peer.is_empty())
+                .collect::<Vec<_>>()
+        };
+
+        let mut entities: BTreeMap<String, Entity> = BTreeMap::new();
+        let mut add = |source: &str, targets: &[String], line: usize| {
+            if targets.iter().any(|target| target == source) {
+                return;
+            }
+
+            entities
+                .entry(source.to_string())
+                .or_insert_with(|| Entity::new(source))
+                .add_require(Self::hint_rule(
+                    source,
+                    targets,
+                    ResourceType::NetworkPolicy,
+                    path,
+                    line,
+                ));
+        };
+
+        for rule in spec.ingress.iter().flatten() {
+            let line = Self::line_of(data, "ingress:");
+            for peer in peers(rule.from.as_ref()) {
+                for source in peer {
+                    if !selected.is_empty() {
+                        add(&source, &selected, line);
+                    }
+                }
+            }
+        }
+
+        for rule in spec.egress.iter().flatten() {
+            let line = Self::line_of(data, "egress:");
+            for peer in peers(rule.to.as_ref()) {
+                for source in selected.iter() {
+                    add(source, &peer, line);
+                }
+            }
+        }
+
+        entities.into_values().collect()
+    }
+
+    // Hints to the services a container names in its environment, e.g. `DB_HOST=postgres`
+    // or `API_URL=http://api.default.svc:8080/v1`
+    fn extract_service_hints(&self, entity: &mut Entity, pod: &PodSpec, data: &str, path: &Path) {
+        if self.services.is_empty() {
+            return;
+        }
+
+        let values = pod
+            .containers
+            .iter()
+            .flat_map(|container| container.env.iter().flatten())
+            .filter_map(|env| env.value.as_deref());
+
+        for value in values {
+            let host = value
+                .split_once("://")
+                .map(|(_, rest)| rest)
+                .unwrap_or(value);
+            let host = host.split([':', '/', '.']).next().unwrap_or_default();
+
+            let Some(backends) = self.services.get(host) else {
+                continue;
+            };
+            if backends
+                .iter()
+                .any(|backend| backend == entity.name.as_ref())
+            {
+                continue;
+            }
+
+            let rule = Self::hint_rule(
+                entity.name.as_ref(),
+                backends,
+                ResourceType::Service,
+                path,
+                Self::line_of(data, value),
+            );
+            entity.add_require(rule);
+        }
+    }
+
+    fn priority_value(&self, class_name: &str) -> Option<i32> {
+        match class_name {
+            "system-cluster-critical" => Some(2_000_000_000),
+            "system-node-critical" => Some(2_000_001_000),
+            name => self.priority_classes.get(name).copied(),
+        }
+    }
+
+    // Explicit `priority` first, then the class, then the global default class. Classes
+    // without a manifest fall back to matching their name, e.g. `critical`.
+    fn resolve_priority(&self, pod: &PodSpec) -> EntityPriority {
+        if let Some(value) = pod.priority {
+            return EntityPriority::from_value(value);
+        }
+
+        match pod.priority_class_name.as_deref() {
+            Some(name) => match self.priority_value(name) {
+                Some(value) => EntityPriority::from_value(value),
+                None => {
+                    debug!("No PriorityClass {}, matching on its name", name);
+                    EntityPriority::from(name)
+                }
+            },
+            None => self
+                .default_priority
+                .map(EntityPriority::from_value)
+                .unwrap_or_default(),
+        }
+    }
+
+    /*
+        Entities of every document of a manifest. A document which is not a supported resource
+        (e.g. a ConfigMap next to its Deployment) is skipped with a warning, a manifest with a
+        single document fails as a whole.
+    */
+    pub fn extract_entity_from_path(&self, path: &Path) -> anyhow::Result<Vec<Entity>> {
+        let data = util::read_source(path)?;
+
+        self.extract_entity_from_data(&data, path)
+    }
+
+    /*
+        Entities of manifests held in memory, e.g. by a server, keyed by a logical name which
+        stands for their path: the rules are located in it and `inject_manifests` and
+        `remove_rules_from_manifests` find their manifest by it. The entities are not merged.
+    */
+    pub fn extract_entities_from_manifests<D: AsRef<[u8]>>(
+        &self,
+        manifests: &BTreeMap<String, D>,
+    ) -> anyhow::Result<Vec<Entity>> {
+        let mut entities = vec![];
+        for (name, data) in manifests.iter() {
+            let path = Path::new(name);
+            let data = util::decode_source(data.as_ref().to_vec(), path);
+
+            entities.extend(
+                self.extract_entity_from_data(&data, path)
+                    .with_context(|| format!("Failed to extract entity from {}", name))?,
+            );
+        }
+
+        Ok(entities)
+    }
+
+    // Entities of the manifest `data`, read as the file at `path`
+    fn extract_entity_from_data(&self, data: &str, path: &Path) -> anyhow::Result<Vec<Entity>> {
+        let documents = documents(data);
+
+        if let [document] = documents.as_slice() {
+            return self.extract_entity_from_document(document, path);
+        }
+
+        let mut entities = vec![];
+        for document in documents {
+            match self.extract_entity_from_document(&document, path) {
+                Ok(extracted) => entities.extend(extracted),
+                Err(err) => warn!(
+                    "Skipping the document at line {} of {}: {:#}",
+                    document.line(),
+                    path.display(),
+                    err
+                ),
+            }
+        }
+
+        Ok(entities)
+    }
+
+    // Entities of a document of a manifest, their rules located in the whole file
+    fn extract_entity_from_document(
+        &self,
+        document: &Document,
+        path: &Path,
+    ) -> anyhow::Result<Vec<Entity>> {
+        self.check_version(document, path);
+
+        let mut entities = self.extract_entity_from_text(document, path)?;
+        for entity in entities.iter_mut() {
+            entity.shift_rules(document.lines_before, document.offset);
+        }
+
+        Ok(entities)
+    }
+
+    // Spans read from the text are lines and offsets of the document
+    fn extract_entity_from_text(
+        &self,
+        document: &Document,
+        path: &Path,
+    ) -> anyhow::Result<Vec<Entity>> {
+        let data = document.text;
+
+        // Name of a pod created by a workload, whose rules belong to the entity of the workload
+        let mut owned_pod = None;
+
+        let (name, spec, resource_type, annotations, schedule, replicas) =
+            if let Ok(deployment) = serde_yaml::from_str::<Deployment>(data) {
+                let annotations = deployment.metadata.annotations;
+                let spec = deployment.spec.context("missing spec in deployment")?;
+                let replicas = spec.replicas;
+
+                let template = spec.template;
+                let metadata = template
+                    .metadata
+                    .context("missing metadata in deployment.template")?;
+
+                let name = if let Some(name) = deployment.metadata.name {
+                    name
+                } else if let Some(name) = metadata.name {
+                    name
+                } else {
+                    anyhow::bail!(
+                        "missing name in deployment.metadata or deployment.spec.template.metadata"
+                    )
+                };
+
+                let spec = template
+                    .spec
+                    .context("missing spec in deployment.template")?;
+
+                (
+                    name,
+                    spec,
+                    ResourceType::Deployment,
+                    annotations,
+                    None,
+                    replicas,
+                )
+            } else if let Ok(stateful_set) = serde_yaml::from_str::<StatefulSet>(data) {
+                let metadata = stateful_set.metadata;
+
+                let name = metadata
+                    .name
+                    .context("missing name in statefulset.metadata")?;
+                let spec = stateful_set.spec.context("missing spec in statefulset")?;
+                let replicas = spec.replicas;
+                let spec = spec
+                    .template
+                    .spec
+                    .context("missing spec in statefulset.template")?;
+
+                (
+                    name,
+                    spec,
+                    ResourceType::StatefulSet,
+                    metadata.annotations,
+                    None,
+                    replicas,
+                )
+            } else if let Ok(daemon_set) = serde_yaml::from_str::<DaemonSet>(data) {
+                let metadata = daemon_set.metadata;
+
+                let name = metadata
+                    .name
+                    .context("missing name in daemonset.metadata")?;
+                let spec = daemon_set
+                    .spec
+                    .context("missing spec in daemonset")?
+                    .template
+                    .spec
+                    .context("missing spec in daemonset.template")?;
+
+                // A pod on every node its affinities allow, without a replica count
+                (
+                    name,
+                    spec,
+                    ResourceType::DaemonSet,
+                    metadata.annotations,
+                    None,
+                    None,
+                )
+            } else if let Ok(replica_set) = serde_yaml::from_str::<ReplicaSet>(data) {
+                let metadata = replica_set.metadata;
+
+                let name = metadata
+                    .name
+                    .context("missing name in replicaset.metadata")?;
+                let spec = replica_set.spec.context("missing spec in replicaset")?;
+                let replicas = spec.replicas;
+                let spec = spec
+                    .template
+                    .context("missing template in replicaset")?
+                    .spec
+                    .context("missing spec in replicaset.template")?;
+
+                (
+                    name,
+                    spec,
+                    ResourceType::ReplicaSet,
+                    metadata.annotations,
+                    None,
+                    replicas,
+                )
+            } else if let Ok(pod) = serde_yaml::from_str::<Pod>(data) {
+                let metadata = pod.metadata;
+
+                let pod_name = metadata
+                    .name
+                    .clone()
+                    .context("missing name in pod.metadata")?;
+                let spec = pod.spec.context("missing spec in pod")?;
+
+                let name = match Self::pod_owner(&metadata) {
+                    Some(owner) => {
+                        debug!("Pod {} is owned by {}", pod_name, owner);
+                        owned_pod = Some(pod_name);
+                        owner
+                    }
+                    None => pod_name,
+                };
+
+                (
+                    name,
+                    spec,
+                    ResourceType::Pod,
+                    metadata.annotations,
+                    None,
+                    None,
+                )
+            } else if let Ok(job) = serde_yaml::from_str::<Job>(data) {
+                let metadata = job.metadata;
+
+                let name = metadata.name.context("missing name in job.metadata")?;
+                let job = job.spec.context("missing spec in job")?;
+                let parallelism = job.parallelism;
+                let spec = job.template.spec.context("missing spec in job.template")?;
+
+                (
+                    name,
+                    spec,
+                    ResourceType::Job,
+                    metadata.annotations,
+                    None,
+                    parallelism,
+                )
+            } else if let Ok(cron_job) = serde_yaml::from_str::<CronJob>(data) {
+                let metadata = cron_job.metadata;
+
+                let name = metadata.name.context("missing name in cronjob.metadata")?;
+                let spec = cron_job.spec.context("missing spec in cronjob")?;
+                let job = spec
+                    .job_template
+                    .spec
+                    .context("missing spec in cronjob.jobTemplate")?;
+
+                // Runs last until their deadline, or the configured duration without one
+                let duration = job
+                    .active_deadline_seconds
+                    .map(|seconds| (seconds.max(0) as u32).div_ceil(60))
+                    .unwrap_or(self.config.job_duration_minutes);
+                let schedule = TimeWindows::from_cron(&spec.schedule, duration)
+                    .map_err(anyhow::Error::msg)
+                    .with_context(|| format!("Invalid schedule in {}", path.display()))?;
+
+                let parallelism = job.parallelism;
+                let spec = job
+                    .template
+                    .spec
+                    .context("missing spec in cronjob.jobTemplate.template")?;
+
+                (
+                    name,
+                    spec,
+                    ResourceType::CronJob,
+                    metadata.annotations,
+                    Some(schedule),
+                    parallelism,
+                )
+            } else if let Ok(node) = serde_yaml::from_str::<Node>(data) {
+                let metadata = node.metadata;
+                let labels = metadata.labels;
+
+                if !self.scans(ResourceType::Node, path) {
+                    return Ok(vec![]);
+                }
+
+                if let Some(labels) = labels {
+                    let map = labels.value.into_iter().collect();
+                    return self.extract_entity_from_node(&map, path);
+                } else {
+                    return Ok(vec![]);
+                }
+            } else if let Ok(policy) = serde_yaml::from_str::<NetworkPolicy>(data) {
+                if !self.scans(ResourceType::NetworkPolicy, path) {
+                    return Ok(vec![]);
+                }
+
+                return Ok(Self::extract_entities_from_network_policy(
+                    &policy, data, path,
+                ));
+            } else if serde_yaml::from_str::<PriorityClass>(data).is_ok() {
+                // Read by `load_priority_classes`
+                return Ok(vec![]);
+            } else if serde_yaml::from_str::<Service>(data).is_ok() {
+                // Read by `load_services`
+                return Ok(vec![]);
+            } else if serde_yaml::from_str::<HorizontalPodAutoscaler>(data).is_ok()
+                || serde_yaml::from_str::<HorizontalPodAutoscalerV1>(data).is_ok()
+            {
+                // Read by `load_autoscalers`
+                return Ok(vec![]);
+            } else if serde_yaml::from_str::<PodDisruptionBudget>(data).is_ok() {
+                // Read by `load_disruption_budgets`
+                return Ok(vec![]);
+            } else if serde_yaml::from_str::<Namespace>(data).is_ok()
+                || serde_yaml::from_str::<ResourceQuota>(data).is_ok()
+            {
+                // Read by `load_resource_quotas`
+                return Ok(vec![]);
+            } else if let Some(entity) = self.extract_entity_from_annotations(document, path)? {
+                return Ok(vec![entity]);
+            } else {
+                anyhow::bail!("Invalid configuration {}", path.display())
+            };
+
+        if !self.scans(resource_type, path) {
+            return Ok(vec![]);
+        }
+
+        let mut entity = self
+            .extract_entity(&name, &spec, resource_type, data, path)
+            .context("failed to extract entity")?;
+        self.extract_service_hints(&mut entity, &spec, data, path);
+
+        let generated = annotations
+            .as_ref()
+            .and_then(|a| a.get(GENERATED_RULES_ANNOTATION))
+            .map(|value| serde_json::from_str::<BTreeSet<String>>(value))
+            .transpose()
+            .with_context(|| format!("Invalid {} annotation", GENERATED_RULES_ANNOTATION))?
+            .unwrap_or_default();
+
+        let window = annotations
+            .as_ref()
+            .and_then(|a| a.get(WINDOW_ANNOTATION))
+            .map(|value| TimeWindows::parse(value).map_err(anyhow::Error::msg))
+            .transpose()
+            .with_context(|| format!("Invalid {} annotation", WINDOW_ANNOTATION))?
+            .or(schedule);
+
+        // `;` ends a metadata value in the IR
+        let desc = annotations
+            .as_ref()
+            .and_then(|a| a.get(DESC_ANNOTATION))
+            .map(|desc| desc.split_whitespace().collect::<Vec<_>>().join(" "))
+            .map(|desc| desc.replace(';', ","));
+        let owner = annotations
+            .as_ref()
+            .and_then(|a| a.get(OWNER_ANNOTATION))
+            .map(|owner| owner.trim().replace(';', ","))
+            .filter(|owner| !owner.is_empty());
+
+        let mut entity = Self::mark_generated_rules(entity, &generated);
+        if let Some(desc) = desc {
+            entity = Self::mark_declared_rules(entity, METADATA_DESC_KEY, desc);
+        }
+        if let Some(owner) = owner {
+            entity = Self::mark_rules(entity, METADATA_OWNER_KEY, owner);
+        }
+        if let Some(window) = window {
+            entity = Self::mark_rules(entity, METADATA_WINDOW_KEY, window.to_string());
+        }
+        if let Some(replicas) = replicas.filter(|replicas| *replicas > 1) {
+            entity = Self::mark_rules(entity, METADATA_REPLICAS_KEY, replicas.to_string());
+        }
+        if let Some((min_replicas, max_replicas)) = self.autoscalers.get(entity.name.as_ref()) {
+            if let Some(min_replicas) = min_replicas {
+                entity =
+                    Self::mark_rules(entity, METADATA_MIN_REPLICAS_KEY, min_replicas.to_string());
+            }
+            entity = Self::mark_rules(entity, METADATA_MAX_REPLICAS_KEY, max_replicas.to_string());
+        }
+        if let Some((key, budget)) = self.disruption_budgets.get(entity.name.as_ref()) {
+            entity = Self::mark_rules(entity, key, budget.clone());
+        }
+        let namespace = serde_yaml::from_str::<Manifest>(data)
+            .ok()
+            .and_then(|manifest| manifest.metadata.namespace);
+        let quota = self
+            .pod_quotas
+            .get(namespace.as_deref().unwrap_or("default"));
+        if let Some(namespace) = namespace {
+            entity = Self::mark_rules(entity, METADATA_NAMESPACE_KEY, namespace);
+        }
+        if let Some(quota) = quota {
+            entity = Self::mark_rules(entity, METADATA_POD_QUOTA_KEY, quota.to_string());
+        }
+        if let Some(pod) = owned_pod {
+            entity = Self::mark_rules(entity, METADATA_POD_KEY, pod);
+            // Rules are injected into the manifest of the owner, merged in by its name
+            entity.source = EntitySource::Unknown;
+        }
+
+        Ok(vec![entity])
+    }
+
+    // Workload owning a pod through its controller reference, the Deployment of a ReplicaSet
+    // named `<deployment>-<pod-template-hash>`. A ReplicaSet without the hash of the pod is
+    // not created by a Deployment and owns the pod itself.
+    fn pod_owner(metadata: &ObjectMeta) -> Option<String> {
+        let owner = metadata
+            .owner_references
+            .as_ref()?
+            .iter()
+            .find(|owner| owner.controller == Some(true))?;
+
+        if owner.kind != "ReplicaSet" {
+            return Some(owner.name.clone());
+        }
+
+        let deployment = metadata
+            .labels
+            .as_ref()
+            .and_then(|labels| labels.value.get("pod-template-hash"))
+            .and_then(|hash| owner.name.strip_suffix(&format!("-{}", hash)));
+
+        Some(deployment.unwrap_or(&owner.name).to_string())
+    }
+
+    // Properties of the workload are carried by every rule of its entity, as in the IR
+    fn mark_rules(mut entity: Entity, key: &str, value: String) -> Entity {
+        let mark = |rules: BTreeSet<EntityRule>| {
+            rules
+                .into_iter()
+                .map(|mut rule| {
+                    rule.add_metadata(key.into(), value.clone());
+                    rule
+                })
+                .collect()
+        };
+
+        entity.requires = mark(entity.requires);
+        entity.excludes = mark(entity.excludes);
+        entity
+    }
+
+    // Marks the rules read from the manifest, leaving out the hints inferred from Services
+    fn mark_declared_rules(mut entity: Entity, key: &str, value: String) -> Entity {
+        let mark = |rules: BTreeSet<EntityRule>| {
+            rules
+                .into_iter()
+                .map(|mut rule| {
+                    if rule.hint().is_none() {
+                        rule.add_metadata(key.into(), value.clone());
+                    }
+                    rule
+                })
+                .collect()
+        };
+
+        entity.requires = mark(entity.requires);
+        entity.excludes = mark(entity.excludes);
+        entity
+    }
 
-pub struct K8sPlugin {}
+    // Identifies an injected term across an inject/import round trip, where the file, line
+    // and the rest of the metadata of the rule change
+    pub fn generated_rule_signature(rule: &EntityRule) -> String {
+        let mut targets = rule
+            .targets()
+            .into_iter()
+            .map(|t| t.as_ref())
+            .collect::<Vec<_>>();
+        targets.sort();
 
-#[derive(Debug, Copy, Clone)]
-pub enum ResourceType {
-    Pod,
-    Deployment,
-    Node,
-}
+        match rule.weight() {
+            Some(weight) => format!("{} {} weight={}", rule.r#type(), targets.join("|"), weight),
+            None => format!("{} {}", rule.r#type(), targets.join("|")),
+        }
+    }
 
-impl AsRef<str> for ResourceType {
-    fn as_ref(&self) -> &str {
-        match self {
-            Self::Pod => "pod",
-            Self::Deployment => "deployment",
-            Self::Node => "node",
+    fn mark_generated_rules(mut entity: Entity, generated: &BTreeSet<String>) -> Entity {
+        if generated.is_empty() {
+            return entity;
         }
+
+        let mark = |rules: BTreeSet<EntityRule>| {
+            rules
+                .into_iter()
+                .map(|mut rule| {
+                    if generated.contains(&Self::generated_rule_signature(&rule)) {
+                        rule.add_metadata(METADATA_GENERATED_KEY.into(), "true".into());
+                    }
+
+                    rule
+                })
+                .collect()
+        };
+
+        entity.requires = mark(entity.requires);
+        entity.excludes = mark(entity.excludes);
+        entity
     }
-}
 
-impl TryFrom<&str> for ResourceType {
-    type Error = &'static str;
+    // Rules not read from a manifest were added by deployfix, e.g. recommended by a solver
+    fn is_injected_rule(rule: &EntityRule) -> bool {
+        rule.is_generated()
+            || !rule
+                .meta_file()
+                .or(rule.file())
+                .is_some_and(|file| file.ends_with(".yaml") || file.ends_with(".yml"))
+    }
 
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value {
-            "pod" => Ok(Self::Pod),
-            "deployment" => Ok(Self::Deployment),
-            _ => Err("unknown resource type"),
+    fn annotate_generated_rules(entity: &Entity, metadata: &mut ObjectMeta) {
+        let generated = entity
+            .requires
+            .iter()
+            .chain(entity.excludes.iter())
+            .filter(|rule| Self::is_injected_rule(rule))
+            .map(Self::generated_rule_signature)
+            .collect::<BTreeSet<_>>();
+
+        let annotations = metadata.annotations.get_or_insert_with(Default::default);
+        if generated.is_empty() {
+            annotations.remove(GENERATED_RULES_ANNOTATION);
+        } else {
+            annotations.insert(
+                GENERATED_RULES_ANNOTATION.to_string(),
+                serde_json::to_string(&generated).unwrap(),
+            );
+        }
+
+        if annotations.is_empty() {
+            metadata.annotations = None;
         }
     }
-}
 
-impl K8sPlugin {
-    pub fn extract_entity_from_path(path: &Path) -> anyhow::Result<Vec<Entity>> {
-        let data = std::fs::read_to_string(path)?;
+    // JSON of the fields of `term` left once the modeled ones are removed, `modeled` paths are
+    // dot-separated and their parents are dropped when nothing else is left in them
+    fn passthrough_fields<T: serde::Serialize>(
+        &self,
+        term: &T,
+        modeled: &[&str],
+    ) -> Option<String> {
+        // Removes `path` from `value`, dropping the objects it leaves empty
+        fn remove(value: &mut serde_json::Value, path: &[&str]) {
+            let (Some(object), Some((first, rest))) = (value.as_object_mut(), path.split_first())
+            else {
+                return;
+            };
 
-        let (name, spec, resource_type) =
-            if let Ok(deployment) = serde_yaml::from_str::<Deployment>(&data) {
-                let spec = deployment.spec.context("missing spec in deployment")?;
+            if let Some(child) = object.get_mut(*first).filter(|_| !rest.is_empty()) {
+                remove(child, rest);
+                if !child.as_object().is_some_and(|child| child.is_empty()) {
+                    return;
+                }
+            }
+            object.remove(*first);
+        }
 
-                let template = spec.template;
-                let metadata = template
-                    .metadata
-                    .context("missing metadata in deployment.template")?;
+        let mut value = serde_json::to_value(term).ok()?;
+        for path in modeled {
+            remove(&mut value, &path.split('.').collect::<Vec<_>>());
+        }
+        // Not served by the target version, the term is read as that version would
+        for field in self.unsupported_term_fields() {
+            remove(&mut value, &[field]);
+        }
 
-                let name = if let Some(name) = deployment.metadata.name {
-                    name
-                } else if let Some(name) = metadata.name {
-                    name
-                } else {
-                    anyhow::bail!(
-                        "missing name in deployment.metadata or deployment.spec.template.metadata"
-                    )
-                };
+        match value.as_object() {
+            Some(object) if !object.is_empty() => {
+                Some(value.to_string().replace('%', "%25").replace(';', "%3B"))
+            }
+            _ => None,
+        }
+    }
 
-                let spec = template
-                    .spec
-                    .context("missing spec in deployment.template")?;
+    // Merges the pass-through fields of `rule` into a generated term, the generated fields win
+    fn restore_passthrough_fields<T>(&self, term: T, rule: &EntityRule) -> anyhow::Result<T>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        fn merge(value: &mut serde_json::Value, extra: serde_json::Value) {
+            if let (serde_json::Value::Object(value), serde_json::Value::Object(extra)) =
+                (value, extra)
+            {
+                for (key, extra) in extra {
+                    match value.get_mut(&key) {
+                        Some(value) => merge(value, extra),
+                        None => {
+                            value.insert(key, extra);
+                        }
+                    }
+                }
+            }
+        }
 
-                (name, spec, ResourceType::Deployment)
-            } else if let Ok(pod) = serde_yaml::from_str::<Pod>(&data) {
-                let metadata = pod.metadata;
+        let Some(fields) = rule.metadata(METADATA_PASSTHROUGH_KEY) else {
+            return Ok(term);
+        };
 
-                let name = metadata.name.context("missing name in pod.metadata")?;
-                let spec = pod.spec.context("missing spec in pod")?;
+        let mut extra: serde_json::Value = serde_json::from_str(
+            &fields.replace("%3B", ";").replace("%25", "%"),
+        )
+        .with_context(|| format!("Invalid `{}` of rule {}", METADATA_PASSTHROUGH_KEY, rule))?;
+        if let Some(extra) = extra.as_object_mut() {
+            for field in self.unsupported_term_fields() {
+                if extra.remove(field).is_some() {
+                    warn!(
+                        "Dropping `{}` of rule {}, the target Kubernetes version does not serve it",
+                        field, rule
+                    );
+                }
+            }
+        }
+        let mut value = serde_json::to_value(&term)?;
+        merge(&mut value, extra);
 
-                (name, spec, ResourceType::Pod)
-            } else if let Ok(node) = serde_yaml::from_str::<Node>(&data) {
-                let metadata = node.metadata;
-                let labels = metadata.labels;
+        serde_json::from_value(value)
+            .with_context(|| format!("Invalid `{}` of rule {}", METADATA_PASSTHROUGH_KEY, rule))
+    }
 
-                if let Some(labels) = labels {
-                    let map = labels.value.into_iter().collect();
-                    return Self::extract_entity_from_node(&map, path);
-                } else {
-                    return Ok(vec![]);
-                }
-            } else {
-                anyhow::bail!("Invalid configuration {}", path.display())
-            };
+    fn span_source<T>(path: &Path, span: &Spanned<T>) -> EntityRuleSource {
+        EntityRuleSource::Span(
+            path.display().to_string(),
+            SourceSpan {
+                line: span.line,
+                column: span.column,
+                index: span.index,
+                len: span.len,
+            },
+        )
+    }
+
+    // Required terms, then the preferred ones with their weight, each with where it is declared
+    fn affinity_terms<'a>(
+        source: &'a Path,
+        required: Option<&'a [Spanned<PodAffinityTerm>]>,
+        preferred: Option<&'a [Spanned<WeightedPodAffinityTerm>]>,
+    ) -> impl Iterator<Item = (&'a PodAffinityTerm, EntityRuleSource, usize, Option<i32>)> + 'a
+    {
+        let required = required.unwrap_or_default().iter().map(|span| {
+            (
+                &span.value,
+                Self::span_source(source, span),
+                span.line,
+                None,
+            )
+        });
+        let preferred = preferred.unwrap_or_default().iter().map(|span| {
+            (
+                &span.value.pod_affinity_term,
+                Self::span_source(source, span),
+                span.line,
+                Some(span.value.weight),
+            )
+        });
 
-        Self::extract_entity(&name, &spec, resource_type, path)
-            .context("failed to extract entity")
-            .map(|e| vec![e])
+        required.chain(preferred)
     }
 
     fn topology_key_to_entity_rule_topology_key(
+        &self,
         topology_key: &str,
     ) -> Option<EntityRuleTopologyKey> {
-        match topology_key {
+        match self.canonical_topology_key(topology_key) {
             "kubernetes.io/hostname" => Some(EntityRuleTopologyKey::Node),
             "topology.kubernetes.io/hostname" => Some(EntityRuleTopologyKey::Node),
-            "topology.kubernetes.io/zone" => Some(EntityRuleTopologyKey::Zone),
-            "topology.kubernetes.io/region" => Some(EntityRuleTopologyKey::Zone),
+            ZONE_TOPOLOGY_LABEL => Some(EntityRuleTopologyKey::Zone),
+            REGION_TOPOLOGY_LABEL => Some(EntityRuleTopologyKey::Zone),
+            RACK_TOPOLOGY_LABEL => Some(EntityRuleTopologyKey::Rack),
+            key if key == self.config.rack_label => Some(EntityRuleTopologyKey::Rack),
             _ => None,
         }
     }
 
+    // Node label matching the scheduling domain of a rule, used when the rule does not carry
+    // an explicit `topology_key`.
+    fn entity_rule_topology_key_to_topology_key(
+        &self,
+        topology: Option<&str>,
+        default: &'static str,
+    ) -> &str {
+        match topology {
+            Some("zone") => ZONE_TOPOLOGY_LABEL,
+            Some("rack") => self.config.rack_label.as_str(),
+            Some("node") => HOSTNAME_TOPOLOGY_LABEL,
+            _ => default,
+        }
+    }
+
+    // Labels of `nodeSelector` are required like the `In` terms of a node affinity, and
+    // `nodeName` requires the hostname label of its node. Neither has a span, their rules point
+    // at the line of their key.
+    fn extract_node_selector_rules(
+        &self,
+        pod: &PodSpec,
+        entity: &mut Entity,
+        resource_type: ResourceType,
+        data: &str,
+        source: &Path,
+    ) {
+        let selector_line = Self::line_of(data, "nodeSelector:");
+        let selector = pod.node_selector.iter().flatten().map(|(key, value)| {
+            let line = Self::line_after(data, selector_line, &format!("{}:", key));
+            (NODE_SELECTOR_TYPE, key.as_str(), value.as_str(), line)
+        });
+        let node_name = pod.node_name.iter().map(|node| {
+            let line = Self::line_of(data, "nodeName:");
+            (NODE_NAME_TYPE, HOSTNAME_TOPOLOGY_LABEL, node.as_str(), line)
+        });
+
+        for (r#type, key, value, line) in selector.chain(node_name) {
+            let key = self.canonical_topology_key(key);
+            let topo = self
+                .topology_key_to_entity_rule_topology_key(key)
+                .unwrap_or(EntityRuleTopologyKey::Node);
+            let topology_key = self.entity_rule_topology_key_to_topology_key(
+                Some(topo.as_ref()),
+                HOSTNAME_TOPOLOGY_LABEL,
+            );
+
+            let mut metadata = EntityRuleMetadata::new(
+                Some(source.display().to_string()),
+                NonZeroUsize::new(line),
+                Some(
+                    vec![(
+                        METADATA_RESOURCE_TYPE_KEY.to_string(),
+                        resource_type.as_ref().to_string(),
+                    )]
+                    .into_iter()
+                    .collect(),
+                ),
+            );
+            metadata.add_metadata("key".into(), key.into());
+            metadata.add_metadata("type".into(), r#type.into());
+            metadata.add_metadata("operator".into(), "In".into());
+            metadata.add_metadata("topology_key".into(), topology_key.into());
+            metadata.add_metadata(METADATA_TOPOLOGY_KEY.into(), topo.to_string());
+
+            let rule_source = EntityRuleSource::File(source.display().to_string(), line);
+            entity.add_require(EntityRule::mono(
+                entity.name.clone(),
+                format!("{}={}", key, value).into(),
+                EntityRuleType::Require,
+                rule_source,
+                Some(metadata),
+            ));
+        }
+    }
+
     fn extract_node_affinity_rules(
+        &self,
         node_affinity: &NodeAffinity,
         entity: &mut Entity,
         resource_type: ResourceType,
@@ -152,7 +1853,7 @@ impl K8sPlugin {
                 .as_ref()
                 .context("Invalid match expressions")?;
 
-            let metadata = EntityRuleMetadata::new(
+            let mut metadata = EntityRuleMetadata::new(
                 Some(source.display().to_string()),
                 NonZeroUsize::new(line),
                 Some(
@@ -164,10 +1865,16 @@ impl K8sPlugin {
                     .collect(),
                 ),
             );
+            if let Some(fields) = self.passthrough_fields(term, &["matchExpressions"]) {
+                metadata.add_metadata(METADATA_PASSTHROUGH_KEY.into(), fields);
+            }
 
             for expr in match_expressions.iter() {
-                let key: &str = expr.key.as_ref();
-                let operator: &str = expr.operator.as_ref();
+                // Node labels are deprecated like topology keys, e.g. the beta zone label
+                let key = self.canonical_topology_key(expr.key.as_ref());
+                let Some(operator) = ExpressionOperator::supported(&expr.operator, expr) else {
+                    continue;
+                };
                 let values: Vec<&str> = expr
                     .values
                     .as_deref()
@@ -176,26 +1883,32 @@ impl K8sPlugin {
                     .map(|s| s.as_ref())
                     .collect();
 
-                let entity_rule_source = EntityRuleSource::File(source.display().to_string(), line);
+                let entity_rule_source = Self::span_source(source, span);
                 let mut metadata = metadata.clone();
                 metadata.add_metadata("key".into(), key.into());
                 metadata.add_metadata("type".into(), "nodeAffinity".into());
-                metadata.add_metadata("topology_key".into(), "kubernetes.io/hostname".into());
-                metadata.add_metadata("topology".into(), "node".into());
+
+                // Selecting on a zone/rack label scopes the rule to that domain
+                let topo = self
+                    .topology_key_to_entity_rule_topology_key(key)
+                    .unwrap_or(EntityRuleTopologyKey::Node);
+                let topology_key = self.entity_rule_topology_key_to_topology_key(
+                    Some(topo.as_ref()),
+                    HOSTNAME_TOPOLOGY_LABEL,
+                );
+                metadata.add_metadata("topology_key".into(), topology_key.into());
+                metadata.add_metadata(METADATA_TOPOLOGY_KEY.into(), topo.to_string());
 
                 match operator {
-                    "In" => {
-                        metadata.add_metadata("operator".into(), operator.into());
+                    ExpressionOperator::In => {
+                        metadata.add_metadata("operator".into(), "In".into());
                     }
-                    "NotIn" => {
+                    ExpressionOperator::NotIn => {
                         warn!("Operator `NotIn` for affinity rule will be transformed into `In` for anti-affinity rule {:?}", expr);
                         warn!("It will be separated into two rules that both are required to be satisfied, which might not be intentional.");
                         metadata.add_metadata("inverse".into(), "true".into());
                         metadata.add_metadata("operator".into(), "In".into());
                     }
-                    _ => {
-                        panic!("Operator is not support yet: {}", operator)
-                    }
                 }
 
                 match values.len() {
@@ -205,21 +1918,20 @@ impl K8sPlugin {
                         let target = format!("{}={}", key, values[0]);
 
                         match operator {
-                            "In" => entity.add_require(EntityRule::mono(
+                            ExpressionOperator::In => entity.add_require(EntityRule::mono(
                                 source,
                                 target.into(),
                                 EntityRuleType::Require,
                                 entity_rule_source,
                                 Some(metadata),
                             )),
-                            "NotIn" => entity.add_exclude(EntityRule::mono(
+                            ExpressionOperator::NotIn => entity.add_exclude(EntityRule::mono(
                                 source,
                                 target.into(),
                                 EntityRuleType::Exclude,
                                 entity_rule_source,
                                 Some(metadata),
                             )),
-                            _ => unreachable!(),
                         }
                     }
                     _ => {
@@ -230,21 +1942,20 @@ impl K8sPlugin {
                             .collect::<BTreeSet<_>>();
 
                         match operator {
-                            "In" => entity.add_require(EntityRule::multi(
+                            ExpressionOperator::In => entity.add_require(EntityRule::multi(
                                 source,
                                 targets,
                                 crate::model::EntityRuleType::Require,
                                 entity_rule_source,
                                 Some(metadata.clone()),
                             )),
-                            "NotIn" => entity.add_exclude(EntityRule::multi(
+                            ExpressionOperator::NotIn => entity.add_exclude(EntityRule::multi(
                                 source,
                                 targets,
                                 crate::model::EntityRuleType::Exclude,
                                 entity_rule_source,
                                 Some(metadata.clone()),
                             )),
-                            _ => unreachable!(),
                         }
                     }
                 }
@@ -255,42 +1966,32 @@ impl K8sPlugin {
     }
 
     fn extract_pod_affinity_rules(
+        &self,
         pod_affinity: &PodAffinity,
         entity: &mut Entity,
         resource_type: ResourceType,
         source: &Path,
     ) -> anyhow::Result<()> {
-        let terms = pod_affinity
-            .required_during_scheduling_ignored_during_execution
-            .as_ref();
-
-        if terms.is_none() {
-            return Ok(());
-        }
-
-        let terms = terms.unwrap();
-
-        if terms.is_empty() {
-            return Ok(());
-        }
-
-        for span in terms.iter() {
-            let term = &span.value;
-            let line = span.line;
+        let terms = Self::affinity_terms(
+            source,
+            pod_affinity
+                .required_during_scheduling_ignored_during_execution
+                .as_deref(),
+            pod_affinity
+                .preferred_during_scheduling_ignored_during_execution
+                .as_deref(),
+        );
 
-            let topology_key: &str = term.topology_key.as_ref();
-            let topo = Self::topology_key_to_entity_rule_topology_key(topology_key)
+        for (term, rule_source, line, weight) in terms {
+            let topology_key = self.canonical_topology_key(term.topology_key.as_ref());
+            let topo = self
+                .topology_key_to_entity_rule_topology_key(topology_key)
                 .context("Invalid topology key")?;
             let label_selector = term
                 .label_selector
                 .as_ref()
                 .context("Invalid label selector")?;
-            let match_expressions = label_selector
-                .match_expressions
-                .as_ref()
-                .context("Invalid match expressions")?;
-
-            let metadata = EntityRuleMetadata::new(
+            let mut metadata = EntityRuleMetadata::new(
                 Some(source.display().to_string()),
                 NonZeroUsize::new(line),
                 Some(
@@ -306,10 +2007,39 @@ impl K8sPlugin {
                     .collect(),
                 ),
             );
+            if let Some(fields) =
+                self.passthrough_fields(term, &["topologyKey", "labelSelector.matchExpressions"])
+            {
+                metadata.add_metadata(METADATA_PASSTHROUGH_KEY.into(), fields);
+            }
+            if let Some(weight) = weight {
+                metadata.add_metadata(METADATA_WEIGHT_KEY.into(), weight.to_string());
+            }
+
+            if let Some(selected) = self.selected_workloads(label_selector)? {
+                metadata.add_metadata("type".into(), "podAffinity".into());
+                let rule = self.selector_rule(
+                    entity,
+                    term,
+                    selected,
+                    EntityRuleType::Require,
+                    rule_source.clone(),
+                    metadata,
+                );
+                entity.add_require(rule);
+                continue;
+            }
+
+            let match_expressions = label_selector
+                .match_expressions
+                .as_ref()
+                .context("Invalid match expressions")?;
 
             for expr in match_expressions.iter() {
                 let key: &str = expr.key.as_ref();
-                let operator: &str = expr.operator.as_ref();
+                let Some(operator) = ExpressionOperator::supported(&expr.operator, expr) else {
+                    continue;
+                };
                 let values: Vec<&str> = expr
                     .values
                     .as_deref()
@@ -318,24 +2048,21 @@ impl K8sPlugin {
                     .map(|s| s.as_ref())
                     .collect();
 
-                let entity_rule_source = EntityRuleSource::File(source.display().to_string(), line);
+                let entity_rule_source = rule_source.clone();
                 let mut metadata = metadata.clone();
                 metadata.add_metadata("key".into(), key.into());
                 metadata.add_metadata("type".into(), "podAffinity".into());
 
                 match operator {
-                    "In" => {
-                        metadata.add_metadata("operator".into(), operator.into());
+                    ExpressionOperator::In => {
+                        metadata.add_metadata("operator".into(), "In".into());
                     }
-                    "NotIn" => {
+                    ExpressionOperator::NotIn => {
                         warn!("Operator `NotIn` for affinity rule will be transformed into `In` for anti-affinity rule {:?}", expr);
                         warn!("It will be separated into two rules that both are required to be satisfied,which might not be intentional.");
                         metadata.add_metadata("inverse".into(), "true".into());
                         metadata.add_metadata("operator".into(), "In".into());
                     }
-                    _ => {
-                        panic!("Operator is not support yet: {}", operator)
-                    }
                 }
 
                 match values.len() {
@@ -345,21 +2072,20 @@ impl K8sPlugin {
                         let target = format!("{}={}", key, values[0]);
 
                         match operator {
-                            "In" => entity.add_require(EntityRule::mono(
+                            ExpressionOperator::In => entity.add_require(EntityRule::mono(
                                 source,
                                 target.into(),
                                 EntityRuleType::Require,
                                 entity_rule_source,
                                 Some(metadata),
                             )),
-                            "NotIn" => entity.add_exclude(EntityRule::mono(
+                            ExpressionOperator::NotIn => entity.add_exclude(EntityRule::mono(
                                 source,
                                 target.into(),
                                 EntityRuleType::Exclude,
                                 entity_rule_source,
                                 Some(metadata),
                             )),
-                            _ => unreachable!(),
                         }
                     }
                     _ => {
@@ -370,21 +2096,20 @@ impl K8sPlugin {
                             .collect::<BTreeSet<_>>();
 
                         match operator {
-                            "In" => entity.add_require(EntityRule::multi(
+                            ExpressionOperator::In => entity.add_require(EntityRule::multi(
                                 source,
                                 targets,
                                 crate::model::EntityRuleType::Require,
                                 entity_rule_source,
                                 Some(metadata.clone()),
                             )),
-                            "NotIn" => entity.add_exclude(EntityRule::multi(
+                            ExpressionOperator::NotIn => entity.add_exclude(EntityRule::multi(
                                 source,
                                 targets,
                                 crate::model::EntityRuleType::Exclude,
                                 entity_rule_source,
                                 Some(metadata.clone()),
                             )),
-                            _ => unreachable!(),
                         }
                     }
                 }
@@ -395,42 +2120,32 @@ impl K8sPlugin {
     }
 
     fn parse_pod_anti_affinity_rules(
+        &self,
         pod_anti_affinity: &PodAntiAffinity,
         entity: &mut Entity,
         resource_type: ResourceType,
         source: &Path,
     ) -> anyhow::Result<()> {
-        let terms = pod_anti_affinity
-            .required_during_scheduling_ignored_during_execution
-            .as_ref();
-
-        if terms.is_none() {
-            return Ok(());
-        }
-
-        let terms = terms.unwrap();
-
-        if terms.is_empty() {
-            return Ok(());
-        }
-
-        for span in terms.iter() {
-            let term = &span.value;
-            let line = span.line;
+        let terms = Self::affinity_terms(
+            source,
+            pod_anti_affinity
+                .required_during_scheduling_ignored_during_execution
+                .as_deref(),
+            pod_anti_affinity
+                .preferred_during_scheduling_ignored_during_execution
+                .as_deref(),
+        );
 
-            let topology_key: &str = term.topology_key.as_ref();
-            let topo = Self::topology_key_to_entity_rule_topology_key(topology_key)
+        for (term, rule_source, line, weight) in terms {
+            let topology_key = self.canonical_topology_key(term.topology_key.as_ref());
+            let topo = self
+                .topology_key_to_entity_rule_topology_key(topology_key)
                 .context("Invalid topology key")?;
             let label_selector = term
                 .label_selector
                 .as_ref()
                 .context("Invalid label selector")?;
-            let match_expressions = label_selector
-                .match_expressions
-                .as_ref()
-                .context("Invalid match expressions")?;
-
-            let metadata = EntityRuleMetadata::new(
+            let mut metadata = EntityRuleMetadata::new(
                 Some(source.display().to_string()),
                 NonZeroUsize::new(line),
                 Some(
@@ -446,10 +2161,39 @@ impl K8sPlugin {
                     .collect(),
                 ),
             );
+            if let Some(fields) =
+                self.passthrough_fields(term, &["topologyKey", "labelSelector.matchExpressions"])
+            {
+                metadata.add_metadata(METADATA_PASSTHROUGH_KEY.into(), fields);
+            }
+            if let Some(weight) = weight {
+                metadata.add_metadata(METADATA_WEIGHT_KEY.into(), weight.to_string());
+            }
+
+            if let Some(selected) = self.selected_workloads(label_selector)? {
+                metadata.add_metadata("type".into(), "podAntiAffinity".into());
+                let rule = self.selector_rule(
+                    entity,
+                    term,
+                    selected,
+                    EntityRuleType::Exclude,
+                    rule_source.clone(),
+                    metadata,
+                );
+                entity.add_exclude(rule);
+                continue;
+            }
+
+            let match_expressions = label_selector
+                .match_expressions
+                .as_ref()
+                .context("Invalid match expressions")?;
 
             for expr in match_expressions.iter() {
                 let key: &str = expr.key.as_ref();
-                let operator: &str = expr.operator.as_ref();
+                let Some(operator) = ExpressionOperator::supported(&expr.operator, expr) else {
+                    continue;
+                };
                 let values: Vec<&str> = expr
                     .values
                     .as_deref()
@@ -458,24 +2202,21 @@ impl K8sPlugin {
                     .map(|s| s.as_ref())
                     .collect();
 
-                let entity_rule_source = EntityRuleSource::File(source.display().to_string(), line);
+                let entity_rule_source = rule_source.clone();
                 let mut metadata = metadata.clone();
                 metadata.add_metadata("key".into(), key.into());
                 metadata.add_metadata("type".into(), "podAntiAffinity".into());
 
                 match operator {
-                    "In" => {
-                        metadata.add_metadata("operator".into(), operator.into());
+                    ExpressionOperator::In => {
+                        metadata.add_metadata("operator".into(), "In".into());
                     }
-                    "NotIn" => {
+                    ExpressionOperator::NotIn => {
                         warn!("Operator `NotIn` for anti-affinity rule will be transformed into `In` for affinity rule {:?}", expr);
                         warn!("It will be separated into two rules that both are required to be satisfied, which might not be intentional.");
                         metadata.add_metadata("inverse".into(), "true".into());
                         metadata.add_metadata("operator".into(), "In".into());
                     }
-                    _ => {
-                        panic!("Operator is not support yet: {}", operator)
-                    }
                 }
 
                 match values.len() {
@@ -485,21 +2226,20 @@ impl K8sPlugin {
                         let target = format!("{}={}", key, values[0]);
 
                         match operator {
-                            "In" => entity.add_exclude(EntityRule::mono(
+                            ExpressionOperator::In => entity.add_exclude(EntityRule::mono(
                                 source,
                                 target.into(),
                                 EntityRuleType::Exclude,
                                 entity_rule_source,
                                 Some(metadata),
                             )),
-                            "NotIn" => entity.add_require(EntityRule::mono(
+                            ExpressionOperator::NotIn => entity.add_require(EntityRule::mono(
                                 source,
                                 target.into(),
                                 EntityRuleType::Require,
                                 entity_rule_source,
                                 Some(metadata),
                             )),
-                            _ => unreachable!(),
                         }
                     }
                     _ => {
@@ -511,57 +2251,151 @@ impl K8sPlugin {
                             .collect::<BTreeSet<_>>();
 
                         match operator {
-                            "In" => entity.add_exclude(EntityRule::multi(
+                            ExpressionOperator::In => entity.add_exclude(EntityRule::multi(
                                 source,
                                 targets,
                                 crate::model::EntityRuleType::Exclude,
                                 entity_rule_source,
                                 Some(metadata.clone()),
                             )),
-                            "NotIn" => entity.add_require(EntityRule::multi(
+                            ExpressionOperator::NotIn => entity.add_require(EntityRule::multi(
                                 source,
                                 targets,
                                 crate::model::EntityRuleType::Require,
                                 entity_rule_source,
                                 Some(metadata.clone()),
                             )),
-                            _ => unreachable!(),
                         }
                     }
                 }
             }
         }
 
-        Ok(())
-    }
-
-    fn extract_entity_from_node(
-        labels: &BTreeMap<String, String>,
-        path: &Path,
-    ) -> anyhow::Result<Vec<Entity>> {
-        let name = labels
-            .get("kubernetes.io/hostname")
-            .expect("No hostname found");
-
-        let entity_source = EntitySource::File(path.display().to_string());
-        let entities = labels
-            .iter()
-            .map(|(key, value)| {
-                let entity_name = format!("{}={}", key, value);
-                let mut entity = Entity::new_with_source(&entity_name, entity_source.clone());
-                entity.priority = EntityPriority::Default;
-
-                entity
-            })
-            .collect::<Vec<_>>();
-
-        Ok(entities)
+        Ok(())
+    }
+
+    fn extract_entity_from_node(
+        &self,
+        labels: &BTreeMap<String, String>,
+        path: &Path,
+    ) -> anyhow::Result<Vec<Entity>> {
+        let name = labels
+            .get("kubernetes.io/hostname")
+            .expect("No hostname found");
+
+        let entity_source = EntitySource::File(path.display().to_string());
+        let entities = labels
+            .iter()
+            .map(|(key, value)| {
+                let entity_name = format!("{}={}", self.canonical_topology_key(key), value);
+                let mut entity = Entity::new_with_source(&entity_name, entity_source.clone());
+                entity.priority = EntityPriority::Default;
+
+                entity
+            })
+            .collect::<Vec<_>>();
+
+        Ok(entities)
+    }
+
+    /*
+        Entity of a resource of another kind (e.g. a custom resource) with the rules of its
+        annotations listed in the `annotation_rules` config section, none without such an
+        annotation. The entity is named after the resource, so that its rules are merged with
+        the ones of the workload of the same name.
+    */
+    fn extract_entity_from_annotations(
+        &self,
+        document: &Document,
+        path: &Path,
+    ) -> anyhow::Result<Option<Entity>> {
+        let data = document.text;
+        let Ok(manifest) = serde_yaml::from_str::<Manifest>(data) else {
+            return Ok(None);
+        };
+        let annotations = manifest.metadata.annotations.unwrap_or_default();
+        let annotated = self
+            .config
+            .annotation_rules
+            .iter()
+            .filter_map(|(key, rule)| Some((key, rule, annotations.get(key)?)))
+            .collect::<Vec<_>>();
+
+        if annotated.is_empty() {
+            return Ok(None);
+        }
+
+        let kind = manifest.kind.to_lowercase();
+        let name = manifest
+            .metadata
+            .name
+            .with_context(|| format!("missing name in {}.metadata", kind))?;
+        let mut entity = Entity::new(&format!("app={}", name));
+
+        for (key, rule, value) in annotated {
+            let targets = value
+                .split(',')
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(|value| EntityName(rule.target.replace("{}", value)))
+                .collect::<BTreeSet<_>>();
+
+            let line = Self::line_of(data, key);
+            if targets.is_empty() {
+                warn!(
+                    "{}:{}: annotation {} names no entity",
+                    path.display(),
+                    document.lines_before + line,
+                    key
+                );
+                continue;
+            }
+
+            let metadata = EntityRuleMetadata::new(
+                Some(path.display().to_string()),
+                NonZeroUsize::new(line),
+                Some(
+                    vec![
+                        (METADATA_TOPOLOGY_KEY.to_string(), rule.topology.to_string()),
+                        (METADATA_RESOURCE_TYPE_KEY.to_string(), kind.clone()),
+                        (METADATA_ANNOTATION_KEY.to_string(), key.clone()),
+                    ]
+                    .into_iter()
+                    .collect(),
+                ),
+            );
+            let rule_source = EntityRuleSource::File(path.display().to_string(), line);
+            let r#type = match rule.rule {
+                AnnotationRuleType::Require => EntityRuleType::Require,
+                AnnotationRuleType::Exclude => EntityRuleType::Exclude,
+            };
+
+            // One rule per value, a multi rule would be satisfied by any one of them
+            for target in targets {
+                let entity_rule = EntityRule::mono(
+                    entity.name.clone(),
+                    target,
+                    r#type.clone(),
+                    rule_source.clone(),
+                    Some(metadata.clone()),
+                );
+
+                match rule.rule {
+                    AnnotationRuleType::Require => entity.add_require(entity_rule),
+                    AnnotationRuleType::Exclude => entity.add_exclude(entity_rule),
+                }
+            }
+        }
+
+        Ok(Some(entity))
     }
 
     fn extract_entity(
+        &self,
         name: &str,
         pod: &PodSpec,
         resource_type: ResourceType,
+        data: &str,
         source: &Path,
     ) -> anyhow::Result<Entity> {
         // FIXME: This is a assumption that all labels are app=xxx
@@ -570,11 +2404,8 @@ impl K8sPlugin {
         let entity_source = EntitySource::File(source.display().to_string());
         let mut entity = Entity::new_with_source(&name, entity_source);
 
-        entity.priority = pod
-            .priority_class_name
-            .as_ref()
-            .map(|e| EntityPriority::from(e.as_str()))
-            .unwrap_or_default();
+        entity.priority = self.resolve_priority(pod);
+        self.extract_node_selector_rules(pod, &mut entity, resource_type, data, source);
 
         let affinity = pod.affinity.as_ref();
         let affinity = match affinity {
@@ -584,18 +2415,18 @@ impl K8sPlugin {
 
         let node_affinity = affinity.node_affinity.as_ref();
         if let Some(node_affinity) = node_affinity {
-            Self::extract_node_affinity_rules(node_affinity, &mut entity, resource_type, source)?;
+            self.extract_node_affinity_rules(node_affinity, &mut entity, resource_type, source)?;
         }
 
         // PodAffinity
         let pod_affinity = affinity.pod_affinity.as_ref();
         if let Some(pod_affinity) = pod_affinity {
-            Self::extract_pod_affinity_rules(pod_affinity, &mut entity, resource_type, source)?;
+            self.extract_pod_affinity_rules(pod_affinity, &mut entity, resource_type, source)?;
         }
         // PodAntiAffinity
         let pod_anti_affinity = affinity.pod_anti_affinity.as_ref();
         if let Some(pod_anti_affinity) = pod_anti_affinity {
-            Self::parse_pod_anti_affinity_rules(
+            self.parse_pod_anti_affinity_rules(
                 pod_anti_affinity,
                 &mut entity,
                 resource_type,
@@ -623,9 +2454,15 @@ impl K8sPlugin {
                     vec![]
                 };
 
+                // Hints are declared by the Service or NetworkPolicy manifests, the rules of
+                // owned pods by the manifest of their owner, and the rules of annotations by
+                // resources which are never rewritten
                 requires
                     .iter()
                     .chain(conflicts.iter())
+                    .filter(|rule| {
+                        rule.hint().is_none() && rule.pod().is_none() && rule.annotation().is_none()
+                    })
                     .filter_map(|rule| rule.meta_file().map(|e| (name, e)))
                     .collect::<Vec<_>>()
                     .into_iter()
@@ -634,13 +2471,13 @@ impl K8sPlugin {
             .filter(|(_, path)| !path.ends_with(".ir"))
             .collect::<Vec<_>>();
 
-        // Check is there duplicates
+        // Check is there duplicates, a symlink and its target are the same source
         let duplicates = mapping
             .iter()
             .fold(HashMap::new(), |mut acc, (name, path)| {
-                let entry: &mut HashSet<&str> = acc.entry(name).or_default();
+                let entry: &mut HashSet<PathBuf> = acc.entry(name).or_default();
 
-                entry.insert(path);
+                entry.insert(std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path)));
 
                 acc
             })
@@ -662,7 +2499,9 @@ impl K8sPlugin {
     }
 
     fn inject_pod_affinity_rules(
+        &self,
         terms: &mut Vec<Spanned<PodAffinityTerm>>,
+        preferred_terms: &mut Vec<Spanned<WeightedPodAffinityTerm>>,
         rules: &BTreeSet<EntityRule>,
     ) -> anyhow::Result<()> {
         // First Implementation: Clear all existing terms And replace with new terms
@@ -684,8 +2523,20 @@ impl K8sPlugin {
             let topology_key = match topology_key {
                 Some(topology_key) => topology_key,
                 None => {
-                    warn!("No `topology_key` found in metadata for rule {:?}, assuming the default value `topology.kubernetes.io/hostname`", rule);
-                    "topology.kubernetes.io/hostname"
+                    let topology_key = self.entity_rule_topology_key_to_topology_key(
+                        rule.metadata(METADATA_TOPOLOGY_KEY),
+                        "topology.kubernetes.io/hostname",
+                    );
+
+                    util::assume_default(
+                        rule,
+                        format!(
+                            "No `topology_key` found in metadata for rule `{}`, assuming `{}` from its topology",
+                            rule_text(rule), topology_key
+                        ),
+                        self.strict,
+                    )?;
+                    topology_key
                 }
             };
 
@@ -693,7 +2544,14 @@ impl K8sPlugin {
             let key = match key {
                 Some(key) => key,
                 None => {
-                    warn!("No `key` found in metadata for rule {:?}, assuming the default value `app`", rule);
+                    util::assume_default(
+                        rule,
+                        format!(
+                            "No `key` found in metadata for rule `{}`, assuming the default value `app`",
+                            rule_text(rule)
+                        ),
+                        self.strict,
+                    )?;
                     "app"
                 }
             };
@@ -702,7 +2560,14 @@ impl K8sPlugin {
             let operator = match operator {
                 Some(operator) => operator,
                 None => {
-                    warn!("No `operator` found in metadata for rule {:?}, assuming the default value `In`", rule);
+                    util::assume_default(
+                        rule,
+                        format!(
+                            "No `operator` found in metadata for rule `{}`, assuming the default value `In`",
+                            rule_text(rule)
+                        ),
+                        self.strict,
+                    )?;
                     "In"
                 }
             };
@@ -764,7 +2629,9 @@ impl K8sPlugin {
             let values = values.into_iter().collect::<Result<Vec<_>, _>>()?;
 
             let term = PodAffinityTerm {
-                topology_key: topology_key.into(),
+                topology_key: self
+                    .versioned_topology_key(self.canonical_topology_key(topology_key))
+                    .into(),
                 label_selector: Some(LabelSelector {
                     match_expressions: Some(vec![LabelSelectorRequirement {
                         key: key.into(),
@@ -775,6 +2642,36 @@ impl K8sPlugin {
                 }),
                 ..Default::default()
             };
+            let term = self.restore_passthrough_fields(term, rule)?;
+
+            self.validate_pod_affinity_term(&term, rule)?;
+
+            // Soft rules keep their weight as preferences, hard rules stay required
+            if let Some(weight) = rule.weight() {
+                let weight = weight
+                    .parse::<i32>()
+                    .ok()
+                    .filter(|w| (1..=100).contains(w))
+                    .with_context(|| {
+                        format!(
+                            "Invalid weight `{}` for rule {:?}, expecting 1-100",
+                            weight, rule
+                        )
+                    })?;
+
+                preferred_terms.push(Spanned {
+                    value: WeightedPodAffinityTerm {
+                        pod_affinity_term: term,
+                        weight,
+                    },
+                    index: 0,
+                    line: 0,
+                    column: 0,
+                    len: 0,
+                });
+
+                continue;
+            }
 
             terms.push(Spanned {
                 value: term,
@@ -788,7 +2685,57 @@ impl K8sPlugin {
         Ok(())
     }
 
+    // Rejects generated terms the scheduler would refuse or silently misinterpret
+    fn validate_pod_affinity_term(
+        &self,
+        term: &PodAffinityTerm,
+        rule: &EntityRule,
+    ) -> anyhow::Result<()> {
+        if term.topology_key.is_empty() {
+            anyhow::bail!(
+                "Rule {} produces an empty topologyKey, set `topology_key` or `topology` in its metadata",
+                rule
+            );
+        }
+
+        let no_values = term
+            .label_selector
+            .as_ref()
+            .and_then(|s| s.match_expressions.as_ref())
+            .map(|exprs| {
+                exprs
+                    .iter()
+                    .any(|e| !matches!(&e.values, Some(v) if !v.is_empty()))
+            })
+            .unwrap_or(true);
+
+        if no_values {
+            anyhow::bail!("Rule {} produces a label selector matching no values", rule);
+        }
+
+        let topology = rule
+            .metadata(METADATA_TOPOLOGY_KEY)
+            .filter(|t| matches!(*t, "zone" | "rack" | "node"));
+        let key_topology = self.topology_key_to_entity_rule_topology_key(&term.topology_key);
+
+        if let (Some(topology), Some(key_topology)) = (topology, key_topology) {
+            if topology != key_topology.as_ref() {
+                anyhow::bail!(
+                    "Rule {} is a {}-level rule but uses the {}-level topologyKey `{}`, use `{}` instead",
+                    rule,
+                    topology,
+                    key_topology.as_ref(),
+                    term.topology_key,
+                    self.entity_rule_topology_key_to_topology_key(Some(topology), HOSTNAME_TOPOLOGY_LABEL)
+                );
+            }
+        }
+
+        Ok(())
+    }
+
     fn inject_node_affinity_rules(
+        &self,
         terms: &mut Vec<Spanned<NodeSelectorTerm>>,
         requires: &BTreeSet<EntityRule>,
         excludes: &BTreeSet<EntityRule>,
@@ -809,30 +2756,60 @@ impl K8sPlugin {
                 _ => continue,
             }
 
-            let topology_key = rule.metadata("topology_key");
-
-            let topology_key = match topology_key {
-                Some(topology_key) => topology_key,
-                None => {
-                    warn!("No `topology_key` found in metadata for rule {:?}, assuming the default value `kubernetes.io/hostname`", rule);
-                    "kubernetes.io/hostname"
-                }
-            };
-
-            let key = rule.metadata("key");
-            let key = match key {
-                Some(key) => key,
+            // Node selector terms have no topology key: the domain of the rule is expressed
+            // through the node label being matched, e.g. `topology.kubernetes.io/zone In [a]`
+            let key = match rule.metadata("key") {
+                Some(key) => key.to_string(),
                 None => {
-                    warn!("No `key` found in metadata for rule {:?}, assuming the default value `app`", rule);
-                    "app"
+                    match rule
+                        .targets()
+                        .first()
+                        .and_then(|t| t.as_ref().split_once('='))
+                    {
+                        Some((prefix, _)) => {
+                            util::assume_default(
+                                rule,
+                                format!(
+                                    "No `key` found in metadata for rule `{}`, using the target label `{}`",
+                                    rule_text(rule), prefix
+                                ),
+                                self.strict,
+                            )?;
+                            prefix.to_string()
+                        }
+                        None => {
+                            let topology_key = self.entity_rule_topology_key_to_topology_key(
+                                rule.metadata(METADATA_TOPOLOGY_KEY),
+                                HOSTNAME_TOPOLOGY_LABEL,
+                            );
+
+                            util::assume_default(
+                                rule,
+                                format!(
+                                    "No `key` found in metadata for rule `{}`, assuming `{}` from its topology",
+                                    rule_text(rule), topology_key
+                                ),
+                                self.strict,
+                            )?;
+                            topology_key.to_string()
+                        }
+                    }
                 }
             };
+            let key = key.as_str();
 
             let operator = rule.metadata("operator");
             let operator = match operator {
                 Some(operator) => operator,
                 None => {
-                    warn!("No `operator` found in metadata for rule {:?}, assuming the default value `In`", rule);
+                    util::assume_default(
+                        rule,
+                        format!(
+                            "No `operator` found in metadata for rule `{}`, assuming the default value `In`",
+                            rule_text(rule)
+                        ),
+                        self.strict,
+                    )?;
                     "In"
                 }
             };
@@ -886,6 +2863,7 @@ impl K8sPlugin {
                 }]),
                 ..Default::default()
             };
+            let term = self.restore_passthrough_fields(term, rule)?;
 
             terms.push(Spanned {
                 value: term,
@@ -899,11 +2877,63 @@ impl K8sPlugin {
         Ok(())
     }
 
+    // The `nodeSelector` and `nodeName` of the pod spec, rewritten from the rules read from them
+    fn inject_node_selector_rules(
+        pod_spec: &mut PodSpec,
+        requires: &BTreeSet<EntityRule>,
+    ) -> anyhow::Result<()> {
+        let mut selector = BTreeMap::new();
+        let mut node_name = None;
+
+        for rule in requires.iter() {
+            let r#type = match rule.metadata("type") {
+                Some(r#type @ (NODE_SELECTOR_TYPE | NODE_NAME_TYPE)) => r#type,
+                _ => continue,
+            };
+
+            let EntityRule::Mono { target, .. } = rule else {
+                anyhow::bail!(
+                    "A {} rule has a single target, not `{}`",
+                    r#type,
+                    rule_text(rule)
+                );
+            };
+            let key = rule.metadata("key").with_context(|| {
+                format!("No `key` found in metadata for rule `{}`", rule_text(rule))
+            })?;
+            let value = target
+                .as_ref()
+                .strip_prefix(&format!("{}=", key))
+                .with_context(|| {
+                    format!(
+                        "Invalid value format: {} for rule {:?}",
+                        target.as_ref(),
+                        rule
+                    )
+                })?;
+
+            match r#type {
+                NODE_NAME_TYPE => node_name = Some(value.to_string()),
+                _ => {
+                    selector.insert(key.to_string(), value.to_string());
+                }
+            }
+        }
+
+        pod_spec.node_selector = (!selector.is_empty()).then_some(selector);
+        pod_spec.node_name = node_name;
+
+        Ok(())
+    }
+
     fn inject_entity_to_pod_spec(
+        &self,
         entity: Entity,
         pod_spec: &mut PodSpec,
         // base_name: String,
     ) -> anyhow::Result<()> {
+        Self::inject_node_selector_rules(pod_spec, &entity.requires)?;
+
         // let name = entity.name.as_ref();
 
         let affinity = pod_spec.affinity.get_or_insert(Default::default());
@@ -915,7 +2945,12 @@ impl K8sPlugin {
                 .required_during_scheduling_ignored_during_execution
                 .get_or_insert(Default::default());
 
-            Self::inject_pod_affinity_rules(terms, &entity.requires)?;
+            let mut preferred_terms = vec![];
+            self.inject_pod_affinity_rules(terms, &mut preferred_terms, &entity.requires)?;
+
+            // Preferred terms are read as weighted rules, they are replaced along the required ones
+            pod_affinity.preferred_during_scheduling_ignored_during_execution =
+                (!preferred_terms.is_empty()).then_some(preferred_terms);
         }
 
         if !entity.excludes.is_empty() {
@@ -925,7 +2960,12 @@ impl K8sPlugin {
                 .required_during_scheduling_ignored_during_execution
                 .get_or_insert(Default::default());
 
-            Self::inject_pod_affinity_rules(terms, &entity.excludes)?;
+            let mut preferred_terms = vec![];
+            self.inject_pod_affinity_rules(terms, &mut preferred_terms, &entity.excludes)?;
+
+            // Preferred terms are read as weighted rules, they are replaced along the required ones
+            pod_anti_affinity.preferred_during_scheduling_ignored_during_execution =
+                (!preferred_terms.is_empty()).then_some(preferred_terms);
         }
 
         if !entity.requires.is_empty() || !entity.excludes.is_empty() {
@@ -937,22 +2977,174 @@ impl K8sPlugin {
 
             let terms = &mut terms.node_selector_terms;
 
-            Self::inject_node_affinity_rules(terms, &entity.requires, &entity.excludes)?;
+            self.inject_node_affinity_rules(terms, &entity.requires, &entity.excludes)?;
         }
 
         Ok(())
     }
 
-    fn inject_entity(entity: Entity, path: &Path) -> anyhow::Result<(String, String)> {
-        let _name = entity.name.as_ref();
+    fn stateful_set_pod_spec(stateful_set: &mut StatefulSet) -> anyhow::Result<&mut PodSpec> {
+        stateful_set
+            .spec
+            .as_mut()
+            .context("missing spec in statefulset")?
+            .template
+            .spec
+            .as_mut()
+            .context("missing spec in statefulset.template")
+    }
+
+    fn daemon_set_pod_spec(daemon_set: &mut DaemonSet) -> anyhow::Result<&mut PodSpec> {
+        daemon_set
+            .spec
+            .as_mut()
+            .context("missing spec in daemonset")?
+            .template
+            .spec
+            .as_mut()
+            .context("missing spec in daemonset.template")
+    }
+
+    fn replica_set_pod_spec(replica_set: &mut ReplicaSet) -> anyhow::Result<&mut PodSpec> {
+        replica_set
+            .spec
+            .as_mut()
+            .context("missing spec in replicaset")?
+            .template
+            .as_mut()
+            .context("missing template in replicaset")?
+            .spec
+            .as_mut()
+            .context("missing spec in replicaset.template")
+    }
+
+    fn job_pod_spec(job: &mut Job) -> anyhow::Result<&mut PodSpec> {
+        job.spec
+            .as_mut()
+            .context("missing spec in job")?
+            .template
+            .spec
+            .as_mut()
+            .context("missing spec in job.template")
+    }
+
+    fn cron_job_pod_spec(cron_job: &mut CronJob) -> anyhow::Result<&mut PodSpec> {
+        cron_job
+            .spec
+            .as_mut()
+            .context("missing spec in cronjob")?
+            .job_template
+            .spec
+            .as_mut()
+            .context("missing spec in cronjob.jobTemplate")?
+            .template
+            .spec
+            .as_mut()
+            .context("missing spec in cronjob.jobTemplate.template")
+    }
+
+    /*
+        Rewrites the manifest at `path` with `rewrite` applied to the document of each entity,
+        given with the number of lines of the file before it. In a manifest of several
+        documents, an entity is in the document of the workload it names and the other
+        documents are kept as written. A workload named in `renames` is in the document of the
+        entity it was renamed to.
+    */
+    fn rewrite_manifest(
+        path: &Path,
+        read: &ManifestReader,
+        entities: Vec<Entity>,
+        renames: &HashMap<String, String>,
+        rewrite: impl Fn(Entity, &str, usize) -> anyhow::Result<String>,
+    ) -> anyhow::Result<String> {
+        let data = read(path)?;
+        let documents = documents(&data);
+
+        if documents.len() <= 1 {
+            return entities
+                .into_iter()
+                .try_fold(data.clone(), |data, entity| rewrite(entity, &data, 0));
+        }
+
+        let mut entities = entities;
+        let mut rewritten = vec![];
+        for document in documents.iter() {
+            let workload = Self::workload_labels(document.text)
+                .map(|(name, _)| renames.get(&name).cloned().unwrap_or(name));
+            let (own, rest): (Vec<_>, Vec<_>) = entities
+                .into_iter()
+                .partition(|entity| Some(entity.name.as_ref()) == workload.as_deref());
+            entities = rest;
+
+            rewritten.push(if own.is_empty() {
+                document.text.to_string()
+            } else {
+                own.into_iter()
+                    .try_fold(document.text.to_string(), |data, entity| {
+                        rewrite(entity, &data, document.lines_before)
+                    })?
+            });
+        }
+
+        if let Some(entity) = entities.first() {
+            anyhow::bail!(
+                "No document of {} declares entity {}",
+                path.display(),
+                entity.name.as_ref()
+            );
+        }
+
+        Ok(join_documents(&rewritten))
+    }
+
+    // Manifests of the entities are read from disk unless given in memory
+    fn read_manifest(path: &Path) -> anyhow::Result<String> {
+        Ok(util::read_source(path)?)
+    }
+
+    fn manifest_reader<D: AsRef<[u8]>>(
+        manifests: &BTreeMap<String, D>,
+    ) -> impl Fn(&Path) -> anyhow::Result<String> + '_ {
+        move |path| {
+            let name = path.display().to_string();
+            let data = manifests
+                .get(&name)
+                .with_context(|| format!("No manifest named {} was given", name))?;
+
+            Ok(util::decode_source(data.as_ref().to_vec(), path))
+        }
+    }
 
+    // Name a rewritten manifest is written under in the output directory
+    fn base_name(path: &Path) -> anyhow::Result<String> {
         let base_name = path.file_name().context("No file name found")?;
         let base_name = base_name.to_str().context("Invalid file name")?;
-        let base_name = base_name.to_string();
 
-        let data = std::fs::read_to_string(path)?;
+        Ok(base_name.to_string())
+    }
+
+    // Entities grouped by the manifest they are written to, in the order of their first entity
+    fn by_manifest(entities: Vec<(Entity, &PathBuf)>) -> Vec<(&PathBuf, Vec<Entity>)> {
+        let mut manifests: Vec<(&PathBuf, Vec<Entity>)> = vec![];
+
+        for (entity, path) in entities {
+            match manifests.iter_mut().find(|(p, _)| *p == path) {
+                Some((_, group)) => group.push(entity),
+                None => manifests.push((path, vec![entity])),
+            }
+        }
+
+        manifests
+    }
+
+    fn is_multi_document(path: &Path, read: &ManifestReader) -> bool {
+        read(path).is_ok_and(|data| documents(&data).len() > 1)
+    }
+
+    fn inject_document(&self, entity: Entity, data: &str) -> anyhow::Result<String> {
+        if let Ok(mut deployment) = serde_yaml::from_str::<Deployment>(data) {
+            Self::annotate_generated_rules(&entity, &mut deployment.metadata);
 
-        if let Ok(mut deployment) = serde_yaml::from_str::<Deployment>(&data) {
             let pod_spec = deployment
                 .spec
                 .as_mut()
@@ -962,25 +3154,110 @@ impl K8sPlugin {
                 .as_mut()
                 .context("missing spec in deployment.template")?;
 
-            Self::inject_entity_to_pod_spec(entity, pod_spec)?;
+            self.inject_entity_to_pod_spec(entity, pod_spec)?;
+
+            Ok(serde_yaml::to_string(&deployment)?)
+        } else if let Ok(mut stateful_set) = serde_yaml::from_str::<StatefulSet>(data) {
+            Self::annotate_generated_rules(&entity, &mut stateful_set.metadata);
+
+            let pod_spec = Self::stateful_set_pod_spec(&mut stateful_set)?;
+            self.inject_entity_to_pod_spec(entity, pod_spec)?;
+
+            Ok(serde_yaml::to_string(&stateful_set)?)
+        } else if let Ok(mut daemon_set) = serde_yaml::from_str::<DaemonSet>(data) {
+            Self::annotate_generated_rules(&entity, &mut daemon_set.metadata);
+
+            let pod_spec = Self::daemon_set_pod_spec(&mut daemon_set)?;
+            self.inject_entity_to_pod_spec(entity, pod_spec)?;
+
+            Ok(serde_yaml::to_string(&daemon_set)?)
+        } else if let Ok(mut replica_set) = serde_yaml::from_str::<ReplicaSet>(data) {
+            Self::annotate_generated_rules(&entity, &mut replica_set.metadata);
+
+            let pod_spec = Self::replica_set_pod_spec(&mut replica_set)?;
+            self.inject_entity_to_pod_spec(entity, pod_spec)?;
+
+            Ok(serde_yaml::to_string(&replica_set)?)
+        } else if let Ok(mut pod) = serde_yaml::from_str::<Pod>(data) {
+            Self::annotate_generated_rules(&entity, &mut pod.metadata);
 
-            Ok((base_name, serde_yaml::to_string(&deployment)?))
-        } else if let Ok(mut pod) = serde_yaml::from_str::<Pod>(&data) {
             let pod_spec = pod.spec.as_mut().context("missing spec in pod")?;
 
-            Self::inject_entity_to_pod_spec(entity, pod_spec)?;
+            self.inject_entity_to_pod_spec(entity, pod_spec)?;
+
+            Ok(serde_yaml::to_string(&pod)?)
+        } else if let Ok(mut job) = serde_yaml::from_str::<Job>(data) {
+            Self::annotate_generated_rules(&entity, &mut job.metadata);
 
-            Ok((base_name, serde_yaml::to_string(&pod)?))
+            let pod_spec = Self::job_pod_spec(&mut job)?;
+            self.inject_entity_to_pod_spec(entity, pod_spec)?;
+
+            Ok(serde_yaml::to_string(&job)?)
+        } else if let Ok(mut cron_job) = serde_yaml::from_str::<CronJob>(data) {
+            Self::annotate_generated_rules(&entity, &mut cron_job.metadata);
+
+            let pod_spec = Self::cron_job_pod_spec(&mut cron_job)?;
+            self.inject_entity_to_pod_spec(entity, pod_spec)?;
+
+            Ok(serde_yaml::to_string(&cron_job)?)
         } else {
-            panic!("Unknown resource type")
+            anyhow::bail!("Unknown resource type")
         }
     }
 
-    pub fn inject_entities(
+    // Hints only inform the checks, they are never written into a manifest, nor are the rules
+    // read from the annotations of resources deployfix does not rewrite
+    fn without_hints(entities: Vec<Entity>) -> Vec<Entity> {
+        entities
+            .into_iter()
+            .map(|mut entity| {
+                entity
+                    .requires
+                    .retain(|rule| rule.hint().is_none() && rule.annotation().is_none());
+                entity.excludes.retain(|rule| rule.annotation().is_none());
+                entity
+            })
+            .collect()
+    }
+
+    pub fn inject_entities_into(
+        &self,
         entities: Vec<Entity>,
         mapping: &HashMap<String, PathBuf>,
     ) -> Result<Vec<(String, String)>, anyhow::Error> {
-        let specs = entities
+        self.inject_with(entities, mapping, &Self::read_manifest, true)?
+            .into_iter()
+            .map(|(path, data)| Ok::<_, anyhow::Error>((Self::base_name(path)?, data)))
+            .collect()
+    }
+
+    // Manifests of `manifests`, by logical name as for `extract_entities_from_manifests`,
+    // rewritten with the rules of `entities`. Manifests without an entity are left out.
+    pub fn inject_manifests<D: AsRef<[u8]>>(
+        &self,
+        entities: Vec<Entity>,
+        manifests: &BTreeMap<String, D>,
+    ) -> anyhow::Result<BTreeMap<String, String>> {
+        let mapping = Self::scan_entity_file_mapping(&entities)?;
+        let read = Self::manifest_reader(manifests);
+
+        Ok(self
+            .inject_with(entities, &mapping, &read, false)?
+            .into_iter()
+            .map(|(path, data)| (path.display().to_string(), data))
+            .collect())
+    }
+
+    // `unique_base_names` when the manifests are written by their base name, which manifests of
+    // different directories must then not share
+    fn inject_with<'a>(
+        &self,
+        entities: Vec<Entity>,
+        mapping: &'a HashMap<String, PathBuf>,
+        read: &ManifestReader,
+        unique_base_names: bool,
+    ) -> anyhow::Result<Vec<(&'a PathBuf, String)>> {
+        let entities = Self::without_hints(entities)
             .into_iter()
             .filter(|entity| !entity.requires.is_empty() || !entity.excludes.is_empty())
             .map(|entity| {
@@ -988,20 +3265,221 @@ impl K8sPlugin {
                     format!("No source file found for entity {}", entity.name.as_ref())
                 })?;
 
-                Self::inject_entity(entity, path)
+                Ok((entity, path))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Self::check_injection_targets(&entities, read, unique_base_names)?;
+
+        Self::by_manifest(entities)
+            .into_iter()
+            .map(|(path, entities)| {
+                let data = Self::rewrite_manifest(
+                    path,
+                    read,
+                    entities,
+                    &self.renames,
+                    |entity, data, _| self.inject_document(entity, data),
+                )?;
+                Ok::<_, anyhow::Error>((path, data))
+            })
+            .collect()
+    }
+
+    // Entity of a service that does not exist yet, requiring and excluding `key=value`
+    // entities on `topology` as its affinity terms would
+    pub fn scaffold_entity(
+        &self,
+        name: &str,
+        requires: &[String],
+        excludes: &[String],
+        topology: EntityRuleTopologyKey,
+    ) -> anyhow::Result<Entity> {
+        let mut entity = Entity::new(name);
+        let topology_key = self.entity_rule_topology_key_to_topology_key(
+            Some(topology.as_ref()),
+            HOSTNAME_TOPOLOGY_LABEL,
+        );
+
+        let rules = requires
+            .iter()
+            .map(|target| (target, EntityRuleType::Require, "podAffinity"))
+            .chain(
+                excludes
+                    .iter()
+                    .map(|target| (target, EntityRuleType::Exclude, "podAntiAffinity")),
+            );
+        for (target, r#type, affinity) in rules {
+            let (key, _) = target
+                .split_once('=')
+                .with_context(|| format!("Invalid target `{}`, expecting `key=value`", target))?;
+
+            let metadata = EntityRuleMetadata::new(
+                None,
+                None,
+                Some(
+                    vec![
+                        ("type".to_string(), affinity.to_string()),
+                        ("key".to_string(), key.to_string()),
+                        ("operator".to_string(), "In".to_string()),
+                        ("topology_key".to_string(), topology_key.to_string()),
+                        (METADATA_TOPOLOGY_KEY.to_string(), topology.to_string()),
+                    ]
+                    .into_iter()
+                    .collect(),
+                ),
+            );
+            let rule = EntityRule::mono(
+                entity.name.clone(),
+                EntityName(target.clone()),
+                r#type.clone(),
+                EntityRuleSource::Unknown,
+                Some(metadata),
+            );
+
+            match r#type {
+                EntityRuleType::Require => entity.add_require(rule),
+                EntityRuleType::Exclude => entity.add_exclude(rule),
+            }
+        }
+
+        Ok(entity)
+    }
+
+    // The `affinity` block of a pod spec carrying the rules of `entity`, ready to paste into
+    // a new manifest
+    pub fn scaffold_affinity(&self, entity: Entity) -> anyhow::Result<String> {
+        #[derive(serde::Serialize)]
+        struct Snippet {
+            affinity: Affinity,
+        }
+
+        let mut pod_spec = PodSpec::default();
+        self.inject_entity_to_pod_spec(entity, &mut pod_spec)?;
+
+        let mut affinity = pod_spec.affinity.unwrap_or_default();
+        // Pod rules leave an empty node selector behind
+        if affinity
+            .node_affinity
+            .as_ref()
+            .and_then(|node| {
+                node.required_during_scheduling_ignored_during_execution
+                    .as_ref()
+            })
+            .is_none_or(|selector| selector.node_selector_terms.is_empty())
+        {
+            affinity.node_affinity = None;
+        }
+
+        Ok(serde_yaml::to_string(&Snippet { affinity })?)
+    }
+
+    // Each injection rewrites the whole manifest, so two entities sharing a file (or two
+    // files sharing an output name) would silently overwrite each other.
+    fn check_injection_targets(
+        entities: &[(Entity, &PathBuf)],
+        read: &ManifestReader,
+        unique_base_names: bool,
+    ) -> anyhow::Result<()> {
+        let mut by_path: BTreeMap<&Path, Vec<&str>> = BTreeMap::new();
+        let mut by_base_name: BTreeMap<&OsStr, BTreeSet<&Path>> = BTreeMap::new();
+
+        for (entity, path) in entities {
+            by_path
+                .entry(path.as_path())
+                .or_default()
+                .push(entity.name.as_ref());
+
+            if let Some(base_name) = path.file_name() {
+                by_base_name.entry(base_name).or_default().insert(path);
+            }
+        }
+
+        let mut errors = by_path
+            .iter()
+            // Entities of a manifest of several documents are each injected into their own
+            .filter(|(path, names)| names.len() > 1 && !Self::is_multi_document(path, read))
+            .map(|(path, names)| {
+                format!(
+                    "entities {} are all injected into {}",
+                    names.join(", "),
+                    path.display()
+                )
             })
             .collect::<Vec<_>>();
 
-        let specs = specs.into_iter().collect::<Result<Vec<_>, _>>()?;
+        errors.extend(
+            by_base_name
+                .iter()
+                .filter(|(_, paths)| unique_base_names && paths.len() > 1)
+                .map(|(base_name, paths)| {
+                    format!(
+                        "{} are all written to {}",
+                        paths
+                            .iter()
+                            .map(|p| p.display().to_string())
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                        base_name.to_string_lossy()
+                    )
+                }),
+        );
+
+        if !errors.is_empty() {
+            anyhow::bail!("Conflicting injections: {}", errors.join("; "));
+        }
+
+        Ok(())
+    }
+
+    // Whether `term` is one of the removed rules, by its column as well when the rule has one:
+    // terms in flow style share a line. `lines_before` the document of the term in its file.
+    fn removed<T>(
+        rules: &HashSet<(usize, Option<usize>)>,
+        lines_before: usize,
+        term: &Spanned<T>,
+    ) -> bool {
+        let line = lines_before + term.line;
 
-        Ok(specs)
+        rules.contains(&(line, Some(term.column))) || rules.contains(&(line, None))
     }
 
+    // `rules` are lines of the file, `lines_before` those before the document of `pod_spec`
     pub fn remove_rule_from_pod_spec(
         entity: Entity,
-        rules: &HashSet<usize>,
+        rules: &HashSet<(usize, Option<usize>)>,
+        lines_before: usize,
         pod_spec: &mut PodSpec,
     ) -> anyhow::Result<()> {
+        // Node selectors and names have no span, their rules carry the line of their key
+        for rule in entity.requires.iter() {
+            if !rule
+                .line()
+                .is_some_and(|line| rules.contains(&(line, None)))
+            {
+                continue;
+            }
+
+            match rule.metadata("type") {
+                Some(NODE_SELECTOR_TYPE) => {
+                    if let (Some(selector), Some(key)) =
+                        (pod_spec.node_selector.as_mut(), rule.metadata("key"))
+                    {
+                        selector.remove(key);
+                    }
+                }
+                Some(NODE_NAME_TYPE) => pod_spec.node_name = None,
+                _ => {}
+            }
+        }
+        if pod_spec
+            .node_selector
+            .as_ref()
+            .is_some_and(|selector| selector.is_empty())
+        {
+            pod_spec.node_selector = None;
+        }
+
         let affinity = pod_spec.affinity.as_mut();
 
         if let Some(affinity) = affinity {
@@ -1016,7 +3494,7 @@ impl K8sPlugin {
                     Some(
                         terms
                             .into_iter()
-                            .filter(|e| !rules.contains(&e.line))
+                            .filter(|e| !Self::removed(rules, lines_before, e))
                             .collect(),
                     )
                 } else {
@@ -1036,7 +3514,7 @@ impl K8sPlugin {
                     Some(
                         terms
                             .into_iter()
-                            .filter(|e| !rules.contains(&e.line))
+                            .filter(|e| !Self::removed(rules, lines_before, e))
                             .collect(),
                     )
                 } else {
@@ -1056,7 +3534,7 @@ impl K8sPlugin {
 
                 *terms = terms
                     .iter()
-                    .filter(|e| !rules.contains(&e.line))
+                    .filter(|e| !Self::removed(rules, lines_before, e))
                     .cloned()
                     .collect();
             }
@@ -1065,21 +3543,18 @@ impl K8sPlugin {
         Ok(())
     }
 
-    pub fn remove_rule_from_entity(
+    pub fn remove_rule_from_document(
         entity: Entity,
-        rules: &HashSet<(String, usize)>,
+        rules: &HashSet<(String, usize, Option<usize>)>,
         path: &Path,
-    ) -> anyhow::Result<(String, String)> {
-        let base_name = path.file_name().context("No file name found")?;
-        let base_name = base_name.to_str().context("Invalid file name")?;
-        let base_name = base_name.to_string();
-
-        let data = std::fs::read_to_string(path)?;
+        data: &str,
+        lines_before: usize,
+    ) -> anyhow::Result<String> {
         let path_string = path.display().to_string();
         let line_numbers = rules
             .iter()
-            .filter(|(file, _)| file.as_str() == &path_string)
-            .map(|(_, line)| *line)
+            .filter(|(file, _, _)| file.as_str() == &path_string)
+            .map(|(_, line, column)| (*line, *column))
             .collect::<HashSet<_>>();
 
         debug!(
@@ -1087,7 +3562,7 @@ impl K8sPlugin {
             entity, line_numbers
         );
 
-        if let Ok(mut deployment) = serde_yaml::from_str::<Deployment>(&data) {
+        if let Ok(mut deployment) = serde_yaml::from_str::<Deployment>(data) {
             let pod_spec = deployment
                 .spec
                 .as_mut()
@@ -1097,48 +3572,114 @@ impl K8sPlugin {
                 .as_mut()
                 .context("missing spec in deployment.template")?;
 
-            Self::remove_rule_from_pod_spec(entity, &line_numbers, pod_spec)?;
+            Self::remove_rule_from_pod_spec(entity, &line_numbers, lines_before, pod_spec)?;
+
+            Ok(serde_yaml::to_string(&deployment)?)
+        } else if let Ok(mut stateful_set) = serde_yaml::from_str::<StatefulSet>(data) {
+            let pod_spec = Self::stateful_set_pod_spec(&mut stateful_set)?;
+            Self::remove_rule_from_pod_spec(entity, &line_numbers, lines_before, pod_spec)?;
 
-            Ok((base_name, serde_yaml::to_string(&deployment)?))
-        } else if let Ok(mut pod) = serde_yaml::from_str::<Pod>(&data) {
+            Ok(serde_yaml::to_string(&stateful_set)?)
+        } else if let Ok(mut daemon_set) = serde_yaml::from_str::<DaemonSet>(data) {
+            let pod_spec = Self::daemon_set_pod_spec(&mut daemon_set)?;
+            Self::remove_rule_from_pod_spec(entity, &line_numbers, lines_before, pod_spec)?;
+
+            Ok(serde_yaml::to_string(&daemon_set)?)
+        } else if let Ok(mut replica_set) = serde_yaml::from_str::<ReplicaSet>(data) {
+            let pod_spec = Self::replica_set_pod_spec(&mut replica_set)?;
+            Self::remove_rule_from_pod_spec(entity, &line_numbers, lines_before, pod_spec)?;
+
+            Ok(serde_yaml::to_string(&replica_set)?)
+        } else if let Ok(mut pod) = serde_yaml::from_str::<Pod>(data) {
             let pod_spec = pod.spec.as_mut().context("missing spec in pod")?;
 
-            Self::remove_rule_from_pod_spec(entity, &line_numbers, pod_spec)?;
+            Self::remove_rule_from_pod_spec(entity, &line_numbers, lines_before, pod_spec)?;
+
+            Ok(serde_yaml::to_string(&pod)?)
+        } else if let Ok(mut job) = serde_yaml::from_str::<Job>(data) {
+            let pod_spec = Self::job_pod_spec(&mut job)?;
+            Self::remove_rule_from_pod_spec(entity, &line_numbers, lines_before, pod_spec)?;
+
+            Ok(serde_yaml::to_string(&job)?)
+        } else if let Ok(mut cron_job) = serde_yaml::from_str::<CronJob>(data) {
+            let pod_spec = Self::cron_job_pod_spec(&mut cron_job)?;
+            Self::remove_rule_from_pod_spec(entity, &line_numbers, lines_before, pod_spec)?;
 
-            Ok((base_name, serde_yaml::to_string(&pod)?))
+            Ok(serde_yaml::to_string(&cron_job)?)
         } else {
-            panic!("Unknown resource type")
+            anyhow::bail!("Unknown resource type")
         }
     }
 
-    pub fn id_entity(path: &Path) -> anyhow::Result<(String, String)> {
-        let base_name = path.file_name().context("No file name found")?;
-        let base_name = base_name.to_str().context("Invalid file name")?;
-        let base_name = base_name.to_string();
-
-        let data = std::fs::read_to_string(path)?;
-
-        if let Ok(deployment) = serde_yaml::from_str::<Deployment>(&data) {
-            Ok((base_name, serde_yaml::to_string(&deployment)?))
-        } else if let Ok(pod) = serde_yaml::from_str::<Pod>(&data) {
-            Ok((base_name, serde_yaml::to_string(&pod)?))
+    pub fn id_document(data: &str) -> anyhow::Result<String> {
+        if let Ok(deployment) = serde_yaml::from_str::<Deployment>(data) {
+            Ok(serde_yaml::to_string(&deployment)?)
+        } else if let Ok(stateful_set) = serde_yaml::from_str::<StatefulSet>(data) {
+            Ok(serde_yaml::to_string(&stateful_set)?)
+        } else if let Ok(daemon_set) = serde_yaml::from_str::<DaemonSet>(data) {
+            Ok(serde_yaml::to_string(&daemon_set)?)
+        } else if let Ok(replica_set) = serde_yaml::from_str::<ReplicaSet>(data) {
+            Ok(serde_yaml::to_string(&replica_set)?)
+        } else if let Ok(pod) = serde_yaml::from_str::<Pod>(data) {
+            Ok(serde_yaml::to_string(&pod)?)
+        } else if let Ok(job) = serde_yaml::from_str::<Job>(data) {
+            Ok(serde_yaml::to_string(&job)?)
+        } else if let Ok(cron_job) = serde_yaml::from_str::<CronJob>(data) {
+            Ok(serde_yaml::to_string(&cron_job)?)
         } else {
-            panic!("Unknown resource type")
+            anyhow::bail!("Unknown resource type")
         }
     }
 
     pub fn remove_rules_from_entities(
+        &self,
         entities: Vec<Entity>,
         rules: &[EntityRule],
         mapping: &HashMap<String, PathBuf>,
     ) -> Result<Vec<(String, String)>, anyhow::Error> {
+        Self::remove_rules_with(
+            entities,
+            rules,
+            mapping,
+            &self.renames,
+            &Self::read_manifest,
+        )?
+        .into_iter()
+        .map(|(path, data)| Ok::<_, anyhow::Error>((Self::base_name(path)?, data)))
+        .collect()
+    }
+
+    // Manifests of `manifests` without `rules`, by logical name as for `inject_manifests`
+    pub fn remove_rules_from_manifests<D: AsRef<[u8]>>(
+        entities: Vec<Entity>,
+        rules: &[EntityRule],
+        manifests: &BTreeMap<String, D>,
+    ) -> anyhow::Result<BTreeMap<String, String>> {
+        let mapping = Self::scan_entity_file_mapping(&entities)?;
+        let read = Self::manifest_reader(manifests);
+
+        Ok(
+            Self::remove_rules_with(entities, rules, &mapping, &HashMap::new(), &read)?
+                .into_iter()
+                .map(|(path, data)| (path.display().to_string(), data))
+                .collect(),
+        )
+    }
+
+    fn remove_rules_with<'a>(
+        entities: Vec<Entity>,
+        rules: &[EntityRule],
+        mapping: &'a HashMap<String, PathBuf>,
+        renames: &HashMap<String, String>,
+        read: &ManifestReader,
+    ) -> anyhow::Result<Vec<(&'a PathBuf, String)>> {
         let file_name_and_lines = rules.iter().fold(HashSet::new(), |mut acc, rule| {
             let source = rule.file().map(|e| e.to_string());
             let line = rule.line();
 
             match (source, line) {
                 (Some(source), Some(line)) => {
-                    acc.insert((source, line));
+                    acc.insert((source, line, rule.column()));
                 }
                 _ => {}
             }
@@ -1151,7 +3692,19 @@ impl K8sPlugin {
             .map(|e| e.0.clone())
             .collect::<HashSet<_>>();
 
-        let specs = entities
+        for rule in rules {
+            if let Some(annotation) = rule.annotation() {
+                warn!(
+                    "{} is read from the {} annotation at {}:{}, remove it there",
+                    rule_text(rule),
+                    annotation,
+                    rule.file().unwrap_or_default(),
+                    rule.line().unwrap_or(1)
+                );
+            }
+        }
+
+        let entities = Self::without_hints(entities)
             .into_iter()
             .filter(|entity| !entity.requires.is_empty() || !entity.excludes.is_empty())
             .map(|entity| {
@@ -1159,25 +3712,46 @@ impl K8sPlugin {
                     format!("No source file found for entity {}", entity.name.as_ref())
                 })?;
 
+                Ok((entity, path))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Self::by_manifest(entities)
+            .into_iter()
+            .map(|(path, entities)| {
                 let path_string = path.display().to_string();
 
-                match files.contains(&path_string) {
+                let data = match files.contains(&path_string) {
                     false => {
                         debug!(
-                            "Entity {} is not found in the mapping, assuming it's a dummy entity, path: {}, {:?}",
-                            entity.name.as_ref(),
+                            "Entities {:?} are not found in the mapping, assuming they are dummy entities, path: {}, {:?}",
+                            entities.iter().map(|e| e.name.as_ref()).collect::<Vec<_>>(),
                             path_string,
                             rules
                         );
-                        Self::id_entity(path)
+                        Self::rewrite_manifest(path, read, entities, renames, |_, data, _| {
+                            Self::id_document(data)
+                        })
                     }
-                    true => Self::remove_rule_from_entity(entity, &file_name_and_lines, path),
-                }
+                    true => Self::rewrite_manifest(
+                        path,
+                        read,
+                        entities,
+                        renames,
+                        |entity, data, lines_before| {
+                            Self::remove_rule_from_document(
+                                entity,
+                                &file_name_and_lines,
+                                path,
+                                data,
+                                lines_before,
+                            )
+                        },
+                    ),
+                }?;
+
+                Ok::<_, anyhow::Error>((path, data))
             })
-            .collect::<Vec<_>>();
-
-        let specs = specs.into_iter().collect::<Result<Vec<_>, _>>()?;
-
-        Ok(specs)
+            .collect()
     }
 }
@@ -0,0 +1,101 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::Deserialize;
+
+// Kubernetes version the manifests are deployed to, given as `1.24`, `v1.24` or `1.24.3`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub struct K8sVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+// A field deployfix reads from or writes into manifests which older versions do not serve,
+// with the first version enabling it by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionedField {
+    pub name: &'static str,
+    pub scope: &'static str,
+    pub since: K8sVersion,
+}
+
+const fn since(name: &'static str, scope: &'static str, minor: u32) -> VersionedField {
+    VersionedField {
+        name,
+        scope,
+        since: K8sVersion { major: 1, minor },
+    }
+}
+
+// Fields of pod affinity and anti-affinity terms
+pub const AFFINITY_TERM_FIELDS: &[VersionedField] = &[
+    since("namespaceSelector", "pod affinity terms", 22),
+    since("matchLabelKeys", "pod affinity terms", 31),
+    since("mismatchLabelKeys", "pod affinity terms", 31),
+];
+
+// `batch/v1` CronJobs, older versions only serve `batch/v1beta1`
+pub const CRON_JOB_V1: VersionedField = since("batch/v1", "CronJobs", 21);
+
+// `topology.kubernetes.io/*` node labels, older versions only set the
+// `failure-domain.beta.kubernetes.io/*` ones
+pub const TOPOLOGY_LABELS: VersionedField = since("topology.kubernetes.io", "node labels", 17);
+
+impl K8sVersion {
+    pub fn supports(&self, field: &VersionedField) -> bool {
+        *self >= field.since
+    }
+
+    pub fn unsupported(&self, fields: &'static [VersionedField]) -> Vec<&'static VersionedField> {
+        fields.iter().filter(|f| !self.supports(f)).collect()
+    }
+}
+
+impl FromStr for K8sVersion {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid Kubernetes version `{}`, expected e.g. 1.28", value);
+
+        let mut parts = value.trim().trim_start_matches('v').split('.');
+        let major = parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(invalid)?;
+        let minor = parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(invalid)?;
+
+        // The patch version does not change the served fields
+        if parts.next().is_some_and(|p| p.parse::<u32>().is_err()) || parts.next().is_some() {
+            return Err(invalid());
+        }
+
+        Ok(Self { major, minor })
+    }
+}
+
+impl TryFrom<String> for K8sVersion {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for K8sVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl Display for VersionedField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "`{}` of {} needs Kubernetes {} or later",
+            self.name, self.scope, self.since
+        )
+    }
+}
@@ -1,2 +1,294 @@
 pub(crate) mod k8s;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 pub(crate) mod yarn;
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    ffi::OsStr,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info, warn};
+
+use crate::{
+    cli::ReportSink,
+    model::{apply_layers, get_parser, merge_entities, DeployIRFormatter, Entity, EntitySource},
+    util::read_source,
+};
+
+// What a deployment platform has to provide to plug into deployfix. The import, merge and
+// inject flows below are shared, a plugin only reads and rewrites its own files.
+pub trait DeployPlugin {
+    // Extension of the deployment files of the platform, e.g. `yaml`
+    const FILE_EXTENSION: &'static str;
+
+    fn accepts(&self, path: &Path) -> bool {
+        path.extension().and_then(OsStr::to_str) == Some(Self::FILE_EXTENSION)
+    }
+
+    fn extract_entities(&self, path: &Path) -> anyhow::Result<Vec<Entity>>;
+
+    // Returns the rewritten deployment files as (name, content) pairs
+    fn inject_entities(&self, entities: Vec<Entity>) -> anyhow::Result<Vec<(String, String)>>;
+
+    // Whether two versions of a deployment file are the same, e.g. regardless of formatting
+    fn same_content(&self, a: &str, b: &str) -> bool {
+        a == b
+    }
+
+    // Output of an import
+    fn format(&self, entities: &Vec<Entity>) -> String {
+        DeployIRFormatter::format(entities)
+    }
+
+    // An entity declared both in a deployment file and in IR files keeps the deployment file
+    // as its source, since that is where the rules get injected
+    fn merge_source(a: &mut EntitySource, b: EntitySource)
+    where
+        Self: Sized,
+    {
+        if let (EntitySource::File(a), EntitySource::File(b)) = (a, b) {
+            if !a.ends_with(&format!(".{}", Self::FILE_EXTENSION)) {
+                warn!("Replacing {} with {}", a, b);
+                *a = b;
+            }
+        }
+    }
+}
+
+/*
+    Drops the files which are another file under a different path (a symlink, or a file given
+    both alone and through its directory) or a byte for byte copy of another file, keeping the
+    first one. Both would declare every entity twice.
+*/
+fn unique_files(files: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut canonical_paths: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut digests: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+
+    files
+        .into_iter()
+        .filter(|file| {
+            // Files which cannot be read are kept, reading them reports the error
+            let same_file = std::fs::canonicalize(file).ok().and_then(|canonical| {
+                match canonical_paths.get(&canonical) {
+                    Some(kept) => Some((kept.clone(), "the same file as")),
+                    None => {
+                        canonical_paths.insert(canonical, file.clone());
+                        None
+                    }
+                }
+            });
+            let duplicate = same_file.or_else(|| {
+                let data = std::fs::read(file).ok()?;
+                let mut hasher = DefaultHasher::new();
+                data.hash(&mut hasher);
+
+                let kept = digests.entry(hasher.finish()).or_default();
+                match kept
+                    .iter()
+                    .find(|kept| std::fs::read(kept).is_ok_and(|other| other == data))
+                {
+                    Some(kept) => Some((kept.clone(), "a copy of")),
+                    None => {
+                        kept.push(file.clone());
+                        None
+                    }
+                }
+            });
+
+            let Some((kept, relation)) = duplicate else {
+                return true;
+            };
+            info!(
+                "Skipping {}, {} {}",
+                file.display(),
+                relation,
+                kept.display()
+            );
+
+            false
+        })
+        .collect()
+}
+
+// Expands directories (one level deep) into the files accepted by `accepts`, explicit file
+// paths are kept as is
+pub(crate) fn expand_paths(paths: &[PathBuf], accepts: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+    paths
+        .iter()
+        .flat_map(|path| {
+            if !path.is_dir() {
+                return vec![path.clone()];
+            }
+
+            let mut files = match std::fs::read_dir(path) {
+                Ok(entries) => entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.is_file() && accepts(path))
+                    .collect::<Vec<_>>(),
+                Err(err) => {
+                    warn!("Failed to read directory {}: {}", path.display(), err);
+                    vec![]
+                }
+            };
+            files.sort();
+
+            files
+        })
+        .collect()
+}
+
+// Files of `paths` as `expand_paths` expands them, symlinks and copies of a file already
+// expanded left out. Inputs are deduplicated once per command, the readers below are then
+// given the files kept so that each input is only hashed once.
+pub fn unique_inputs(paths: &[PathBuf], accepts: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+    unique_files(expand_paths(paths, accepts))
+}
+
+pub fn extract_entities<P: DeployPlugin>(plugin: &P, paths: &[PathBuf]) -> Vec<Entity> {
+    expand_paths(paths, |path| plugin.accepts(path))
+        .into_iter()
+        .filter_map(|path| {
+            debug!("Importing from {}", path.display());
+
+            match plugin.extract_entities(&path) {
+                Ok(entities) => {
+                    debug!("Imported entities {:?} from {}", entities, path.display());
+
+                    Some(entities)
+                }
+                Err(err) => {
+                    warn!("Failed to extract entity from {}: {}", path.display(), err);
+                    None
+                }
+            }
+        })
+        .flatten()
+        .collect()
+}
+
+// Reads deployfix IR files, directories are scanned for `.ir` files. Each file is a layer
+// whose directives apply to the files before it.
+pub fn read_ir_entities(paths: &[PathBuf]) -> Vec<Entity> {
+    let parser = get_parser("deployfix").unwrap();
+
+    let layers = expand_paths(paths, |path| path.extension() == Some(OsStr::new("ir")))
+        .into_iter()
+        .map(|path| {
+            debug!("Importing from {}", path.display());
+
+            let data = read_source(&path)
+                .unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+
+            parser
+                .parse(
+                    &data,
+                    EntitySource::File(path.to_str().unwrap().to_string()),
+                )
+                .expect("Failed to parse deployfix file")
+        })
+        .collect();
+
+    let (entities, changes) = apply_layers(layers);
+    for change in changes.iter() {
+        if change.replaced.is_empty() {
+            warn!("{}", change);
+        } else {
+            info!("{}", change);
+        }
+    }
+
+    entities
+}
+
+// Output of an import, `strict` rejects the rules the IR cannot represent exactly
+pub fn format_import<P: DeployPlugin>(
+    plugin: &P,
+    entities: &Vec<Entity>,
+    strict: bool,
+) -> anyhow::Result<String> {
+    if strict {
+        for rule in entities.iter().flat_map(Entity::rules) {
+            DeployIRFormatter::check_round_trip(rule)?;
+        }
+    }
+
+    Ok(plugin.format(entities))
+}
+
+pub fn merge<P: DeployPlugin>(entities: Vec<Entity>) -> Vec<Entity> {
+    merge_entities(entities, Some(P::merge_source))
+}
+
+// Restricts an inject to part of the entities, to roll changes out incrementally
+#[derive(Debug, Clone, Default)]
+pub struct InjectOptions {
+    // Glob patterns of the injected entities, matched against the whole name (`app=web`) or
+    // its value (`web`). Every entity is injected when empty.
+    pub only: Vec<glob::Pattern>,
+    // Leave the files whose content would not change untouched
+    pub skip_unchanged: bool,
+}
+
+impl InjectOptions {
+    pub fn selects(&self, entity: &Entity) -> bool {
+        let name = entity.name.as_ref();
+        let value = name.split_once('=').map(|(_, value)| value).unwrap_or(name);
+
+        self.only.is_empty()
+            || self
+                .only
+                .iter()
+                .any(|pattern| pattern.matches(name) || pattern.matches(value))
+    }
+}
+
+pub fn inject<P: DeployPlugin>(
+    plugin: &P,
+    entities: Vec<Entity>,
+    sink: &dyn ReportSink,
+    options: &InjectOptions,
+) -> anyhow::Result<()> {
+    let total = entities.len();
+    let entities = entities
+        .into_iter()
+        .filter(|entity| options.selects(entity))
+        .collect::<Vec<_>>();
+    if entities.len() < total {
+        info!(
+            "Injecting {} of {} entities matching {}",
+            entities.len(),
+            total,
+            options
+                .only
+                .iter()
+                .map(|p| p.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    let mut unchanged = 0;
+    for (name, content) in plugin.inject_entities(entities)? {
+        if options.skip_unchanged
+            && sink
+                .read(&name)
+                .is_some_and(|existing| plugin.same_content(&existing, &content))
+        {
+            debug!("Skipped unchanged {}", sink.location(&name));
+            unchanged += 1;
+            continue;
+        }
+
+        sink.write(&name, &content)?;
+        debug!("Injected {}", sink.location(&name));
+    }
+
+    if unchanged > 0 {
+        info!("Skipped {} unchanged files", unchanged);
+    }
+
+    Ok(())
+}
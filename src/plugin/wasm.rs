@@ -0,0 +1,230 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use wasmtime::{Engine, Instance, Module, Store};
+
+use crate::model::{rule_location, rule_text, Entity, EntityName, EntityRule};
+
+// Version of the model passes are given, bumped when the layout below changes
+pub const PASS_ABI_VERSION: u32 = 1;
+
+// Instructions a pass may execute per run, a pass looping forever fails instead of hanging
+pub const PASS_FUEL: u64 = 1_000_000_000;
+
+/*
+    A custom analysis pass compiled to WASM (or written as WAT). It imports nothing, so it
+    can neither read files nor reach the network, and exports:
+
+    memory                                  the linear memory the model is written to
+    deployfix_alloc(len: i32) -> i32        a buffer of `len` bytes for the model
+    deployfix_check(ptr: i32, len: i32) -> i64
+                                            checks the model, returns `ptr << 32 | len` of
+                                            its findings
+
+    The model is JSON, rules are indexed in entity order:
+
+    { "version": 1, "entities": [{ "name": "app=web", "priority": "default", "rules": [
+        { "type": "require", "targets": ["app=db"], "location": "web.yaml:12",
+          "metadata": { "topology": "node" } }
+    ] }] }
+
+    and so are the findings:
+
+    [{ "entity": "app=web", "rule": 0, "message": "requires a database of another team" }]
+*/
+#[derive(Debug, Clone)]
+pub struct AnalysisPass {
+    name: String,
+    engine: Engine,
+    module: Module,
+}
+
+#[derive(Debug, Serialize)]
+struct PassInput<'a> {
+    version: u32,
+    entities: Vec<PassEntity<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct PassEntity<'a> {
+    name: &'a str,
+    priority: &'a str,
+    rules: Vec<PassRule<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct PassRule<'a> {
+    r#type: String,
+    targets: Vec<&'a str>,
+    location: Option<String>,
+    metadata: BTreeMap<&'a str, &'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PassOutput {
+    entity: String,
+    rule: Option<usize>,
+    message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct PassFinding {
+    pub entity: EntityName,
+    pub pass: String,
+    pub rule: Option<String>,
+    pub location: Option<String>,
+    pub message: String,
+}
+
+impl Display for PassFinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} ({})",
+            self.location.as_deref().unwrap_or(self.entity.as_ref()),
+            self.message,
+            self.pass
+        )?;
+        if let Some(rule) = self.rule.as_deref() {
+            write!(f, "\n  rule: {}", rule)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn pass_rule(rule: &EntityRule) -> PassRule<'_> {
+    let metadata = rule
+        .metadata_ref()
+        .and_then(|metadata| metadata.get_metadata())
+        .map(|metadata| {
+            metadata
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    PassRule {
+        r#type: rule.r#type().to_string(),
+        targets: rule.targets().into_iter().map(|t| t.as_ref()).collect(),
+        location: rule_location(rule),
+        metadata,
+    }
+}
+
+impl AnalysisPass {
+    // Compiles the module at `path`, named after its file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+
+        let engine = Engine::new(&config)?;
+        let module = Module::from_file(&engine, path)
+            .map_err(anyhow::Error::from)
+            .with_context(|| format!("Failed to load analysis pass {}", path.display()))?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.display().to_string());
+
+        Ok(Self {
+            name,
+            engine,
+            module,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Runs the pass over `entities` in a fresh instance
+    pub fn run(&self, entities: &[Entity]) -> anyhow::Result<Vec<PassFinding>> {
+        let input = PassInput {
+            version: PASS_ABI_VERSION,
+            entities: entities
+                .iter()
+                .map(|entity| PassEntity {
+                    name: entity.name.as_ref(),
+                    priority: entity.priority.as_str(),
+                    rules: entity.rules().map(pass_rule).collect(),
+                })
+                .collect(),
+        };
+        let input = serde_json::to_vec(&input)?;
+
+        let mut store = Store::new(&self.engine, ());
+        store.set_fuel(PASS_FUEL)?;
+
+        let instance = Instance::new(&mut store, &self.module, &[])
+            .map_err(anyhow::Error::from)
+            .with_context(|| format!("Failed to instantiate pass {}", self.name))?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .with_context(|| format!("Pass {} exports no `memory`", self.name))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "deployfix_alloc")?;
+        let check = instance.get_typed_func::<(i32, i32), i64>(&mut store, "deployfix_check")?;
+
+        let len = i32::try_from(input.len()).context("Model too large for an analysis pass")?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as u32 as usize, &input)?;
+
+        let packed = check
+            .call(&mut store, (ptr, len))
+            .map_err(anyhow::Error::from)
+            .with_context(|| format!("Pass {} failed", self.name))?;
+        let (ptr, len) = ((packed >> 32) as u32 as usize, packed as u32 as usize);
+
+        let mut output = vec![0; len];
+        memory
+            .read(&store, ptr, &mut output)
+            .with_context(|| format!("Pass {} returned findings out of its memory", self.name))?;
+        let outputs: Vec<PassOutput> = serde_json::from_slice(&output)
+            .with_context(|| format!("Invalid findings of pass {}", self.name))?;
+
+        let by_name = entities
+            .iter()
+            .map(|entity| (entity.name.as_ref(), entity))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut findings = outputs
+            .into_iter()
+            .map(|output| {
+                let rule = output.rule.and_then(|index| {
+                    by_name
+                        .get(output.entity.as_str())
+                        .and_then(|entity| entity.rules().nth(index))
+                });
+
+                PassFinding {
+                    entity: EntityName(output.entity),
+                    pass: self.name.clone(),
+                    rule: rule.map(rule_text),
+                    location: rule.and_then(rule_location),
+                    message: output.message,
+                }
+            })
+            .collect::<Vec<_>>();
+        findings.sort();
+
+        Ok(findings)
+    }
+}
+
+// Loads and runs every pass of `paths` over the model
+pub fn run_passes(paths: &[PathBuf], entities: &[Entity]) -> anyhow::Result<Vec<PassFinding>> {
+    let mut findings = vec![];
+
+    for path in paths {
+        let pass = AnalysisPass::load(path)?;
+        findings.extend(pass.run(entities)?);
+    }
+
+    Ok(findings)
+}
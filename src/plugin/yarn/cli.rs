@@ -1,11 +1,27 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use clap::Subcommand;
-use log::{debug, info, warn};
+use log::{debug, error, info};
 
 use crate::{
-    model::{get_parser, merge_entities, DeployIRFormatter, EntitySource},
-    plugin::yarn::{formatter::YarnFormatter, parser::parser::YarnSpecParser},
+    cli::{
+        audit_placements, format_violations, load_impact_weights, load_normalizations,
+        load_suppressions, today, Config, ConflictReport, DirectorySink, RunOptions, SinkArgs,
+    },
+    model::{format_envs, Entity, EnvParser},
+    plugin::{
+        self,
+        yarn::{
+            env::{parse_placements, YarnNodeReportParser},
+            YarnConfig, YarnPlugin,
+        },
+        DeployPlugin, InjectOptions,
+    },
+    solver::{get_solver_with, EntityMap, SolverOptions, SolverOutput},
+    util::{
+        self,
+        http::{self, HttpUrl},
+    },
 };
 
 #[derive(Subcommand)]
@@ -13,6 +29,8 @@ pub enum YarnCommands {
     Import {
         #[clap(value_name = "PATH", help = "Paths to Yarn Placement Spec files")]
         paths: Vec<PathBuf>,
+        #[command(flatten)]
+        sink: SinkArgs,
     },
     Inject {
         #[clap(value_name = "OUTPUT", help = "Output Yarn Placement Spec File")]
@@ -20,94 +38,253 @@ pub enum YarnCommands {
         #[clap(value_name = "PATH", help = "Paths to deployfix files")]
         paths: Vec<PathBuf>,
     },
+    ImportEnv {
+        #[clap(
+            value_name = "REPORT",
+            help = "Node report, RM REST API JSON (/ws/v1/cluster/nodes) or `yarn node -list` output"
+        )]
+        report: PathBuf,
+        #[clap(short, long, default_value = "output.env", help = "Output env file")]
+        output: PathBuf,
+    },
+    Check {
+        #[clap(value_name = "PATH", help = "Paths to Yarn Placement Spec files")]
+        paths: Vec<PathBuf>,
+        #[clap(long, help = "Node report the specs are checked against")]
+        node_report: Option<PathBuf>,
+        #[clap(long, help = "Enable cycle check", default_value = "false")]
+        cycle_check: bool,
+    },
+    CheckLive {
+        #[clap(value_name = "PATH", help = "Paths to Yarn Placement Spec files")]
+        paths: Vec<PathBuf>,
+        #[clap(
+            long,
+            value_name = "URL",
+            help = "ResourceManager web address, e.g. http://rm:8088"
+        )]
+        rm_url: String,
+        #[clap(long, help = "Enable cycle check", default_value = "false")]
+        cycle_check: bool,
+    },
+    Audit {
+        #[clap(value_name = "PATH", help = "Paths to Yarn Placement Spec files")]
+        paths: Vec<PathBuf>,
+        #[clap(
+            long,
+            value_name = "FILE",
+            help = "Container placements exported from the cluster (JSON)"
+        )]
+        placements: PathBuf,
+    },
+}
+
+pub fn import_specs(paths: &[PathBuf]) -> Vec<Entity> {
+    let yarn = YarnPlugin::default();
+    let paths = plugin::unique_inputs(paths, |path| yarn.accepts(path));
+
+    plugin::merge::<YarnPlugin>(plugin::extract_entities(&yarn, &paths))
+}
+
+fn read_node_report(path: &Path, strict: bool) -> Vec<crate::model::Env> {
+    let data = util::read_source(path).expect("Failed to read node report");
+
+    YarnNodeReportParser::new(strict)
+        .parse(&data)
+        .expect("Failed to parse node report")
+}
+
+// Fetches the running nodes and their allocation tags from the ResourceManager
+fn fetch_node_report(rm_url: &str, strict: bool) -> anyhow::Result<Vec<crate::model::Env>> {
+    let url = HttpUrl::parse(rm_url)?.join("/ws/v1/cluster/nodes");
+    info!("Fetching node report from {}", url);
+
+    let data = http::get(&url)?;
+
+    Ok(YarnNodeReportParser::new(strict).parse(&data)?)
 }
 
-fn inject(entities: Vec<crate::model::Entity>, output_file_path: PathBuf) {
-    let formatter = YarnFormatter::new();
-    let output = formatter.format(&entities);
+fn check(
+    entities: Vec<Entity>,
+    envs: Option<Vec<crate::model::Env>>,
+    cycle_check: bool,
+    options: &SolverOptions,
+    mut report: ConflictReport,
+) {
+    let entity_map: EntityMap = entities.try_into().unwrap();
+
+    let z3_solver = get_solver_with("z3", options).unwrap();
+    if let Some(envs) = envs {
+        z3_solver.set_envs(envs);
+    }
+
+    let mut result = z3_solver.solve(&entity_map);
+    if cycle_check {
+        let ring_solver = get_solver_with("ring", options).unwrap();
+        result = result.merge(ring_solver.solve(&entity_map));
+    }
+
+    let (result, undetermined) = result.take_undetermined();
+    if let Some(undetermined) = undetermined {
+        report.add_undetermined(&undetermined);
+    }
+    let (result, soft) = result.take_soft();
+    report.add_soft(&soft);
 
-    let parent_dir = output_file_path.parent().unwrap();
-    if !parent_dir.exists() {
-        std::fs::create_dir_all(parent_dir).unwrap();
+    if let SolverOutput::Conflict(conflicts, solvers) = result {
+        report.add(&conflicts, &solvers, &entity_map.entities);
     }
 
-    if output_file_path.exists() {
-        std::fs::remove_file(&output_file_path).unwrap();
+    if report.has_conflict() {
+        error!("Conflicts found");
+        report.print_summary();
+        std::process::exit(1);
+    }
 
-        warn!("Removed existing file {}", output_file_path.display());
+    if report.has_undetermined() {
+        error!("Some entities could not be checked");
+        report.print_summary();
+        std::process::exit(1);
     }
 
-    std::fs::write(output_file_path, output).unwrap();
+    info!("No conflicts found");
+    report.print_summary();
 }
 
-pub fn execute(commands: YarnCommands) {
+fn inject(entities: Vec<Entity>, output_file_path: PathBuf, config: YarnConfig) {
+    let name = output_file_path
+        .file_name()
+        .expect("Invalid output file")
+        .to_string_lossy();
+    let sink = DirectorySink::new(output_file_path.parent().unwrap_or(Path::new(".")));
+
+    plugin::inject(
+        &YarnPlugin::new(&name, config),
+        entities,
+        &sink,
+        &InjectOptions::default(),
+    )
+    .expect("Failed to inject entities");
+}
+
+pub fn execute(commands: YarnCommands, run: &RunOptions, config: &Config) {
+    let suppressions = load_suppressions(config);
+    let impact_weights = load_impact_weights(config);
+    let normalizations = load_normalizations(config);
+    let config: YarnConfig = config.section().unwrap_or_else(|err| {
+        error!("{:#}", err);
+        std::process::exit(1);
+    });
+
     match commands {
-        YarnCommands::Import { paths } => {
-            let entities = paths
-                .into_iter()
-                .flat_map(|path| {
-                    let parser = YarnSpecParser::new();
-                    let data = std::fs::read_to_string(&path).unwrap();
-
-                    parser.parse(&data, path).unwrap()
-                })
-                .collect::<Vec<_>>();
-
-            let entities = merge_entities(
-                entities,
-                Some(|a, b| match (a, b) {
-                    (EntitySource::File(a), EntitySource::File(b)) => {
-                        if !a.ends_with(".spec") {
-                            warn!("Replacing {} with {}", a, b);
-                            *a = b;
-                        }
-                    }
-                    _ => {}
-                }),
-            );
+        YarnCommands::Import { paths, sink } => {
+            let sink = sink.build(Path::new(".")).unwrap_or_else(|err| {
+                error!("{:#}", err);
+                std::process::exit(1);
+            });
+
+            let entities = normalizations.apply(import_specs(&paths));
             debug!("Imported entities: {:?}", entities);
 
-            let output = DeployIRFormatter::format(&entities);
+            let output =
+                plugin::format_import(&YarnPlugin::default(), &entities, run.solver.strict)
+                    .unwrap_or_else(|err| {
+                        error!("{}", err);
+                        std::process::exit(1);
+                    });
 
             info!("{}", output);
 
-            std::fs::write("output.deployfix", output).unwrap();
+            sink.write("output.deployfix", &output)
+                .unwrap_or_else(|err| {
+                    error!("Failed to write output.deployfix: {:#}", err);
+                    std::process::exit(1);
+                });
         }
         YarnCommands::Inject {
             output_file: output_dir,
             paths,
         } => {
-            let entities = paths
-                .into_iter()
-                .flat_map(|path| {
-                    debug!("Importing from {}", path.display());
-
-                    get_parser("deployfix")
-                        .unwrap()
-                        .parse(
-                            &std::fs::read_to_string(&path).unwrap(),
-                            crate::model::EntitySource::File(path.to_str().unwrap().to_string()),
-                        )
-                        .unwrap()
-                })
-                .collect::<Vec<_>>();
-
-            let entities = merge_entities(
-                entities,
-                Some(|a, b| match (a, b) {
-                    (EntitySource::File(a), EntitySource::File(b)) => {
-                        if !a.ends_with(".spec") {
-                            warn!("Replacing {} with {}", a, b);
-                            *a = b;
-                        }
-                    }
-                    _ => {}
-                }),
-            );
+            let paths = plugin::unique_inputs(&paths, |path| {
+                path.extension().is_some_and(|ext| ext == "ir")
+            });
+            let entities = plugin::merge::<YarnPlugin>(plugin::read_ir_entities(&paths));
+
+            debug!("Imported entities: {:?}", entities);
+
+            inject(entities, output_dir, config)
+        }
+        YarnCommands::ImportEnv { report, output } => {
+            let envs = read_node_report(&report, run.solver.strict);
+            info!("Imported {} nodes from {}", envs.len(), report.display());
+
+            std::fs::write(&output, format_envs(&envs)).expect("Failed to write env file");
+            info!("Env written to {}", output.display());
+        }
+        YarnCommands::Check {
+            paths,
+            node_report,
+            cycle_check,
+        } => {
+            let entities = normalizations.apply(import_specs(&paths));
+            debug!("Imported entities: {:?}", entities);
+
+            let envs = node_report
+                .as_deref()
+                .map(|path| read_node_report(path, run.solver.strict));
+
+            let report = ConflictReport::new(run.level)
+                .with_gates(run.gates.clone())
+                .with_suppressions(suppressions, today())
+                .with_impact_weights(impact_weights);
+
+            check(entities, envs, cycle_check, &run.solver, report)
+        }
+        YarnCommands::CheckLive {
+            paths,
+            rm_url,
+            cycle_check,
+        } => {
+            let entities = normalizations.apply(import_specs(&paths));
+            debug!("Imported entities: {:?}", entities);
+
+            let envs = match fetch_node_report(&rm_url, run.solver.strict) {
+                Ok(envs) => envs,
+                Err(err) => {
+                    error!("Failed to fetch the live state of {}: {:#}", rm_url, err);
+                    std::process::exit(1);
+                }
+            };
+            info!("Checking against {} node groups", envs.len());
+
+            let report = ConflictReport::new(run.level)
+                .with_gates(run.gates.clone())
+                .with_suppressions(suppressions, today())
+                .with_impact_weights(impact_weights);
 
+            check(entities, Some(envs), cycle_check, &run.solver, report)
+        }
+        YarnCommands::Audit { paths, placements } => {
+            let entities = import_specs(&paths);
             debug!("Imported entities: {:?}", entities);
 
-            inject(entities, output_dir)
+            let placements = util::read_source(&placements)
+                .map_err(anyhow::Error::from)
+                .and_then(|data| parse_placements(&data))
+                .unwrap_or_else(|err| {
+                    error!("Failed to read {}: {:#}", placements.display(), err);
+                    std::process::exit(1);
+                });
+            info!("Auditing {} containers", placements.len());
+
+            let violations = audit_placements(&entities, &placements);
+            if !violations.is_empty() {
+                error!("{} rules broken by running containers", violations.len());
+                print!("{}", format_violations(&violations));
+                std::process::exit(1);
+            }
+
+            info!("No rules broken by running containers");
         }
     }
 }
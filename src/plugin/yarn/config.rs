@@ -0,0 +1,23 @@
+use serde::Deserialize;
+
+use crate::cli::ConfigSection;
+
+// `yarn` section of the config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct YarnConfig {
+    // Scope of injected rules which do not carry one, NODE or RACK
+    pub default_scope: String,
+}
+
+impl Default for YarnConfig {
+    fn default() -> Self {
+        Self {
+            default_scope: "NODE".to_string(),
+        }
+    }
+}
+
+impl ConfigSection for YarnConfig {
+    const NAME: &'static str = "yarn";
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+
+use log::{debug, warn};
+use serde::Deserialize;
+
+use crate::{
+    cli::Placement,
+    model::{group_envs, Env, EnvParseError, EnvParser},
+};
+
+/*
+    ResourceManager REST API, GET /ws/v1/cluster/nodes
+    {"nodes": {"node": [{"id": "host1:45454", "nodeHostName": "host1", "rack": "/rack1",
+      "state": "RUNNING", "nodeLabels": ["gpu"],
+      "allocationTags": {"allocationTagInfo": [{"allocationTag": "hbase", "allocationsCount": 2}]}}]}}
+*/
+#[derive(Debug, Deserialize)]
+struct RMNodes {
+    nodes: Option<RMNodeList>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RMNodeList {
+    #[serde(default)]
+    node: Vec<RMNode>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RMNode {
+    id: String,
+    node_host_name: Option<String>,
+    rack: Option<String>,
+    state: Option<String>,
+    #[serde(default)]
+    node_labels: Vec<String>,
+    allocation_tags: Option<RMAllocationTags>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RMAllocationTags {
+    #[serde(default)]
+    allocation_tag_info: Vec<RMAllocationTag>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RMAllocationTag {
+    allocation_tag: String,
+}
+
+// Reads a YARN node report into the env model: every running node becomes an env whose
+// labels are the allocation tags placed on it, plus `rack=...` and `node=...` for its rack
+// and node labels. Accepts either the RM REST API JSON or the output of `yarn node -list`,
+// which carries no tags, assumed empty unless `strict`.
+pub struct YarnNodeReportParser {
+    strict: bool,
+}
+
+impl YarnNodeReportParser {
+    pub fn new(strict: bool) -> Self {
+        Self { strict }
+    }
+
+    fn parse_rest(&self, data: &str) -> Result<HashMap<String, Vec<String>>, EnvParseError> {
+        let report: RMNodes =
+            serde_json::from_str(data).map_err(|e| EnvParseError::InvalidData(e.to_string()))?;
+
+        let nodes = report.nodes.map(|n| n.node).unwrap_or_default();
+
+        Ok(nodes
+            .into_iter()
+            .filter(|node| {
+                let running = node.state.as_deref().unwrap_or("RUNNING") == "RUNNING";
+                if !running {
+                    debug!("Skipping node {} in state {:?}", node.id, node.state);
+                }
+
+                running
+            })
+            .map(|node| {
+                let mut labels = node
+                    .allocation_tags
+                    .map(|t| t.allocation_tag_info)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|t| t.allocation_tag)
+                    .collect::<Vec<_>>();
+
+                if let Some(rack) = node.rack {
+                    labels.push(format!("rack={}", rack));
+                }
+                labels.extend(node.node_labels.into_iter().map(|l| format!("node={}", l)));
+                labels.sort();
+                labels.dedup();
+
+                (node.node_host_name.unwrap_or(node.id), labels)
+            })
+            .collect())
+    }
+
+    /*
+        Total Nodes:2
+                 Node-Id             Node-State Node-Http-Address       Number-of-Running-Containers
+             host1:45454                RUNNING        host1:8042                                  2
+    */
+    fn parse_cli(&self, data: &str) -> Result<HashMap<String, Vec<String>>, EnvParseError> {
+        let nodes = data
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .filter(|parts| parts.len() >= 2 && parts[0] != "Node-Id" && parts[0].contains(':'))
+            .filter(|parts| parts[1] == "RUNNING")
+            .map(|parts| {
+                let host = parts[0].split(':').next().unwrap_or(parts[0]);
+
+                (host.to_string(), vec![])
+            })
+            .collect::<HashMap<_, _>>();
+
+        if !nodes.is_empty() {
+            let message =
+                "`yarn node -list` output has no allocation tags, the nodes are assumed empty";
+            if self.strict {
+                return Err(EnvParseError::InvalidData(format!(
+                    "{} (rejected by --strict)",
+                    message
+                )));
+            }
+
+            warn!("{}", message);
+        }
+
+        Ok(nodes)
+    }
+}
+
+impl EnvParser for YarnNodeReportParser {
+    fn parse(&self, data: &str) -> Result<Vec<Env>, EnvParseError> {
+        let nodes = if data.trim_start().starts_with('{') {
+            self.parse_rest(data)?
+        } else {
+            self.parse_cli(data)?
+        };
+
+        group_envs(nodes)
+    }
+}
+
+/*
+    Container placements exported from the cluster, one entry per running container
+    {"containers": [{"id": "container_1700000000000_0001_01_000002", "node": "host1:45454",
+      "rack": "/rack1", "allocationTags": ["hbase"]}]}
+*/
+#[derive(Debug, Deserialize)]
+struct YarnContainers {
+    #[serde(default)]
+    containers: Vec<YarnContainer>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct YarnContainer {
+    id: Option<String>,
+    node: String,
+    rack: Option<String>,
+    #[serde(default)]
+    allocation_tags: Vec<String>,
+}
+
+// Reads exported container placements, nodes named by host as in the node report
+pub fn parse_placements(data: &str) -> anyhow::Result<Vec<Placement>> {
+    let containers: YarnContainers = serde_json::from_str(data)?;
+
+    Ok(containers
+        .containers
+        .into_iter()
+        .filter(|container| {
+            if container.allocation_tags.is_empty() {
+                debug!(
+                    "Skipping container {:?} without allocation tags",
+                    container.id
+                );
+            }
+
+            !container.allocation_tags.is_empty()
+        })
+        .map(|container| Placement {
+            node: container
+                .node
+                .split(':')
+                .next()
+                .unwrap_or(&container.node)
+                .to_string(),
+            rack: container.rack,
+            tags: container.allocation_tags,
+        })
+        .collect())
+}
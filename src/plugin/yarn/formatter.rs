@@ -1,19 +1,24 @@
 use crate::model::{Entity, EntityRule, EntityRuleType};
 
-pub struct YarnFormatter;
+pub struct YarnFormatter {
+    // Scope of the rules which do not carry one
+    default_scope: String,
+}
 
 /*
     Format: zk=3,NOTIN,NODE,zk:hbase=5,IN,RACK,zk
 */
 
 impl YarnFormatter {
-    pub fn new() -> Self {
-        Self
+    pub fn new(default_scope: &str) -> Self {
+        Self {
+            default_scope: default_scope.to_string(),
+        }
     }
 
-    fn format_rule(rule: &EntityRule) -> String {
+    fn format_rule(&self, rule: &EntityRule) -> String {
         // let number_of_containers = rule.metadata("numberOfContainers").unwrap_or("0");
-        let scope = rule.metadata("scope").unwrap_or("NODE");
+        let scope = rule.metadata("scope").unwrap_or(&self.default_scope);
         let r#type = rule.r#type();
         let op = match r#type {
             EntityRuleType::Require => "IN",
@@ -51,7 +56,7 @@ impl YarnFormatter {
         }
     }
 
-    fn format_entity(entity: &Entity) -> String {
+    fn format_entity(&self, entity: &Entity) -> String {
         let mut output = String::new();
 
         output.push_str(entity.name.as_ref());
@@ -67,12 +72,12 @@ impl YarnFormatter {
 
         match has_one_more_rules {
             false => {
-                output.push_str(Self::format_rule(any_rule).as_str());
+                output.push_str(self.format_rule(any_rule).as_str());
             }
             true => {
                 let inner = entity
                     .rules()
-                    .map(Self::format_rule)
+                    .map(|rule| self.format_rule(rule))
                     .collect::<Vec<_>>()
                     .join(":");
 
@@ -88,7 +93,7 @@ impl YarnFormatter {
     pub fn format(&self, entities: &[Entity]) -> String {
         let output = entities
             .iter()
-            .map(Self::format_entity)
+            .map(|entity| self.format_entity(entity))
             .collect::<Vec<_>>()
             .join(":");
 
@@ -1,5 +1,10 @@
 mod cli;
+mod config;
+mod env;
 mod formatter;
 mod parser;
+mod plugin;
 
-pub use cli::{execute, YarnCommands};
+pub use cli::{execute, import_specs, YarnCommands};
+pub use config::YarnConfig;
+pub use plugin::YarnPlugin;
@@ -0,0 +1,53 @@
+use std::path::Path;
+
+use crate::{
+    model::Entity,
+    plugin::{
+        yarn::{config::YarnConfig, formatter::YarnFormatter, parser::parser::YarnSpecParser},
+        DeployPlugin,
+    },
+    util::read_source,
+};
+
+// All entities are injected into a single placement spec named `output_name`
+pub struct YarnPlugin {
+    output_name: String,
+    config: YarnConfig,
+}
+
+impl YarnPlugin {
+    pub fn new(output_name: &str, config: YarnConfig) -> Self {
+        Self {
+            output_name: output_name.to_string(),
+            config,
+        }
+    }
+}
+
+impl Default for YarnPlugin {
+    fn default() -> Self {
+        Self::new("output.spec", YarnConfig::default())
+    }
+}
+
+impl DeployPlugin for YarnPlugin {
+    const FILE_EXTENSION: &'static str = "spec";
+
+    fn extract_entities(&self, path: &Path) -> anyhow::Result<Vec<Entity>> {
+        let data = read_source(path)?;
+
+        YarnSpecParser::new().parse(&data, path.to_path_buf())
+    }
+
+    fn inject_entities(&self, entities: Vec<Entity>) -> anyhow::Result<Vec<(String, String)>> {
+        let entities = entities
+            .into_iter()
+            .filter(|entity| entity.rules_len() > 0)
+            .collect::<Vec<_>>();
+
+        Ok(vec![(
+            self.output_name.clone(),
+            YarnFormatter::new(&self.config.default_scope).format(&entities),
+        )])
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use log::{debug, info, warn};
+use petgraph::unionfind::UnionFind;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::model::{Entity, EntityRule};
+
+use super::{encode_snapshot, SolverOutput};
+
+// Directory of the cache files, relative to the output directory of a run
+pub const CACHE_DIR: &str = ".deployfix-cache";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct CachedComponent {
+    conflicts: BTreeMap<String, Vec<EntityRule>>,
+    solvers: BTreeMap<String, BTreeSet<String>>,
+}
+
+// Conflicts found by previous runs for every connected component of a model, keyed by a
+// hash of the rules of the component and the solver options. Components with a known hash
+// are not solved again, so an iterative fix only re-solves the components it touched.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SolveCache {
+    components: BTreeMap<String, CachedComponent>,
+}
+
+impl SolveCache {
+    fn path(dir: &Path, domain: &str) -> PathBuf {
+        dir.join(CACHE_DIR)
+            .join(format!("{}.json", domain.replace('/', "_")))
+    }
+
+    // A missing or unreadable cache is an empty one
+    pub fn load(dir: &Path, domain: &str) -> Self {
+        let path = Self::path(dir, domain);
+
+        let data = match std::fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(_) => return Self::default(),
+        };
+
+        serde_json::from_str(&data).unwrap_or_else(|err| {
+            warn!("Ignoring invalid cache {}: {}", path.display(), err);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, dir: &Path, domain: &str) -> anyhow::Result<()> {
+        let path = Self::path(dir, domain);
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create dir {}", parent.display()))?;
+        }
+
+        std::fs::write(&path, serde_json::to_string(self)?)
+            .with_context(|| format!("Failed to write cache {}", path.display()))
+    }
+
+    // Solves the components missing from the cache with `solve` and merges the cached
+    // conflicts of the others. `options` must describe everything else the result depends
+    // on, e.g. the enabled solvers. Returns the result and the cache of this run.
+    pub fn solve(
+        &self,
+        components: Vec<Vec<Entity>>,
+        options: &str,
+        solve: impl FnOnce(Vec<Entity>) -> SolverOutput,
+    ) -> (SolverOutput, SolveCache) {
+        let keys = components
+            .iter()
+            .map(|component| component_key(component, options))
+            .collect::<Vec<_>>();
+
+        let (dirty, clean): (Vec<_>, Vec<_>) =
+            (0..components.len()).partition(|i| !self.components.contains_key(&keys[*i]));
+
+        info!(
+            "{} of {} components unchanged since the last run",
+            clean.len(),
+            components.len()
+        );
+
+        let mut cache = SolveCache::default();
+        let mut result = SolverOutput::Ok;
+
+        for i in clean {
+            let cached = self.components[&keys[i]].clone();
+
+            if !cached.conflicts.is_empty() {
+                result = result.merge(SolverOutput::Conflict(
+                    cached.conflicts.clone().into_iter().collect(),
+                    cached.solvers.clone().into_iter().collect(),
+                ));
+            }
+
+            cache.components.insert(keys[i].clone(), cached);
+        }
+
+        if dirty.is_empty() {
+            return (result, cache);
+        }
+
+        // Entity name -> index of its dirty component
+        let owners = dirty
+            .iter()
+            .flat_map(|i| components[*i].iter().map(move |e| (e.name.0.as_str(), *i)))
+            .collect::<HashMap<_, _>>();
+
+        let solved = solve(
+            dirty
+                .iter()
+                .flat_map(|i| components[*i].iter().cloned())
+                .collect(),
+        );
+
+        let mut solved_components = dirty
+            .iter()
+            .map(|i| (*i, CachedComponent::default()))
+            .collect::<HashMap<_, _>>();
+        let mut cacheable = true;
+
+        // Entities the solver gave up on may be decided by the next run
+        if let SolverOutput::Undetermined(..) = &solved {
+            cacheable = false;
+        }
+
+        if let SolverOutput::Conflict(conflicts, solvers)
+        | SolverOutput::Undetermined(_, conflicts, solvers) = &solved
+        {
+            for (name, rules) in conflicts {
+                match owners.get(name.as_str()) {
+                    Some(i) => {
+                        let component = solved_components.get_mut(i).unwrap();
+                        component.conflicts.insert(name.clone(), rules.clone());
+                        if let Some(solvers) = solvers.get(name) {
+                            component.solvers.insert(name.clone(), solvers.clone());
+                        }
+                    }
+                    None => {
+                        debug!("Conflict of {} matches no component, not caching", name);
+                        cacheable = false;
+                    }
+                }
+            }
+        }
+
+        if cacheable {
+            for (i, component) in solved_components {
+                cache.components.insert(keys[i].clone(), component);
+            }
+        }
+
+        (result.merge(solved), cache)
+    }
+}
+
+// Groups the entities linked by a rule, in the order of their first entity
+pub fn components(entities: &[Entity]) -> Vec<Vec<Entity>> {
+    let mut index = HashMap::new();
+    for entity in entities {
+        let next = index.len();
+        index.entry(entity.name.0.as_str()).or_insert(next);
+
+        for rule in entity.rules() {
+            for target in rule.targets() {
+                let next = index.len();
+                index.entry(target.0.as_str()).or_insert(next);
+            }
+        }
+    }
+
+    let mut union_find = UnionFind::<usize>::new(index.len());
+    for entity in entities {
+        let a = index[entity.name.0.as_str()];
+
+        for rule in entity.rules() {
+            for target in rule.targets() {
+                union_find.union(a, index[target.0.as_str()]);
+            }
+        }
+    }
+
+    let mut components: Vec<Vec<Entity>> = vec![];
+    let mut positions = HashMap::new();
+    for entity in entities {
+        let root = union_find.find(index[entity.name.0.as_str()]);
+        let position = *positions.entry(root).or_insert_with(|| {
+            components.push(vec![]);
+            components.len() - 1
+        });
+
+        components[position].push(entity.clone());
+    }
+
+    components
+}
+
+// SHA-256 of the rules of a component, independent of the order they were read in. The key is
+// saved in the cache, it must not change between releases as the std hashers may.
+fn component_key(component: &[Entity], options: &str) -> String {
+    let mut component = component.iter().collect::<Vec<_>>();
+    component.sort_by(|a, b| a.name.cmp(&b.name));
+    let component = component.into_iter().cloned().collect::<Vec<_>>();
+
+    let digest = Sha256::new()
+        .chain_update(options)
+        .chain_update([0u8])
+        .chain_update(encode_snapshot(&component))
+        .finalize();
+
+    format!("{:x}", digest)
+}
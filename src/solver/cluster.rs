@@ -0,0 +1,78 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::model::EntityRule;
+
+use super::repro::find;
+
+// Conflicting entities whose unsat cores share rules, with the rules they share the most
+#[derive(Debug, Clone)]
+pub struct ConflictCluster {
+    pub entities: BTreeSet<String>,
+    pub rules: BTreeSet<EntityRule>,
+    // Rules in the conflicts of more than one entity, with the number of these entities,
+    // most frequent first
+    pub roots: Vec<(EntityRule, usize)>,
+}
+
+// Groups the conflicts sharing a conflicting rule, the largest clusters first. A single rule
+// behind the conflicts of many entities, e.g. an exclude between two popular targets, is the
+// root of its cluster.
+pub fn cluster_conflicts(conflicts: &HashMap<String, Vec<EntityRule>>) -> Vec<ConflictCluster> {
+    // Rule -> entities whose conflicts include it
+    let mut owners: BTreeMap<&EntityRule, BTreeSet<&str>> = BTreeMap::new();
+    for (name, rules) in conflicts.iter() {
+        for rule in rules {
+            owners.entry(rule).or_default().insert(name.as_str());
+        }
+    }
+
+    let mut parents = HashMap::new();
+    for names in owners.values() {
+        let mut names = names.iter();
+        let Some(first) = names.next() else {
+            continue;
+        };
+
+        let root = find(&mut parents, first);
+        for name in names {
+            let other = find(&mut parents, name);
+            parents.insert(other, root.clone());
+        }
+    }
+
+    let mut clusters: BTreeMap<String, ConflictCluster> = BTreeMap::new();
+    for (name, rules) in conflicts.iter() {
+        let cluster = clusters
+            .entry(find(&mut parents, name))
+            .or_insert_with(|| ConflictCluster {
+                entities: BTreeSet::new(),
+                rules: BTreeSet::new(),
+                roots: vec![],
+            });
+
+        cluster.entities.insert(name.clone());
+        cluster.rules.extend(rules.iter().cloned());
+    }
+
+    let mut clusters = clusters.into_values().collect::<Vec<_>>();
+    for cluster in clusters.iter_mut() {
+        let mut roots = cluster
+            .rules
+            .iter()
+            .map(|rule| (rule.clone(), owners[rule].len()))
+            .filter(|(_, count)| *count > 1)
+            .collect::<Vec<_>>();
+        roots.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+
+        cluster.roots = roots;
+    }
+
+    clusters.sort_by(|a, b| {
+        b.entities
+            .len()
+            .cmp(&a.entities.len())
+            .then_with(|| a.entities.cmp(&b.entities))
+    });
+
+    clusters
+}
@@ -0,0 +1,122 @@
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    fmt::Display,
+};
+
+use crate::model::{Entity, EntityRule, EntityRuleType};
+
+// Why one alternative of a multi require (`a require b|c|d`) cannot host the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetDiagnosis {
+    // No entity of that name declares rules in the model, as for the unknown solver
+    Unknown,
+    // The first entity excludes the second, one of them is the source or an entity it
+    // requires and the other the alternative or an entity the alternative requires
+    Excluded(String, String),
+    // The alternative requires the source back
+    Cyclic,
+    // The alternative cannot be placed itself
+    Unschedulable,
+    // No single rule explains it, the alternative fails in combination with others
+    Undetermined,
+}
+
+impl Display for TargetDiagnosis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unknown => write!(f, "unknown entity"),
+            Self::Excluded(by, excluded) => write!(f, "{} excludes {}", by, excluded),
+            Self::Cyclic => write!(f, "cyclic, it requires the source back"),
+            Self::Unschedulable => write!(f, "unschedulable itself"),
+            Self::Undetermined => write!(f, "no direct cause found"),
+        }
+    }
+}
+
+// Entities placed together with `name`: itself and everything it requires through mono rules
+fn bound<'a>(entities: &HashMap<&'a str, &'a Entity>, name: &'a str) -> BTreeSet<&'a str> {
+    let mut bound = BTreeSet::from([name]);
+    let mut queue = VecDeque::from([name]);
+
+    while let Some(name) = queue.pop_front() {
+        let Some(entity) = entities.get(name) else {
+            continue;
+        };
+
+        for rule in entity.requires.iter() {
+            if let EntityRule::Mono { target, .. } = rule {
+                if bound.insert(target.as_ref()) {
+                    queue.push_back(target.as_ref());
+                }
+            }
+        }
+    }
+
+    bound
+}
+
+fn excludes(entities: &HashMap<&str, &Entity>, a: &str, b: &str) -> bool {
+    entities
+        .get(a)
+        .is_some_and(|entity| entity.excludes.iter().any(|rule| rule.is_in_target(b)))
+}
+
+// Explains each alternative of a multi require taking part in a conflict, nothing for other
+// rules. `conflicts` are the unschedulable entities reported for the same model.
+pub fn diagnose_multi_rule(
+    entities: &[Entity],
+    conflicts: &HashMap<String, Vec<EntityRule>>,
+    rule: &EntityRule,
+) -> Vec<(String, TargetDiagnosis)> {
+    let (source, targets) = match rule {
+        EntityRule::Multi {
+            source,
+            targets,
+            r#type: EntityRuleType::Require,
+            ..
+        } => (source.as_ref(), targets),
+        _ => return vec![],
+    };
+
+    let entities = entities
+        .iter()
+        .map(|entity| (entity.name.as_ref(), entity))
+        .collect::<HashMap<_, _>>();
+    let source_bound = bound(&entities, source);
+
+    targets
+        .iter()
+        .map(|target| {
+            let target = target.as_ref();
+            let target_bound = bound(&entities, target);
+
+            let exclusion = source_bound.iter().find_map(|a| {
+                target_bound.iter().find_map(|b| {
+                    if excludes(&entities, a, b) {
+                        Some((a.to_string(), b.to_string()))
+                    } else if excludes(&entities, b, a) {
+                        Some((b.to_string(), a.to_string()))
+                    } else {
+                        None
+                    }
+                })
+            });
+
+            // The most specific cause first, names without rules of their own are often
+            // placeholders for other deployments
+            let diagnosis = if let Some((by, excluded)) = exclusion {
+                TargetDiagnosis::Excluded(by, excluded)
+            } else if target_bound.contains(source) {
+                TargetDiagnosis::Cyclic
+            } else if conflicts.contains_key(target) {
+                TargetDiagnosis::Unschedulable
+            } else if !entities.contains_key(target) {
+                TargetDiagnosis::Unknown
+            } else {
+                TargetDiagnosis::Undetermined
+            };
+
+            (target.to_string(), diagnosis)
+        })
+        .collect()
+}
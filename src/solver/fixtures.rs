@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use crate::model::{EntityRule, EntityRuleSource, EntityRuleType};
+
+use super::{SolverOutput, Undetermined};
+
+// File the rules of the fixtures are declared in
+pub const FIXTURE_FILE: &str = "fixture.ir";
+
+// Builders of solver outputs as the solvers return them, for tests of the code combining
+// them. Conflicts are kept as given, duplicates included, like a solver building its own.
+
+// `source require|exclude targets` at `line` of the fixture file, a multi rule for several
+// targets
+pub fn rule(source: &str, r#type: EntityRuleType, targets: &[&str], line: usize) -> EntityRule {
+    let rule_source = EntityRuleSource::new(FIXTURE_FILE, line);
+
+    match targets {
+        [target] => EntityRule::mono(source.into(), (*target).into(), r#type, rule_source, None),
+        targets => EntityRule::multi(
+            source.into(),
+            targets.iter().map(|target| (*target).into()).collect(),
+            r#type,
+            rule_source,
+            None,
+        ),
+    }
+}
+
+// Conflicts found by `solver`, the rules of each entity in the given order
+pub fn conflict(solver: &str, conflicts: &[(&str, Vec<EntityRule>)]) -> SolverOutput {
+    let conflicts = conflicts
+        .iter()
+        .map(|(name, rules)| (name.to_string(), rules.clone()))
+        .collect::<HashMap<_, _>>();
+
+    SolverOutput::Conflict(conflicts, HashMap::new()).with_solver(solver)
+}
+
+// Entities a solver gave up on with the reason of each, and its statistics
+pub fn undetermined(entities: &[(&str, &str)], statistics: &[(&str, &str)]) -> SolverOutput {
+    let mut undetermined = Undetermined::new(
+        entities
+            .iter()
+            .map(|(name, reason)| (name.to_string(), reason.to_string())),
+    );
+    undetermined.statistics = statistics
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+    SolverOutput::new_undetermined(undetermined)
+}
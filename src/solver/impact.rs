@@ -0,0 +1,119 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::model::{Entity, EntityPriority};
+
+// Weights of the parts of an impact score, e.g.
+//
+//   impact:
+//     priority: 10
+//     replicas: 1
+//     dependents: 2
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImpactWeights {
+    // Per priority tier above low, a critical entity scores 3 tiers
+    pub priority: f64,
+    // Per replica of the entity
+    pub replicas: f64,
+    // Per entity requiring it, directly or through other requires
+    pub dependents: f64,
+}
+
+impl Default for ImpactWeights {
+    fn default() -> Self {
+        Self {
+            priority: 10.0,
+            replicas: 1.0,
+            dependents: 2.0,
+        }
+    }
+}
+
+// How much damage leaving an entity unscheduled does
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImpactScore {
+    pub score: f64,
+    pub priority: EntityPriority,
+    pub replicas: usize,
+    pub dependents: usize,
+}
+
+fn tiers(priority: &EntityPriority) -> f64 {
+    match priority {
+        EntityPriority::Critical => 3.0,
+        EntityPriority::High => 2.0,
+        EntityPriority::Default => 1.0,
+        EntityPriority::Low => 0.0,
+    }
+}
+
+// Scores each entity of `names` against the model `entities`. Self-conflicting entities are
+// split into `name_1` and `name_2` by the solvers, both parts score as the entity itself.
+pub fn impact_scores<'a>(
+    entities: &[Entity],
+    names: impl IntoIterator<Item = &'a String>,
+    weights: &ImpactWeights,
+) -> HashMap<String, ImpactScore> {
+    let by_name = entities
+        .iter()
+        .map(|entity| (entity.name.as_ref(), entity))
+        .collect::<HashMap<_, _>>();
+
+    // Target -> entities requiring it
+    let mut required_by: HashMap<&str, Vec<&str>> = HashMap::new();
+    for entity in entities {
+        for rule in entity.requires.iter() {
+            for target in rule.targets() {
+                required_by
+                    .entry(target.as_ref())
+                    .or_default()
+                    .push(entity.name.as_ref());
+            }
+        }
+    }
+
+    names
+        .into_iter()
+        .map(|name| {
+            let base = match name.strip_suffix("_1").or(name.strip_suffix("_2")) {
+                Some(base) if !by_name.contains_key(name.as_str()) => base,
+                _ => name.as_str(),
+            };
+            let entity = by_name.get(base);
+
+            let priority = entity
+                .map(|entity| entity.priority.clone())
+                .unwrap_or_default();
+            let replicas = entity
+                .and_then(|entity| entity.rules().find_map(|rule| rule.replicas()))
+                .unwrap_or(1);
+
+            let mut reached = HashSet::from([base]);
+            let mut queue = VecDeque::from([base]);
+            while let Some(name) = queue.pop_front() {
+                for source in required_by.get(name).into_iter().flatten() {
+                    if reached.insert(source) {
+                        queue.push_back(source);
+                    }
+                }
+            }
+            let dependents = reached.len() - 1;
+
+            let score = weights.priority * tiers(&priority)
+                + weights.replicas * replicas as f64
+                + weights.dependents * dependents as f64;
+
+            (
+                name.clone(),
+                ImpactScore {
+                    score,
+                    priority,
+                    replicas,
+                    dependents,
+                },
+            )
+        })
+        .collect()
+}
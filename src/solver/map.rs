@@ -1,15 +1,43 @@
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 use log::warn;
 use thiserror::Error;
 
-use crate::model::{Entity, EntityName, EntityRule};
+use crate::model::{Entity, EntityName, EntityRule, TimeWindows};
 
 #[derive(Debug, serde::Serialize)]
 pub struct EntityMap {
     pub entities: Vec<Entity>,
     pub names: HashSet<String>,
     pub self_conflicts: HashSet<String>,
+    // Values of the scope keys (see `METADATA_SCOPE_KEY`) declared by each entity's rules
+    pub scopes: HashMap<String, BTreeMap<String, String>>,
+    // Daily windows (see `METADATA_WINDOW_KEY`) of the entities that do not run all the time
+    #[serde(skip)]
+    pub windows: HashMap<String, TimeWindows>,
+    // Replicas (see `METADATA_REPLICAS_KEY`) of the entities, by their name before splitting.
+    // Autoscaled entities count the maximum of their autoscaler.
+    pub replicas: HashMap<String, usize>,
+    // Minimum replicas of the autoscaled entities (see `METADATA_MIN_REPLICAS_KEY`)
+    pub min_replicas: HashMap<String, usize>,
+    // Dependency hints (see `METADATA_HINT_KEY`), kept apart from the entities' rules as only
+    // the ring solver uses them
+    #[serde(skip)]
+    pub hints: Vec<EntityRule>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProblemSize {
+    pub entities: usize,
+    pub rules: usize,
+    pub clauses: usize,
+    pub split_entities: usize,
+}
+
+impl ProblemSize {
+    pub fn variables(&self) -> usize {
+        self.entities + self.rules
+    }
 }
 
 #[derive(Debug, Error)]
@@ -468,6 +496,37 @@ impl EntityMap {
         (entities, self_conflicts)
     }
 
+    // Moves the hint rules out of the entities
+    fn take_hints(entities: Vec<Entity>) -> (Vec<Entity>, Vec<EntityRule>) {
+        let mut hints = vec![];
+
+        let entities = entities
+            .into_iter()
+            .map(|mut e| {
+                let (entity_hints, requires): (BTreeSet<_>, _) =
+                    e.requires.into_iter().partition(|r| r.hint().is_some());
+                e.requires = requires;
+                hints.extend(entity_hints);
+                e
+            })
+            .collect();
+
+        (entities, hints)
+    }
+
+    // The entities with their hints put back, as they were built from
+    pub fn entities_with_hints(&self) -> Vec<Entity> {
+        let mut entities = self.entities.clone();
+
+        for hint in self.hints.iter() {
+            if let Some(entity) = entities.iter_mut().find(|e| &e.name == hint.source()) {
+                entity.add_require(hint.clone());
+            }
+        }
+
+        entities
+    }
+
     fn collect_entity_names(entities: &[Entity]) -> HashSet<String> {
         entities
             .iter()
@@ -503,17 +562,217 @@ impl EntityMap {
             .collect::<HashSet<_>>()
     }
 
+    // Collects, for every key used to scope a rule, the value each entity declares for it
+    // in the metadata of its own rules (e.g. `scope_key=tenant;tenant=acme;`).
+    fn collect_scopes(entities: &[Entity]) -> HashMap<String, BTreeMap<String, String>> {
+        let keys = entities
+            .iter()
+            .flat_map(|e| e.rules())
+            .filter_map(|r| r.scope_key())
+            .collect::<HashSet<_>>();
+
+        if keys.is_empty() {
+            return HashMap::new();
+        }
+
+        entities
+            .iter()
+            .filter_map(|e| {
+                let values = e
+                    .rules()
+                    .flat_map(|r| {
+                        keys.iter()
+                            .filter_map(|key| r.metadata(key).map(|v| (key.to_string(), v)))
+                            .collect::<Vec<_>>()
+                    })
+                    .fold(BTreeMap::new(), |mut acc, (key, value)| {
+                        if let Some(existing) = acc.get(&key) {
+                            if existing != value {
+                                warn!(
+                                    "Entity `{}` declares conflicting values for scope `{}`: {} and {}, keeping {}",
+                                    e.name.as_ref(),
+                                    key,
+                                    existing,
+                                    value,
+                                    existing
+                                );
+                            }
+                        } else {
+                            acc.insert(key, value.to_string());
+                        }
+
+                        acc
+                    });
+
+                if values.is_empty() {
+                    None
+                } else {
+                    Some((e.name.0.clone(), values))
+                }
+            })
+            .collect()
+    }
+
+    // Collects the window each entity declares in the metadata of its own rules
+    fn collect_windows(entities: &[Entity]) -> HashMap<String, TimeWindows> {
+        entities
+            .iter()
+            .filter_map(|e| {
+                let value = e.rules().find_map(|r| r.window())?;
+
+                match TimeWindows::parse(value) {
+                    Ok(windows) => Some((e.name.0.clone(), windows)),
+                    Err(err) => {
+                        warn!(
+                            "Ignoring the window of `{}`, it runs all the time: {}",
+                            e.name.as_ref(),
+                            err
+                        );
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn collect_replicas(entities: &[Entity]) -> HashMap<String, usize> {
+        entities
+            .iter()
+            .filter_map(|e| {
+                let replicas = e.rules().find_map(|r| r.replicas());
+                let max_replicas = e.rules().find_map(|r| r.max_replicas());
+
+                Some((e.name.0.clone(), replicas.max(max_replicas)?))
+            })
+            .collect()
+    }
+
+    // Without a minimum an autoscaler keeps at least one replica
+    fn collect_min_replicas(entities: &[Entity]) -> HashMap<String, usize> {
+        entities
+            .iter()
+            .filter(|e| e.rules().any(|r| r.max_replicas().is_some()))
+            .map(|e| {
+                let min_replicas = e.rules().find_map(|r| r.min_replicas()).unwrap_or(1);
+
+                (e.name.0.clone(), min_replicas)
+            })
+            .collect()
+    }
+
+    // Entities with a self anti-affinity and more than one replica, along with their replicas.
+    // Each replica needs a node of its own.
+    pub fn spread_entities(&self) -> Vec<(&str, usize)> {
+        let mut spread = self
+            .replicas
+            .iter()
+            .filter(|(name, replicas)| {
+                **replicas > 1
+                    && self.names.contains(&format!("{}_1", name))
+                    && self.names.contains(&format!("{}_2", name))
+            })
+            .map(|(name, replicas)| (name.as_str(), *replicas))
+            .collect::<Vec<_>>();
+        spread.sort();
+
+        spread
+    }
+
+    // Whether `a` and `b` may run at the same time, entities without a window always run
+    pub fn concurrent(&self, a: &str, b: &str) -> bool {
+        match (self.windows.get(a), self.windows.get(b)) {
+            (Some(a), Some(b)) => a.overlaps(b),
+            _ => true,
+        }
+    }
+
+    // Rough size of the z3 encoding: one boolean per entity and per rule tracker, one
+    // clause per rule target, checked once per entity
+    pub fn problem_size(&self) -> ProblemSize {
+        let rules = self.entities.iter().map(|e| e.rules_len()).sum();
+        let clauses = self
+            .entities
+            .iter()
+            .flat_map(|e| e.rules())
+            .map(|r| match r {
+                EntityRule::Mono { .. } => 1,
+                EntityRule::Multi { targets, .. } => targets.len(),
+            })
+            .sum();
+
+        ProblemSize {
+            entities: self.names.len(),
+            rules,
+            clauses,
+            split_entities: self.self_conflicts.len(),
+        }
+    }
+
+    pub fn scope_value(&self, name: &str, key: &str) -> Option<&str> {
+        self.scopes
+            .get(name)
+            .and_then(|values| values.get(key))
+            .map(|e| e.as_str())
+    }
+
+    // Whether the rule of `a` applies between `a` and `b`: rules without a scope always do,
+    // scoped ones when both entities declare the same value for its key. An entity declaring
+    // none is in no scope.
+    pub fn in_scope(&self, rule: &EntityRule, a: &str, b: &str) -> bool {
+        let Some(key) = rule.scope_key() else {
+            return true;
+        };
+
+        match (self.scope_value(a, key), self.scope_value(b, key)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    // Scoped rules the entities they name are left out of, for lack of a scope value
+    fn warn_unscoped(entities: &[Entity], scopes: &HashMap<String, BTreeMap<String, String>>) {
+        let value = |name: &str, key: &str| scopes.get(name).and_then(|values| values.get(key));
+
+        for rule in entities.iter().flat_map(|e| e.rules()) {
+            let Some(key) = rule.scope_key() else {
+                continue;
+            };
+
+            for name in std::iter::once(rule.source()).chain(rule.targets()) {
+                if value(name.as_ref(), key).is_none() {
+                    warn!(
+                        "{} declares no `{}` scope, the scoped rule {} does not apply to it",
+                        name.as_ref(),
+                        key,
+                        rule
+                    );
+                }
+            }
+        }
+    }
+
     pub fn build(entities: &[Entity]) -> Result<Self, EntityMapError> {
         // Check for duplicate names
         Self::check_duplicate_names(entities)?;
+        let replicas = Self::collect_replicas(entities);
+        let min_replicas = Self::collect_min_replicas(entities);
 
         let (entities, self_conflicts) = Self::preprocessing_self_conflicts(entities.to_owned());
+        let (entities, hints) = Self::take_hints(entities);
         let names = Self::collect_entity_names(&entities);
+        let scopes = Self::collect_scopes(&entities);
+        Self::warn_unscoped(&entities, &scopes);
+        let windows = Self::collect_windows(&entities);
 
         Ok(Self {
             entities,
             names,
             self_conflicts,
+            scopes,
+            windows,
+            replicas,
+            min_replicas,
+            hints,
         })
     }
 }
@@ -1,7 +1,31 @@
+mod cache;
+mod cluster;
+mod diagnose;
+pub mod fixtures;
+mod impact;
 mod map;
+mod query;
+mod radius;
+mod repro;
 mod ring;
+mod snapshot;
 mod solver;
+mod structural;
 mod unknown;
 mod z3;
 
-pub use solver::{get_solver, SolverOutput};
+pub use cache::{components, SolveCache, CACHE_DIR};
+pub use cluster::{cluster_conflicts, ConflictCluster};
+pub use diagnose::{diagnose_multi_rule, TargetDiagnosis};
+pub use impact::{impact_scores, ImpactScore, ImpactWeights};
+pub use map::{EntityMap, EntityMapError, ProblemSize};
+pub use radius::{blast_radius, BlastRadius};
+pub use repro::{conflict_slices, ConflictSlice};
+pub use snapshot::{
+    decode_snapshot, encode_snapshot, SnapshotError, SNAPSHOT_EXTENSION, SNAPSHOT_VERSION,
+};
+
+pub use solver::{
+    explain_solver, get_solver, get_solver_with, is_soft_conflict, ConflictSolvers, SolverError,
+    SolverOptions, SolverOutput, Undetermined, DEFAULT_MAX_CYCLES, SOLVER_NAMES,
+};
@@ -0,0 +1,65 @@
+use std::collections::{BTreeSet, VecDeque};
+
+use crate::model::{Entity, EntityRule};
+
+use super::EntityMap;
+
+// Queries over the rules of the map, by entity name as in the map: self-conflicting entities
+// are split into `name_1` and `name_2`. Unknown names have no rules and are in no pair.
+impl EntityMap {
+    pub fn entity(&self, name: &str) -> Option<&Entity> {
+        self.entities.iter().find(|e| e.name.as_ref() == name)
+    }
+
+    // Targets of the require rules of `name`, every alternative of a multi rule included
+    pub fn requires_of(&self, name: &str) -> BTreeSet<&str> {
+        self.entity(name)
+            .into_iter()
+            .flat_map(|e| e.requires.iter())
+            .flat_map(EntityRule::targets)
+            .map(|t| t.as_ref())
+            .collect()
+    }
+
+    // Entities with an exclude rule naming `name`
+    pub fn excluded_by(&self, name: &str) -> BTreeSet<&str> {
+        self.entities
+            .iter()
+            .filter(|e| e.excludes.iter().any(|r| r.is_in_target(name)))
+            .map(|e| e.name.as_ref())
+            .collect()
+    }
+
+    // Entities `name` requires directly or through the entities it requires, without itself
+    pub fn transitive_requires(&self, name: &str) -> BTreeSet<&str> {
+        let mut reached = BTreeSet::new();
+        let mut queue = VecDeque::from([name]);
+
+        while let Some(current) = queue.pop_front() {
+            for target in self.requires_of(current) {
+                if target != name && reached.insert(target) {
+                    queue.push_back(target);
+                }
+            }
+        }
+
+        reached
+    }
+
+    // Pairs of entities an exclude rule keeps apart, each pair once with the smaller name
+    // first, in name order. Self excludes are left out, they are split by the map.
+    pub fn conflicting_pairs(&self) -> Vec<(&str, &str)> {
+        self.entities
+            .iter()
+            .flat_map(|e| e.excludes.iter())
+            .flat_map(|r| {
+                let source = r.source().as_ref();
+                r.targets().into_iter().map(move |t| (source, t.as_ref()))
+            })
+            .filter(|(source, target)| source != target)
+            .map(|(a, b)| if a < b { (a, b) } else { (b, a) })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
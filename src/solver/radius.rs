@@ -0,0 +1,125 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
+
+use serde::Serialize;
+
+use crate::model::{Entity, EntityRule};
+
+use super::{SolveCache, SolverOutput};
+
+// Entities the rules of one entity make unschedulable
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BlastRadius {
+    pub entity: String,
+    // The entity itself and the targets of its rules, with their conflicting rules
+    pub direct: BTreeMap<String, Vec<EntityRule>>,
+    // Entities reached through the requires of other entities
+    pub transitive: BTreeMap<String, Vec<EntityRule>>,
+    // Entities already unschedulable without the rules, left out of both
+    pub unchanged: BTreeSet<String>,
+}
+
+impl BlastRadius {
+    pub fn is_empty(&self) -> bool {
+        self.direct.is_empty() && self.transitive.is_empty()
+    }
+}
+
+/*
+    Solves `entities` without the rules of `name`, then with them. The second solve reuses the
+    conflicts of the first for every component the rules do not touch, so only the components
+    they link are solved again. Self-conflicting entities are reported as the solvers name them.
+*/
+pub fn blast_radius(
+    entities: &[Entity],
+    name: &str,
+    solve: impl Fn(Vec<Entity>) -> SolverOutput,
+) -> anyhow::Result<BlastRadius> {
+    let Some(entity) = entities.iter().find(|e| e.name.as_ref() == name) else {
+        anyhow::bail!("Unknown entity {}", name);
+    };
+
+    let targets = entity
+        .rules()
+        .flat_map(EntityRule::targets)
+        .map(|t| t.as_ref())
+        .collect::<BTreeSet<_>>();
+
+    let without = entities
+        .iter()
+        .cloned()
+        .map(|mut e| {
+            if e.name.as_ref() == name {
+                e.requires.clear();
+                e.excludes.clear();
+            }
+            e
+        })
+        .collect::<Vec<_>>();
+
+    let (before, cache) = SolveCache::default().solve(super::components(&without), "", &solve);
+    let (after, _) = cache.solve(super::components(entities), "", &solve);
+
+    let unchanged = before.get_unscheduable().unwrap_or_default();
+    let mut radius = BlastRadius {
+        entity: name.to_string(),
+        unchanged: unchanged.iter().cloned().collect(),
+        ..Default::default()
+    };
+
+    for (conflicting, rules) in after.get_conflict_rules().unwrap_or_default() {
+        if unchanged.contains(&conflicting) {
+            continue;
+        }
+
+        let base = conflicting
+            .strip_suffix("_1")
+            .or(conflicting.strip_suffix("_2"))
+            .unwrap_or(&conflicting);
+        if base == name || targets.contains(base) {
+            radius.direct.insert(conflicting, rules);
+        } else {
+            radius.transitive.insert(conflicting, rules);
+        }
+    }
+
+    Ok(radius)
+}
+
+/*
+    Enforcing the rules of app=web makes 2 entities unschedulable:
+      app=web (direct)
+        app=web must not be co-located with app=db on the same node (from web.ir:3)
+      app=api (transitive)
+        app=api must be placed with app=web on the same node (from api.ir:1)
+*/
+impl Display for BlastRadius {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(
+                f,
+                "Enforcing the rules of {} makes no entity unschedulable",
+                self.entity
+            );
+        }
+
+        writeln!(
+            f,
+            "Enforcing the rules of {} makes {} entities unschedulable:",
+            self.entity,
+            self.direct.len() + self.transitive.len()
+        )?;
+        for (kind, entities) in [("direct", &self.direct), ("transitive", &self.transitive)] {
+            for (name, rules) in entities {
+                writeln!(f, "  {} ({})", name, kind)?;
+                for rule in rules {
+                    writeln!(f, "    {}", rule.describe())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
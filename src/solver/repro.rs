@@ -0,0 +1,141 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::model::{
+    rule_location, DeployIRFormatter, Entity, EntityRule, EntityRuleType, METADATA_PROVENANCE_KEY,
+};
+
+use super::ConflictSolvers;
+
+// Entities of one conflict, keeping only the rules reported for it
+#[derive(Debug, Clone)]
+pub struct ConflictSlice {
+    pub entities: Vec<Entity>,
+    pub solvers: BTreeSet<String>,
+}
+
+pub(super) fn find(parents: &mut HashMap<String, String>, name: &str) -> String {
+    let parent = parents
+        .entry(name.to_string())
+        .or_insert_with(|| name.to_string())
+        .clone();
+
+    if parent == name {
+        return parent;
+    }
+
+    let root = find(parents, &parent);
+    parents.insert(name.to_string(), root.clone());
+
+    root
+}
+
+// Splits the conflicts into groups of entities linked by their conflicting rules, each group
+// can be checked and fixed on its own. `entities` are the checked model, used for the source
+// and priority of the sliced entities.
+pub fn conflict_slices(
+    entities: &[Entity],
+    conflicts: &HashMap<String, Vec<EntityRule>>,
+    solvers: &ConflictSolvers,
+) -> Vec<ConflictSlice> {
+    let mut parents = HashMap::new();
+
+    for (name, rules) in conflicts.iter() {
+        for rule in rules {
+            let root = find(&mut parents, name);
+            for other in std::iter::once(rule.source()).chain(rule.targets()) {
+                let other = find(&mut parents, other.as_ref());
+                parents.insert(other, root.clone());
+            }
+        }
+    }
+
+    let mut groups: BTreeMap<String, (BTreeSet<&EntityRule>, BTreeSet<String>)> = BTreeMap::new();
+    for (name, rules) in conflicts.iter() {
+        let root = find(&mut parents, name);
+        let (group_rules, group_solvers) = groups.entry(root).or_default();
+
+        group_rules.extend(rules.iter());
+        group_solvers.extend(solvers.get(name).into_iter().flatten().cloned());
+    }
+
+    let originals = entities
+        .iter()
+        .map(|entity| (entity.name.as_ref(), entity))
+        .collect::<HashMap<_, _>>();
+
+    let mut slices = groups
+        .into_values()
+        .map(|(rules, solvers)| {
+            let mut sliced: BTreeMap<&str, Entity> = BTreeMap::new();
+
+            for rule in rules {
+                let name = rule.source().as_ref();
+                let entity = sliced.entry(name).or_insert_with(|| {
+                    let mut entity = Entity::new(name);
+                    if let Some(original) = originals.get(name) {
+                        entity.source = original.source.clone();
+                        entity.priority = original.priority.clone();
+                    }
+
+                    entity
+                });
+
+                match rule.r#type() {
+                    EntityRuleType::Require => entity.add_require(rule.clone()),
+                    EntityRuleType::Exclude => entity.add_exclude(rule.clone()),
+                }
+            }
+
+            ConflictSlice {
+                entities: sliced.into_values().collect(),
+                solvers,
+            }
+        })
+        .filter(|slice| !slice.entities.is_empty())
+        .collect::<Vec<_>>();
+    slices.sort_by(|a, b| a.entities[0].name.cmp(&b.entities[0].name));
+
+    slices
+}
+
+impl ConflictSlice {
+    // Standalone IR of the slice, each rule preceded by a comment with the places declaring it
+    pub fn to_ir(&self) -> String {
+        let names = self
+            .entities
+            .iter()
+            .map(|entity| entity.name.as_ref())
+            .collect::<Vec<_>>();
+
+        let mut ir = format!("// Conflict between {}\n", names.join(", "));
+        if !self.solvers.is_empty() {
+            ir.push_str(&format!(
+                "// Reported by {}\n",
+                self.solvers.iter().cloned().collect::<Vec<_>>().join(", ")
+            ));
+        }
+
+        for entity in self.entities.iter() {
+            for rule in entity.rules() {
+                let locations = rule
+                    .metadata(METADATA_PROVENANCE_KEY)
+                    .map(|locations| locations.replace(',', ", "))
+                    .or_else(|| rule_location(rule));
+
+                let mut single = Entity::new(entity.name.as_ref());
+                match rule.r#type() {
+                    EntityRuleType::Require => single.add_require(rule.clone()),
+                    EntityRuleType::Exclude => single.add_exclude(rule.clone()),
+                }
+
+                ir.push_str(&format!(
+                    "\n// Declared at {}\n",
+                    locations.as_deref().unwrap_or("an unknown location")
+                ));
+                ir.push_str(&DeployIRFormatter::format(&vec![single]));
+            }
+        }
+
+        ir
+    }
+}
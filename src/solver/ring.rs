@@ -1,20 +1,164 @@
-use graph_cycles::Cycles;
-use std::collections::{HashMap, HashSet};
+use log::{debug, warn};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use crate::model::{Entity, EntityRule};
 
-use super::{map::EntityMap, solver::Solver, SolverOutput};
+use super::{map::EntityMap, solver::Solver, SolverOutput, Undetermined};
 use petgraph::{
+    algo::tarjan_scc,
     graph::NodeIndex,
     visit::{EdgeRef, NodeRef},
     Graph,
 };
 
-pub struct RingSolver;
+// Reports require cycles. When `relaxed`, only the ones co-location cannot satisfy are: cycles
+// between entities excluding each other, or going through a multi rule. Other require cycles
+// are satisfied by placing all their members together and only warned about.
+//
+// Cycles are enumerated per strongly connected component of the require graph, at most
+// `max_cycles` of them as a dense component has exponentially many. The members of a
+// component whose cycles were not all enumerated are undetermined, the component is
+// skipped rather than checked on some of its cycles.
+pub struct RingSolver {
+    relaxed: bool,
+    max_cycles: usize,
+}
+
+// Cycles of the require graph, and the components they were cut short in
+struct RequireCycles {
+    cycles: Vec<HashSet<NodeIndex>>,
+    truncated: Vec<Vec<NodeIndex>>,
+}
 
 impl RingSolver {
-    pub fn new() -> Self {
-        Self
+    pub fn new(relaxed: bool, max_cycles: usize) -> Self {
+        Self {
+            relaxed,
+            max_cycles,
+        }
+    }
+
+    // Simple cycles of a strongly connected component, each found from its first member in
+    // `component` order. Gives up past `max_cycles` cycles, or as many steps per member.
+    fn component_cycles(
+        graph: &Graph<String, EntityRule>,
+        component: &[NodeIndex],
+        max_cycles: usize,
+    ) -> Option<Vec<Vec<NodeIndex>>> {
+        let position = component
+            .iter()
+            .enumerate()
+            .map(|(idx, node)| (*node, idx))
+            .collect::<HashMap<_, _>>();
+        let neighbors = |node: NodeIndex, start: usize| {
+            let mut next = graph
+                .neighbors(node)
+                .filter(|next| position.get(next).is_some_and(|idx| *idx >= start))
+                .collect::<Vec<_>>();
+            next.sort();
+            next.dedup();
+            next.into_iter()
+        };
+
+        let mut cycles = vec![];
+        let mut steps = 0;
+        let budget = max_cycles.saturating_mul(component.len());
+
+        for (start, root) in component.iter().enumerate() {
+            let mut path = vec![*root];
+            let mut on_path = HashSet::from([*root]);
+            let mut stack = vec![neighbors(*root, start)];
+
+            while let Some(next) = stack.last_mut() {
+                let Some(node) = next.next() else {
+                    stack.pop();
+                    on_path.remove(&path.pop().unwrap());
+                    continue;
+                };
+
+                steps += 1;
+                if steps > budget {
+                    return None;
+                }
+
+                if node == *root {
+                    if cycles.len() == max_cycles {
+                        return None;
+                    }
+                    cycles.push(path.clone());
+                } else if on_path.insert(node) {
+                    path.push(node);
+                    stack.push(neighbors(node, start));
+                }
+            }
+        }
+
+        Some(cycles)
+    }
+
+    fn find_cycles(&self, graph: &Graph<String, EntityRule>) -> RequireCycles {
+        let mut cycles = vec![];
+        let mut truncated = vec![];
+
+        for component in tarjan_scc(graph) {
+            // Self requires are satisfied by the entity itself
+            if component.len() == 1 {
+                continue;
+            }
+
+            let Some(found) = Self::component_cycles(graph, &component, self.max_cycles) else {
+                warn!(
+                    "Require graph component of {} entities has too many cycles to check, raise --max-cycles above {}",
+                    component.len(),
+                    self.max_cycles
+                );
+                truncated.push(component);
+                continue;
+            };
+
+            debug!(
+                "Require graph component of {} entities: {} cycles, the longest of {} entities",
+                component.len(),
+                found.len(),
+                found
+                    .iter()
+                    .map(|cycle| cycle.len())
+                    .max()
+                    .unwrap_or_default()
+            );
+            cycles.extend(
+                found
+                    .into_iter()
+                    .map(|cycle| cycle.into_iter().collect::<HashSet<_>>()),
+            );
+        }
+
+        RequireCycles { cycles, truncated }
+    }
+
+    // Whether a member of the cycle excludes another one
+    fn crosses_exclude(map: &EntityMap, members: &HashSet<&str>) -> bool {
+        map.entities
+            .iter()
+            .filter(|entity| members.contains(entity.name.as_ref()))
+            .flat_map(|entity| entity.excludes.iter())
+            .filter(|rule| !rule.is_soft())
+            .any(|rule| {
+                rule.targets().iter().any(|target| {
+                    members.contains(target.as_ref())
+                        && map.concurrent(rule.source().as_ref(), target.as_ref())
+                        && map.in_scope(rule, rule.source().as_ref(), target.as_ref())
+                })
+            })
+    }
+
+    // Whether an edge of the cycle comes from a multi rule
+    fn has_multi_edge(graph: &Graph<String, EntityRule>, cycle: &HashSet<NodeIndex>) -> bool {
+        cycle.iter().any(|node| {
+            graph
+                .edges(*node)
+                .any(|edge| cycle.contains(&edge.target()) && edge.weight().targets().len() > 1)
+        })
     }
 
     fn get_or_create_node(
@@ -39,7 +183,15 @@ impl RingSolver {
             let name = entity.name.0.as_str();
             let node = Self::get_or_create_node(name, &mut graph, &mut nodes);
 
-            for rule in entity.requires.iter() {
+            // Members of a colocation group require each other on purpose, preferences do not
+            // bind the scheduler
+            let hints = map.hints.iter().filter(|r| r.source() == &entity.name);
+            for rule in entity
+                .requires
+                .iter()
+                .filter(|r| r.group().is_none() && !r.is_soft())
+                .chain(hints)
+            {
                 match rule {
                     EntityRule::Mono { target, .. } => {
                         let target_node =
@@ -65,14 +217,10 @@ impl Solver<'_> for RingSolver {
     fn solve(&self, entities: &EntityMap) -> SolverOutput {
         let (graph, nodes) = Self::build_graph(entities);
 
-        let cycles = graph.cycles();
-        if cycles.is_empty() {
+        let RequireCycles { cycles, truncated } = self.find_cycles(&graph);
+        if cycles.is_empty() && truncated.is_empty() {
             return SolverOutput::Ok;
         }
-        let cycles = cycles
-            .into_iter()
-            .map(|e| e.into_iter().collect::<HashSet<_>>())
-            .collect::<Vec<_>>();
 
         let mut conflicts = HashMap::new();
         let mut rule_ways: HashMap<EntityRule, HashSet<String>> = HashMap::new();
@@ -82,6 +230,25 @@ impl Solver<'_> for RingSolver {
                 continue;
             }
 
+            let members = cycle
+                .iter()
+                .map(|node| graph.node_weight(*node).unwrap().as_str())
+                .collect::<HashSet<_>>();
+            if self.relaxed
+                && !Self::crosses_exclude(entities, &members)
+                && !Self::has_multi_edge(&graph, cycle)
+            {
+                let mut members = members.into_iter().collect::<Vec<_>>();
+                members.sort();
+                warn!(
+                    "Require cycle of {} entities between {} is satisfied by co-locating its members",
+                    members.len(),
+                    members.join(", ")
+                );
+
+                continue;
+            }
+
             for source_node_index in cycle {
                 let source_name = graph.node_weight(*source_node_index).unwrap();
                 let edges = graph.edges(*source_node_index);
@@ -155,14 +322,30 @@ impl Solver<'_> for RingSolver {
             .filter(|(_, rules)| !rules.is_empty())
             .collect();
 
-        if conflicts.is_empty() {
+        let mut undetermined = BTreeMap::new();
+        for component in truncated.iter() {
+            for node in component {
+                let name = graph.node_weight(*node).unwrap();
+                let reason = format!(
+                    "too many require cycles in its component of {} entities for --max-cycles {}",
+                    component.len(),
+                    self.max_cycles
+                );
+                undetermined.insert(name.clone(), reason);
+            }
+        }
+
+        if !undetermined.is_empty() {
+            let undetermined = Undetermined {
+                entities: undetermined,
+                statistics: [("max_cycles".to_string(), self.max_cycles.to_string())].into(),
+            };
+
+            SolverOutput::Undetermined(undetermined, conflicts, HashMap::new())
+        } else if conflicts.is_empty() {
             SolverOutput::Ok
         } else {
-            SolverOutput::Conflict(conflicts)
+            SolverOutput::Conflict(conflicts, HashMap::new())
         }
     }
-
-    fn set_envs(&self, envs: Vec<crate::model::Env>) {
-        unreachable!()
-    }
 }
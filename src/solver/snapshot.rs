@@ -0,0 +1,406 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    num::NonZeroUsize,
+};
+
+use thiserror::Error;
+
+use crate::model::{
+    Entity, EntityName, EntityPriority, EntityRule, EntityRuleMetadata, EntityRuleSource,
+    EntityRuleType, EntitySource, SourceSpan,
+};
+
+use super::EntityMap;
+
+/*
+    Compact binary snapshot of a model, much faster to write and read back than the IR or
+    YAML dumps of large estates. All integers are LEB128 varints and every string is stored
+    once in a table and referenced by index:
+
+    "DFXS" version strings:[len bytes]* entities:[name source priority rules:[rule]*]*
+    rule = flags(exclude, multi) source targets:[target]* rule_source metadata
+*/
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"DFXS";
+// Bumped on every change of the layout, older snapshots are rejected instead of misread
+pub const SNAPSHOT_VERSION: u64 = 2;
+pub const SNAPSHOT_EXTENSION: &str = "snapshot";
+
+const RULE_EXCLUDE: u64 = 1;
+const RULE_MULTI: u64 = 2;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("Not a deployfix snapshot")]
+    InvalidMagic,
+    #[error("Unsupported snapshot version {0}, expected {SNAPSHOT_VERSION}")]
+    UnsupportedVersion(u64),
+    #[error("Truncated snapshot")]
+    Truncated,
+    #[error("Invalid string in snapshot: {0}")]
+    InvalidString(String),
+    #[error("Invalid {0} {1} in snapshot")]
+    InvalidValue(&'static str, u64),
+}
+
+#[derive(Default)]
+struct Encoder<'a> {
+    strings: Vec<&'a str>,
+    indexes: HashMap<&'a str, u64>,
+    body: Vec<u8>,
+}
+
+impl<'a> Encoder<'a> {
+    fn write(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+
+            if value == 0 {
+                self.body.push(byte);
+                return;
+            }
+            self.body.push(byte | 0x80);
+        }
+    }
+
+    fn write_str(&mut self, value: &'a str) {
+        let next = self.strings.len() as u64;
+        let index = *self.indexes.entry(value).or_insert(next);
+        if index == next {
+            self.strings.push(value);
+        }
+
+        self.write(index);
+    }
+
+    // A 0/1 presence flag, followed by the index of the string when present
+    fn write_opt_str(&mut self, value: Option<&'a str>) {
+        match value {
+            Some(value) => {
+                self.write(1);
+                self.write_str(value);
+            }
+            None => self.write(0),
+        }
+    }
+
+    fn write_rule(&mut self, rule: &'a EntityRule) {
+        let (source, targets, r#type, rule_source, metadata, multi) = match rule {
+            EntityRule::Mono {
+                source,
+                target,
+                r#type,
+                rule_source,
+                metadata,
+            } => (source, vec![target], r#type, rule_source, metadata, false),
+            EntityRule::Multi {
+                source,
+                targets,
+                r#type,
+                rule_source,
+                metadata,
+            } => (
+                source,
+                targets.iter().collect(),
+                r#type,
+                rule_source,
+                metadata,
+                true,
+            ),
+        };
+
+        let mut flags = 0;
+        if r#type == &EntityRuleType::Exclude {
+            flags |= RULE_EXCLUDE;
+        }
+        if multi {
+            flags |= RULE_MULTI;
+        }
+        self.write(flags);
+
+        self.write_str(source.as_ref());
+        self.write(targets.len() as u64);
+        for target in targets {
+            self.write_str(target.as_ref());
+        }
+
+        match rule_source {
+            EntityRuleSource::File(file, line) => {
+                self.write(1);
+                self.write_str(file);
+                self.write(*line as u64);
+            }
+            EntityRuleSource::Span(file, span) => {
+                self.write(2);
+                self.write_str(file);
+                for value in [span.line, span.column, span.index, span.len] {
+                    self.write(value as u64);
+                }
+            }
+            EntityRuleSource::Unknown => self.write(0),
+        }
+
+        match metadata {
+            Some(metadata) => {
+                self.write(1);
+                self.write_opt_str(metadata.file());
+                self.write(metadata.line().unwrap_or(0) as u64);
+
+                // 0 without a map, otherwise the number of pairs plus one
+                match metadata.get_metadata() {
+                    Some(pairs) => {
+                        self.write(pairs.len() as u64 + 1);
+                        for (key, value) in pairs {
+                            self.write_str(key);
+                            self.write_str(value);
+                        }
+                    }
+                    None => self.write(0),
+                }
+            }
+            None => self.write(0),
+        }
+    }
+
+    fn write_entity(&mut self, entity: &'a Entity) {
+        self.write_str(entity.name.as_ref());
+
+        match &entity.source {
+            EntitySource::File(file) => {
+                self.write(1);
+                self.write_str(file);
+            }
+            EntitySource::Unknown => self.write(0),
+        }
+
+        self.write(match entity.priority {
+            EntityPriority::Critical => 0,
+            EntityPriority::High => 1,
+            EntityPriority::Default => 2,
+            EntityPriority::Low => 3,
+        });
+
+        for rules in [&entity.requires, &entity.excludes] {
+            self.write(rules.len() as u64);
+            for rule in rules {
+                self.write_rule(rule);
+            }
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let mut header = Encoder::default();
+        header.body.extend_from_slice(SNAPSHOT_MAGIC);
+        header.write(SNAPSHOT_VERSION);
+
+        header.write(self.strings.len() as u64);
+        for value in &self.strings {
+            header.write(value.len() as u64);
+            header.body.extend_from_slice(value.as_bytes());
+        }
+
+        header.body.extend_from_slice(&self.body);
+        header.body
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    strings: Vec<&'a str>,
+}
+
+impl<'a> Decoder<'a> {
+    fn read(&mut self) -> Result<u64, SnapshotError> {
+        let mut value = 0u64;
+
+        for shift in (0..64).step_by(7) {
+            let (byte, rest) = self.data.split_first().ok_or(SnapshotError::Truncated)?;
+            self.data = rest;
+
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(SnapshotError::InvalidValue("varint", value))
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.data.len() < len {
+            return Err(SnapshotError::Truncated);
+        }
+
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+
+        Ok(bytes)
+    }
+
+    fn read_str(&mut self) -> Result<String, SnapshotError> {
+        let index = self.read()?;
+
+        self.strings
+            .get(index as usize)
+            .map(|s| s.to_string())
+            .ok_or(SnapshotError::InvalidValue("string index", index))
+    }
+
+    fn read_opt_str(&mut self) -> Result<Option<String>, SnapshotError> {
+        match self.read()? {
+            0 => Ok(None),
+            _ => self.read_str().map(Some),
+        }
+    }
+
+    fn read_rule(&mut self) -> Result<EntityRule, SnapshotError> {
+        let flags = self.read()?;
+        let r#type = if flags & RULE_EXCLUDE != 0 {
+            EntityRuleType::Exclude
+        } else {
+            EntityRuleType::Require
+        };
+
+        let source = EntityName::from(self.read_str()?);
+        let targets = (0..self.read()?)
+            .map(|_| self.read_str().map(EntityName::from))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let rule_source = match self.read()? {
+            0 => EntityRuleSource::Unknown,
+            2 => EntityRuleSource::Span(
+                self.read_str()?,
+                SourceSpan {
+                    line: self.read()? as usize,
+                    column: self.read()? as usize,
+                    index: self.read()? as usize,
+                    len: self.read()? as usize,
+                },
+            ),
+            _ => EntityRuleSource::File(self.read_str()?, self.read()? as usize),
+        };
+
+        let metadata = match self.read()? {
+            0 => None,
+            _ => {
+                let file = self.read_opt_str()?;
+                let line = NonZeroUsize::new(self.read()? as usize);
+                let pairs = match self.read()? {
+                    0 => None,
+                    len => Some(
+                        (1..len)
+                            .map(|_| Ok((self.read_str()?, self.read_str()?)))
+                            .collect::<Result<BTreeMap<_, _>, SnapshotError>>()?,
+                    ),
+                };
+
+                Some(EntityRuleMetadata::new(file, line, pairs))
+            }
+        };
+
+        if flags & RULE_MULTI != 0 {
+            Ok(EntityRule::Multi {
+                source,
+                targets: targets.into_iter().collect::<BTreeSet<_>>(),
+                r#type,
+                rule_source,
+                metadata,
+            })
+        } else {
+            let [target]: [EntityName; 1] = targets
+                .try_into()
+                .map_err(|t: Vec<_>| SnapshotError::InvalidValue("target count", t.len() as u64))?;
+
+            Ok(EntityRule::Mono {
+                source,
+                target,
+                r#type,
+                rule_source,
+                metadata,
+            })
+        }
+    }
+
+    fn read_entity(&mut self) -> Result<Entity, SnapshotError> {
+        let name = EntityName::from(self.read_str()?);
+        let source = match self.read()? {
+            0 => EntitySource::Unknown,
+            _ => EntitySource::File(self.read_str()?),
+        };
+        let priority = match self.read()? {
+            0 => EntityPriority::Critical,
+            1 => EntityPriority::High,
+            2 => EntityPriority::Default,
+            3 => EntityPriority::Low,
+            value => return Err(SnapshotError::InvalidValue("priority", value)),
+        };
+
+        let mut rules = [BTreeSet::new(), BTreeSet::new()];
+        for rules in rules.iter_mut() {
+            for _ in 0..self.read()? {
+                rules.insert(self.read_rule()?);
+            }
+        }
+        let [requires, excludes] = rules;
+
+        Ok(Entity {
+            name,
+            requires,
+            excludes,
+            source,
+            priority,
+        })
+    }
+}
+
+pub fn encode_snapshot(entities: &[Entity]) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+
+    encoder.write(entities.len() as u64);
+    for entity in entities {
+        encoder.write_entity(entity);
+    }
+
+    encoder.finish()
+}
+
+pub fn decode_snapshot(data: &[u8]) -> Result<Vec<Entity>, SnapshotError> {
+    let mut decoder = Decoder {
+        data,
+        strings: vec![],
+    };
+
+    if decoder.read_bytes(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+        return Err(SnapshotError::InvalidMagic);
+    }
+
+    let version = decoder.read()?;
+    if version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+
+    for _ in 0..decoder.read()? {
+        let len = decoder.read()? as usize;
+        let bytes = decoder.read_bytes(len)?;
+        let value =
+            std::str::from_utf8(bytes).map_err(|e| SnapshotError::InvalidString(e.to_string()))?;
+
+        decoder.strings.push(value);
+    }
+
+    (0..decoder.read()?)
+        .map(|_| decoder.read_entity())
+        .collect()
+}
+
+impl EntityMap {
+    pub fn to_snapshot(&self) -> Vec<u8> {
+        encode_snapshot(&self.entities_with_hints())
+    }
+
+    pub fn from_snapshot(data: &[u8]) -> anyhow::Result<Self> {
+        let entities = decode_snapshot(data)?;
+
+        Ok(Self::try_from(entities)?)
+    }
+}
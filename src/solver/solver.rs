@@ -1,20 +1,71 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fmt::Display,
     pin::Pin,
-    sync::atomic::AtomicBool,
+    time::{Duration, Instant},
 };
 
+use serde::Serialize;
 use thiserror::Error;
 
 use crate::model::{EntityRule, Env};
 
-use super::{map::EntityMap, ring::RingSolver, unknown::UnknownSolver, z3::Z3Solver};
+use super::{
+    map::EntityMap, ring::RingSolver, structural::StructuralSolver, unknown::UnknownSolver,
+    z3::Z3Solver,
+};
+
+// Names of the solvers which reported each unscheduable entity
+pub type ConflictSolvers = HashMap<String, BTreeSet<String>>;
+
+// Entities a solver could not decide, e.g. when z3 gives up on a timeout
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Undetermined {
+    // Reason given by the solver for each entity
+    pub entities: BTreeMap<String, String>,
+    // Statistics of the solver when it gave up, e.g. `conflicts` or `decisions`
+    pub statistics: BTreeMap<String, String>,
+}
+
+impl Undetermined {
+    pub fn new(entities: impl IntoIterator<Item = (String, String)>) -> Self {
+        Self {
+            entities: entities.into_iter().collect(),
+            statistics: BTreeMap::new(),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum SolverOutput {
     Ok,
-    Conflict(HashMap<String, Vec<EntityRule>>),
+    Conflict(HashMap<String, Vec<EntityRule>>, ConflictSolvers),
+    // Some entities could not be decided, along with the conflicts found for the others
+    Undetermined(
+        Undetermined,
+        HashMap<String, Vec<EntityRule>>,
+        ConflictSolvers,
+    ),
+}
+
+// Conflicts of soft rules only (e.g. preferred affinities) leave their entity schedulable,
+// some of its preferences just cannot all be met
+pub fn is_soft_conflict(rules: &[EntityRule]) -> bool {
+    !rules.is_empty() && rules.iter().all(EntityRule::is_soft)
+}
+
+// Short explanation of why a solver reports a conflict
+pub fn explain_solver(name: &str) -> &'static str {
+    match name {
+        "z3" => "unsat core",
+        "ring" => "cycle detected",
+        "ring-relaxed" => "require cycle co-location cannot satisfy",
+        "unknown" => "unknown reference",
+        "structural" => "requires and excludes the same target",
+        "disruption" => "disruption budget blocks every eviction",
+        "quota" => "namespace pod quota exhausted, not an affinity",
+        _ => "unknown solver",
+    }
 }
 
 impl SolverOutput {
@@ -32,62 +83,201 @@ impl SolverOutput {
             })
             .collect();
 
-        Self::Conflict(conflicts)
+        Self::Conflict(conflicts, HashMap::new())
     }
 
+    // Entities a solver gave up on, without conflicts for the others
+    pub fn new_undetermined(undetermined: Undetermined) -> Self {
+        Self::Undetermined(undetermined, HashMap::new(), HashMap::new())
+    }
+
+    // Record `solver` as the origin of every conflict in this output
+    pub fn with_solver(self, solver: &str) -> Self {
+        let record = |conflicts: &HashMap<String, Vec<EntityRule>>,
+                      solvers: &mut ConflictSolvers| {
+            for name in conflicts.keys() {
+                solvers
+                    .entry(name.clone())
+                    .or_default()
+                    .insert(solver.to_string());
+            }
+        };
+
+        match self {
+            Self::Ok => Self::Ok,
+            Self::Conflict(conflicts, mut solvers) => {
+                record(&conflicts, &mut solvers);
+                Self::Conflict(conflicts, solvers)
+            }
+            Self::Undetermined(undetermined, conflicts, mut solvers) => {
+                record(&conflicts, &mut solvers);
+                Self::Undetermined(undetermined, conflicts, solvers)
+            }
+        }
+    }
+
+    // Splits off the entities only missing preferences, leaving the conflicts of hard rules
+    pub fn take_soft(self) -> (Self, HashMap<String, Vec<EntityRule>>) {
+        let split = |conflicts: HashMap<String, Vec<EntityRule>>, solvers: &mut ConflictSolvers| {
+            let (soft, conflicts): (HashMap<_, _>, HashMap<_, _>) = conflicts
+                .into_iter()
+                .partition(|(_, rules)| is_soft_conflict(rules));
+            solvers.retain(|name, _| conflicts.contains_key(name));
+
+            (conflicts, soft)
+        };
+
+        match self {
+            Self::Ok => (Self::Ok, HashMap::new()),
+            Self::Conflict(conflicts, mut solvers) => match split(conflicts, &mut solvers) {
+                (conflicts, soft) if conflicts.is_empty() => (Self::Ok, soft),
+                (conflicts, soft) => (Self::Conflict(conflicts, solvers), soft),
+            },
+            Self::Undetermined(undetermined, conflicts, mut solvers) => {
+                let (conflicts, soft) = split(conflicts, &mut solvers);
+                (Self::Undetermined(undetermined, conflicts, solvers), soft)
+            }
+        }
+    }
+
+    // Splits off the undetermined entities, leaving the conflicts found for the others
+    pub fn take_undetermined(self) -> (Self, Option<Undetermined>) {
+        match self {
+            Self::Undetermined(undetermined, conflicts, _) if conflicts.is_empty() => {
+                (Self::Ok, Some(undetermined))
+            }
+            Self::Undetermined(undetermined, conflicts, solvers) => {
+                (Self::Conflict(conflicts, solvers), Some(undetermined))
+            }
+            output => (output, None),
+        }
+    }
+
+    /*
+        Combines the outputs of two solvers run on the same entities. Per entity, a conflict
+        wins over undetermined, which wins over ok:
+
+        Ok           + Ok           = Ok
+        Ok           + Conflict     = Conflict
+        Ok           + Undetermined = Undetermined
+        Conflict     + Undetermined = Undetermined with the conflicts, minus the conflicting
+                                      entities from the undetermined ones
+        Conflict     + Conflict     = Conflict
+
+        The conflicting rules of an entity are the union of both outputs, sorted and without
+        duplicates, and so are the solvers reporting it. An output whose conflicts name no
+        entity counts as Ok. Merging is commutative, except that `other` wins when both give
+        a reason or a statistic under the same key.
+    */
     pub fn merge(self, other: Self) -> SolverOutput {
-        match (self, other) {
-            (Self::Ok, Self::Ok) => {
-                // Do nothing
-                SolverOutput::Ok
+        let (this, undetermined) = self.take_undetermined();
+        let (other, other_undetermined) = other.take_undetermined();
+
+        let merged = this.merge_conflicts(other);
+        let mut undetermined = match (undetermined, other_undetermined) {
+            (None, None) => return merged,
+            (undetermined, other) => {
+                let mut undetermined = undetermined.unwrap_or_default();
+                if let Some(other) = other {
+                    undetermined.entities.extend(other.entities);
+                    undetermined.statistics.extend(other.statistics);
+                }
+                undetermined
             }
-            (Self::Ok, Self::Conflict(conflicts)) => SolverOutput::Conflict(conflicts),
-            (Self::Conflict(conflicts), Self::Ok) => {
-                // Do nothing
-                SolverOutput::Conflict(conflicts)
+        };
+
+        match merged {
+            Self::Conflict(conflicts, solvers) => {
+                // An entity with a conflict is decided whatever the other solvers say
+                undetermined
+                    .entities
+                    .retain(|name, _| !conflicts.contains_key(name));
+                Self::Undetermined(undetermined, conflicts, solvers)
             }
-            (Self::Conflict(conflicts), Self::Conflict(other_conflicts)) => {
-                let mut merged_conflicts = conflicts;
+            _ => Self::Undetermined(undetermined, HashMap::new(), HashMap::new()),
+        }
+    }
+
+    fn merge_conflicts(self, other: Self) -> SolverOutput {
+        let merged = match (self, other) {
+            (Self::Ok, other) => other,
+            (this, Self::Ok) => this,
+            (
+                Self::Conflict(mut conflicts, mut solvers),
+                Self::Conflict(other_conflicts, other_solvers),
+            ) => {
                 for (name, rules) in other_conflicts {
-                    if let Some(existing) = merged_conflicts.get_mut(&name) {
-                        existing.extend(rules);
-                        existing.sort();
-                        existing.dedup();
-                    } else {
-                        merged_conflicts.insert(name, rules);
-                    }
+                    conflicts.entry(name).or_default().extend(rules);
+                }
+                for (name, names) in other_solvers {
+                    solvers.entry(name).or_default().extend(names);
                 }
 
-                SolverOutput::Conflict(merged_conflicts)
+                SolverOutput::Conflict(conflicts, solvers)
             }
+            _ => unreachable!("undetermined entities are split off first"),
+        };
+
+        // Solvers build their conflicts as they find them, only `new_conflict` cleans them up
+        match merged {
+            Self::Conflict(conflicts, _) if conflicts.is_empty() => SolverOutput::Ok,
+            Self::Conflict(mut conflicts, solvers) => {
+                for rules in conflicts.values_mut() {
+                    rules.sort();
+                    rules.dedup();
+                }
+
+                SolverOutput::Conflict(conflicts, solvers)
+            }
+            merged => merged,
         }
     }
 
     pub fn is_ok(&self) -> bool {
         match self {
             SolverOutput::Ok => true,
-            SolverOutput::Conflict(_) => false,
+            SolverOutput::Conflict(..) | SolverOutput::Undetermined(..) => false,
         }
     }
 
     pub fn is_conflict(&self) -> bool {
         match self {
             SolverOutput::Ok => false,
-            SolverOutput::Conflict(_) => true,
+            SolverOutput::Conflict(..) => true,
+            SolverOutput::Undetermined(_, conflicts, _) => !conflicts.is_empty(),
         }
     }
 
     pub fn get_unscheduable(&self) -> Option<HashSet<String>> {
         match self {
             SolverOutput::Ok => None,
-            SolverOutput::Conflict(conflicts) => Some(conflicts.keys().cloned().collect()),
+            SolverOutput::Conflict(conflicts, _) | SolverOutput::Undetermined(_, conflicts, _) => {
+                Some(
+                    conflicts
+                        .iter()
+                        .filter(|(_, rules)| !is_soft_conflict(rules))
+                        .map(|(name, _)| name.clone())
+                        .collect(),
+                )
+            }
         }
     }
 
     pub fn get_conflict_rules(&self) -> Option<HashMap<String, Vec<EntityRule>>> {
         match self {
             SolverOutput::Ok => None,
-            SolverOutput::Conflict(conflicts) => Some(conflicts.clone()),
+            SolverOutput::Conflict(conflicts, _) | SolverOutput::Undetermined(_, conflicts, _) => {
+                Some(conflicts.clone())
+            }
+        }
+    }
+
+    pub fn get_conflict_solvers(&self) -> Option<ConflictSolvers> {
+        match self {
+            SolverOutput::Ok => None,
+            SolverOutput::Conflict(_, solvers) | SolverOutput::Undetermined(_, _, solvers) => {
+                Some(solvers.clone())
+            }
         }
     }
 }
@@ -96,9 +286,24 @@ impl Display for SolverOutput {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             SolverOutput::Ok => write!(f, "SolverResult::Ok"),
-            SolverOutput::Conflict(conflicts) => {
+            SolverOutput::Undetermined(undetermined, conflicts, solvers) => {
+                for (name, reason) in undetermined.entities.iter() {
+                    writeln!(f, "Undetermined: {} ({})", name, reason)?;
+                }
+
+                write!(
+                    f,
+                    "{}",
+                    SolverOutput::Conflict(conflicts.clone(), solvers.clone())
+                )
+            }
+            SolverOutput::Conflict(conflicts, solvers) => {
                 for (name, sources) in conflicts.iter() {
                     writeln!(f, "Unscheduable: {}", name)?;
+                    if let Some(solvers) = solvers.get(name) {
+                        let solvers = solvers.iter().cloned().collect::<Vec<_>>();
+                        writeln!(f, "  Found by: {}", solvers.join(", "))?;
+                    }
                     writeln!(f, "  Conflicts: ")?;
                     for source in sources.iter() {
                         writeln!(f, "  {}", source)?;
@@ -119,7 +324,8 @@ pub enum SolverError {
 pub trait Solver<'instance> {
     fn solve(&'instance self, entities: &EntityMap) -> SolverOutput;
 
-    fn set_envs(&'instance self, envs: Vec<Env>);
+    // Only solvers placing entities on nodes read the envs, the others ignore them
+    fn set_envs(&'instance self, _envs: Vec<Env>) {}
 }
 
 pub struct SolverImpl {
@@ -129,7 +335,7 @@ pub struct SolverImpl {
 
 impl SolverImpl {
     pub fn solve(&self, entities: &EntityMap) -> SolverOutput {
-        self.solver.solve(entities)
+        self.solver.solve(entities).with_solver(&self.name)
     }
 
     pub fn set_envs(&self, envs: Vec<Env>) {
@@ -139,10 +345,49 @@ impl SolverImpl {
     }
 }
 
+// Require cycles the ring solver enumerates per strongly connected component of the require
+// graph by default
+pub const DEFAULT_MAX_CYCLES: usize = 10_000;
+
+// Options of the solvers built by `get_solver_with`, given by each caller instead of being
+// shared by the process, so that embedders and the tenants of the server keep their own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SolverOptions {
+    // Makes `ring` behave like `ring-relaxed`
+    pub relaxed_cycles: bool,
+    pub max_cycles: usize,
+    // Time z3 may spend on one check before giving up on the entity, without a limit when none
+    pub timeout: Option<Duration>,
+    // End of the whole solve, the entities z3 has not decided by then are undetermined
+    pub deadline: Option<Instant>,
+    // Turns every default assumed for a missing setting into an error
+    pub strict: bool,
+}
+
+impl Default for SolverOptions {
+    fn default() -> Self {
+        Self {
+            relaxed_cycles: false,
+            max_cycles: DEFAULT_MAX_CYCLES,
+            timeout: None,
+            deadline: None,
+            strict: false,
+        }
+    }
+}
+
+// Names accepted by `get_solver`
+pub static SOLVER_NAMES: &[&str] = &["z3", "ring", "ring-relaxed", "unknown", "structural"];
+
+// Solver of the default options
 pub fn get_solver(name: &str) -> Result<SolverImpl, SolverError> {
+    get_solver_with(name, &SolverOptions::default())
+}
+
+pub fn get_solver_with(name: &str, options: &SolverOptions) -> Result<SolverImpl, SolverError> {
     match name {
         "z3" => {
-            let solver = Z3Solver::new();
+            let solver = Z3Solver::new(options.timeout, options.deadline);
             let solver = unsafe {
                 std::mem::transmute::<Pin<Box<dyn Solver<'_>>>, Pin<Box<dyn for<'a> Solver<'a>>>>(
                     solver,
@@ -154,8 +399,11 @@ pub fn get_solver(name: &str) -> Result<SolverImpl, SolverError> {
                 solver,
             })
         }
-        "ring" => {
-            let solver = Box::pin(RingSolver::new());
+        "ring" | "ring-relaxed" => {
+            let solver = Box::pin(RingSolver::new(
+                name == "ring-relaxed" || options.relaxed_cycles,
+                options.max_cycles,
+            ));
             let solver = unsafe {
                 std::mem::transmute::<Pin<Box<dyn Solver<'_>>>, Pin<Box<dyn for<'a> Solver<'a>>>>(
                     solver,
@@ -180,6 +428,19 @@ pub fn get_solver(name: &str) -> Result<SolverImpl, SolverError> {
                 solver,
             })
         }
+        "structural" => {
+            let solver = Box::pin(StructuralSolver::new());
+            let solver = unsafe {
+                std::mem::transmute::<Pin<Box<dyn Solver<'_>>>, Pin<Box<dyn for<'a> Solver<'a>>>>(
+                    solver,
+                )
+            };
+
+            Ok(SolverImpl {
+                name: name.to_string(),
+                solver,
+            })
+        }
         _ => Err(SolverError::UnknownSolver(name.to_string())),
     }
 }
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use crate::model::EntityRule;
+
+use super::{map::EntityMap, solver::Solver, SolverOutput};
+
+// Cheap pre-filter reporting entities which both require and exclude the same target.
+// Such pairs are unsatisfiable regardless of the rest of the model. Soft rules are left to z3,
+// which reports the preferences it cannot meet apart from the conflicts.
+pub struct StructuralSolver;
+
+impl StructuralSolver {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Solver<'_> for StructuralSolver {
+    fn solve(&self, entities: &EntityMap) -> SolverOutput {
+        let conflicts = entities
+            .entities
+            .iter()
+            .filter_map(|e| {
+                let excludes = e
+                    .excludes
+                    .iter()
+                    .filter(|r| !r.is_soft())
+                    .filter_map(|r| match r {
+                        EntityRule::Mono { target, .. }
+                            if entities.concurrent(e.name.as_ref(), target.as_ref())
+                                && entities.in_scope(r, e.name.as_ref(), target.as_ref()) =>
+                        {
+                            Some((target, r))
+                        }
+                        _ => None,
+                    })
+                    .collect::<HashMap<_, _>>();
+
+                let rules = e
+                    .requires
+                    .iter()
+                    .filter(|r| !r.is_soft())
+                    .filter_map(|r| match r {
+                        EntityRule::Mono { target, .. } => excludes
+                            .get(target)
+                            .map(|exclude| [r.clone(), (*exclude).clone()]),
+                        EntityRule::Multi { .. } => None,
+                    })
+                    .flatten()
+                    .collect::<Vec<_>>();
+
+                if rules.is_empty() {
+                    None
+                } else {
+                    Some((e.name.0.clone(), rules))
+                }
+            })
+            .collect::<HashMap<_, _>>();
+
+        if conflicts.is_empty() {
+            SolverOutput::Ok
+        } else {
+            SolverOutput::Conflict(conflicts, HashMap::new())
+        }
+    }
+}
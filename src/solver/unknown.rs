@@ -1,6 +1,6 @@
 use std::collections::{HashMap, HashSet};
 
-use crate::model::{EntityName, EntityRule, Env};
+use crate::model::{EntityName, EntityRule};
 
 use super::{map::EntityMap, solver::Solver, SolverOutput};
 
@@ -53,11 +53,7 @@ impl Solver<'_> for UnknownSolver {
         if conflicts.is_empty() {
             SolverOutput::Ok
         } else {
-            SolverOutput::Conflict(conflicts)
+            SolverOutput::Conflict(conflicts, HashMap::new())
         }
     }
-
-    fn set_envs(&self, envs: Vec<Env>) {
-        unreachable!()
-    }
 }
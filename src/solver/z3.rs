@@ -1,31 +1,55 @@
 use std::{
     cell::RefCell,
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     pin::Pin,
+    time::{Duration, Instant},
 };
 
 use log::{debug, warn};
-use z3::{Config, Context};
+use petgraph::unionfind::UnionFind;
+use z3::{ast::Ast, Config, Context};
 
-use crate::model::{EntityRule, Env};
+use crate::model::{EntityRule, Env, METADATA_SCALE_OUT_KEY};
 
 use super::{
     map::EntityMap,
-    solver::{self, Solver, SolverOutput},
+    solver::{Solver, SolverOutput, Undetermined},
 };
+
+// Outcome of checking one entity
+enum CheckResult {
+    Sat,
+    // Rules of the unsat core
+    Unsat(Vec<EntityRule>),
+    // Reason z3 gave up, e.g. `timeout` or `canceled`
+    Unknown(String),
+}
 pub struct Z3Solver<'ctx> {
     vars: RefCell<HashMap<String, z3::ast::Bool<'ctx>>>,
-    rule_trackers: RefCell<HashMap<String, z3::ast::Bool<'ctx>>>,
+    // Trackers are named by a unique id of the rule, distinct rules may render the same (e.g.
+    // the same rule of two entities) and sharing a tracker would mix up their unsat cores
+    rule_trackers: RefCell<HashMap<EntityRule, z3::ast::Bool<'ctx>>>,
+    // Rules by the name of their tracker
     rule_mapping: RefCell<HashMap<String, EntityRule>>,
+    // Rules by their rendering, to report the distinct rules rendering the same
+    rule_renderings: RefCell<HashMap<String, EntityRule>>,
+    // Trackers of the soft rules, which only hold when assumed
+    soft_trackers: RefCell<Vec<(z3::ast::Bool<'ctx>, EntityRule)>>,
     self_conflicts: RefCell<HashMap<String, z3::ast::Bool<'ctx>>>,
     ctx: Context,
     envs: RefCell<Option<Vec<Env>>>,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
     _unpin: std::marker::PhantomPinned,
 }
 
 impl<'ctx> Z3Solver<'ctx> {
-    pub fn new() -> Pin<Box<Self>> {
-        let config = Config::new();
+    pub fn new(timeout: Option<Duration>, deadline: Option<Instant>) -> Pin<Box<Self>> {
+        let mut config = Config::new();
+        // Entities z3 gives up on are reported as undetermined
+        if let Some(timeout) = timeout {
+            config.set_timeout_msec(timeout.as_millis() as u64);
+        }
         let cpus = num_cpus::get();
         // enable parallelism
         z3::set_global_param("parallel.enable", "true");
@@ -39,7 +63,11 @@ impl<'ctx> Z3Solver<'ctx> {
             self_conflicts: RefCell::new(HashMap::new()),
             rule_trackers: RefCell::new(HashMap::new()),
             rule_mapping: RefCell::new(HashMap::new()),
+            rule_renderings: RefCell::new(HashMap::new()),
+            soft_trackers: RefCell::new(vec![]),
             envs: RefCell::new(None),
+            timeout,
+            deadline,
             _unpin: std::marker::PhantomPinned,
         };
 
@@ -55,17 +83,33 @@ impl<'ctx> Z3Solver<'ctx> {
     }
 
     fn create_rule_tracker(&'ctx self, rule: &EntityRule) -> z3::ast::Bool<'ctx> {
-        let source_string = format!("{}", rule);
-
         let mut trackers = RefCell::borrow_mut(&self.rule_trackers);
-        let mut mapping = RefCell::borrow_mut(&self.rule_mapping);
+        if let Some(tracker) = trackers.get(rule) {
+            return tracker.clone();
+        }
 
-        mapping.insert(source_string.clone(), rule.clone());
+        let rendering = format!("{}", rule);
+        let mut renderings = RefCell::borrow_mut(&self.rule_renderings);
+        match renderings.get(&rendering) {
+            Some(other) if other != rule => warn!(
+                "Rules of {} and {} both read `{}`, tracking them apart",
+                other.source().as_ref(),
+                rule.source().as_ref(),
+                rendering
+            ),
+            Some(_) => {}
+            None => {
+                renderings.insert(rendering.clone(), rule.clone());
+            }
+        }
 
-        trackers
-            .entry(source_string.clone())
-            .or_insert_with(|| z3::ast::Bool::new_const(&self.ctx, source_string))
-            .clone()
+        // The id keeps the name unique, the rendering keeps the solver dumps readable
+        let name = format!("#{} {}", trackers.len(), rendering);
+        let tracker = z3::ast::Bool::new_const(&self.ctx, name.clone());
+        RefCell::borrow_mut(&self.rule_mapping).insert(name, rule.clone());
+        trackers.insert(rule.clone(), tracker.clone());
+
+        tracker
     }
 
     fn require(&'ctx self, a: &str, b: &str) -> z3::ast::Bool<'ctx> {
@@ -82,6 +126,31 @@ impl<'ctx> Z3Solver<'ctx> {
         z3::ast::Bool::or(&self.ctx, &[&a.not(), &b.not()])
     }
 
+    fn scoped_conflict(
+        &'ctx self,
+        map: &EntityMap,
+        rule: &EntityRule,
+        a: &str,
+        b: &str,
+    ) -> z3::ast::Bool<'ctx> {
+        // Entities running in disjoint time windows never share a node
+        if !map.concurrent(a, b) {
+            debug!(
+                "{} and {} never run at the same time, ignoring {}",
+                a, b, rule
+            );
+            return z3::ast::Bool::from_bool(&self.ctx, true);
+        }
+
+        // Scopes are known from the metadata, pairs of different scopes are never encoded
+        if !map.in_scope(rule, a, b) {
+            debug!("{} and {} are in different scopes, ignoring {}", a, b, rule);
+            return z3::ast::Bool::from_bool(&self.ctx, true);
+        }
+
+        self.conflict(a, b)
+    }
+
     fn track(
         &'ctx self,
         solver: &z3::Solver,
@@ -90,15 +159,299 @@ impl<'ctx> Z3Solver<'ctx> {
     ) {
         let tracker = self.create_rule_tracker(entity_rule);
 
-        solver.assert_and_track(rule, &tracker);
+        // A soft rule is left out of the hard check, its tracker is assumed once the entity
+        // is schedulable to find the preferences it cannot meet
+        if entity_rule.is_soft() {
+            solver.assert(&tracker.implies(rule));
+            RefCell::borrow_mut(&self.soft_trackers).push((tracker, entity_rule.clone()));
+        } else {
+            solver.assert_and_track(rule, &tracker);
+        }
+    }
+
+    // Rules without a valid weight weigh the least
+    fn preference_weight(rule: &EntityRule) -> u64 {
+        rule.weight()
+            .and_then(|weight| weight.parse().ok())
+            .unwrap_or(1)
+    }
+
+    /*
+        Soft rules given up for the current assertions to hold along with `assumptions`. The
+        lightest soft rule of each unsat core is dropped until the others hold, an approximation
+        of the MaxSAT solution keeping the most weight. Empty when every preference is met.
+    */
+    fn unmet_preferences(
+        &'ctx self,
+        solver: &mut z3::Solver<'ctx>,
+        assumptions: &[z3::ast::Bool<'ctx>],
+    ) -> Vec<EntityRule> {
+        let mut kept = RefCell::borrow(&self.soft_trackers).clone();
+        let mut dropped = vec![];
+
+        while !kept.is_empty() {
+            let checked = assumptions
+                .iter()
+                .cloned()
+                .chain(kept.iter().map(|(tracker, _)| tracker.clone()))
+                .collect::<Vec<_>>();
+
+            let core = match self.check_and_get(solver, &checked) {
+                CheckResult::Unsat(core) => core,
+                CheckResult::Sat => break,
+                CheckResult::Unknown(reason) => {
+                    debug!("z3 gave up on the preferences ({}), keeping them", reason);
+                    break;
+                }
+            };
+
+            let lightest = kept
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, rule))| core.contains(rule))
+                .min_by_key(|(_, (_, rule))| Self::preference_weight(rule))
+                .map(|(idx, _)| idx);
+            match lightest {
+                Some(idx) => dropped.push(kept.remove(idx).1),
+                // The hard rules alone hold, a core always has a soft rule
+                None => break,
+            }
+        }
+
+        dropped
+    }
+
+    // Groups the names linked by a rule, labels of different groups cannot constrain each other
+    fn components(map: &EntityMap) -> (HashMap<&str, usize>, HashMap<usize, Vec<&str>>) {
+        let names = map
+            .names
+            .iter()
+            .map(|n| n.as_str())
+            .collect::<BTreeSet<_>>();
+        let index = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (*name, i))
+            .collect::<HashMap<_, _>>();
+
+        let mut union_find = UnionFind::<usize>::new(names.len());
+        for entity in map.entities.iter() {
+            let Some(&a) = index.get(entity.name.as_ref()) else {
+                continue;
+            };
+
+            for rule in entity.requires.iter().chain(entity.excludes.iter()) {
+                let targets = match rule {
+                    EntityRule::Mono { target, .. } => vec![target],
+                    EntityRule::Multi { targets, .. } => targets.iter().collect(),
+                };
+
+                for target in targets {
+                    if let Some(&b) = index.get(target.0.as_str()) {
+                        union_find.union(a, b);
+                    }
+                }
+            }
+        }
+
+        let components = index
+            .into_iter()
+            .map(|(name, i)| (name, union_find.find(i)))
+            .collect::<HashMap<_, _>>();
+
+        let mut members: HashMap<usize, Vec<&str>> = HashMap::new();
+        for (name, component) in components.iter() {
+            members.entry(*component).or_default().push(name);
+        }
+
+        (components, members)
+    }
+
+    // Resolves the labels placed on each env to solver variables, once for all checked names
+    fn env_vars<'a>(
+        &'ctx self,
+        map: &EntityMap,
+        envs: &'a [Env],
+    ) -> Vec<(&'a str, HashSet<String>)> {
+        let vars = RefCell::borrow(&self.vars);
+
+        envs.iter()
+            .map(|env| {
+                let mut placed = HashSet::new();
+
+                for label in &env.labels {
+                    if map.self_conflicts.contains(label) {
+                        let var1 = format!("{}_1", label);
+                        let var2 = format!("{}_2", label);
+
+                        if vars.contains_key(&var1) && vars.contains_key(&var2) {
+                            placed.insert(var1);
+                            placed.insert(var2);
+                        } else {
+                            warn!("No variable for {}, skipping...", label);
+                        }
+                    } else if vars.contains_key(label) {
+                        placed.insert(label.clone());
+                    } else {
+                        warn!("No variable for {}, skipping...", label);
+                    }
+                }
+
+                (env.name.as_str(), placed)
+            })
+            .collect()
+    }
+
+    // Labels fixed when placing `name` on an env: the labels of the env hold and the other
+    // labels of the components they touch do not. Only those components can become
+    // unsatisfiable, every other one holds with all labels false.
+    fn env_assumptions<'a>(
+        components: &HashMap<&'a str, usize>,
+        members: &HashMap<usize, Vec<&'a str>>,
+        name: &'a str,
+        placed: &'a HashSet<String>,
+    ) -> BTreeMap<&'a str, bool> {
+        let scope = placed
+            .iter()
+            .map(|label| label.as_str())
+            .chain(std::iter::once(name))
+            .filter_map(|label| components.get(label))
+            .collect::<BTreeSet<_>>();
+
+        let mut assumptions = placed
+            .iter()
+            .map(|label| (label.as_str(), true))
+            .collect::<BTreeMap<_, _>>();
+        for label in scope.into_iter().flat_map(|c| &members[c]) {
+            if *label != name && !placed.contains(*label) {
+                assumptions.insert(label, false);
+            }
+        }
+
+        assumptions
     }
 
-    fn check_and_get(&'ctx self, solver: &mut z3::Solver) -> Option<Vec<EntityRule>> {
-        match solver.check() {
+    // A self anti-affinity spreads the replicas of an entity over distinct nodes, so the envs
+    // able to host one replica need as many nodes as there are replicas
+    fn check_capacity(
+        &'ctx self,
+        solver: &mut z3::Solver<'ctx>,
+        map: &EntityMap,
+        envs: &[Env],
+        env_vars: &[(&str, HashSet<String>)],
+        components: &HashMap<&str, usize>,
+        members: &HashMap<usize, Vec<&str>>,
+    ) -> HashMap<String, Vec<EntityRule>> {
+        let vars = RefCell::borrow(&self.vars);
+        let mut conflicts = HashMap::new();
+
+        for (name, replicas) in map.spread_entities() {
+            let (first, second) = (format!("{}_1", name), format!("{}_2", name));
+            let Some(var) = vars.get(&first) else {
+                continue;
+            };
+
+            solver.push();
+            solver.assert(var);
+
+            let capacity = envs
+                .iter()
+                .zip(env_vars.iter())
+                .filter(|(_, (_, placed))| {
+                    let assumptions = Self::env_assumptions(components, members, &first, placed)
+                        .into_iter()
+                        .map(|(label, value)| match value {
+                            true => vars[label].clone(),
+                            false => vars[label].not(),
+                        })
+                        .collect::<Vec<_>>();
+
+                    self.limit(solver)
+                        && solver.check_assumptions(&assumptions) == z3::SatResult::Sat
+                })
+                .map(|(env, _)| env.capacity())
+                .sum::<usize>();
+
+            solver.pop(1u32);
+
+            // Without any node the entity is already reported as unschedulable
+            if capacity == 0 || capacity >= replicas {
+                continue;
+            }
+
+            // An autoscaled entity whose minimum fits only conflicts once it scales out
+            let scale_out = map
+                .min_replicas
+                .get(name)
+                .is_some_and(|min_replicas| capacity >= *min_replicas);
+            if scale_out {
+                warn!(
+                    "{} replicas of {} at the maximum of its autoscaler need distinct nodes, only {} can host them",
+                    replicas, name, capacity
+                );
+            } else {
+                warn!(
+                    "{} replicas of {} need distinct nodes, only {} can host them",
+                    replicas, name, capacity
+                );
+            }
+            let rules = map
+                .entities
+                .iter()
+                .filter(|e| e.name.as_ref() == first)
+                .flat_map(|e| e.excludes.iter())
+                .filter(|r| r.is_in_target(&second))
+                .cloned()
+                .map(|mut rule| {
+                    if scale_out {
+                        rule.add_metadata(METADATA_SCALE_OUT_KEY.to_string(), "true".to_string());
+                    }
+                    rule
+                })
+                .collect::<Vec<_>>();
+
+            conflicts.insert(name.to_string(), rules);
+        }
+
+        conflicts
+    }
+
+    // Bounds the next check by the time left before the deadline, false once it passed
+    fn limit(&'ctx self, solver: &z3::Solver<'ctx>) -> bool {
+        let Some(deadline) = self.deadline else {
+            return true;
+        };
+
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return false;
+        }
+
+        let left = self.timeout.map_or(left, |timeout| timeout.min(left));
+        let mut params = z3::Params::new(&self.ctx);
+        params.set_u32(
+            "timeout",
+            left.as_millis().clamp(1, u32::MAX as u128) as u32,
+        );
+        solver.set_params(&params);
+
+        true
+    }
+
+    fn check_and_get(
+        &'ctx self,
+        solver: &mut z3::Solver<'ctx>,
+        assumptions: &[z3::ast::Bool<'ctx>],
+    ) -> CheckResult {
+        if !self.limit(solver) {
+            return CheckResult::Unknown("timeout".to_string());
+        }
+
+        match solver.check_assumptions(assumptions) {
             z3::SatResult::Sat => {
                 debug!("Solver result: {:?}", solver.get_model());
 
-                None
+                CheckResult::Sat
             }
             z3::SatResult::Unsat => {
                 let unsat_core = solver
@@ -141,10 +494,10 @@ impl<'ctx> Z3Solver<'ctx> {
                     debug!("Self-conflict assumptions: {:?}", output);
                 }
 
-                Some(unsat_core)
+                CheckResult::Unsat(unsat_core)
             }
             z3::SatResult::Unknown => {
-                unreachable!()
+                CheckResult::Unknown(solver.get_reason_unknown().unwrap_or_default())
             }
         }
     }
@@ -180,13 +533,13 @@ impl<'ctx> Solver<'ctx> for Z3Solver<'ctx> {
             for exclude in excludes.iter() {
                 match exclude {
                     EntityRule::Mono { target: rule, .. } => {
-                        let rule = self.conflict(name, &rule.0);
+                        let rule = self.scoped_conflict(map, exclude, name, &rule.0);
                         self.track(&solver, &rule, exclude);
                     }
                     EntityRule::Multi { targets: rules, .. } => {
                         let rules = rules
                             .iter()
-                            .map(|r| self.conflict(name, &r.0))
+                            .map(|r| self.scoped_conflict(map, exclude, name, &r.0))
                             .collect::<Vec<_>>();
 
                         let rule = z3::ast::Bool::and(&self.ctx, &rules.iter().collect::<Vec<_>>());
@@ -196,11 +549,16 @@ impl<'ctx> Solver<'ctx> for Z3Solver<'ctx> {
             }
         }
 
-        let ret: HashMap<String, Vec<EntityRule>> = map
+        let (components, members) = Self::components(map);
+        let envs = RefCell::borrow(&self.envs);
+        let env_vars = envs.as_ref().map(|envs| self.env_vars(map, envs));
+        let mut undetermined = BTreeMap::new();
+
+        let mut ret: HashMap<String, Vec<EntityRule>> = map
             .names
             .iter()
             .filter_map(|name| {
-                let vars = RefCell::borrow_mut(&self.vars);
+                let vars = RefCell::borrow(&self.vars);
                 let var = match vars.get(name) {
                     Some(var) => var,
                     None => {
@@ -216,64 +574,77 @@ impl<'ctx> Solver<'ctx> for Z3Solver<'ctx> {
 
                 debug!("Considering {}: {:?}", name, solver.to_string());
 
-                // if we have envs, we need to assert them
-                let envs = RefCell::borrow(&self.envs);
-                let result = match envs.as_ref() {
-                    Some(envs) => {
+                // if we have envs, we need to assume them
+                let result = match env_vars.as_ref() {
+                    Some(env_vars) => {
                         let mut results = HashSet::new();
+                        let mut checked = HashSet::new();
+                        // Preferences given up on the env keeping the most weight
+                        let mut unmet: Option<Vec<EntityRule>> = None;
 
-                        for env in envs {
-                            debug!("Cosidering env: {:?}", env.name);
-
-                            solver.push();
-
-                            let labels = &env.labels;
-                            for label in labels {
-                                if map.self_conflicts.contains(label) {
-                                    let var1 = vars.get(format!("{}_1", label).as_str());
-                                    let var2 = vars.get(format!("{}_2", label).as_str());
-
-                                    match (var1, var2) {
-                                        (Some(var1), Some(var2)) => {
-                                            solver.assert(var1);
-                                            solver.assert(var2);
-                                        }
-                                        _ => {
-                                            warn!("No variable for {}, skipping...", label);
-                                        }
-                                    }
-                                } else if let Some(var) = vars.get(label) {
-                                    solver.assert(var);
-                                } else {
-                                    warn!("No variable for {}, skipping...", label);
-                                }
-                            }
+                        for (env, placed) in env_vars {
+                            debug!("Cosidering env: {:?}", env);
 
-                            for label in &map.names {
-                                if labels.contains(label) || name == label {
-                                    continue;
-                                }
+                            let assumptions =
+                                Self::env_assumptions(&components, &members, name, placed);
 
-                                let var = vars.get(label).unwrap();
-                                solver.assert(&var.not());
+                            // Envs that look the same to this name share one check
+                            if !checked.insert(assumptions.clone()) {
+                                continue;
                             }
 
-                            let result = self.check_and_get(&mut solver);
-                            match result {
-                                Some(r) => results.extend(r),
-                                None => return None,
-                            }
+                            let assumptions = assumptions
+                                .into_iter()
+                                .map(|(label, value)| match value {
+                                    true => vars[label].clone(),
+                                    false => vars[label].not(),
+                                })
+                                .collect::<Vec<_>>();
+
+                            match self.check_and_get(&mut solver, &assumptions) {
+                                CheckResult::Unsat(r) => results.extend(r),
+                                CheckResult::Sat => {
+                                    let dropped = self.unmet_preferences(&mut solver, &assumptions);
+                                    if dropped.is_empty() {
+                                        solver.pop(1u32);
+                                        return None;
+                                    }
 
-                            solver.pop(1u32);
+                                    let weight = |rules: &[EntityRule]| {
+                                        rules.iter().map(Self::preference_weight).sum::<u64>()
+                                    };
+                                    if unmet
+                                        .as_ref()
+                                        .map_or(true, |unmet| weight(&dropped) < weight(unmet))
+                                    {
+                                        unmet = Some(dropped);
+                                    }
+                                }
+                                // The entity may fit on this env, it cannot be reported
+                                CheckResult::Unknown(reason) => {
+                                    undetermined.insert(name.clone(), reason);
+                                    solver.pop(1u32);
+                                    return None;
+                                }
+                            }
                         }
 
-                        if results.is_empty() {
-                            return None;
+                        // Schedulable on some env, only its preferences are reported
+                        match unmet {
+                            Some(unmet) => Some(unmet),
+                            None if results.is_empty() => None,
+                            None => Some(results.into_iter().collect::<Vec<_>>()),
                         }
-
-                        Some(results.into_iter().collect::<Vec<_>>())
                     }
-                    None => self.check_and_get(&mut solver),
+                    None => match self.check_and_get(&mut solver, &[]) {
+                        CheckResult::Unsat(r) => Some(r),
+                        CheckResult::Sat => Some(self.unmet_preferences(&mut solver, &[]))
+                            .filter(|unmet| !unmet.is_empty()),
+                        CheckResult::Unknown(reason) => {
+                            undetermined.insert(name.clone(), reason);
+                            None
+                        }
+                    },
                 };
 
                 solver.pop(1u32);
@@ -312,10 +683,71 @@ impl<'ctx> Solver<'ctx> for Z3Solver<'ctx> {
                 acc
             });
 
-        match ret.len() {
+        if let (Some(envs), Some(env_vars)) = (envs.as_ref(), env_vars.as_ref()) {
+            let capacity =
+                self.check_capacity(&mut solver, map, envs, env_vars, &components, &members);
+
+            for (name, rules) in capacity {
+                let existing = ret.entry(name).or_default();
+                for rule in rules {
+                    if !existing.contains(&rule) {
+                        existing.push(rule);
+                    }
+                }
+            }
+        }
+
+        let output = match ret.len() {
             0 => SolverOutput::Ok,
-            _ => SolverOutput::Conflict(ret),
+            _ => SolverOutput::Conflict(ret, HashMap::new()),
+        };
+        if undetermined.is_empty() {
+            return output;
         }
+
+        warn!(
+            "z3 could not decide {} entities: {:?}",
+            undetermined.len(),
+            undetermined
+        );
+        // Self-conflicting entities are checked as `name_1` and `name_2`
+        let entities = undetermined
+            .into_iter()
+            .map(|(name, reason)| {
+                let name = match name.strip_suffix("_1").or(name.strip_suffix("_2")) {
+                    Some(base) if map.self_conflicts.contains(base) => base.to_string(),
+                    _ => name,
+                };
+                let reason = if reason.is_empty() {
+                    "unknown".to_string()
+                } else {
+                    reason
+                };
+
+                (name, reason)
+            })
+            .collect();
+        let statistics = solver
+            .get_statistics()
+            .entries()
+            .map(|entry| {
+                let value = match entry.value {
+                    z3::StatisticsValue::UInt(value) => value.to_string(),
+                    z3::StatisticsValue::Double(value) => value.to_string(),
+                };
+
+                (entry.key, value)
+            })
+            .collect();
+
+        output.merge(SolverOutput::Undetermined(
+            Undetermined {
+                entities,
+                statistics,
+            },
+            HashMap::new(),
+            HashMap::new(),
+        ))
     }
 
     fn set_envs(&'ctx self, envs: Vec<Env>) {
@@ -0,0 +1,67 @@
+use rand::{rngs::SmallRng, seq::index, Rng, SeedableRng};
+
+use crate::model::{Entity, EntityRule, EntityRuleSource, EntityRuleType};
+
+// Builds a random model of `entities` apps named app0..appN, modeled after the random graphs
+// in tests/advance.rs. `density` is the fraction of ordered app pairs connected by a rule,
+// `exclude_ratio` the fraction of those rules which are exclusions, and `self_conflicts` apps
+// additionally get both self-affinity and self-anti-affinity.
+pub fn generate_entities(
+    entities: u32,
+    density: f64,
+    exclude_ratio: f64,
+    self_conflicts: u32,
+    seed: u64,
+) -> Vec<Entity> {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut generated = (0..entities)
+        .map(|i| Entity::new(&format!("app{}", i)))
+        .collect::<Vec<_>>();
+
+    if entities == 0 {
+        return generated;
+    }
+
+    // Ordered pairs of distinct apps, each drawn at most once so `density` is exact
+    let n = entities as usize;
+    let pairs = n * (n - 1);
+    let edges = (pairs as f64 * density.clamp(0.0, 1.0)).round() as usize;
+
+    for pair in index::sample(&mut rng, pairs, edges.min(pairs)) {
+        let a = pair / (n - 1);
+        let b = pair % (n - 1);
+        let b = if b >= a { b + 1 } else { b };
+
+        let r#type = if rng.gen_bool(exclude_ratio.clamp(0.0, 1.0)) {
+            EntityRuleType::Exclude
+        } else {
+            EntityRuleType::Require
+        };
+
+        add_rule(&mut generated[a], &format!("app{}", b), r#type);
+    }
+
+    for a in index::sample(&mut rng, n, (self_conflicts as usize).min(n)) {
+        let name = generated[a].name.0.clone();
+
+        add_rule(&mut generated[a], &name, EntityRuleType::Require);
+        add_rule(&mut generated[a], &name, EntityRuleType::Exclude);
+    }
+
+    generated
+}
+
+fn add_rule(entity: &mut Entity, target: &str, r#type: EntityRuleType) {
+    let rule = EntityRule::mono(
+        entity.name.clone(),
+        target.into(),
+        r#type.clone(),
+        EntityRuleSource::Unknown,
+        None,
+    );
+
+    match r#type {
+        EntityRuleType::Require => entity.add_require(rule),
+        EntityRuleType::Exclude => entity.add_exclude(rule),
+    }
+}
@@ -0,0 +1,325 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use anyhow::Context;
+use thiserror::Error;
+
+// Minimal plain http:// client, enough to talk to webhooks and cluster REST APIs
+#[derive(Debug, Clone)]
+pub struct HttpUrl {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl HttpUrl {
+    pub fn parse(url: &str) -> anyhow::Result<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .with_context(|| format!("Only http:// URLs are supported, got {}", url))?;
+
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .with_context(|| format!("Invalid port in URL {}", url))?,
+            ),
+            None => (authority, 80),
+        };
+
+        if host.is_empty() {
+            anyhow::bail!("Missing host in URL {}", url);
+        }
+
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    // Appends `path` to the path of the URL, e.g. the RM address and `/ws/v1/cluster/nodes`
+    pub fn join(&self, path: &str) -> Self {
+        Self {
+            path: format!("{}{}", self.path.trim_end_matches('/'), path),
+            ..self.clone()
+        }
+    }
+}
+
+impl std::fmt::Display for HttpUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+// An unreachable or stalled server fails the request instead of blocking the run
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn connect(url: &HttpUrl) -> anyhow::Result<TcpStream> {
+    let mut last = None;
+    for address in (url.host.as_str(), url.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(READ_TIMEOUT))?;
+                stream.set_write_timeout(Some(READ_TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(err) => last = Some(err),
+        }
+    }
+
+    match last {
+        Some(err) => Err(err.into()),
+        None => anyhow::bail!("No address found for {}", url.host),
+    }
+}
+
+// Sends the raw request and returns the status code and body of the response. HTTP/1.0 is
+// used so that servers answer without chunked encoding.
+fn request(
+    url: &HttpUrl,
+    method: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> anyhow::Result<(u16, String)> {
+    let mut stream =
+        connect(url).with_context(|| format!("Failed to connect to {}:{}", url.host, url.port))?;
+
+    write!(
+        stream,
+        "{} {} HTTP/1.0\r\nHost: {}\r\n",
+        method, url.path, url.host
+    )?;
+    for (key, value) in headers {
+        write!(stream, "{}: {}\r\n", key, value)?;
+    }
+    write!(
+        stream,
+        "Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )?;
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .with_context(|| format!("Failed to read the response of {}", url))?;
+
+    let status = response
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse::<u16>().ok())
+        .with_context(|| format!("Invalid response from {}", url))?;
+
+    let body = response
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body.to_string())
+        .unwrap_or_default();
+
+    Ok((status, body))
+}
+
+pub fn get(url: &HttpUrl) -> anyhow::Result<String> {
+    let (status, body) = request(url, "GET", &[("Accept", "application/json")], "")?;
+
+    if !(200..300).contains(&status) {
+        anyhow::bail!("GET {} failed with status {}", url, status);
+    }
+
+    Ok(body)
+}
+
+pub fn post(url: &HttpUrl, headers: &[(&str, &str)], body: &str) -> anyhow::Result<u16> {
+    request(url, "POST", headers, body).map(|(status, _)| status)
+}
+
+// Largest request body the server side accepts
+pub const MAX_BODY_SIZE: usize = 64 << 20;
+// Longest request or header line, and most header lines, the server side reads
+pub const MAX_LINE_SIZE: usize = 8 << 10;
+pub const MAX_HEADERS: usize = 100;
+
+// A request refused by the server side before it is handled
+#[derive(Debug, Error)]
+pub enum RequestError {
+    #[error("Request line longer than {} bytes", MAX_LINE_SIZE)]
+    LineTooLong,
+    #[error(
+        "Request headers exceed {} lines of {} bytes",
+        MAX_HEADERS,
+        MAX_LINE_SIZE
+    )]
+    HeadersTooLarge,
+    #[error("Request body of {0} bytes is too large")]
+    BodyTooLarge(usize),
+    #[error(transparent)]
+    Invalid(#[from] anyhow::Error),
+}
+
+impl RequestError {
+    // Status answered to the client
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::HeadersTooLarge => 431,
+            Self::BodyTooLarge(_) => 413,
+            Self::LineTooLong | Self::Invalid(_) => 400,
+        }
+    }
+}
+
+// Request read by the server side, header names are lowercased
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|v| v.as_str())
+    }
+
+    // Token of an `Authorization: Bearer <token>` header
+    pub fn bearer_token(&self) -> Option<&str> {
+        self.header("authorization")?
+            .strip_prefix("Bearer ")
+            .map(|t| t.trim())
+    }
+}
+
+fn parse_request_line(line: &str) -> anyhow::Result<(String, String)> {
+    let mut parts = line.split_whitespace();
+
+    match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => Ok((method.to_string(), path.to_string())),
+        _ => anyhow::bail!("Invalid request line {:?}", line.trim_end()),
+    }
+}
+
+fn parse_header(line: &str, headers: &mut HashMap<String, String>) {
+    if let Some((key, value)) = line.split_once(':') {
+        headers.insert(key.trim().to_lowercase(), value.trim().to_string());
+    }
+}
+
+fn content_length(headers: &HashMap<String, String>) -> Result<usize, RequestError> {
+    let length = match headers.get("content-length") {
+        Some(length) => length
+            .parse::<usize>()
+            .with_context(|| format!("Invalid Content-Length {}", length))?,
+        None => 0,
+    };
+    if length > MAX_BODY_SIZE {
+        return Err(RequestError::BodyTooLarge(length));
+    }
+
+    Ok(length)
+}
+
+// A line of at most `MAX_LINE_SIZE` bytes, `None` when it is longer
+async fn read_line<R>(reader: &mut R) -> anyhow::Result<Option<String>>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+{
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+
+    let mut line = String::new();
+    reader
+        .take(MAX_LINE_SIZE as u64 + 1)
+        .read_line(&mut line)
+        .await?;
+
+    Ok((line.len() <= MAX_LINE_SIZE).then_some(line))
+}
+
+pub async fn read_request<S>(stream: &mut S) -> Result<Request, RequestError>
+where
+    S: tokio::io::AsyncRead + Unpin,
+{
+    use tokio::io::AsyncReadExt;
+
+    let mut reader = tokio::io::BufReader::new(stream);
+
+    let line = read_line(&mut reader)
+        .await?
+        .ok_or(RequestError::LineTooLong)?;
+    let (method, path) = parse_request_line(&line)?;
+
+    let mut headers = HashMap::new();
+    for count in 0.. {
+        let line = read_line(&mut reader)
+            .await?
+            .ok_or(RequestError::HeadersTooLarge)?;
+        if line.trim_end().is_empty() {
+            break;
+        }
+        if count == MAX_HEADERS {
+            return Err(RequestError::HeadersTooLarge);
+        }
+
+        parse_header(&line, &mut headers);
+    }
+
+    let mut body = vec![0; content_length(&headers)?];
+    reader
+        .read_exact(&mut body)
+        .await
+        .context("Failed to read the request body")?;
+
+    Ok(Request {
+        method,
+        path,
+        headers,
+        body: String::from_utf8(body).context("Request body is not UTF-8")?,
+    })
+}
+
+fn format_response(status: u16, body: &str) -> String {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "",
+    };
+
+    format!(
+        "HTTP/1.0 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    )
+}
+
+pub async fn write_response<S>(stream: &mut S, status: u16, body: &str) -> anyhow::Result<()>
+where
+    S: tokio::io::AsyncWrite + Unpin,
+{
+    use tokio::io::AsyncWriteExt;
+
+    stream
+        .write_all(format_response(status, body).as_bytes())
+        .await?;
+    stream.flush().await?;
+
+    Ok(())
+}
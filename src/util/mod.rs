@@ -1,15 +1,60 @@
+mod generate;
+pub mod http;
+mod text;
+
 use std::collections::{BTreeSet, HashMap, HashSet};
 
 use log::{debug, warn};
 
-use crate::model::{Entity, EntityRule, EntityRuleType};
+use crate::model::{rule_location, rule_text, Entity, EntityRule, EntityRuleType};
+
+pub use generate::generate_entities;
+pub use text::{decode_source, read_source};
+
+// Compares every byte, the time taken does not tell how much of a forged signature or token
+// matched
+pub fn same_secret(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+// Reports a default assumed for a missing setting of `rule`, fails when `strict` for teams
+// which want fully explicit configurations
+pub fn assume_default(rule: &EntityRule, message: String, strict: bool) -> anyhow::Result<()> {
+    let location = rule_location(rule).unwrap_or_else(|| "unknown location".to_string());
+    if strict {
+        anyhow::bail!("{}: {} (rejected by --strict)", location, message);
+    }
+
+    warn!("{}: {}", location, message);
+    Ok(())
+}
 
 pub fn split_by_metadata(
     entities: &[Entity],
     meta_key: &str,
     default_meta_key: &str,
-) -> HashMap<String, Vec<Entity>> {
-    entities
+    strict: bool,
+) -> anyhow::Result<HashMap<String, Vec<Entity>>> {
+    for rule in entities.iter().flat_map(|entity| entity.rules()) {
+        if rule.metadata(meta_key).is_none() {
+            assume_default(
+                rule,
+                format!(
+                    "Missing `{}` for rule `{}`, assuming the default value {}",
+                    meta_key,
+                    rule_text(rule),
+                    default_meta_key
+                ),
+                strict,
+            )?;
+        }
+    }
+
+    Ok(entities
         .iter()
         .map(|entity| {
             let requires = &entity.requires;
@@ -18,17 +63,7 @@ pub fn split_by_metadata(
             let require_topo = requires.iter().fold(HashMap::new(), |mut acc, rule| {
                 let key = rule.metadata(meta_key);
 
-                let key = match key {
-                    Some(key) => key,
-                    None => {
-                        warn!(
-                            "Missing `{}` for rule {:?}, assuming the default value {}",
-                            meta_key, rule, default_meta_key
-                        );
-
-                        default_meta_key
-                    }
-                };
+                let key = key.unwrap_or(default_meta_key);
 
                 let rules: &mut Vec<EntityRule> = acc.entry(key.to_string()).or_default();
                 rules.push(rule.clone());
@@ -39,17 +74,7 @@ pub fn split_by_metadata(
             let conflict_topo = conflicts.iter().fold(HashMap::new(), |mut acc, rule| {
                 let key = rule.metadata(meta_key);
 
-                let key = match key {
-                    Some(key) => key,
-                    None => {
-                        warn!(
-                            "Missing `{}` for rule {:?}, assuming the default value {}",
-                            meta_key, rule, default_meta_key
-                        );
-
-                        default_meta_key
-                    }
-                };
+                let key = key.unwrap_or(default_meta_key);
 
                 let rules: &mut Vec<EntityRule> = acc.entry(key.to_string()).or_default();
                 rules.push(rule.clone());
@@ -104,7 +129,7 @@ pub fn split_by_metadata(
             }
 
             acc
-        })
+        }))
 }
 
 pub fn rule_set_to_entity_set(rules: Vec<EntityRule>) -> Vec<Entity> {
@@ -0,0 +1,53 @@
+use std::path::Path;
+
+use log::warn;
+
+const UTF8_BOM: &[u8] = &[0xef, 0xbb, 0xbf];
+const UTF16_LE_BOM: &[u8] = &[0xff, 0xfe];
+const UTF16_BE_BOM: &[u8] = &[0xfe, 0xff];
+
+// Text of a file exported by another tool, decoded like `decode_source`
+pub fn read_source(path: impl AsRef<Path>) -> std::io::Result<String> {
+    let path = path.as_ref();
+
+    Ok(decode_source(std::fs::read(path)?, path))
+}
+
+// Text of `data` with `\n` line endings and without a byte order mark. UTF-16 is decoded from
+// its mark, bytes which are not UTF-8 (e.g. Latin-1 comments) are replaced with a warning.
+// Parsers and annotations read files through here, the offsets of one match the other.
+pub fn decode_source(data: Vec<u8>, path: &Path) -> String {
+    let utf16 = |data: &[u8], unit: fn([u8; 2]) -> u16| {
+        let units = data
+            .chunks_exact(2)
+            .map(|pair| unit([pair[0], pair[1]]))
+            .collect::<Vec<_>>();
+        String::from_utf16_lossy(&units)
+    };
+
+    let text = if let Some(data) = data.strip_prefix(UTF8_BOM) {
+        String::from_utf8_lossy(data).into_owned()
+    } else if let Some(data) = data.strip_prefix(UTF16_LE_BOM) {
+        utf16(data, u16::from_le_bytes)
+    } else if let Some(data) = data.strip_prefix(UTF16_BE_BOM) {
+        utf16(data, u16::from_be_bytes)
+    } else {
+        match String::from_utf8(data) {
+            Ok(text) => text,
+            Err(err) => {
+                warn!(
+                    "{} is not valid UTF-8 at byte {}, replacing the invalid bytes",
+                    path.display(),
+                    err.utf8_error().valid_up_to()
+                );
+                String::from_utf8_lossy(err.as_bytes()).into_owned()
+            }
+        }
+    };
+
+    if text.contains('\r') {
+        text.replace("\r\n", "\n")
+    } else {
+        text
+    }
+}
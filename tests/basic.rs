@@ -1,8 +1,24 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use deployfix::{
-    model::{Entity, EntityName, EntityRule, EntityRuleSource, EntityRuleType, EntitySource},
-    solver::{self, get_solver, SolverOutput},
+    cli::{
+        analyze, diff_outputs, format_output, init_logger, stamp, AnalysisEvent, Certificate,
+        CertificateError, Config, ConflictAnnotater, ConflictReport, LogFormat, OutputFormat,
+        OutputLevel, RunOptions, Session, SessionOptions, SourceCache, SourceDigests, StaleSource,
+        Workspace, MAX_ANNOTATED_FILE_BYTES, OUTPUT_VERSION, WORKSPACE_FILE,
+    },
+    model::{
+        apply_layers, get_parser, merge_entities, DefaultEnvParser, Entity, EntityName,
+        EntityPriority, EntityRule, EntityRuleSource, EntityRuleType, EntitySource, EnvParser,
+        LayerDirective, RuleLifecycle, TimeWindows,
+    },
+    plugin::{read_ir_entities, unique_inputs},
+    solver::{
+        self, cluster_conflicts, conflict_slices, diagnose_multi_rule,
+        fixtures::{self, rule},
+        get_solver, get_solver_with, impact_scores, ImpactWeights, SolveCache, SolverOptions,
+        SolverOutput, TargetDiagnosis, Undetermined, DEFAULT_MAX_CYCLES,
+    },
 };
 use either::Either;
 use log::debug;
@@ -35,10 +51,13 @@ fn solve(entities: Vec<Entity>) -> bool {
             debug!("No conflict found");
             true
         }
-        SolverOutput::Conflict(conflicts) => {
+        SolverOutput::Conflict(conflicts, _) => {
             debug!("Conflicts found: {:?}", conflicts);
             false
         }
+        SolverOutput::Undetermined(undetermined, ..) => {
+            panic!("Undetermined entities: {:?}", undetermined)
+        }
     }
 }
 
@@ -287,3 +306,1461 @@ fn test_circular_dependencies() {
 
     assert!(!solve(entities));
 }
+
+/*
+    app1 require app2
+    app2 require app1
+    Expected: satisfied by co-locating app1 and app2 when cycles are relaxed
+*/
+#[test]
+fn test_circular_dependencies_relaxed() {
+    let entities = vec![
+        new_with_mono_rules("app1", vec!["app2"], vec![]),
+        new_with_mono_rules("app2", vec!["app1"], vec![]),
+    ];
+    let entity_map = entities.try_into().unwrap();
+
+    assert!(!get_solver("ring-relaxed")
+        .unwrap()
+        .solve(&entity_map)
+        .is_conflict());
+}
+
+/*
+    app1 require app2
+    app2 require app3
+    app3 require app1
+    app3 exclude app1
+*/
+#[test]
+fn test_circular_dependencies_crossing_exclude() {
+    let entities = vec![
+        new_with_mono_rules("app1", vec!["app2"], vec![]),
+        new_with_mono_rules("app2", vec!["app3"], vec![]),
+        new_with_mono_rules("app3", vec!["app1"], vec!["app1"]),
+    ];
+    let entity_map = entities.try_into().unwrap();
+
+    assert!(get_solver("ring-relaxed")
+        .unwrap()
+        .solve(&entity_map)
+        .is_conflict());
+}
+
+fn parse_ir(data: &str) -> Vec<Entity> {
+    get_parser("deployfix")
+        .unwrap()
+        .parse(data, EntitySource::Unknown)
+        .unwrap()
+}
+
+/*
+    app1 require app2 // tenant=a
+    app1 exclude app2 // scope_key=tenant;tenant=a
+    app2 require app3 // tenant=b
+    Expected: satisfiable, the exclusion does not cross tenants
+*/
+#[test]
+fn test_tenant_scoped_anti_affinity_across_tenants() {
+    let entities = parse_ir(
+        "app1 require app2 //tenant=a;\n\
+         app1 exclude app2 //scope_key=tenant;tenant=a;\n\
+         app2 require app3 //tenant=b;\n",
+    );
+
+    assert!(solve(entities));
+}
+
+/*
+    app1 require app2 // tenant=a
+    app1 exclude app2 // scope_key=tenant;tenant=a
+    app2 require app3 // tenant=a
+    Expected: unsatisfiable
+*/
+#[test]
+fn test_tenant_scoped_anti_affinity_within_tenant() {
+    let entities = parse_ir(
+        "app1 require app2 //tenant=a;\n\
+         app1 exclude app2 //scope_key=tenant;tenant=a;\n\
+         app2 require app3 //tenant=a;\n",
+    );
+
+    assert!(!solve(entities));
+}
+
+/*
+    app1 require app2 // tenant=a
+    app1 exclude app2 // scope_key=tenant;tenant=a
+    app2 declares no rule, so no tenant
+    Expected: satisfiable for z3 and the structural check, app2 is in no tenant
+*/
+#[test]
+fn test_tenant_scoped_anti_affinity_unscoped_target() {
+    let entities = parse_ir(
+        "app1 require app2 //tenant=a;\n\
+         app1 exclude app2 //scope_key=tenant;tenant=a;\n",
+    );
+
+    let map: solver::EntityMap = entities.clone().try_into().unwrap();
+    assert!(matches!(
+        get_solver("structural").unwrap().solve(&map),
+        SolverOutput::Ok
+    ));
+    assert!(solve(entities));
+}
+
+/*
+    app1 require app2 // window=01:00-03:00
+    app1 exclude app2 // window=01:00-03:00
+    app2 require app3 // window=22:00-01:00, then 02:30-04:00
+    Expected: satisfiable only when the windows of app1 and app2 do not overlap
+*/
+#[test]
+fn test_disjoint_windows_do_not_conflict() {
+    let model = |window: &str| {
+        parse_ir(&format!(
+            "app1 require app2 //window=01:00-03:00;\n\
+             app1 exclude app2 //window=01:00-03:00;\n\
+             app2 require app3 //window={};\n",
+            window
+        ))
+    };
+
+    assert!(solve(model("22:00-01:00")));
+    assert!(!solve(model("02:30-04:00")));
+
+    // A nightly CronJob running for 90 minutes, in the ranges written to the IR
+    let nightly = TimeWindows::from_cron("30 23 * * *", 90).unwrap();
+    assert_eq!(nightly.to_string(), "00:00-01:00,23:30-24:00");
+    assert!(!nightly.overlaps(&TimeWindows::parse("01:00-03:00").unwrap()));
+}
+
+/*
+    app=web exclude app=web // replicas=3
+    app=web require node=ssd
+    pool: ssd 2 node=ssd;  then  pool: ssd 3 node=ssd;
+    Expected: the three replicas only fit when the ssd pool has three nodes
+*/
+#[test]
+fn test_node_pool_capacity_for_spread_replicas() {
+    let solve_with_pool = |size: usize| {
+        let entities = parse_ir(
+            "app=web exclude app=web //replicas=3;\n\
+             app=web require node=ssd //replicas=3;\n",
+        );
+        let envs = DefaultEnvParser {}
+            .parse(&format!("pool: ssd {} node=ssd;\nnode3 node=hdd;\n", size))
+            .unwrap();
+        assert_eq!(
+            envs.iter().map(|env| env.capacity()).sum::<usize>(),
+            size + 1
+        );
+
+        let solver = get_solver("z3").unwrap();
+        solver.set_envs(envs);
+        solver.solve(&entities.try_into().unwrap())
+    };
+
+    match solve_with_pool(2) {
+        SolverOutput::Conflict(conflicts, _) => {
+            assert!(conflicts["app=web"]
+                .iter()
+                .any(|rule| rule.r#type() == EntityRuleType::Exclude));
+        }
+        output => panic!("Expected the ssd pool to be too small, got {}", output),
+    }
+    assert!(!solve_with_pool(3).is_conflict());
+}
+
+/*
+    app=web exclude app=web // min_replicas=2; max_replicas=4
+    pool: ssd 3 node=ssd
+    Expected: the two replicas of the minimum fit, the four of the maximum do not and the
+    conflict is marked as a scale-out one
+*/
+#[test]
+fn test_autoscaled_replicas_conflict_at_scale_out() {
+    let entities = parse_ir("app=web exclude app=web //min_replicas=2;max_replicas=4;\n");
+    let envs = DefaultEnvParser {}
+        .parse("pool: ssd 3 node=ssd;\n")
+        .unwrap();
+
+    let solver = get_solver("z3").unwrap();
+    solver.set_envs(envs);
+
+    match solver.solve(&entities.try_into().unwrap()) {
+        SolverOutput::Conflict(conflicts, _) => {
+            let rule = &conflicts["app=web"][0];
+            assert!(rule.is_scale_out());
+            assert_eq!(
+                (rule.min_replicas(), rule.max_replicas()),
+                (Some(2), Some(4))
+            );
+        }
+        output => panic!("Expected the maximum not to fit, got {}", output),
+    }
+}
+
+/*
+    app1 require and exclude app2, the exclude deprecated
+    app3 require and exclude app4, the require proposed
+    app5 require and exclude app6
+    Expected: only the conflict of app5 fails the run unless proposed rules are gated too
+*/
+#[test]
+fn test_rule_lifecycle_gates_conflicts() {
+    let entities = parse_ir(
+        "app1 exclude app2 //lifecycle=Deprecated;\n\
+         app1 require app2\n\
+         app3 require app4 //lifecycle=proposed;\n\
+         app3 exclude app4\n\
+         app5 require app6 //lifecycle=enforcd;\n\
+         app5 exclude app6\n",
+    );
+    assert_eq!(
+        entities
+            .iter()
+            .flat_map(Entity::rules)
+            .map(EntityRule::lifecycle)
+            .collect::<BTreeSet<_>>(),
+        BTreeSet::from([
+            RuleLifecycle::Deprecated,
+            RuleLifecycle::Proposed,
+            RuleLifecycle::Enforced
+        ])
+    );
+
+    let solvers = HashMap::new();
+    let conflicts = get_solver("z3")
+        .unwrap()
+        .solve(&entities.try_into().unwrap())
+        .get_conflict_rules()
+        .unwrap();
+    let only = |names: &[&str]| {
+        conflicts
+            .iter()
+            .filter(|(name, _)| names.contains(&name.as_str()))
+            .map(|(name, rules)| (name.clone(), rules.clone()))
+            .collect::<HashMap<_, _>>()
+    };
+    let report = |gates: &[RuleLifecycle], names: &[&str]| {
+        let mut report =
+            ConflictReport::new(OutputLevel::Quiet).with_gates(gates.iter().copied().collect());
+        report.add(&only(names), &solvers, &[]);
+        report
+    };
+
+    let enforced = report(&[RuleLifecycle::Enforced], &["app1", "app3"]);
+    assert!(!enforced.has_conflict());
+    assert!(enforced.totals().contains("from deprecated rules"));
+    assert!(enforced.totals().contains("from proposed rules"));
+
+    // A typo in the state keeps the rule enforced
+    assert!(report(&[RuleLifecycle::Enforced], &["app5"]).has_conflict());
+
+    let proposed = report(
+        &[RuleLifecycle::Enforced, RuleLifecycle::Proposed],
+        &["app1", "app3"],
+    );
+    assert!(proposed.has_conflict());
+    assert!(!proposed.totals().contains("from proposed rules"));
+}
+
+/*
+    app=a require app=b, app=c require app=a, app=e require and exclude app=f,
+    app=g require app=b
+    New policy: app=a exclude app=b
+    Expected: app=a is broken directly, app=c through its require, app=e was already broken
+*/
+#[test]
+fn test_blast_radius_of_a_new_policy() {
+    let entities = parse_ir(
+        "app=a require app=b\napp=c require app=a\napp=e require app=f\napp=e exclude app=f\napp=a exclude app=b\napp=g require app=b\n",
+    );
+    let solve = |entities: Vec<Entity>| {
+        get_solver("z3")
+            .unwrap()
+            .solve(&entities.try_into().unwrap())
+    };
+
+    let radius = solver::blast_radius(&entities, "app=a", solve).unwrap();
+    assert_eq!(radius.direct.keys().collect::<Vec<_>>(), ["app=a"]);
+    assert_eq!(radius.transitive.keys().collect::<Vec<_>>(), ["app=c"]);
+    assert_eq!(radius.unchanged, BTreeSet::from(["app=e".to_string()]));
+    assert!(radius
+        .to_string()
+        .contains("makes 2 entities unschedulable"));
+
+    // app=c requires the broken app=a, it is only broken by its own rule
+    let radius = solver::blast_radius(&entities, "app=c", solve).unwrap();
+    assert_eq!(radius.direct.keys().collect::<Vec<_>>(), ["app=c"]);
+    assert!(radius.unchanged.contains("app=a"));
+
+    let radius = solver::blast_radius(&entities, "app=g", solve).unwrap();
+    assert!(radius.is_empty());
+
+    assert!(solver::blast_radius(&entities, "app=x", solve).is_err());
+}
+
+/*
+    app1 require app2
+    app2 require app1
+    app2 exclude app1
+    Expected: the conflict is attributed to the ring solver
+*/
+#[test]
+fn test_conflict_records_solver() {
+    let entities = vec![
+        new_with_mono_rules("app1", vec!["app2"], vec![]),
+        new_with_mono_rules("app2", vec!["app1"], vec!["app1"]),
+    ];
+
+    let entity_map = entities.try_into().unwrap();
+
+    let z3_result = get_solver("z3").unwrap().solve(&entity_map);
+    let ring_result = get_solver("ring").unwrap().solve(&entity_map);
+    let result = z3_result.merge(ring_result);
+
+    let solvers = result.get_conflict_solvers().unwrap();
+    assert!(solvers.get("app1").unwrap().contains("ring"));
+}
+
+/*
+    app1 require app2, app2 exclude app1    (conflict)
+    app3 exclude app3, app3 require app3    (conflict)
+    app5 require app6
+    Expected: one standalone slice per conflict, each reproducing it alone
+*/
+#[test]
+fn test_conflict_slices_reproduce_each_conflict() {
+    let parser = get_parser("deployfix").unwrap();
+    let data = "app1 require app2\napp2 exclude app1\napp3 exclude app3\napp3 require app3\n\
+                app5 require app6\n";
+    let entities = parser
+        .parse(data, EntitySource::File("model.ir".to_string()))
+        .unwrap();
+
+    let entity_map = (&entities).try_into().unwrap();
+    let result = get_solver("z3").unwrap().solve(&entity_map);
+    let solvers = result.get_conflict_solvers().unwrap();
+    let SolverOutput::Conflict(conflicts, _) = result else {
+        panic!("Expected a conflict");
+    };
+
+    let slices = conflict_slices(&entities, &conflicts, &solvers);
+    let names = slices
+        .iter()
+        .map(|slice| {
+            slice
+                .entities
+                .iter()
+                .map(|e| e.name.as_ref())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec![vec!["app1", "app2"], vec!["app3"]]);
+
+    for slice in slices {
+        let ir = slice.to_ir();
+        assert!(ir.contains("// Declared at model.ir:"), "{}", ir);
+
+        let entities = parser.parse(&ir, EntitySource::Unknown).unwrap();
+        assert!(!solve(entities), "{}", ir);
+    }
+}
+
+/*
+    x exclude y, a1..a3 require x and y     (conflicts sharing `x exclude y`)
+    c require c, c exclude c                (conflict of its own)
+    Expected: two clusters, `x exclude y` the root of the conflicts of a1, a2 and a3
+*/
+#[test]
+fn test_cluster_conflicts_finds_root_rules() {
+    let parser = get_parser("deployfix").unwrap();
+    let data = "x exclude y\na1 require x\na1 require y\na2 require x\na2 require y\n\
+                a3 require x\na3 require y\nc require c\nc exclude c\n";
+    let entities = parser
+        .parse(data, EntitySource::File("model.ir".to_string()))
+        .unwrap();
+
+    let entity_map = (&entities).try_into().unwrap();
+    let SolverOutput::Conflict(conflicts, _) = get_solver("z3").unwrap().solve(&entity_map) else {
+        panic!("Expected a conflict");
+    };
+
+    let clusters = cluster_conflicts(&conflicts);
+    assert_eq!(clusters.len(), 2);
+    assert_eq!(
+        clusters[0].entities,
+        BTreeSet::from(["a1", "a2", "a3"].map(String::from))
+    );
+
+    let (root, count) = &clusters[0].roots[0];
+    assert_eq!(root.source().as_ref(), "x");
+    assert_eq!(root.r#type(), EntityRuleType::Exclude);
+    assert_eq!(*count, 3);
+    assert!(clusters[1].roots.is_empty());
+}
+
+/*
+    app1 require app2, app1 exclude app2    (conflict)
+    app3 require app4                       (changed between the runs)
+    Expected: the second run only solves the component of app3
+*/
+#[test]
+fn test_solve_cache_reuses_unchanged_components() {
+    let z3 = |entities: Vec<Entity>| {
+        let entity_map = entities.try_into().unwrap();
+        get_solver("z3").unwrap().solve(&entity_map)
+    };
+
+    let entities = vec![
+        new_with_mono_rules("app1", vec!["app2"], vec!["app2"]),
+        new_with_mono_rules("app3", vec!["app4"], vec![]),
+    ];
+    let (result, cache) =
+        SolveCache::default().solve(solver::components(&entities), "", |entities| {
+            assert_eq!(entities.len(), 2);
+            z3(entities)
+        });
+    assert!(result.is_conflict());
+
+    let entities = vec![
+        new_with_mono_rules("app1", vec!["app2"], vec!["app2"]),
+        new_with_mono_rules("app3", vec!["app4", "app5"], vec![]),
+    ];
+    let (result, _) = cache.solve(solver::components(&entities), "", |entities| {
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities[0].name.0, "app3");
+        z3(entities)
+    });
+
+    match result {
+        SolverOutput::Conflict(conflicts, _) => {
+            assert_eq!(conflicts.keys().collect::<Vec<_>>(), vec!["app1"])
+        }
+        output => panic!("cached conflict of app1 was lost, got {}", output),
+    }
+}
+
+#[test]
+fn test_init_logger_keeps_existing_logger() {
+    // The logger installed by `init` stays in place, repeated calls do not panic
+    assert!(!init_logger(None, LogFormat::Json));
+    assert!(!init_logger(None, LogFormat::Text));
+}
+
+#[test]
+fn test_diagnose_multi_rule_alternatives() {
+    let data = "a require b,c,d,e,x\na exclude b\na require f\nf exclude c\nd require a\ne require g\ng exclude e\n";
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(data, EntitySource::Unknown)
+        .unwrap();
+    let entities = deployfix::model::merge_entities(entities, None);
+
+    let rule = entities
+        .iter()
+        .flat_map(|e| e.requires.iter())
+        .find(|r| matches!(r, EntityRule::Multi { .. }))
+        .unwrap();
+    let conflicts = [("e".to_string(), vec![])].into_iter().collect();
+
+    assert_eq!(
+        diagnose_multi_rule(&entities, &conflicts, rule),
+        vec![
+            (
+                "b".to_string(),
+                TargetDiagnosis::Excluded("a".to_string(), "b".to_string())
+            ),
+            (
+                "c".to_string(),
+                TargetDiagnosis::Excluded("f".to_string(), "c".to_string())
+            ),
+            ("d".to_string(), TargetDiagnosis::Cyclic),
+            ("e".to_string(), TargetDiagnosis::Unschedulable),
+            ("x".to_string(), TargetDiagnosis::Unknown),
+        ]
+    );
+}
+
+/*
+    app1 exclude app2
+    app1 require app2, app2 require app1    (network hints)
+    Expected: only the ring solver sees the hints, their cycle crosses the exclusion
+*/
+#[test]
+fn test_network_hints_only_feed_ring_solver() {
+    let data = "app1 exclude app2\napp1 require app2 // hint=network;\n\
+                app2 require app1 // hint=network;\n";
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(data, EntitySource::Unknown)
+        .unwrap();
+
+    let entity_map: solver::EntityMap = (&entities).try_into().unwrap();
+    assert_eq!(entity_map.hints.len(), 2);
+    assert!(entity_map.entities.iter().all(|e| e.requires.is_empty()));
+
+    assert!(!get_solver("z3").unwrap().solve(&entity_map).is_conflict());
+
+    let SolverOutput::Conflict(conflicts, _) = get_solver("ring").unwrap().solve(&entity_map)
+    else {
+        panic!("Expected a conflict");
+    };
+    let mut names = conflicts.keys().collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec!["app1", "app2"]);
+}
+
+/*
+    app2 require app1, app3 require app2    (app1 has two dependents)
+    app4 exclude app1                       (critical, three replicas)
+    Expected: scores add up the weighted priority tiers, replicas and dependents
+*/
+#[test]
+fn test_impact_scores_weigh_priority_replicas_and_dependents() {
+    let data = "app2 require app1\napp3 require app2\napp4 exclude app1 // replicas=3;\n";
+    let mut entities = get_parser("deployfix")
+        .unwrap()
+        .parse(data, EntitySource::Unknown)
+        .unwrap();
+    entities
+        .iter_mut()
+        .find(|e| e.name.as_ref() == "app4")
+        .unwrap()
+        .priority = EntityPriority::Critical;
+
+    let names = ["app1".to_string(), "app4".to_string()];
+    let scores = impact_scores(&entities, names.iter(), &ImpactWeights::default());
+
+    assert_eq!(scores["app1"].dependents, 2);
+    assert_eq!(scores["app1"].replicas, 1);
+    assert_eq!(scores["app1"].score, 10.0 + 1.0 + 4.0);
+    assert_eq!(scores["app4"].dependents, 0);
+    assert_eq!(scores["app4"].replicas, 3);
+    assert_eq!(scores["app4"].score, 30.0 + 3.0);
+
+    let weights = ImpactWeights {
+        priority: 0.0,
+        replicas: 0.0,
+        dependents: 1.0,
+    };
+    let scores = impact_scores(&entities, names.iter(), &weights);
+    assert!(scores["app1"].score > scores["app4"].score);
+}
+
+/*
+    app1 exclude app1 (conflict), app1 and app2 undetermined by another solver
+    Expected: the conflict decides app1, app2 stays undetermined
+*/
+#[test]
+fn test_undetermined_entities_survive_merge() {
+    let entity = new_with_mono_rules("app1", vec![], vec!["app1"]);
+    let rule = entity.excludes.iter().next().unwrap().clone();
+    let conflict =
+        SolverOutput::new_conflict([("app1".to_string(), vec![rule])].into_iter().collect())
+            .with_solver("structural");
+
+    let undetermined = Undetermined {
+        entities: [
+            ("app1".to_string(), "timeout".to_string()),
+            ("app2".to_string(), "timeout".to_string()),
+        ]
+        .into_iter()
+        .collect(),
+        statistics: [("conflicts".to_string(), "42".to_string())]
+            .into_iter()
+            .collect(),
+    };
+    let undetermined =
+        SolverOutput::Undetermined(undetermined, HashMap::new(), HashMap::new()).with_solver("z3");
+    assert!(!undetermined.is_ok());
+    assert!(!undetermined.is_conflict());
+
+    let merged = conflict.merge(undetermined);
+    assert!(merged.is_conflict());
+
+    let (merged, undetermined) = merged.take_undetermined();
+    let undetermined = undetermined.unwrap();
+    assert_eq!(
+        undetermined.entities.keys().collect::<Vec<_>>(),
+        vec!["app2"]
+    );
+    assert_eq!(undetermined.statistics["conflicts"], "42");
+    assert_eq!(
+        merged.get_conflict_solvers().unwrap()["app1"],
+        BTreeSet::from(["structural".to_string()])
+    );
+
+    let (ok, undetermined) = SolverOutput::Ok
+        .merge(SolverOutput::Undetermined(
+            Undetermined::default(),
+            HashMap::new(),
+            HashMap::new(),
+        ))
+        .take_undetermined();
+    assert!(ok.is_ok());
+    assert!(undetermined.is_some());
+}
+
+/*
+    a require b, b exclude a solved by z3 after the deadline of the solve passed
+    Expected: z3 checks nothing, a and b are undetermined on a timeout
+*/
+#[test]
+fn test_z3_gives_up_after_deadline() {
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse("a require b\nb exclude a\n", EntitySource::Unknown)
+        .unwrap();
+    let entity_map = entities.try_into().unwrap();
+
+    let options = SolverOptions {
+        deadline: Some(std::time::Instant::now()),
+        ..Default::default()
+    };
+    let output = get_solver_with("z3", &options).unwrap().solve(&entity_map);
+
+    let (output, undetermined) = output.take_undetermined();
+    assert!(!output.is_conflict());
+    let undetermined = undetermined.unwrap();
+    assert_eq!(
+        undetermined.entities,
+        BTreeMap::from([
+            ("a".to_string(), "timeout".to_string()),
+            ("b".to_string(), "timeout".to_string()),
+        ])
+    );
+}
+
+// A solver output with the entities it reports as conflicting and undetermined
+struct MergeFixture {
+    output: SolverOutput,
+    conflicts: BTreeSet<&'static str>,
+    undetermined: BTreeSet<&'static str>,
+}
+
+fn merge_fixtures() -> Vec<MergeFixture> {
+    let exclude = |source: &str, target: &str| rule(source, EntityRuleType::Exclude, &[target], 1);
+    let fixture =
+        |output, conflicts: &[&'static str], undetermined: &[&'static str]| MergeFixture {
+            output,
+            conflicts: conflicts.iter().copied().collect(),
+            undetermined: undetermined.iter().copied().collect(),
+        };
+
+    vec![
+        fixture(SolverOutput::Ok, &[], &[]),
+        fixture(fixtures::conflict("ring", &[]), &[], &[]),
+        fixture(
+            fixtures::conflict("ring", &[("a", vec![exclude("a", "a")])]),
+            &["a"],
+            &[],
+        ),
+        fixture(
+            fixtures::conflict("z3", &[("b", vec![exclude("b", "c")])]),
+            &["b"],
+            &[],
+        ),
+        fixture(
+            fixtures::undetermined(&[("a", "timeout")], &[]),
+            &[],
+            &["a"],
+        ),
+        fixture(
+            fixtures::conflict("z3", &[("c", vec![exclude("c", "b")])])
+                .merge(fixtures::undetermined(&[("b", "timeout")], &[])),
+            &["c"],
+            &["b"],
+        ),
+    ]
+}
+
+/*
+    Every pair of ok, empty conflict, conflicts, undetermined and mixed outputs merged
+    Expected: a conflict wins over undetermined, which wins over ok, in both merge orders
+*/
+#[test]
+fn test_merge_precedence_of_every_output_pair() {
+    let count = merge_fixtures().len();
+
+    for i in 0..count {
+        for j in 0..count {
+            let left = merge_fixtures().swap_remove(i);
+            let right = merge_fixtures().swap_remove(j);
+            let is_undetermined =
+                |output: &SolverOutput| matches!(output, SolverOutput::Undetermined(..));
+            let undetermined_input =
+                is_undetermined(&left.output) || is_undetermined(&right.output);
+
+            let conflicts = left
+                .conflicts
+                .union(&right.conflicts)
+                .map(|name| name.to_string())
+                .collect::<BTreeSet<_>>();
+            let undetermined = left
+                .undetermined
+                .union(&right.undetermined)
+                .map(|name| name.to_string())
+                .filter(|name| !conflicts.contains(name))
+                .collect::<BTreeSet<_>>();
+
+            let merged = left.output.merge(right.output);
+            assert_eq!(merged.is_conflict(), !conflicts.is_empty(), "{} + {}", i, j);
+            assert_eq!(
+                merged.is_ok(),
+                conflicts.is_empty() && !undetermined_input,
+                "{} + {}",
+                i,
+                j
+            );
+            assert_eq!(
+                merged
+                    .get_unscheduable()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect::<BTreeSet<_>>(),
+                conflicts,
+                "{} + {}",
+                i,
+                j
+            );
+
+            let (rest, taken) = merged.take_undetermined();
+            assert!(!is_undetermined(&rest));
+            assert_eq!(taken.is_some(), undetermined_input, "{} + {}", i, j);
+            assert_eq!(
+                taken
+                    .map(|taken| taken.entities.into_keys().collect::<BTreeSet<_>>())
+                    .unwrap_or_default(),
+                undetermined,
+                "{} + {}",
+                i,
+                j
+            );
+        }
+    }
+}
+
+/*
+    a: [r2, r1, r1] by ring merged with a: [r1], b: [r2, r2] by z3, and b: [r2, r2] alone
+    Expected: the rules of each entity sorted and deduplicated, the solvers of both kept
+*/
+#[test]
+fn test_merge_dedups_conflicting_rules_and_solvers() {
+    let r1 = rule("a", EntityRuleType::Exclude, &["b"], 1);
+    let r2 = rule("a", EntityRuleType::Require, &["b", "c"], 2);
+    let mut sorted = vec![r1.clone(), r2.clone()];
+    sorted.sort();
+
+    let merged = fixtures::conflict("ring", &[("a", vec![r2.clone(), r1.clone(), r1.clone()])])
+        .merge(fixtures::conflict(
+            "z3",
+            &[("a", vec![r1.clone()]), ("b", vec![r2.clone(), r2.clone()])],
+        ));
+
+    let rules = merged.get_conflict_rules().unwrap();
+    assert_eq!(rules["a"], sorted);
+    assert_eq!(rules["b"], vec![r2.clone()]);
+
+    let solvers = merged.get_conflict_solvers().unwrap();
+    assert_eq!(
+        solvers["a"],
+        BTreeSet::from(["ring".to_string(), "z3".to_string()])
+    );
+    assert_eq!(solvers["b"], BTreeSet::from(["z3".to_string()]));
+
+    let alone = SolverOutput::Ok.merge(fixtures::conflict("ring", &[("b", vec![r2.clone(), r2])]));
+    assert_eq!(alone.get_conflict_rules().unwrap()["b"].len(), 1);
+}
+
+/*
+    a undetermined by two solvers with different reasons and statistics
+    Expected: the reason and statistics of the second output are kept
+*/
+#[test]
+fn test_merge_keeps_the_reasons_of_the_second_output() {
+    let merged =
+        fixtures::undetermined(&[("a", "timeout"), ("b", "timeout")], &[("conflicts", "1")]).merge(
+            fixtures::undetermined(&[("a", "memout")], &[("conflicts", "2")]),
+        );
+
+    let (_, undetermined) = merged.take_undetermined();
+    let undetermined = undetermined.unwrap();
+    assert_eq!(undetermined.entities["a"], "memout");
+    assert_eq!(undetermined.entities["b"], "timeout");
+    assert_eq!(undetermined.statistics["conflicts"], "2");
+}
+
+/*
+    base.ir: app1 require app2, app2 exclude app1, app3 exclude app4   (conflict)
+    team.ir: override app1 require app5, remove app2 exclude *, remove app6 require app7
+    Expected: the team layer resolves the conflict and records what it replaced
+*/
+#[test]
+fn test_layers_override_and_remove_earlier_rules() {
+    let parser = get_parser("deployfix").unwrap();
+    let base = parser
+        .parse(
+            "app1 require app2\napp2 exclude app1\napp3 exclude app4\n",
+            EntitySource::File("base.ir".to_string()),
+        )
+        .unwrap();
+    let team = parser
+        .parse(
+            "override app1 require app5\nremove app2 exclude *\nremove app6 require app7\n",
+            EntitySource::File("team.ir".to_string()),
+        )
+        .unwrap();
+    assert!(!solve(merge_entities(base.clone(), None)));
+
+    let (entities, changes) = apply_layers(vec![base, team]);
+    let entities = merge_entities(entities, None);
+    assert!(entities.iter().all(|e| e.name.as_ref() != "app6"));
+    assert!(entities
+        .iter()
+        .flat_map(|e| e.rules())
+        .all(|rule| rule.layer().is_none()));
+
+    let changes = changes
+        .iter()
+        .map(|change| {
+            (
+                change.directive,
+                change.rule.location.clone().unwrap(),
+                change
+                    .replaced
+                    .iter()
+                    .map(|r| r.location.clone().unwrap())
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        changes,
+        vec![
+            (
+                LayerDirective::Override,
+                "team.ir:1".to_string(),
+                vec!["base.ir:1".to_string()]
+            ),
+            (
+                LayerDirective::Remove,
+                "team.ir:2".to_string(),
+                vec!["base.ir:2".to_string()]
+            ),
+            (LayerDirective::Remove, "team.ir:3".to_string(), vec![]),
+        ]
+    );
+
+    let app1 = entities.iter().find(|e| e.name.as_ref() == "app1").unwrap();
+    assert!(app1.requires.iter().all(|rule| rule.is_in_target("app5")));
+    assert!(solve(entities));
+}
+
+/*
+    app1 exclude app2 declared in a small manifest with a very long line, and in a manifest
+    over the annotation limit
+    Expected: a capped snippet for the first, a summary line without snippet for the second
+*/
+#[test]
+fn test_annotater_caps_snippets_and_summarizes_large_files() {
+    let dir = std::env::temp_dir().join(format!("deployfix-annotate-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let small = dir.join("small.yaml");
+    let long_line = format!("  labels: {}", "x".repeat(10_000));
+    let lines = (1..=100)
+        .map(|i| {
+            if i == 5 {
+                long_line.clone()
+            } else {
+                format!("line{}", i)
+            }
+        })
+        .collect::<Vec<_>>();
+    std::fs::write(&small, lines.join("\n")).unwrap();
+
+    let large = dir.join("large.yaml");
+    std::fs::write(&large, "x".repeat(MAX_ANNOTATED_FILE_BYTES as usize + 1)).unwrap();
+
+    let sources = SourceCache::new();
+    for (file, large) in [(&small, false), (&large, true)] {
+        let entities = parse_ir(&format!(
+            "app1 exclude app2 // File={};Line=4;\n",
+            file.display()
+        ));
+        let rule = entities[0].excludes.iter().next().unwrap();
+
+        let annotater = ConflictAnnotater::new_with_cache("app1", rule, &sources);
+        let output = annotater.annotate();
+        if large {
+            assert!(annotater.get_source().is_empty());
+            assert!(output.contains("annotation limit"), "{}", output);
+        } else {
+            assert!(annotater.get_source().lines().count() <= 16);
+            assert!(annotater.get_source().len() < 2_000);
+            assert!(annotater.get_source().contains("line4"));
+        }
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/*
+    A workspace file listing two projects and global policies under a subdirectory
+    Expected: paths resolved relative to the workspace file, duplicate project names rejected
+*/
+#[test]
+fn test_workspace_resolves_paths_and_rejects_duplicate_projects() {
+    let dir = std::env::temp_dir().join(format!("deployfix-workspace-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join(WORKSPACE_FILE);
+    std::fs::write(
+        &path,
+        "projects:\n  - name: web\n    paths: [web/]\n    format: k8s\n    domain_key: tenant\n  - name: db\n    paths: [db.ir]\nglobal: [policies/]\n",
+    )
+    .unwrap();
+
+    let workspace = Workspace::load(&path).unwrap();
+    assert_eq!(workspace.projects.len(), 2);
+    assert_eq!(workspace.projects[0].paths, vec![dir.join("web/")]);
+    assert_eq!(workspace.projects[0].domain_key.as_deref(), Some("tenant"));
+    assert_eq!(workspace.projects[1].default_domain, "default");
+    assert_eq!(workspace.global, vec![dir.join("policies/")]);
+
+    std::fs::write(
+        &path,
+        "projects:\n  - name: web\n    paths: [a.ir]\n  - name: web\n    paths: [b.ir]\n",
+    )
+    .unwrap();
+    assert!(Workspace::load(&path).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/*
+    a require b, b exclude a checked with the session outputs recorded, written and loaded back
+    Expected: the replay reports the recorded conflicts, a changed record is reported as a diff
+*/
+#[test]
+fn test_session_export_replays_recorded_conflicts() {
+    let dir = std::env::temp_dir().join(format!("deployfix-session-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let model = dir.join("model.ir");
+    std::fs::write(&model, "a require b\nb exclude a\n").unwrap();
+    let entities = parse_ir(&std::fs::read_to_string(&model).unwrap());
+
+    let options = SessionOptions {
+        format: None,
+        domain: None,
+        default_domain_key: None,
+        cycle_check: true,
+        fast_fail: false,
+        tags: Default::default(),
+        budget: serde_json::from_str(r#"{"max_variables": 20000, "max_clauses": 200000}"#).unwrap(),
+        relaxed_cycles: false,
+        max_cycles: DEFAULT_MAX_CYCLES,
+    };
+    let today = chrono::NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+    let mut session = Session::new(
+        &[model.clone()],
+        options,
+        &Config::default(),
+        today,
+        entities,
+    );
+    assert_eq!(session.inputs.len(), 1);
+
+    let run = RunOptions {
+        level: OutputLevel::Quiet,
+        ..Default::default()
+    };
+    session.outputs = session.replay(&run);
+    assert_eq!(session.outputs.conflicts.len(), 2);
+    assert!(session.outputs.conflicts.iter().all(|c| c.entity == "a"));
+
+    let path = dir.join("session.json");
+    session.write(&path).unwrap();
+    let loaded = Session::load(&path).unwrap();
+    assert_eq!(loaded.outputs, session.outputs);
+
+    let replayed = loaded.replay(&run);
+    assert!(diff_outputs(&loaded.outputs, &replayed).is_empty());
+
+    let mut recorded = loaded.outputs.clone();
+    recorded.conflicts.pop();
+    assert_eq!(diff_outputs(&recorded, &replayed).len(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/*
+    Two manifests stamped on a dump.ir and a conflicts.yaml, then one changed and one removed
+    Expected: both artifacts record the SHA-256 digests, only the touched manifests are stale
+*/
+#[test]
+fn test_source_digests_detect_stale_artifacts() {
+    let dir = std::env::temp_dir().join(format!("deployfix-digest-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let (web, db) = (dir.join("web.yaml"), dir.join("db.yaml"));
+    std::fs::write(&web, "kind: Deployment\n").unwrap();
+    std::fs::write(&db, "kind: Deployment\n").unwrap();
+    let sources = SourceDigests::of(&[web.clone(), db.clone()]);
+    assert_eq!(
+        sources.0[&web.display().to_string()],
+        "2e15259aa2d978f7affbf2000945c972ebf0beed0e2dcb7b0c490ee33871f120"
+    );
+
+    let dump = stamp(&sources, "dump.ir", "a require b\n");
+    assert_eq!(parse_ir(&dump).len(), 1);
+    let conflicts = stamp(
+        &sources,
+        "node/conflicts.yaml",
+        "unscheduable_entities: []\n",
+    );
+    assert!(serde_yaml::from_str::<serde_yaml::Value>(&conflicts).is_ok());
+    assert_eq!(stamp(&sources, "solution/web.json", "{}"), "{}");
+
+    for artifact in [&dump, &conflicts] {
+        let recorded = SourceDigests::parse(artifact);
+        assert_eq!(recorded, sources);
+        assert!(recorded.stale().is_empty());
+    }
+
+    std::fs::write(&web, "kind: StatefulSet\n").unwrap();
+    std::fs::remove_file(&db).unwrap();
+    assert_eq!(
+        SourceDigests::parse(&dump).stale(),
+        vec![
+            StaleSource::Missing(db.display().to_string()),
+            StaleSource::Changed(web.display().to_string()),
+        ]
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/*
+    a.ir given alone and through its directory, which also holds a copy and a symlink of it
+    Expected: a.ir is read once, its rule keeps the path it was first given with
+*/
+#[test]
+fn test_duplicated_inputs_are_read_once() {
+    let dir = std::env::temp_dir().join(format!("deployfix-duplicates-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let a = dir.join("a.ir");
+    std::fs::write(&a, "a require b\n").unwrap();
+    std::fs::write(dir.join("b.ir"), "a require b\n").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(&a, dir.join("c.ir")).unwrap();
+
+    let files = unique_inputs(&[a.clone(), dir.clone()], |path| {
+        path.extension().is_some_and(|ext| ext == "ir")
+    });
+    let entities = read_ir_entities(&files);
+    let rules = entities
+        .iter()
+        .flat_map(|e| e.rules())
+        .map(|rule| rule.file().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(rules, vec![a.display().to_string()]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/*
+    a require b, b exclude a analyzed with a channel listening to the events
+    Expected: the file, the solve, every conflict and the summary are sent in order
+*/
+#[test]
+fn test_analyze_sends_progress_events() {
+    let dir = std::env::temp_dir().join(format!("deployfix-events-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let model = dir.join("model.ir");
+    std::fs::write(&model, "a require b\nb exclude a\n").unwrap();
+
+    let options = SessionOptions {
+        format: None,
+        domain: None,
+        default_domain_key: None,
+        cycle_check: true,
+        fast_fail: false,
+        tags: Default::default(),
+        budget: serde_json::from_str(r#"{"max_variables": 20000, "max_clauses": 200000}"#).unwrap(),
+        relaxed_cycles: false,
+        max_cycles: DEFAULT_MAX_CYCLES,
+    };
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let outputs = analyze(
+        &[model.clone()],
+        &options,
+        &RunOptions::default(),
+        &Config::default(),
+        &sender,
+    );
+    let events = receiver.try_iter().collect::<Vec<_>>();
+
+    assert_eq!(
+        events[0],
+        AnalysisEvent::FileParsed {
+            path: model.display().to_string(),
+            entities: 2
+        }
+    );
+    assert!(matches!(
+        events[1],
+        AnalysisEvent::DomainStarted { domain: None, .. }
+    ));
+
+    let conflicts = events
+        .iter()
+        .filter_map(|e| match e {
+            AnalysisEvent::ConflictFound(conflict) => Some(conflict),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(conflicts.len(), outputs.conflicts.len());
+    assert!(conflicts.iter().any(|c| c.entity == "a"));
+
+    assert_eq!(
+        events.last(),
+        Some(&AnalysisEvent::Finished {
+            summary: outputs.summary.clone()
+        })
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/*
+    a require b, b exclude a analyzed, then formatted as json, yaml and sarif
+    Expected: every conflict carries its rule type, file and line, sarif points at the file
+*/
+#[test]
+fn test_structured_output_of_conflicts() {
+    let dir = std::env::temp_dir().join(format!("deployfix-output-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let model = dir.join("model.ir");
+    std::fs::write(&model, "a require b\nb exclude a\n").unwrap();
+
+    let options = SessionOptions {
+        format: None,
+        domain: None,
+        default_domain_key: None,
+        cycle_check: true,
+        fast_fail: false,
+        tags: Default::default(),
+        budget: serde_json::from_str(r#"{"max_variables": 20000, "max_clauses": 200000}"#).unwrap(),
+        relaxed_cycles: false,
+        max_cycles: DEFAULT_MAX_CYCLES,
+    };
+    let outputs = analyze(
+        &[model.clone()],
+        &options,
+        &RunOptions::default(),
+        &Config::default(),
+        &|_: AnalysisEvent| {},
+    );
+    assert!(!outputs.conflicts.is_empty());
+
+    let json: serde_json::Value =
+        serde_json::from_str(&format_output(&outputs, OutputFormat::Json).unwrap()).unwrap();
+    assert_eq!(json["version"], OUTPUT_VERSION);
+    assert_eq!(json["summary"], outputs.summary.as_str());
+    for conflict in json["conflicts"].as_array().unwrap() {
+        assert_eq!(conflict["entity"], "a");
+        assert!(conflict["rule_type"] == "require" || conflict["rule_type"] == "exclude");
+        assert_eq!(conflict["file"], model.display().to_string().as_str());
+        assert!(conflict["line"].as_u64().is_some());
+    }
+
+    let yaml: serde_yaml::Value =
+        serde_yaml::from_str(&format_output(&outputs, OutputFormat::Yaml).unwrap()).unwrap();
+    assert_eq!(
+        yaml["conflicts"].as_sequence().unwrap().len(),
+        outputs.conflicts.len()
+    );
+
+    let sarif: serde_json::Value =
+        serde_json::from_str(&format_output(&outputs, OutputFormat::Sarif).unwrap()).unwrap();
+    assert_eq!(sarif["version"], "2.1.0");
+    let results = sarif["runs"][0]["results"].as_array().unwrap();
+    assert_eq!(results.len(), outputs.conflicts.len());
+    assert_eq!(
+        results[0]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"],
+        model.display().to_string().as_str()
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/*
+    Certificate of a model signed with a key, then checked with another key and after the
+    model changed
+    Expected: only the right key on the unchanged model verifies
+*/
+#[test]
+fn test_certificate_detects_forgery_and_changes() {
+    let dir = std::env::temp_dir().join(format!("deployfix-certificate-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let model = dir.join("model.ir");
+    std::fs::write(&model, "a require b\n").unwrap();
+    let path = dir.join("certificate.json");
+
+    Certificate::new(
+        "check",
+        &[model.clone()],
+        "deployfix summary: ok".to_string(),
+    )
+    .unwrap()
+    .sign(b"secret")
+    .write(&path)
+    .unwrap();
+    let certificate = Certificate::load(&path).unwrap();
+
+    assert_eq!(certificate.verify(Some(b"secret".as_slice())), vec![]);
+    assert_eq!(
+        certificate.verify(Some(b"other".as_slice())),
+        vec![CertificateError::Signature]
+    );
+
+    let mut forged = certificate.clone();
+    forged.summary = "deployfix summary: forged".to_string();
+    assert_eq!(
+        forged.verify(Some(b"secret".as_slice())),
+        vec![CertificateError::Signature]
+    );
+
+    std::fs::write(&model, "a require b\na exclude b\n").unwrap();
+    assert_eq!(
+        certificate.verify(None),
+        vec![CertificateError::Stale(StaleSource::Changed(
+            model.display().to_string()
+        ))]
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/*
+    app=web prefers app=cache it excludes, app=api requires and excludes app=db
+    Expected: the preference of app=web is split off as soft, only app=api is unschedulable
+*/
+#[test]
+fn test_soft_rules_are_reported_apart() {
+    let data = "app=web require app=cache // weight=50;\n\
+                app=web exclude app=cache\n\
+                app=api require app=db\n\
+                app=api exclude app=db\n";
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(data, EntitySource::File("deploy.ir".to_string()))
+        .unwrap();
+    let entity_map = merge_entities(entities, None).try_into().unwrap();
+
+    let output = get_solver("z3").unwrap().solve(&entity_map);
+    let (output, soft) = output.take_soft();
+
+    assert_eq!(soft.keys().collect::<Vec<_>>(), vec!["app=web"]);
+    assert!(soft["app=web"].iter().all(EntityRule::is_soft));
+    assert_eq!(
+        output.get_unscheduable(),
+        Some(["app=api".to_string()].into_iter().collect())
+    );
+
+    // The structural check leaves the preference to z3
+    let structural = get_solver("structural").unwrap().solve(&entity_map);
+    assert_eq!(
+        structural.get_unscheduable(),
+        Some(["app=api".to_string()].into_iter().collect())
+    );
+}
+
+/*
+    A WAT pass flagging the first rule of app=web, and a pass which never returns
+    Expected: the finding points at the rule, the looping pass runs out of fuel
+*/
+#[cfg(feature = "wasm")]
+#[test]
+fn test_wasm_pass_reports_findings() {
+    use deployfix::plugin::wasm::run_passes;
+
+    let dir = std::env::temp_dir().join(format!("deployfix-passes-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let naming = dir.join("naming.wat");
+    std::fs::write(
+        &naming,
+        r#"(module
+  (memory (export "memory") 1)
+  (data (i32.const 0) "[{\"entity\":\"app=web\",\"rule\":0,\"message\":\"requires a workload of another team\"}]")
+  (func (export "deployfix_alloc") (param i32) (result i32) (i32.const 1024))
+  (func (export "deployfix_check") (param i32 i32) (result i64) (i64.const 79)))
+"#,
+    )
+    .unwrap();
+    let looping = dir.join("looping.wat");
+    std::fs::write(
+        &looping,
+        r#"(module
+  (memory (export "memory") 1)
+  (func (export "deployfix_alloc") (param i32) (result i32) (i32.const 1024))
+  (func (export "deployfix_check") (param i32 i32) (result i64) (loop (br 0)) (i64.const 0)))
+"#,
+    )
+    .unwrap();
+
+    let parser = get_parser("deployfix").unwrap();
+    let entities = parser
+        .parse(
+            "app=web require app=db\n",
+            EntitySource::File("model.ir".to_string()),
+        )
+        .unwrap();
+
+    let findings = run_passes(&[naming], &entities).unwrap();
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].entity.as_ref(), "app=web");
+    assert_eq!(findings[0].pass, "naming");
+    assert_eq!(findings[0].rule.as_deref(), Some("app=web require app=db"));
+    assert_eq!(findings[0].location.as_deref(), Some("model.ir:1"));
+
+    assert!(run_passes(&[looping], &entities).is_err());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/*
+    Dashboard over a model with a conflict, the model fixed on disk then solved again
+    Expected: the conflict of a is previewed as an annotated snippet, then goes away
+*/
+#[cfg(feature = "tui")]
+#[test]
+fn test_dashboard_resolves_edited_models() {
+    use deployfix::cli::{Dashboard, DashboardAction, Pane};
+
+    let dir = std::env::temp_dir().join(format!("deployfix-tui-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let model = dir.join("model.ir");
+    std::fs::write(&model, "a require b\nb exclude a\n").unwrap();
+
+    let mut dashboard = Dashboard::new(vec![model.clone()], None, None, true, Default::default());
+    assert!(dashboard.conflicts.contains_key("a"));
+
+    assert!(dashboard.apply(DashboardAction::PreviousPane));
+    assert_eq!(dashboard.pane, Pane::Conflicts);
+    assert!(dashboard.preview().contains("Unscheduable entity: a"));
+
+    std::fs::write(&model, "a require b\n").unwrap();
+    assert!(dashboard.apply(DashboardAction::Resolve));
+    assert!(dashboard.conflicts.is_empty());
+    assert_eq!(dashboard.preview(), "No conflict");
+    assert!(!dashboard.apply(DashboardAction::Quit));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/*
+    Checker of the library over a model with a conflict, then with an unknown solver and an
+    input of an unknown format
+    Expected: the conflict of a with the rule to remove, then errors instead of an exit
+*/
+#[test]
+fn test_checker_returns_results_and_errors() {
+    use deployfix::api::{CheckError, Checker, RecommendPolicy};
+
+    let dir = std::env::temp_dir().join(format!("deployfix-api-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let model = dir.join("model.ir");
+    std::fs::write(&model, "a require b\nb exclude a\n").unwrap();
+
+    let result = Checker::new()
+        .recommend(RecommendPolicy::All)
+        .check_paths(&[model.clone()])
+        .unwrap();
+    assert!(!result.is_conflict_free());
+    assert!(result.conflict("a").unwrap().solvers.contains("z3"));
+    assert!(!result.recommendations.is_empty());
+    assert_eq!(result.actions.len(), result.recommendations.len());
+
+    let err = Checker::new().solvers(["nope"]).check(vec![]).unwrap_err();
+    assert!(matches!(err, CheckError::Solver(_)));
+
+    let unknown = dir.join("model");
+    std::fs::write(&unknown, "a require b\n").unwrap();
+    let err = Checker::new().check_paths(&[unknown]).unwrap_err();
+    assert!(matches!(err, CheckError::UnknownFormat(_)));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/*
+    k8s go over two deployments with an env file, the node topology checked by ring and
+    unknown alone through --topology-solvers
+    Expected: the envs are ignored by both solvers and the topology is summarized as ok
+*/
+#[test]
+fn test_k8s_go_topology_solvers_with_env_file() {
+    use std::ffi::OsStr;
+
+    use clap::Parser;
+    use deployfix::cli::{Cli, SUMMARY_FILE};
+
+    let dir =
+        std::env::temp_dir().join(format!("deployfix-topology-solvers-{}", std::process::id()));
+    let (source, inject, output) = (dir.join("src"), dir.join("inject"), dir.join("out"));
+    std::fs::create_dir_all(&source).unwrap();
+    std::fs::create_dir_all(&inject).unwrap();
+    for (name, other) in [("web", "db"), ("db", "web")] {
+        std::fs::write(
+            source.join(format!("{name}.yaml")),
+            format!(
+                "apiVersion: apps/v1\n\
+                 kind: Deployment\n\
+                 metadata:\n  name: {name}\n\
+                 spec:\n  selector:\n    matchLabels:\n      app: {name}\n\
+                 \x20 template:\n    metadata:\n      labels:\n        app: {name}\n\
+                 \x20   spec:\n      affinity:\n        podAntiAffinity:\n\
+                 \x20         requiredDuringSchedulingIgnoredDuringExecution:\n\
+                 \x20         - labelSelector:\n              matchExpressions:\n\
+                 \x20             - key: app\n                operator: In\n\
+                 \x20               values:\n                - {other}\n\
+                 \x20           topologyKey: kubernetes.io/hostname\n\
+                 \x20     containers:\n        - name: {name}\n          image: nginx\n"
+            ),
+        )
+        .unwrap();
+    }
+    let env = dir.join("cluster.env");
+    std::fs::write(&env, "node1 disk=ssd;\nnode2 disk=hdd;\n").unwrap();
+
+    let cli = Cli::parse_from([
+        OsStr::new("deployfix"),
+        OsStr::new("k8s"),
+        OsStr::new("go"),
+        source.as_os_str(),
+        inject.as_os_str(),
+        output.as_os_str(),
+        OsStr::new("--env-file"),
+        env.as_os_str(),
+        OsStr::new("--topology-solvers"),
+        OsStr::new("node=ring,unknown"),
+    ]);
+    deployfix::cli::execute(cli);
+
+    let summary = std::fs::read_to_string(output.join(SUMMARY_FILE)).unwrap();
+    assert!(summary.contains("topology: node"));
+    assert!(summary.contains("result: ok"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
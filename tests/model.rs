@@ -0,0 +1,2385 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    num::NonZeroUsize,
+};
+
+use chrono::NaiveDate;
+
+use deployfix::{
+    cli::{
+        audit_placements, check_disruption_budgets, check_pod_quotas, compare_envs, deploy_order,
+        disruption_output, domain_candidates, dump_definitions, format_comparison, format_headroom,
+        format_violations, headroom, plan_file, quota_output, recommend_actions, reverse_lookup,
+        write_stats, ActionKind, BudgetArgs, Config, ConflictReport, Definition, DirectorySink,
+        DomainResult, DomainSummary, LogFormat, Normalizations, OutputLayout, OutputLevel,
+        Placement, PlanChange, Profile, Profiles, RuleCounts, RunSummary, Server, ServerConfig,
+        SolveStats, Suppression, SuppressionStatus, Suppressions, TelemetryConfig, Tenant,
+        DISRUPTION_SOLVER, INDEX_FILE, QUOTA_SOLVER,
+    },
+    model::{
+        filter_rules_by_tags, format_envs, get_parser, lint_model, load_env_files, merge_entities,
+        DefaultEnvParser, DeployIRFormatter, Entity, EntityName, EntityPriority, EntityRule,
+        EntityRuleMetadata, EntityRuleSource, EntityRuleType, EntitySource, EnvParseError,
+        EnvParser, LintKind, RuleLifecycle, Selector, SelectorOperator, SourceSpan,
+        METADATA_GENERATED_KEY, METADATA_PROVENANCE_KEY, METADATA_TOPOLOGY_KEY,
+    },
+    plugin::{inject, DeployPlugin, InjectOptions},
+    solver::{
+        decode_snapshot, encode_snapshot, get_solver, get_solver_with, EntityMap, SnapshotError,
+        SolverOptions, SolverOutput, SNAPSHOT_VERSION,
+    },
+    util::{
+        decode_source, generate_entities,
+        http::{self, HttpUrl, Request},
+        split_by_metadata,
+    },
+};
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+
+fn new_with_require(name: &str, target: &str, file: &str, line: usize) -> Entity {
+    let mut entity = Entity::new_with_source(name, EntitySource::File(file.to_string()));
+    entity.add_require(EntityRule::mono(
+        name.into(),
+        target.into(),
+        EntityRuleType::Require,
+        EntityRuleSource::new(file, line),
+        None,
+    ));
+
+    entity
+}
+
+/*
+    app1 require app2 // app1.yaml:10
+    app1 require app2 // inject.ir:3
+    Expected: a single rule remembering both locations
+*/
+#[test]
+fn test_merge_collapses_duplicate_rules() {
+    let entities = vec![
+        new_with_require("app1", "app2", "app1.yaml", 10),
+        new_with_require("app1", "app2", "inject.ir", 3),
+    ];
+
+    let entities = merge_entities(entities, None);
+    assert_eq!(entities.len(), 1);
+
+    let entity = &entities[0];
+    assert_eq!(entity.requires.len(), 1);
+
+    let rule = entity.requires.iter().next().unwrap();
+    assert_eq!(
+        rule.metadata(METADATA_PROVENANCE_KEY),
+        Some("app1.yaml:10,inject.ir:3")
+    );
+}
+
+/*
+    app1 require app2 // app1.yaml:10, topology=node
+    app1 require app2 // inject.ir:3, topology=zone
+    Expected: both rules kept, they constrain different domains
+*/
+#[test]
+fn test_merge_keeps_rules_of_different_topologies() {
+    let with_topology = |mut entity: Entity, topology: &str| {
+        let mut rule = entity.requires.pop_first().unwrap();
+        rule.add_metadata("topology".to_string(), topology.to_string());
+        entity.add_require(rule);
+        entity
+    };
+    let entities = vec![
+        with_topology(new_with_require("app1", "app2", "app1.yaml", 10), "node"),
+        with_topology(new_with_require("app1", "app2", "inject.ir", 3), "zone"),
+    ];
+
+    let entities = merge_entities(entities, None);
+    let topologies = entities[0]
+        .requires
+        .iter()
+        .map(|rule| rule.metadata("topology").unwrap())
+        .collect::<BTreeSet<_>>();
+    assert_eq!(topologies, BTreeSet::from(["node", "zone"]));
+}
+
+#[test]
+fn test_merge_keeps_distinct_rules() {
+    let entities = vec![
+        new_with_require("app1", "app2", "app1.yaml", 10),
+        new_with_require("app1", "app3", "inject.ir", 3),
+    ];
+
+    let entities = merge_entities(entities, None);
+    let entity = &entities[0];
+
+    assert_eq!(entity.requires.len(), 2);
+    assert!(entity
+        .requires
+        .iter()
+        .all(|r| r.metadata(METADATA_PROVENANCE_KEY).is_none()));
+}
+
+/*
+    app1 require app3 // shared.yaml:1
+    app2 require app3 // shared.yaml:1
+    app3 require app4, app3 exclude app4
+    Expected: the rules of app1 and app2 read the same, each entity is reported with its own
+*/
+#[test]
+fn test_z3_tracks_identically_rendered_rules_apart() {
+    let mut app3 = new_with_require("app3", "app4", "app3.yaml", 1);
+    app3.add_exclude(EntityRule::mono(
+        "app3".into(),
+        "app4".into(),
+        EntityRuleType::Exclude,
+        EntityRuleSource::new("app3.yaml", 2),
+        None,
+    ));
+    let entities = vec![
+        new_with_require("app1", "app3", "shared.yaml", 1),
+        new_with_require("app2", "app3", "shared.yaml", 1),
+        app3,
+    ];
+    let map: EntityMap = entities.try_into().unwrap();
+
+    let conflicts = get_solver("z3")
+        .unwrap()
+        .solve(&map)
+        .get_conflict_rules()
+        .unwrap();
+    for name in ["app1", "app2"] {
+        let sources = conflicts[name]
+            .iter()
+            .filter(|rule| rule.targets()[0].as_ref() == "app3")
+            .map(|rule| rule.source().as_ref())
+            .collect::<Vec<_>>();
+        assert_eq!(sources, vec![name]);
+    }
+}
+
+#[test]
+fn test_lint_model_flags_redundant_subsumed_and_self_rules() {
+    let parser = get_parser("deployfix").unwrap();
+
+    let a = "app1 require app2\napp1 require app2, app3\napp4 require app4\n\
+             app5 require app6\napp5 exclude app6\napp6 require app7, app8\n";
+    let b = "app1 require app2\n";
+
+    let mut entities = parser
+        .parse(a, EntitySource::File("a.ir".to_string()))
+        .unwrap();
+    entities.extend(
+        parser
+            .parse(b, EntitySource::File("b.ir".to_string()))
+            .unwrap(),
+    );
+
+    let findings = lint_model(&merge_entities(entities, None))
+        .into_iter()
+        .map(|f| (f.entity.as_ref().to_string(), f.kind, f.rule))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        findings,
+        vec![
+            (
+                "app1".to_string(),
+                LintKind::Redundant,
+                "app1 require app2".to_string()
+            ),
+            (
+                "app1".to_string(),
+                LintKind::Subsumed,
+                "app1 require app2, app3".to_string()
+            ),
+            (
+                "app4".to_string(),
+                LintKind::SelfRequire,
+                "app4 require app4".to_string()
+            ),
+            (
+                "app5".to_string(),
+                LintKind::Contradictory,
+                "app5 require app6".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_selector_overlaps_and_matches() {
+    let values = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+    let app_a = Selector::new().with("app", SelectorOperator::In(values(&["a"])));
+    let app_ab = Selector::new().with("app", SelectorOperator::In(values(&["a", "b"])));
+    let app_a_db = app_a
+        .clone()
+        .with("tier", SelectorOperator::In(values(&["db"])));
+
+    assert_eq!(app_ab.to_string(), "app in (a, b)");
+    assert_eq!(app_a_db.to_string(), "app=a, tier=db");
+
+    // Different names, same pods
+    assert!(app_ab.overlaps(&app_a));
+    assert!(app_a_db.overlaps(&app_ab));
+    assert!(!app_a.overlaps(&Selector::new().with("app", SelectorOperator::NotIn(values(&["a"])))));
+    assert!(!app_a_db.overlaps(&Selector::new().with("tier", SelectorOperator::DoesNotExist)));
+    assert!(Selector::new().overlaps(&app_a_db));
+
+    let labels = |pairs: &[(&str, &str)]| {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let web = labels(&[("app", "a"), ("tier", "web")]);
+
+    assert!(app_ab.matches(&web));
+    assert!(!app_a_db.matches(&web));
+    assert!(Selector::from_labels(&web).overlaps(&app_ab));
+    assert!(!Selector::new()
+        .with("legacy", SelectorOperator::Exists)
+        .matches(&web));
+}
+
+#[test]
+fn test_filter_rules_by_tags() {
+    let data =
+        "A require B // tags=security, capacity;\nA exclude C // tags=capacity;\nA exclude D\n";
+    let parse = || {
+        get_parser("deployfix")
+            .unwrap()
+            .parse(data, EntitySource::Unknown)
+            .unwrap()
+    };
+
+    let mut entities = parse();
+    let dropped = filter_rules_by_tags(&mut entities, &["security".to_string()], &[]);
+    let entity = entities.iter().find(|e| e.name.0 == "A").unwrap();
+    assert_eq!(dropped, 2);
+    assert_eq!(
+        entity.requires.iter().next().unwrap().tags(),
+        vec!["security", "capacity"]
+    );
+    assert!(entity.excludes.is_empty());
+
+    let mut entities = parse();
+    let dropped = filter_rules_by_tags(&mut entities, &[], &["capacity".to_string()]);
+    let entity = entities.iter().find(|e| e.name.0 == "A").unwrap();
+    assert_eq!(dropped, 2);
+    assert!(entity.requires.is_empty());
+    assert!(entity.excludes.iter().all(|r| r.tags().is_empty()));
+    assert_eq!(entities.len(), parse().len());
+}
+
+#[test]
+fn test_rule_desc_survives_formatting() {
+    let parser = get_parser("deployfix").unwrap();
+    let entities = parser
+        .parse(
+            "A require B // desc=\"A reads B through a local socket\";\nA exclude C // desc= ;\n",
+            EntitySource::Unknown,
+        )
+        .unwrap();
+    let entity = entities.iter().find(|e| e.name.0 == "A").unwrap();
+
+    let require = entity.requires.iter().next().unwrap();
+    assert_eq!(require.desc(), Some("A reads B through a local socket"));
+    assert_eq!(entity.excludes.iter().next().unwrap().desc(), None);
+
+    let formatted = DeployIRFormatter::format(&entities);
+    let entities = parser.parse(&formatted, EntitySource::Unknown).unwrap();
+    let entity = entities.iter().find(|e| e.name.0 == "A").unwrap();
+    assert_eq!(
+        entity.requires.iter().next().unwrap().desc(),
+        Some("A reads B through a local socket")
+    );
+}
+
+#[test]
+fn test_strict_rejects_defaulted_domain_keys() {
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(
+            "A require B // tenant=blue;\nA exclude C\n",
+            EntitySource::File("model.ir".to_string()),
+        )
+        .unwrap();
+
+    let domains = split_by_metadata(&entities, "tenant", "shared", false).unwrap();
+    let mut names = domains.keys().map(|k| k.as_str()).collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec!["blue", "shared"]);
+
+    let err = split_by_metadata(&entities, "tenant", "shared", true).unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        "model.ir:2: Missing `tenant` for rule `A exclude C`, assuming the default value shared \
+         (rejected by --strict)"
+    );
+}
+
+#[test]
+fn test_compare_envs_aligns_rules_by_name_and_topology() {
+    let parse = |ir: &str, file: &str| {
+        get_parser("deployfix")
+            .unwrap()
+            .parse(ir, EntitySource::File(file.to_string()))
+            .unwrap()
+    };
+    let staging = parse(
+        "web require db\nweb exclude batch\ndebug require web\n",
+        "staging.ir",
+    );
+    let prod = parse(
+        "web exclude batch // topology=zone;\nweb require db\n",
+        "prod.ir",
+    );
+
+    let comparison = compare_envs(&staging, &prod)
+        .with_conflicts(&Default::default(), &["web".to_string()].into());
+    assert_eq!(comparison.left_entities, vec!["debug"]);
+    assert!(comparison.right_entities.is_empty());
+
+    assert_eq!(
+        format_comparison(&comparison, "staging", "prod"),
+        "Entities only in staging:\n  debug\n\
+         Rules only in staging:\n  debug require web on node (staging.ir:3)\n  \
+         web exclude batch on node (staging.ir:2)\n\
+         Rules only in prod:\n  web exclude batch on zone (prod.ir:1)\n\
+         Conflicts only in prod:\n  web\n"
+    );
+}
+
+#[test]
+fn test_compare_envs_hints_at_the_labels_making_the_difference() {
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(
+            "app=train require node=gpu\napp=web exclude app=batch\n",
+            EntitySource::File("model.ir".to_string()),
+        )
+        .unwrap();
+    let staging = DefaultEnvParser {}
+        .parse("node1 node=gpu;\nnode2 node=cpu;app=batch;\n")
+        .unwrap();
+    let prod = DefaultEnvParser {}.parse("node1 node=cpu;\n").unwrap();
+
+    let conflicts = |envs: &Vec<_>| {
+        let solver = get_solver("z3").unwrap();
+        solver.set_envs(envs.clone());
+        let map: EntityMap = entities.clone().try_into().unwrap();
+        solver
+            .solve(&map)
+            .get_unscheduable()
+            .unwrap_or_default()
+            .into_iter()
+            .collect::<BTreeSet<_>>()
+    };
+
+    let comparison = compare_envs(&entities, &entities)
+        .with_conflicts(&conflicts(&staging), &conflicts(&prod))
+        .with_env_hints((&entities, &staging), (&entities, &prod));
+    assert!(comparison.left_conflicts.is_empty());
+    assert_eq!(comparison.right_conflicts, vec!["app=train"]);
+
+    // app=batch is only placed in staging, but the rules of app=train do not name it
+    assert_eq!(
+        format_comparison(&comparison, "staging", "prod"),
+        "Conflicts only in prod:\n  app=train\n    \
+         satisfiable in staging because node=gpu exists\n"
+    );
+}
+
+#[test]
+fn test_recommend_actions_pick_the_least_invasive_change() {
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(
+            "app=web require app=db // topology=zone;\n\
+             app=web exclude app=db // topology=zone;\n\
+             app=web require app.kubernetes.io/name=cache\n\
+             app=cache exclude app=batch\n\
+             app=db exclude app=batch\n",
+            EntitySource::File("web.ir".to_string()),
+        )
+        .unwrap();
+    let entities = merge_entities(entities, None);
+    let rules = entities
+        .iter()
+        .flat_map(|entity| entity.rules())
+        .filter(|rule| rule.source().as_ref() != "app=db")
+        .cloned()
+        .collect::<Vec<_>>();
+    let conflicts = [("app=web".to_string(), rules.clone())]
+        .into_iter()
+        .collect();
+
+    let actions = recommend_actions(&entities, &conflicts, &rules);
+    let kind = |text: &str| {
+        actions
+            .iter()
+            .find(|action| action.rule == text)
+            .map(|action| (action.action, action.from.clone(), action.to.clone()))
+            .unwrap()
+    };
+
+    assert_eq!(
+        kind("app=web exclude app=db"),
+        (
+            ActionKind::ChangeTopology,
+            Some("zone".to_string()),
+            Some("node".to_string())
+        )
+    );
+    assert_eq!(
+        kind("app=web require app.kubernetes.io/name=cache"),
+        (
+            ActionKind::RewriteTarget,
+            Some("app.kubernetes.io/name=cache".to_string()),
+            Some("app=cache".to_string())
+        )
+    );
+    assert_eq!(kind("app=web require app=db").0, ActionKind::RemoveRule);
+    assert_eq!(
+        actions
+            .iter()
+            .find(|action| action.rule == "app=web require app=db")
+            .unwrap()
+            .reason,
+        "makes app=web unschedulable"
+    );
+    assert_eq!(
+        kind("app=cache exclude app=batch").0,
+        ActionKind::RemoveRule
+    );
+    assert!(actions
+        .iter()
+        .filter(|action| action.action == ActionKind::RemoveRule)
+        .all(|action| action.from.is_none() && action.location.is_some()));
+}
+
+#[test]
+fn test_deploy_order_waves_and_cycles() {
+    let parse = |ir: &str| {
+        get_parser("deployfix")
+            .unwrap()
+            .parse(ir, EntitySource::File("order.ir".to_string()))
+            .unwrap()
+    };
+
+    let order = deploy_order(&parse(
+        "web require db\nworker require db, queue\nqueue require queue\nweb exclude worker\n",
+    ))
+    .unwrap();
+    assert_eq!(
+        order.waves,
+        vec![
+            vec!["db".to_string(), "queue".to_string()],
+            vec!["web".to_string(), "worker".to_string()]
+        ]
+    );
+
+    let err = deploy_order(&parse(
+        "a require b\nb require a\nc require a\nd require c\n",
+    ))
+    .unwrap_err();
+    assert_eq!(err.cycles, vec![vec!["a".to_string(), "b".to_string()]]);
+    assert_eq!(err.blocked, vec!["c".to_string(), "d".to_string()]);
+}
+
+#[test]
+fn test_run_summary_table_and_yaml() {
+    let mut summary = RunSummary::default();
+    summary.push(DomainSummary::new(
+        "kubernetes.io/hostname",
+        12,
+        2,
+        false,
+        std::time::Duration::from_millis(840),
+    ));
+    summary.push(DomainSummary::new(
+        "rack",
+        3,
+        0,
+        true,
+        std::time::Duration::from_millis(5),
+    ));
+    summary.push(DomainSummary::skipped("topology.kubernetes.io/zone", 5));
+
+    let results = summary
+        .domains
+        .iter()
+        .map(|domain| domain.result)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        results,
+        vec![
+            DomainResult::Conflict,
+            DomainResult::Undetermined,
+            DomainResult::Skipped
+        ]
+    );
+
+    assert_eq!(
+        summary.format_table(),
+        "TOPOLOGY                     ENTITIES  CONFLICTS  DURATION  RESULT\n\
+         kubernetes.io/hostname       12        2          840ms     conflict\n\
+         rack                         3         0          5ms       undetermined\n\
+         topology.kubernetes.io/zone  5         0          0ms       skipped\n"
+    );
+
+    let yaml = summary.to_yaml();
+    assert!(yaml.contains("- topology: kubernetes.io/hostname\n  entities: 12\n  conflicts: 2\n  duration_ms: 840\n  result: conflict\n"));
+    assert_eq!(serde_yaml::from_str::<RunSummary>(&yaml).unwrap(), summary);
+}
+
+#[test]
+fn test_profiles_bundle_check_options() {
+    let dir = std::env::temp_dir().join(format!("deployfix-profiles-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join("deployfix.yaml");
+    std::fs::write(
+        &path,
+        "profiles:\n  ci-fast:\n    fast_fail: true\n    cycle_check: false\n    solve_timeout: 30\n\
+         \x20 security:\n    only_tags: [security]\n    gate: [proposed, enforced]\n    log_format: json\n",
+    )
+    .unwrap();
+    let profiles = Config::load(&path).unwrap().section::<Profiles>().unwrap();
+
+    assert_eq!(
+        profiles.get("ci-fast").unwrap(),
+        &Profile {
+            cycle_check: Some(false),
+            fast_fail: Some(true),
+            solve_timeout: Some(30),
+            ..Default::default()
+        }
+    );
+    let security = profiles.get("security").unwrap();
+    assert_eq!(security.only_tags, vec!["security"]);
+    assert_eq!(
+        security.gate,
+        vec![RuleLifecycle::Proposed, RuleLifecycle::Enforced]
+    );
+    assert_eq!(security.log_format, Some(LogFormat::Json));
+    assert_eq!(
+        profiles.get("nightly").unwrap_err().to_string(),
+        "Unknown profile nightly, the `profiles` config section has: ci-fast, security"
+    );
+
+    // A misspelled option fails instead of being ignored
+    std::fs::write(&path, "profiles:\n  ci-fast:\n    fastfail: true\n").unwrap();
+    assert!(Config::load(&path).unwrap().section::<Profiles>().is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_normalizations_align_names_of_other_teams() {
+    let dir = std::env::temp_dir().join(format!("deployfix-normalize-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join("deployfix.yaml");
+    std::fs::write(
+        &path,
+        "normalize:\n  - pattern: '^(app\\.kubernetes\\.io/name|service)=(.+)$'\n    replace: app=$2\n",
+    )
+    .unwrap();
+    let normalizations = Config::load(&path)
+        .unwrap()
+        .section::<Normalizations>()
+        .unwrap();
+
+    std::fs::write(
+        &path,
+        "normalize:\n  - pattern: '(unclosed'\n    replace: x\n",
+    )
+    .unwrap();
+    assert!(Config::load(&path)
+        .unwrap()
+        .section::<Normalizations>()
+        .is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(
+            "service=web require app.kubernetes.io/name=db\napp=web exclude app=batch\n",
+            EntitySource::File("teams.ir".to_string()),
+        )
+        .unwrap();
+    let (entities, applied) = normalizations.normalize(entities);
+
+    assert_eq!(
+        applied
+            .iter()
+            .map(|applied| (applied.from.as_ref(), applied.to.as_ref()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("app.kubernetes.io/name=db", "app=db"),
+            ("service=web", "app=web")
+        ]
+    );
+
+    let web = entities
+        .iter()
+        .find(|entity| entity.name.as_ref() == "app=web")
+        .unwrap();
+    assert_eq!(web.rules_len(), 2);
+    assert!(web.requires.iter().all(|rule| rule.is_in_target("app=db")));
+    assert!(entities
+        .iter()
+        .all(|entity| !entity.name.as_ref().starts_with("service=")));
+}
+
+#[test]
+fn test_audit_placements_reports_broken_rules_per_node() {
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(
+            "hbase exclude zk // topology=rack;\nhbase require hdfs\n",
+            EntitySource::File("cluster.ir".to_string()),
+        )
+        .unwrap();
+    let placement = |node: &str, rack: &str, tag: &str| Placement {
+        node: node.to_string(),
+        rack: Some(rack.to_string()),
+        tags: vec![tag.to_string()],
+    };
+    let placements = vec![
+        placement("host1", "/rack1", "hbase"),
+        placement("host1", "/rack1", "hbase"),
+        placement("host1", "/rack1", "hdfs"),
+        placement("host2", "/rack1", "zk"),
+        placement("host3", "/rack2", "hbase"),
+    ];
+
+    let violations = audit_placements(&entities, &placements);
+    assert_eq!(
+        format_violations(&violations),
+        "hbase on host1 (2 containers): runs on rack /rack1 with zk\n  \
+         rule: hbase exclude zk (cluster.ir:1)\n\
+         hbase on host3 (1 container): no container of its targets runs on node host3\n  \
+         rule: hbase require hdfs (cluster.ir:2)\n"
+    );
+}
+
+#[test]
+fn test_ring_solver_bounds_cycles_per_component() {
+    // Every entity requires every other one, a component of 5 entities with 84 cycles
+    let names = ["a", "b", "c", "d", "e"];
+    let ir = names
+        .iter()
+        .flat_map(|source| {
+            names
+                .iter()
+                .filter(move |target| *target != source)
+                .map(move |target| format!("{} require {}\n", source, target))
+        })
+        .collect::<String>();
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(&ir, EntitySource::Unknown)
+        .unwrap();
+    let entity_map: EntityMap = entities.try_into().unwrap();
+
+    let options = SolverOptions {
+        max_cycles: 10,
+        ..Default::default()
+    };
+    let bounded = get_solver_with("ring", &options)
+        .unwrap()
+        .solve(&entity_map);
+
+    let (_, undetermined) = bounded.take_undetermined();
+    let undetermined = undetermined.unwrap();
+    assert_eq!(undetermined.entities.len(), 5);
+    assert_eq!(
+        undetermined.entities["a"],
+        "too many require cycles in its component of 5 entities for --max-cycles 10"
+    );
+
+    // Co-locating all of them satisfies every cycle
+    let complete = get_solver("ring").unwrap().solve(&entity_map);
+    assert!(!complete.is_conflict());
+    assert!(complete.take_undetermined().1.is_none());
+}
+
+#[test]
+fn test_domain_candidates_suggest_split_keys() {
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(
+            "A require B // tenant=blue;team=x;\nA exclude C // tenant=blue;\n\
+             B require C // tenant=red;team=y;\nB exclude D // tenant=red;\n\
+             C require D // desc=x;\n",
+            EntitySource::Unknown,
+        )
+        .unwrap();
+
+    let candidates = domain_candidates(&entities);
+    let keys = candidates
+        .iter()
+        .map(|c| c.key.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(keys, vec!["tenant", "team"]);
+
+    assert!(candidates[0].suggested);
+    assert_eq!(candidates[0].rules, 5);
+    assert_eq!(candidates[0].default_value(), Some("blue"));
+    assert!(!candidates[1].suggested);
+}
+
+#[test]
+fn test_parse_label_expressions() {
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(
+            "A require app in (frontend, frontend-canary)\nA exclude tier notin (db)\n",
+            EntitySource::Unknown,
+        )
+        .unwrap();
+
+    let entity = entities.iter().find(|e| e.name.0 == "A").unwrap();
+
+    let rule = entity
+        .requires
+        .iter()
+        .find(|r| r.metadata("key") == Some("app"))
+        .unwrap();
+    match rule {
+        EntityRule::Multi { targets, .. } => {
+            let targets = targets.iter().map(|t| t.0.as_str()).collect::<Vec<_>>();
+            assert_eq!(targets, vec!["app=frontend", "app=frontend-canary"]);
+        }
+        _ => panic!("Expected a multi rule, got {:?}", rule),
+    }
+    assert_eq!(rule.metadata("key"), Some("app"));
+    assert_eq!(rule.metadata("operator"), Some("In"));
+
+    // `exclude ... notin` becomes a require on the listed values
+    let rule = entity
+        .requires
+        .iter()
+        .find(|r| r.metadata("key") == Some("tier"))
+        .unwrap();
+    assert!(rule.is_require());
+    assert_eq!(rule.metadata("inverse"), Some("true"));
+    assert!(entity.excludes.is_empty());
+}
+
+#[test]
+fn test_parse_group_expands_to_pairwise_requires() {
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse("group payments = api, db, cache\n", EntitySource::Unknown)
+        .unwrap();
+
+    assert_eq!(entities.len(), 3);
+    for entity in entities.iter() {
+        assert_eq!(entity.requires.len(), 2);
+        assert!(entity
+            .requires
+            .iter()
+            .all(|r| r.group() == Some("payments")));
+    }
+}
+
+#[test]
+fn test_format_envs_round_trips() {
+    let data = "node1 app=app1;rack=r1;\nnode2 app=app1;rack=r1;\nnode3 \n";
+
+    let envs = DefaultEnvParser {}.parse(data).unwrap();
+    assert_eq!(envs.len(), 2);
+
+    let formatted = format_envs(&envs);
+    assert_eq!(formatted, data);
+
+    let reparsed = DefaultEnvParser {}.parse(&formatted).unwrap();
+    assert_eq!(format_envs(&reparsed), formatted);
+}
+
+#[test]
+fn test_parse_reads_file_and_line_in_any_case() {
+    let parser = get_parser("deployfix").unwrap();
+
+    for data in [
+        "a require b // File=a.yaml;Line=3;tenant=t1;\n",
+        "a require b // file=a.yaml;line=3;tenant=t1;\n",
+    ] {
+        let entities = parser.parse(data, EntitySource::Unknown).unwrap();
+        let rule = entities[0].requires.iter().next().unwrap();
+
+        assert_eq!(rule.meta_file(), Some("a.yaml"));
+        assert_eq!(rule.meta_line(), Some(3));
+        assert_eq!(rule.metadata("tenant"), Some("t1"));
+        assert_eq!(rule.metadata("File"), None);
+    }
+}
+
+#[test]
+fn test_generated_rules_survive_formatting() {
+    let parser = get_parser("deployfix").unwrap();
+
+    let data =
+        "a exclude b // File=a.yaml;Line=3;generated=true;\na exclude c // File=a.yaml;Line=5;\n";
+    let entities = parser.parse(data, EntitySource::Unknown).unwrap();
+
+    let generated = entities[0]
+        .excludes
+        .iter()
+        .map(|rule| (rule.targets()[0].as_ref().to_string(), rule.is_generated()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        generated,
+        vec![("b".to_string(), true), ("c".to_string(), false)]
+    );
+
+    let formatted = DeployIRFormatter::format(&entities);
+    assert!(formatted.contains(&format!("{}=true", METADATA_GENERATED_KEY)));
+}
+
+#[test]
+fn test_generated_model_draws_distinct_pairs() {
+    // 4 apps have 12 ordered pairs, a density of 1 connects all of them once
+    let entities = generate_entities(4, 1.0, 0.5, 2, 7);
+
+    let mut pairs = BTreeSet::new();
+    let mut self_conflicting = BTreeSet::new();
+    for entity in entities.iter() {
+        for rule in entity.rules() {
+            let target = rule.targets()[0].as_ref().to_string();
+            if target == entity.name.0 {
+                self_conflicting.insert(target);
+            } else {
+                assert!(pairs.insert((entity.name.0.clone(), target)));
+            }
+        }
+    }
+
+    assert_eq!(pairs.len(), 12);
+    assert_eq!(self_conflicting.len(), 2);
+}
+
+fn random_string(rng: &mut SmallRng, alphabet: &[char]) -> String {
+    let len = rng.gen_range(0..6);
+
+    (0..len).map(|_| *alphabet.choose(rng).unwrap()).collect()
+}
+
+fn random_rule(rng: &mut SmallRng) -> EntityRule {
+    // Mostly valid characters, with a few separators mixed in
+    let name_alphabet = ['a', 'b', 'p', '=', '.', '-', '/', '_', ' ', ',', ';'];
+    let value_alphabet = ['x', 'y', '=', '.', '/', ' ', ';'];
+    let keys = ["tenant", "topology", "weight", "line", "k;x", " pad", ""];
+
+    let source = EntityName(format!("s{}", random_string(rng, &name_alphabet)));
+    let r#type = if rng.gen_bool(0.5) {
+        EntityRuleType::Require
+    } else {
+        EntityRuleType::Exclude
+    };
+
+    let metadata = if rng.gen_bool(0.8) {
+        let file = rng
+            .gen_bool(0.5)
+            .then(|| format!("{}.yaml", random_string(rng, &value_alphabet)));
+        let line = rng
+            .gen_bool(0.5)
+            .then(|| NonZeroUsize::new(rng.gen_range(1..100)).unwrap());
+        let map = rng.gen_bool(0.7).then(|| {
+            (0..rng.gen_range(1..3))
+                .map(|_| {
+                    (
+                        keys.choose(rng).unwrap().to_string(),
+                        random_string(rng, &value_alphabet),
+                    )
+                })
+                .collect::<BTreeMap<_, _>>()
+        });
+
+        Some(EntityRuleMetadata::new(file, line, map))
+    } else {
+        None
+    };
+
+    let targets = (0..rng.gen_range(1..4))
+        .map(|_| EntityName(format!("t{}", random_string(rng, &name_alphabet))))
+        .collect::<std::collections::BTreeSet<_>>();
+
+    if targets.len() == 1 && rng.gen_bool(0.8) {
+        let target = targets.into_iter().next().unwrap();
+        EntityRule::mono(source, target, r#type, EntityRuleSource::Unknown, metadata)
+    } else {
+        EntityRule::multi(source, targets, r#type, EntityRuleSource::Unknown, metadata)
+    }
+}
+
+// parse(format(x)) == x for every rule the strict check accepts
+#[test]
+fn test_format_parse_round_trip() {
+    let mut rng = SmallRng::seed_from_u64(42);
+    let parser = get_parser("deployfix").unwrap();
+    let (mut accepted, mut rejected) = (0, 0);
+
+    for _ in 0..2000 {
+        let rule = random_rule(&mut rng);
+
+        let mut entity = Entity::new(rule.source().as_ref());
+        match rule.r#type() {
+            EntityRuleType::Require => entity.add_require(rule.clone()),
+            EntityRuleType::Exclude => entity.add_exclude(rule.clone()),
+        }
+        let entities = vec![entity];
+
+        let formatted = match DeployIRFormatter::format_strict(&entities) {
+            Ok(formatted) => formatted,
+            Err(_) => {
+                rejected += 1;
+                continue;
+            }
+        };
+        accepted += 1;
+
+        let parsed = parser
+            .parse(&formatted, EntitySource::Unknown)
+            .unwrap_or_else(|e| panic!("Failed to parse `{}`: {}", formatted, e));
+        let mut parsed = parsed
+            .into_iter()
+            .flat_map(|e| e.requires.into_iter().chain(e.excludes))
+            .collect::<Vec<_>>();
+
+        assert_eq!(parsed.len(), 1, "{}", formatted);
+        parsed[0].set_rule_source(EntityRuleSource::Unknown);
+        assert_eq!(parsed[0], rule, "{}", formatted);
+    }
+
+    assert!(
+        accepted > 100 && rejected > 100,
+        "{} {}",
+        accepted,
+        rejected
+    );
+}
+
+#[test]
+fn test_priority_tiers_from_class_values() {
+    assert_eq!(
+        EntityPriority::from_value(2_000_000_000),
+        EntityPriority::Critical
+    );
+    assert_eq!(
+        EntityPriority::from_value(1_000_000),
+        EntityPriority::Critical
+    );
+    assert_eq!(EntityPriority::from_value(1000), EntityPriority::High);
+    assert_eq!(EntityPriority::from_value(0), EntityPriority::Default);
+    assert_eq!(EntityPriority::from_value(-10), EntityPriority::Low);
+
+    // Higher tiers sort first
+    assert!(EntityPriority::Critical < EntityPriority::High);
+    assert!(EntityPriority::Default < EntityPriority::Low);
+}
+
+/*
+    base.env:    node1 app=app1;  node2 app=app2;
+    prod.env:    extends: base.env, node2 app=app3;
+    extra.env:   node3 app=app1;
+    Expected: node2 is overridden, node1 and node3 share an env
+*/
+#[test]
+fn test_env_files_extend_and_override() {
+    let dir = std::env::temp_dir().join(format!("deployfix-env-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    std::fs::write(dir.join("base.env"), "node1 app=app1;\nnode2 app=app2;\n").unwrap();
+    std::fs::write(dir.join("prod.env"), "extends: base.env\nnode2 app=app3;\n").unwrap();
+    std::fs::write(dir.join("extra.env"), "node3 app=app1;\n").unwrap();
+
+    let envs = load_env_files(&[dir.join("prod.env"), dir.join("extra.env")]).unwrap();
+    assert_eq!(
+        format_envs(&envs),
+        "node1 app=app1;\nnode2 app=app3;\nnode3 app=app1;\n"
+    );
+
+    std::fs::write(dir.join("loop.env"), "extends: loop.env\n").unwrap();
+    assert!(matches!(
+        load_env_files(&[dir.join("loop.env")]),
+        Err(EnvParseError::CyclicExtends(_))
+    ));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_reverse_lookup_matches_file_suffix() {
+    let definition = |name: &str, references: &[&str]| Definition {
+        name: name.to_string(),
+        source: format!("k8s/{}.yaml", name),
+        references: references.iter().map(|r| r.to_string()).collect(),
+        ..Default::default()
+    };
+    let definitions = vec![
+        definition("app1", &["k8s/app2.yaml:19", "k8s/app3.yaml:7"]),
+        definition("app2", &["k8s/app1.yaml:19"]),
+        definition("app3", &["k8s/app2.yaml:25"]),
+    ];
+
+    let found = reverse_lookup(definitions.clone(), "app2.yaml:19");
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].name, "app1");
+    assert_eq!(found[0].references, vec!["k8s/app2.yaml:19"]);
+
+    let found = reverse_lookup(definitions.clone(), "k8s/app2.yaml");
+    let names = found.iter().map(|d| d.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["app1", "app3"]);
+
+    assert!(reverse_lookup(definitions, "p2.yaml:19").is_empty());
+}
+
+/*
+    app=web and app=db declare rules, app=cache is only referenced by both
+    Expected: one entry per entity sorted by name, with the owner, replicas and rule counts of
+    the declaring entities and the references of app=cache sorted by line
+*/
+#[test]
+fn test_definitions_inventory_entities() {
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(
+            "app=web require app=cache // owner=frontend;replicas=3;\n\
+             app=web exclude app=web // owner=frontend;replicas=3;\n\
+             app=db require app=cache // min_replicas=2;max_replicas=5;\n",
+            EntitySource::File("model.ir".to_string()),
+        )
+        .unwrap();
+    let mut entities = merge_entities(entities, None);
+    for entity in entities.iter_mut() {
+        if entity.name.as_ref() == "app=db" {
+            entity.priority = EntityPriority::High;
+        }
+    }
+
+    let definitions: Vec<Definition> =
+        serde_yaml::from_str(&dump_definitions(&entities).unwrap()).unwrap();
+    let names = definitions
+        .iter()
+        .map(|d| d.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["app=cache", "app=db", "app=web"]);
+
+    let (cache, db, web) = (&definitions[0], &definitions[1], &definitions[2]);
+    assert_eq!(cache.source, "unknown");
+    assert_eq!(cache.rules, RuleCounts::default());
+    assert_eq!(cache.references, vec!["model.ir:1", "model.ir:3"]);
+
+    assert_eq!(db.priority, EntityPriority::High);
+    assert_eq!((db.min_replicas, db.max_replicas), (Some(2), Some(5)));
+    assert_eq!(db.owner, None);
+
+    assert_eq!(web.owner.as_deref(), Some("frontend"));
+    assert_eq!(web.replicas, Some(3));
+    assert_eq!(
+        web.rules,
+        RuleCounts {
+            requires: 1,
+            excludes: 1
+        }
+    );
+    assert_eq!(web.references, vec!["model.ir:2"]);
+}
+
+/*
+    app=web is parsed twice without merging
+    Expected: dumping the definitions fails with the duplicate name instead of panicking
+*/
+#[test]
+fn test_definitions_duplicate_entity() {
+    let parse = || {
+        get_parser("deployfix")
+            .unwrap()
+            .parse(
+                "app=web require app=cache\n",
+                EntitySource::File("model.ir".to_string()),
+            )
+            .unwrap()
+    };
+    let entities = parse().into_iter().chain(parse()).collect::<Vec<_>>();
+
+    let err = dump_definitions(&entities).unwrap_err();
+    assert_eq!(err.to_string(), "Duplicate definition found: app=web");
+}
+
+#[test]
+fn test_suppressions_expire_and_track_usage() {
+    let today = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+    let suppression =
+        |entity: &str, target: Option<&str>, expires: Option<NaiveDate>| Suppression {
+            entity: entity.to_string(),
+            target: target.map(|t| t.to_string()),
+            expires,
+            reason: None,
+        };
+
+    let suppressions = Suppressions(vec![
+        suppression("app1", Some("app2"), NaiveDate::from_ymd_opt(2024, 6, 1)),
+        suppression("app1", None, NaiveDate::from_ymd_opt(2024, 5, 31)),
+        suppression("app3", None, None),
+    ]);
+
+    let rule = |target: &str, line: usize| {
+        EntityRule::mono(
+            "app1".into(),
+            target.into(),
+            EntityRuleType::Exclude,
+            EntityRuleSource::new("app1.yaml", line),
+            None,
+        )
+    };
+    let conflicts = HashMap::from([("app1".to_string(), vec![rule("app2", 3), rule("app4", 7)])]);
+
+    let mut report = ConflictReport::new(OutputLevel::Quiet).with_suppressions(suppressions, today);
+    report.add(&conflicts, &HashMap::new(), &[]);
+
+    // The expired suppression no longer hides the rule on app4
+    assert!(report.has_conflict());
+    assert_eq!(
+        report.summary(),
+        "deployfix summary: 1 conflicts across 1 entities in 1 files, 1 suppressed"
+    );
+
+    let statuses = report
+        .suppression_statuses()
+        .into_iter()
+        .map(|(_, status)| status)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        statuses,
+        vec![
+            SuppressionStatus::Active,
+            SuppressionStatus::Expired,
+            SuppressionStatus::Unused
+        ]
+    );
+}
+
+// Writes one `<name>.txt` file per entity, ignoring trailing whitespace when comparing
+struct TextPlugin;
+
+impl DeployPlugin for TextPlugin {
+    const FILE_EXTENSION: &'static str = "txt";
+
+    fn extract_entities(&self, _path: &std::path::Path) -> anyhow::Result<Vec<Entity>> {
+        Ok(vec![])
+    }
+
+    fn inject_entities(&self, entities: Vec<Entity>) -> anyhow::Result<Vec<(String, String)>> {
+        Ok(entities
+            .iter()
+            .map(|e| {
+                (
+                    format!("{}.txt", e.name.as_ref()),
+                    e.name.as_ref().to_string(),
+                )
+            })
+            .collect())
+    }
+
+    fn same_content(&self, a: &str, b: &str) -> bool {
+        a.trim_end() == b.trim_end()
+    }
+}
+
+#[test]
+fn test_inject_only_selected_and_changed_files() {
+    let dir = std::env::temp_dir().join(format!("deployfix-inject-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("web-api.txt"), "web-api\n\n").unwrap();
+    std::fs::write(dir.join("web-ui.txt"), "old").unwrap();
+
+    let entities = ["web-api", "web-ui", "db"]
+        .into_iter()
+        .map(|name| Entity::new_with_source(name, EntitySource::Unknown))
+        .collect::<Vec<_>>();
+    let options = InjectOptions {
+        only: vec![glob::Pattern::new("web-*").unwrap()],
+        skip_unchanged: true,
+    };
+    inject(&TextPlugin, entities, &DirectorySink::new(&dir), &options).unwrap();
+
+    let read = |name: &str| std::fs::read_to_string(dir.join(name)).ok();
+    assert_eq!(read("web-api.txt").as_deref(), Some("web-api\n\n"));
+    assert_eq!(read("web-ui.txt").as_deref(), Some("web-ui"));
+    assert_eq!(read("db.txt"), None);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_output_layout_indexes_domain_files() {
+    let dir = std::env::temp_dir().join(format!("deployfix-layout-{}", std::process::id()));
+    let sink = DirectorySink::new(&dir);
+
+    let layout = OutputLayout::new(&sink, false);
+    let path = layout.domain_file("node", "conflicts.yaml", "conflicts-node.yaml");
+    assert_eq!(path, "node/conflicts.yaml");
+    layout
+        .write_artifact(
+            &path,
+            "conflicts",
+            Some("node"),
+            "unscheduable_entities: []\n",
+        )
+        .unwrap();
+    layout.write_index().unwrap();
+
+    assert!(dir.join("node/conflicts.yaml").is_file());
+    let index = std::fs::read_to_string(dir.join(INDEX_FILE)).unwrap();
+    assert_eq!(
+        index,
+        "layout: domain\nartifacts:\n- path: node/conflicts.yaml\n  kind: conflicts\n  domain: node\n"
+    );
+
+    let flat = OutputLayout::new(&sink, true);
+    assert_eq!(
+        flat.domain_file("node", "conflicts.yaml", "conflicts-node.yaml"),
+        "conflicts-node.yaml"
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// Unlike the IR, snapshots keep every rule unchanged, separators included
+#[test]
+fn test_headroom_counts_domains_able_to_host_replicas() {
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(
+            "app=web exclude app=web // replicas=3;\n\
+             app=web require app=cache\n\
+             app=db exclude app=db // topology=zone;replicas=2;\n\
+             app=batch exclude app=db\n\
+             app=gpu require node=gpu\n",
+            EntitySource::Unknown,
+        )
+        .unwrap();
+    let entities = merge_entities(entities, None);
+    let envs = DefaultEnvParser {}
+        .parse(
+            "n1 app=cache;zone=a;\n\
+             pool: p1 4 app=cache;zone=b;\n\
+             n2 app=db;zone=a;\n\
+             n3 zone=c;\n",
+        )
+        .unwrap();
+
+    let headroom = headroom(&entities, &envs);
+    let of = |name: &str| headroom.iter().find(|h| h.entity == name).unwrap();
+
+    assert_eq!(
+        (of("app=web").domains, of("app=web").headroom),
+        (5, Some(2))
+    );
+    assert_eq!(of("app=db").topology, "zone");
+    assert_eq!((of("app=db").domains, of("app=db").headroom), (3, Some(1)));
+    // Not spread, every node but the one running app=db hosts it
+    assert_eq!(
+        (of("app=batch").domains, of("app=batch").headroom),
+        (6, None)
+    );
+    assert_eq!(of("app=gpu").headroom, Some(0));
+    assert_eq!(headroom[0].entity, "app=gpu");
+    assert!(
+        format_headroom(&headroom).contains("app=batch  node      1         6        unlimited")
+    );
+}
+
+/*
+    app=web spread, 3 replicas, minAvailable 3
+    app=db spread, 3 replicas, maxUnavailable 1, on 2 nodes
+    app=api 3 replicas, minAvailable 50%
+    Expected: app=web never allows an eviction, app=db only once its spread is limited by the
+    nodes, app=api keeps one eviction
+*/
+#[test]
+fn test_disruption_budgets_blocking_every_eviction() {
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(
+            "app=web exclude app=web // replicas=3;min_available=3;\n\
+             app=db exclude app=db // replicas=3;max_unavailable=1;\n\
+             app=api require app=db // replicas=3;min_available=50%;\n",
+            EntitySource::Unknown,
+        )
+        .unwrap();
+    let entities = merge_entities(entities, None);
+    let envs = DefaultEnvParser {}
+        .parse("n1 zone=a;\nn2 zone=b;\n")
+        .unwrap();
+    let blocked = |envs: &[_]| {
+        check_disruption_budgets(&entities, envs)
+            .into_iter()
+            .map(|c| (c.entity, c.available, c.running))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(blocked(&[]), vec![("app=web".to_string(), 3, 3)]);
+    assert_eq!(
+        blocked(&envs[..]),
+        vec![("app=db".to_string(), 2, 2), ("app=web".to_string(), 3, 2)]
+    );
+
+    match disruption_output(&check_disruption_budgets(&entities, &[])) {
+        SolverOutput::Conflict(conflicts, solvers) => {
+            assert_eq!(conflicts["app=web"][0].r#type(), EntityRuleType::Exclude);
+            assert!(solvers["app=web"].contains(DISRUPTION_SOLVER));
+        }
+        output => panic!("Expected a disruption conflict, got {}", output),
+    }
+}
+
+#[test]
+fn test_entity_map_queries() {
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(
+            "web require api\n\
+             api require db,cache\n\
+             db require web\n\
+             batch exclude web,db\n\
+             db exclude batch\n",
+            EntitySource::Unknown,
+        )
+        .unwrap();
+    let map: EntityMap = merge_entities(entities, None).try_into().unwrap();
+    let set = |names: &[&'static str]| names.iter().copied().collect::<BTreeSet<_>>();
+
+    assert_eq!(map.requires_of("api"), set(&["cache", "db"]));
+    assert_eq!(map.requires_of("cache"), set(&[]));
+    assert_eq!(map.excluded_by("db"), set(&["batch"]));
+    assert_eq!(map.excluded_by("batch"), set(&["db"]));
+    assert_eq!(map.transitive_requires("web"), set(&["api", "cache", "db"]));
+    assert_eq!(map.transitive_requires("unknown"), set(&[]));
+    assert_eq!(
+        map.conflicting_pairs(),
+        vec![("batch", "db"), ("batch", "web")]
+    );
+}
+
+#[test]
+fn test_rule_descriptions_leave_out_metadata() {
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(
+            "app=frontend exclude app=db // provenance=pod;\n\
+             app=frontend require app=cache,app=redis // topology=zone;weight=50;\n",
+            EntitySource::File("deploy/frontend.ir".to_string()),
+        )
+        .unwrap();
+    let descriptions = entities
+        .iter()
+        .flat_map(|entity| entity.rules())
+        .map(|rule| rule.describe())
+        .collect::<Vec<_>>();
+
+    assert!(descriptions.contains(
+        &"app=frontend must not be co-located with app=db on the same node \
+          (from deploy/frontend.ir:1)"
+            .to_string()
+    ));
+    assert!(descriptions.contains(
+        &"app=frontend should be placed with one of app=cache, app=redis on the same zone \
+          (from deploy/frontend.ir:2)"
+            .to_string()
+    ));
+}
+
+#[test]
+fn test_decode_source_of_exported_manifests() {
+    let path = std::path::Path::new("exported.yaml");
+    let expected = "kind: Pod # caf\u{e9}\nspec: {}\n";
+
+    let mut bom = vec![0xef, 0xbb, 0xbf];
+    bom.extend_from_slice("kind: Pod # caf\u{e9}\r\nspec: {}\r\n".as_bytes());
+    assert_eq!(decode_source(bom, path), expected);
+
+    let mut utf16 = vec![0xff, 0xfe];
+    utf16.extend(expected.encode_utf16().flat_map(u16::to_le_bytes));
+    assert_eq!(decode_source(utf16, path), expected);
+
+    // Latin-1 comment, the offsets of the lines after it are kept
+    let latin1 = b"kind: Pod # caf\xe9\r\nspec: {}\r\n".to_vec();
+    let decoded = decode_source(latin1, path);
+    assert_eq!(decoded, "kind: Pod # caf\u{fffd}\nspec: {}\n");
+    assert_eq!(decoded.lines().nth(1), Some("spec: {}"));
+
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(
+            &decode_source(b"\xef\xbb\xbfa require b\r\nb exclude c\r\n".to_vec(), path),
+            EntitySource::Unknown,
+        )
+        .unwrap();
+    assert_eq!(entities.len(), 2);
+}
+
+// Telemetry lines only carry sizes and timings, never the names of the model
+#[test]
+fn test_telemetry_appends_anonymous_stats() {
+    let path =
+        std::env::temp_dir().join(format!("deployfix-telemetry-{}.jsonl", std::process::id()));
+    let config = TelemetryConfig {
+        file: Some(path.clone()),
+        endpoint: None,
+    };
+
+    let entities = vec![new_with_require(
+        "app=secret",
+        "app=vault",
+        "secret.yaml",
+        3,
+    )];
+    let map: EntityMap = entities.try_into().unwrap();
+    for _ in 0..2 {
+        let stats = SolveStats::new(
+            "check",
+            &map.problem_size(),
+            std::time::Duration::from_millis(42),
+            1,
+        );
+        write_stats(&config, &stats).unwrap();
+    }
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(content.lines().count(), 2);
+    assert!(!content.contains("secret") && !content.contains("vault"));
+
+    let line = serde_json::from_str::<serde_json::Value>(content.lines().next().unwrap()).unwrap();
+    assert_eq!(line["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(line["command"], "check");
+    assert_eq!(line["entities"], 2);
+    assert_eq!(line["rules"], 1);
+    assert_eq!(line["solve_ms"], 42);
+    assert_eq!(line["conflicts"], 1);
+}
+
+/*
+    app=web both requires and excludes itself, app=shard_1 and app=shard_2 are plain entities
+    Expected: only app=web counts as split, and a tiny budget is exceeded
+*/
+#[test]
+fn test_budget_counts_split_entities() {
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(
+            "app=web require app=web\n\
+             app=web exclude app=web\n\
+             app=shard_1 require app=shard_2\n\
+             app=shard_2 exclude app=shard_1\n",
+            EntitySource::File("model.ir".to_string()),
+        )
+        .unwrap();
+    let map: EntityMap = merge_entities(entities, None).try_into().unwrap();
+
+    let size = map.problem_size();
+    assert_eq!(size.split_entities, 1);
+
+    let budget = |limits: &str| serde_yaml::from_str::<BudgetArgs>(limits).unwrap();
+    assert!(!budget("max_variables: 1\nmax_clauses: 1\n").check(&size, "splitting the model"));
+    assert!(budget("max_variables: 1000\nmax_clauses: 1000\n").check(&size, "splitting the model"));
+}
+
+#[test]
+fn test_snapshot_round_trip() {
+    let mut rng = SmallRng::seed_from_u64(7);
+
+    let entities = (0..200)
+        .map(|i| {
+            let rule = random_rule(&mut rng);
+
+            let mut entity = Entity::new(rule.source().as_ref());
+            entity.source = EntitySource::File(format!("app{}.yaml", i % 3));
+            entity.priority = EntityPriority::from_value((i - 100) * 20_000);
+            match rule.r#type() {
+                EntityRuleType::Require => entity.add_require(rule),
+                EntityRuleType::Exclude => entity.add_exclude(rule),
+            }
+
+            entity
+        })
+        .collect::<Vec<_>>();
+
+    let snapshot = encode_snapshot(&entities);
+    assert_eq!(decode_snapshot(&snapshot).unwrap(), entities);
+
+    assert!(matches!(
+        decode_snapshot(&snapshot[..snapshot.len() - 1]),
+        Err(SnapshotError::Truncated)
+    ));
+
+    let mut newer = snapshot.clone();
+    newer[4] = SNAPSHOT_VERSION as u8 + 1;
+    assert!(matches!(
+        decode_snapshot(&newer),
+        Err(SnapshotError::UnsupportedVersion(_))
+    ));
+}
+
+// Terms of manifests keep their column and byte span, ahead of the offsets of their metadata
+#[test]
+fn test_span_sources_keep_columns_through_snapshots() {
+    let span = SourceSpan {
+        line: 12,
+        column: 9,
+        index: 240,
+        len: 31,
+    };
+    let mut metadata = EntityRuleMetadata::default();
+    metadata.add_metadata("index".to_string(), "0".to_string());
+    metadata.add_metadata("len".to_string(), "4".to_string());
+    let rule = EntityRule::mono(
+        "app=web".into(),
+        "app=db".into(),
+        EntityRuleType::Exclude,
+        EntityRuleSource::Span("k8s/web.yaml".to_string(), span),
+        Some(metadata),
+    );
+
+    assert_eq!(rule.rule_source().to_string(), "k8s/web.yaml:12:9");
+    assert_eq!(rule.file(), Some("k8s/web.yaml"));
+    assert_eq!((rule.line(), rule.column()), (Some(12), Some(9)));
+    assert_eq!(rule.range(), Some((240, 271)));
+
+    let mut entity = Entity::new("app=web");
+    entity.add_exclude(rule);
+    let entities = vec![entity];
+    assert_eq!(
+        decode_snapshot(&encode_snapshot(&entities)).unwrap(),
+        entities
+    );
+}
+
+#[test]
+fn test_server_isolates_tenants_and_audits_checks() {
+    let tenant = |name: &str, token: &str| Tenant {
+        name: name.to_string(),
+        token: token.to_string(),
+        max_entities: Some(2),
+        max_models: Some(1),
+        max_solve_seconds: None,
+    };
+    let request = |method: &str, path: &str, token: &str, body: &str| Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        headers: HashMap::from([("authorization".to_string(), format!("Bearer {}", token))]),
+        body: body.to_string(),
+    };
+
+    let audit_log = std::env::temp_dir().join(format!("deployfix-audit-{}", std::process::id()));
+    let config = ServerConfig {
+        tenants: vec![tenant("payments", "t1"), tenant("search", "t2")],
+        ..Default::default()
+    };
+    let server = Server::new(config, Some(&audit_log)).unwrap();
+
+    let model = "app1 require app2\napp1 exclude app2\napp2 exclude app1\n";
+    let (status, _) = server.handle(&request("PUT", "/models/prod", "t1", model));
+    assert_eq!(status, 200);
+
+    let (status, _) = server.handle(&request(
+        "PUT",
+        "/models/big",
+        "t1",
+        "a require b\nc require d\nd require a\n",
+    ));
+    assert_eq!(status, 413);
+    // Over the model quota, while replacing the model in place is allowed
+    let (status, _) = server.handle(&request("PUT", "/models/staging", "t1", "a require b\n"));
+    assert_eq!(status, 413);
+    let (status, _) = server.handle(&request("PUT", "/models/prod", "t1", model));
+    assert_eq!(status, 200);
+    let (status, _) = server.handle(&request("GET", "/models", "nope", ""));
+    assert_eq!(status, 401);
+    let (status, _) = server.handle(&request("GET", "/models", "t1x", ""));
+    assert_eq!(status, 401);
+
+    let (status, body) = server.handle(&request("POST", "/models/prod/check", "t1", ""));
+    assert_eq!(status, 200);
+    assert!(body["conflicts"]["app1"].is_array());
+
+    // Other tenants do not see the model
+    let (status, _) = server.handle(&request("POST", "/models/prod/check", "t2", ""));
+    assert_eq!(status, 404);
+
+    let audit = std::fs::read_to_string(&audit_log).unwrap();
+    let records = audit
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .map(|record| {
+            (
+                record["tenant"].as_str().unwrap().to_string(),
+                record["status"].as_u64().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        records,
+        vec![("payments".to_string(), 200), ("search".to_string(), 404)]
+    );
+
+    std::fs::remove_file(&audit_log).unwrap();
+}
+
+/*
+    A client connects and stalls without sending its request
+    Expected: other requests are still answered, and shutting down only waits for the
+    stalled one during the grace period
+*/
+#[test]
+fn test_server_handles_connections_concurrently_and_shuts_down() {
+    let config = ServerConfig {
+        tenants: vec![Tenant {
+            name: "payments".to_string(),
+            token: "t1".to_string(),
+            max_entities: None,
+            max_models: None,
+            max_solve_seconds: None,
+        }],
+        shutdown_grace_seconds: Some(1),
+        ..Default::default()
+    };
+    let server = std::sync::Arc::new(Server::new(config, None).unwrap());
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let listener = runtime
+        .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+        .unwrap();
+    let address = listener.local_addr().unwrap();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let serving = runtime.spawn(server.serve_until(listener, async {
+        let _ = stopped.await;
+    }));
+
+    let _stalled = std::net::TcpStream::connect(address).unwrap();
+
+    let url = HttpUrl::parse(&format!("http://{}", address)).unwrap();
+    let health = http::get(&url.join("/healthz")).unwrap();
+    let health = serde_json::from_str::<serde_json::Value>(&health).unwrap();
+    assert_eq!(health["status"], "ok");
+    assert_eq!(health["queued_solves"], 0);
+
+    let status = http::post(&url.join("/models/prod/check"), &[], "").unwrap();
+    assert_eq!(status, 401);
+
+    let started = std::time::Instant::now();
+    stop.send(()).unwrap();
+    runtime.block_on(serving).unwrap().unwrap();
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+}
+
+/*
+    Requests with a header line over the line limit, with more headers than the header limit
+    and with a request line over the line limit
+    Expected: 431, 431 and 400 without reading the rest of the request
+*/
+#[test]
+fn test_server_limits_request_lines_and_headers() {
+    use std::io::{Read, Write};
+
+    let config = ServerConfig {
+        tenants: vec![Tenant {
+            name: "payments".to_string(),
+            token: "t1".to_string(),
+            max_entities: None,
+            max_models: None,
+            max_solve_seconds: None,
+        }],
+        shutdown_grace_seconds: Some(1),
+        ..Default::default()
+    };
+    let server = std::sync::Arc::new(Server::new(config, None).unwrap());
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let listener = runtime
+        .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+        .unwrap();
+    let address = listener.local_addr().unwrap();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let serving = runtime.spawn(server.serve_until(listener, async {
+        let _ = stopped.await;
+    }));
+
+    let status = |request: String| {
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        // The server may answer and close before the whole request is sent
+        let _ = stream.write_all(request.as_bytes());
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        response
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    let long_header = format!(
+        "GET /healthz HTTP/1.0\r\nX-Long: {}\r\n\r\n",
+        "a".repeat(http::MAX_LINE_SIZE)
+    );
+    assert_eq!(status(long_header), "431");
+
+    let many_headers = format!(
+        "GET /healthz HTTP/1.0\r\n{}\r\n",
+        "X-Header: a\r\n".repeat(http::MAX_HEADERS + 1)
+    );
+    assert_eq!(status(many_headers), "431");
+
+    let long_path = format!("GET /{} HTTP/1.0\r\n\r\n", "a".repeat(http::MAX_LINE_SIZE));
+    assert_eq!(status(long_path), "400");
+
+    let ok = format!(
+        "GET /healthz HTTP/1.0\r\n{}\r\n",
+        "X-Header: a\r\n".repeat(http::MAX_HEADERS)
+    );
+    assert_eq!(status(ok), "200");
+
+    stop.send(()).unwrap();
+    runtime.block_on(serving).unwrap().unwrap();
+}
+
+/*
+    A conflicts file changing one rule, a new file and an unchanged one
+    Expected: one hunk replacing the rule with its surrounding lines as context, every line of
+    the new file added and no diff for the unchanged file
+*/
+#[test]
+fn test_plan_diffs_output_files() {
+    let current =
+        "conflicts:\n- entity: app=web\n  rule: app=web exclude app=db\n  location: web.yaml:30\n";
+    let content =
+        "conflicts:\n- entity: app=web\n  rule: app=web exclude app=api\n  location: web.yaml:30\n";
+
+    let planned = plan_file("node/conflicts.yaml", Some(current), content);
+    assert_eq!(planned.change, PlanChange::Update);
+    assert_eq!((planned.added, planned.removed), (1, 1));
+    assert_eq!(
+        planned.to_string(),
+        "update node/conflicts.yaml (+1 -1)\n\
+         @@ -1,4 +1,4 @@\n \
+         conflicts:\n \
+         - entity: app=web\n\
+         -  rule: app=web exclude app=db\n\
+         +  rule: app=web exclude app=api\n   \
+         location: web.yaml:30\n"
+    );
+
+    let planned = plan_file("dump.ir", None, "app=web exclude app=db\n");
+    assert_eq!(planned.change, PlanChange::Create);
+    assert_eq!(planned.diff, vec!["+app=web exclude app=db"]);
+
+    let planned = plan_file("dump.ir", Some(content), content);
+    assert_eq!(planned.change, PlanChange::Unchanged);
+    assert!(planned.diff.is_empty());
+    assert_eq!(planned.to_string(), "unchanged dump.ir\n");
+}
+
+/*
+    Namespace shop allows 4 pods for app=web (3 replicas spread over nodes) and app=db (2),
+    namespace batch allows 2 for app=job
+    Expected: shop is over its quota, limited by the quota alone on 3 nodes and by the nodes
+    as well on 2, batch fits
+*/
+#[test]
+fn test_pod_quotas_limit_namespaces() {
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(
+            "app=web exclude app=web // replicas=3;namespace=shop;pod_quota=4;\n\
+             app=db require app=web // replicas=2;namespace=shop;pod_quota=4;\n\
+             app=job require app=web // replicas=2;namespace=batch;pod_quota=2;\n",
+            EntitySource::Unknown,
+        )
+        .unwrap();
+    let entities = merge_entities(entities, None);
+
+    let conflicts = check_pod_quotas(&entities, &[]);
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].namespace, "shop");
+    assert_eq!((conflicts[0].quota, conflicts[0].pods), (4, 5));
+    assert_eq!(
+        conflicts[0].entities.keys().collect::<Vec<_>>(),
+        vec!["app=db", "app=web"]
+    );
+    assert!(conflicts[0].quota_only);
+
+    let output = quota_output(&conflicts);
+    assert!(output.get_conflict_solvers().unwrap()["app=web"].contains(QUOTA_SOLVER));
+
+    let quota_only = |nodes: &str| {
+        let envs = DefaultEnvParser {}.parse(nodes).unwrap();
+        check_pod_quotas(&entities, &envs)[0].quota_only
+    };
+    assert!(quota_only("n1 app=web;\nn2 app=web;\nn3 app=web;\n"));
+    assert!(!quota_only("n1 app=web;\nn2 app=web;\n"));
+}
+
+/*
+    Spreadsheet export with a header, a quoted multi target and a blank row, then the same
+    rows without a header and a row with an unknown relation
+    Expected: the rules with their metadata and rows as lines, the error naming its line
+*/
+#[test]
+fn test_csv_parser_reads_inventories() {
+    let parser = get_parser("csv").unwrap();
+    let source = EntitySource::File("inventory.csv".to_string());
+
+    let mut entities = parser
+        .parse(
+            "Source,Relation,Target,topology,owner\r\n\
+             app=web,require,app=db,zone,payments\r\n\
+             app=web,exclude,\"app=batch, app=etl\",,\r\n\
+             ,,,,\r\n",
+            source.clone(),
+        )
+        .unwrap();
+    entities.sort_by(|a, b| a.name.cmp(&b.name));
+    assert_eq!(entities.len(), 1);
+
+    let require = entities[0].requires.iter().next().unwrap();
+    assert_eq!(require.targets()[0].as_ref(), "app=db");
+    assert_eq!(require.metadata("topology"), Some("zone"));
+    assert_eq!(require.metadata("owner"), Some("payments"));
+    assert_eq!(require.line(), Some(2));
+
+    let exclude = entities[0].excludes.iter().next().unwrap();
+    let targets = exclude
+        .targets()
+        .into_iter()
+        .map(|target| target.as_ref())
+        .collect::<Vec<_>>();
+    assert_eq!(targets, vec!["app=batch", "app=etl"]);
+    assert_eq!(exclude.metadata("owner"), None);
+
+    let entities = parser
+        .parse("app=web,require,app=db,topology=zone\n", source.clone())
+        .unwrap();
+    let require = entities[0].requires.iter().next().unwrap();
+    assert_eq!(require.metadata("topology"), Some("zone"));
+
+    let err = parser
+        .parse("app=web,require,app=db\napp=web,needs,app=db\n", source)
+        .unwrap_err();
+    assert!(err.to_string().contains("Line 2: Invalid relation `needs`"));
+}
+
+/*
+    Deployment placed with a nodeSelector and a nodeName, injected back after dropping the
+    rule of its nodeName
+    Expected: requires of the node labels at the lines of their keys, the selector kept and
+    the node name gone
+*/
+#[test]
+fn test_k8s_node_selector_and_node_name() {
+    use deployfix::plugin::k8s::K8sPlugin;
+
+    let dir = std::env::temp_dir().join(format!("deployfix-node-selector-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let manifest = dir.join("web.yaml");
+    std::fs::write(
+        &manifest,
+        "apiVersion: apps/v1\n\
+         kind: Deployment\n\
+         metadata:\n  name: web\n\
+         spec:\n  selector:\n    matchLabels:\n      app: web\n\
+         \x20 template:\n    metadata:\n      labels:\n        app: web\n\
+         \x20   spec:\n      nodeName: node-1\n      nodeSelector:\n        disktype: ssd\n\
+         \x20     containers:\n        - name: web\n          image: nginx\n",
+    )
+    .unwrap();
+
+    let plugin = K8sPlugin::default();
+    let mut entities = plugin.extract_entities(&manifest).unwrap();
+    assert_eq!(entities.len(), 1);
+
+    let requires = entities[0]
+        .requires
+        .iter()
+        .map(|rule| {
+            (
+                rule.targets()[0].as_ref().to_string(),
+                rule.metadata("type").unwrap().to_string(),
+                rule.line(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        requires,
+        vec![
+            (
+                "disktype=ssd".to_string(),
+                "nodeSelector".to_string(),
+                Some(16)
+            ),
+            (
+                "kubernetes.io/hostname=node-1".to_string(),
+                "nodeName".to_string(),
+                Some(14)
+            ),
+        ]
+    );
+
+    entities[0]
+        .requires
+        .retain(|rule| rule.metadata("type") != Some("nodeName"));
+    let injected = plugin.inject_entities(entities).unwrap();
+    assert_eq!(injected.len(), 1);
+    assert!(injected[0]
+        .1
+        .contains("nodeSelector:\n        disktype: ssd"));
+    assert!(!injected[0].1.contains("nodeName"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/*
+    Test: a manifest given in memory under a logical name, with a node selector and a node
+    name, extracted, injected without its nodeName rule and with the rule removed
+    Expected: rules located in the logical name, both rewrites keyed by it without nodeName,
+    and an error for a manifest which was not given
+*/
+#[test]
+fn test_k8s_in_memory_manifests() {
+    use deployfix::plugin::k8s::K8sPlugin;
+
+    let manifest = "apiVersion: apps/v1\n\
+                    kind: Deployment\n\
+                    metadata:\n  name: web\n\
+                    spec:\n  selector:\n    matchLabels:\n      app: web\n\
+                    \x20 template:\n    metadata:\n      labels:\n        app: web\n\
+                    \x20   spec:\n      nodeName: node-1\n      nodeSelector:\n        disktype: ssd\n\
+                    \x20     containers:\n        - name: web\n          image: nginx\n";
+    let manifests = BTreeMap::from([("team/web.yaml".to_string(), manifest.to_string())]);
+
+    let plugin = K8sPlugin::default();
+    let entities = plugin.extract_entities_from_manifests(&manifests).unwrap();
+    assert_eq!(entities.len(), 1);
+    assert!(entities[0]
+        .requires
+        .iter()
+        .all(|rule| rule.file() == Some("team/web.yaml")));
+    let node_name = entities[0]
+        .requires
+        .iter()
+        .find(|rule| rule.metadata("type") == Some("nodeName"))
+        .unwrap()
+        .clone();
+
+    let mut kept = entities.clone();
+    kept[0].requires.retain(|rule| rule != &node_name);
+    let injected = plugin.inject_manifests(kept, &manifests).unwrap();
+    assert_eq!(injected.keys().collect::<Vec<_>>(), vec!["team/web.yaml"]);
+    assert!(injected["team/web.yaml"].contains("nodeSelector:\n        disktype: ssd"));
+    assert!(!injected["team/web.yaml"].contains("nodeName"));
+
+    let bytes = BTreeMap::from([("team/web.yaml".to_string(), manifest.as_bytes().to_vec())]);
+    let removed =
+        K8sPlugin::remove_rules_from_manifests(entities.clone(), &[node_name], &bytes).unwrap();
+    assert!(removed["team/web.yaml"].contains("disktype: ssd"));
+    assert!(!removed["team/web.yaml"].contains("nodeName"));
+
+    let err = plugin
+        .inject_manifests(entities, &BTreeMap::<String, String>::new())
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("No manifest named team/web.yaml"));
+}
+
+/*
+    Test: a manifest spreading its pods over a custom rack label, read in the same process by
+    a plugin configured with that label and by one with the default config
+    Expected: a rack rule from the configured plugin, an invalid topology key from the other
+*/
+#[test]
+fn test_k8s_config_per_plugin() {
+    use deployfix::plugin::k8s::{K8sConfig, K8sPlugin};
+
+    let manifest = "apiVersion: apps/v1\n\
+                    kind: Deployment\n\
+                    metadata:\n  name: web\n\
+                    spec:\n  selector:\n    matchLabels:\n      app: web\n\
+                    \x20 template:\n    metadata:\n      labels:\n        app: web\n\
+                    \x20   spec:\n      affinity:\n        podAntiAffinity:\n\
+                    \x20         requiredDuringSchedulingIgnoredDuringExecution:\n\
+                    \x20         - labelSelector:\n              matchExpressions:\n\
+                    \x20             - key: app\n                operator: In\n\
+                    \x20               values:\n                - db\n\
+                    \x20           topologyKey: example.com/rack\n\
+                    \x20     containers:\n        - name: web\n          image: nginx\n";
+    let manifests = BTreeMap::from([("web.yaml".to_string(), manifest.to_string())]);
+
+    let racks = K8sPlugin::new(K8sConfig {
+        rack_label: "example.com/rack".to_string(),
+        ..Default::default()
+    });
+    let entities = racks.extract_entities_from_manifests(&manifests).unwrap();
+    let topologies = entities[0]
+        .excludes
+        .iter()
+        .map(|rule| rule.metadata(METADATA_TOPOLOGY_KEY))
+        .collect::<Vec<_>>();
+    assert_eq!(topologies, vec![Some("rack")]);
+
+    let err = K8sPlugin::default()
+        .extract_entities_from_manifests(&manifests)
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("Invalid topology key"));
+}
+
+/*
+    Test: an anti-affinity term with an `Exists` expression next to an `In` one, and a document
+    of a kind the plugin does not model, given in memory
+    Expected: the `Exists` expression skipped with the `In` one kept, and an error rather than
+    a panic for the unknown document
+*/
+#[test]
+fn test_k8s_unsupported_operator_and_resource() {
+    use deployfix::plugin::k8s::K8sPlugin;
+
+    let manifest = "apiVersion: apps/v1\n\
+                    kind: Deployment\n\
+                    metadata:\n  name: web\n\
+                    spec:\n  selector:\n    matchLabels:\n      app: web\n\
+                    \x20 template:\n    metadata:\n      labels:\n        app: web\n\
+                    \x20   spec:\n      affinity:\n        podAntiAffinity:\n\
+                    \x20         requiredDuringSchedulingIgnoredDuringExecution:\n\
+                    \x20         - labelSelector:\n              matchExpressions:\n\
+                    \x20             - key: tier\n                operator: Exists\n\
+                    \x20             - key: app\n                operator: In\n\
+                    \x20               values:\n                - db\n\
+                    \x20           topologyKey: kubernetes.io/hostname\n\
+                    \x20     containers:\n        - name: web\n          image: nginx\n";
+    let manifests = BTreeMap::from([("web.yaml".to_string(), manifest.to_string())]);
+
+    let entities = K8sPlugin::default()
+        .extract_entities_from_manifests(&manifests)
+        .unwrap();
+    let keys = entities[0]
+        .excludes
+        .iter()
+        .map(|rule| rule.metadata("key"))
+        .collect::<Vec<_>>();
+    assert_eq!(keys, vec![Some("app")]);
+
+    let err = K8sPlugin::id_document("apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: web\n")
+        .unwrap_err();
+    assert!(err.to_string().contains("Unknown resource type"));
+}
+
+/*
+    Test: a custom resource annotated with two comma separated entities under a configured
+    annotation rule, and a config giving an annotation rule an unknown topology
+    Expected: one require per entity rather than a rule satisfied by either, and the config
+    rejected when its section is loaded
+*/
+#[test]
+fn test_k8s_annotation_rules() {
+    use deployfix::plugin::k8s::{K8sConfig, K8sPlugin};
+
+    let config = serde_yaml::from_str::<Config>(
+        "k8s:\n  annotation_rules:\n    mycompany.io/colocate-with:\n      rule: require\n",
+    )
+    .unwrap()
+    .section::<K8sConfig>()
+    .unwrap();
+    let manifest = "apiVersion: example.com/v1\n\
+                    kind: Widget\n\
+                    metadata:\n  name: web\n\
+                    \x20 annotations:\n    mycompany.io/colocate-with: \"db, cache\"\n";
+    let manifests = BTreeMap::from([("web.yaml".to_string(), manifest.to_string())]);
+
+    let entities = K8sPlugin::new(config)
+        .extract_entities_from_manifests(&manifests)
+        .unwrap();
+    let requires = entities[0]
+        .requires
+        .iter()
+        .map(|rule| {
+            rule.targets()
+                .iter()
+                .map(|target| target.as_ref().to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect::<BTreeSet<_>>();
+    assert_eq!(
+        requires,
+        BTreeSet::from([vec!["app=cache".to_string()], vec!["app=db".to_string()]])
+    );
+
+    let err = serde_yaml::from_str::<Config>(
+        "k8s:\n  annotation_rules:\n    mycompany.io/spread-from:\n      rule: exclude\n      topology: region\n",
+    )
+    .unwrap()
+    .section::<K8sConfig>()
+    .unwrap_err();
+    assert!(format!("{:#}", err).contains("region"));
+}
+
+/*
+    Test: a pod affinity term with a namespace selector and labels deployfix does not model,
+    imported, written as IR, read back and injected into the manifest
+    Expected: the IR carries the fields as passthrough metadata and the injected term keeps
+    them, `;` included
+*/
+#[test]
+fn test_k8s_passthrough_round_trip() {
+    use deployfix::plugin::k8s::K8sPlugin;
+
+    let manifest = "apiVersion: apps/v1\n\
+                    kind: Deployment\n\
+                    metadata:\n  name: web\n\
+                    spec:\n  selector:\n    matchLabels:\n      app: web\n\
+                    \x20 template:\n    metadata:\n      labels:\n        app: web\n\
+                    \x20   spec:\n      affinity:\n        podAffinity:\n\
+                    \x20         requiredDuringSchedulingIgnoredDuringExecution:\n\
+                    \x20         - labelSelector:\n              matchLabels:\n\
+                    \x20               tier: a;b\n              matchExpressions:\n\
+                    \x20             - key: app\n                operator: In\n\
+                    \x20               values:\n                - db\n\
+                    \x20           namespaceSelector:\n              matchLabels:\n\
+                    \x20               team: pay\n\
+                    \x20           topologyKey: kubernetes.io/hostname\n\
+                    \x20     containers:\n        - name: web\n          image: nginx\n";
+    let manifests = BTreeMap::from([("web.yaml".to_string(), manifest.to_string())]);
+
+    let plugin = K8sPlugin::default();
+    let entities = plugin.extract_entities_from_manifests(&manifests).unwrap();
+    let ir = DeployIRFormatter::format(&entities);
+    assert!(ir.contains(r#"passthrough={"labelSelector":{"matchLabels":{"tier":"a%3Bb"}},"namespaceSelector":{"matchLabels":{"team":"pay"}}}"#));
+
+    let entities = get_parser("deployfix")
+        .unwrap()
+        .parse(&ir, EntitySource::Unknown)
+        .unwrap();
+    let injected = plugin.inject_manifests(entities, &manifests).unwrap();
+    let injected = &injected["web.yaml"];
+    assert!(injected.contains("namespaceSelector:"));
+    assert!(injected.contains("team: pay"));
+    assert!(injected.contains("tier: a;b"));
+}
+
+/*
+    Test: the deployments db and web written alone and as the documents of one manifest, then
+    the exclude of web removed from that manifest
+    Expected: the rules of web read from the manifest at the lines and bytes of the file, and
+    the removal rewriting only the document of web, which keeps its node selector
+*/
+#[test]
+fn test_k8s_multi_document_lines() {
+    use deployfix::plugin::k8s::K8sPlugin;
+
+    let deployment = |name: &str, other: &str, node_selector: &str| {
+        format!(
+            "apiVersion: apps/v1\n\
+             kind: Deployment\n\
+             metadata:\n  name: {name}\n\
+             spec:\n  selector:\n    matchLabels:\n      app: {name}\n\
+             \x20 template:\n    metadata:\n      labels:\n        app: {name}\n\
+             \x20   spec:\n{node_selector}      affinity:\n        podAntiAffinity:\n\
+             \x20         requiredDuringSchedulingIgnoredDuringExecution:\n\
+             \x20         - labelSelector:\n              matchExpressions:\n\
+             \x20             - key: app\n                operator: In\n\
+             \x20               values:\n                - {other}\n\
+             \x20           topologyKey: kubernetes.io/hostname\n\
+             \x20     containers:\n        - name: {name}\n          image: nginx\n"
+        )
+    };
+    let db = deployment("db", "cache", "");
+    let web = deployment("web", "db", "      nodeSelector:\n        disktype: ssd\n");
+    let manifest = format!("# db and web\n{db}---\n{web}");
+
+    let plugin = K8sPlugin::default();
+    let extract = |data: &str| {
+        let manifests = BTreeMap::from([("m.yaml".to_string(), data.to_string())]);
+        let entities = plugin.extract_entities_from_manifests(&manifests).unwrap();
+        entities
+            .into_iter()
+            .find(|e| e.name.0 == "app=web")
+            .unwrap()
+    };
+    let (alone, within) = (extract(&web), extract(&manifest));
+
+    let offset = manifest.len() - web.len();
+    let lines_before = manifest[..offset].matches('\n').count();
+    let exclude = |entity: &Entity| entity.excludes.iter().next().unwrap().clone();
+    let (term, moved) = (exclude(&alone), exclude(&within));
+    assert_eq!(moved.line(), Some(term.line().unwrap() + lines_before));
+    assert_eq!(moved.column(), term.column());
+    let (start, end) = moved.range().unwrap();
+    assert_eq!((start - offset, end - offset), term.range().unwrap());
+    assert_eq!(&manifest[start..end], &web[start - offset..end - offset]);
+    let selector = |entity: &Entity| {
+        entity
+            .requires
+            .iter()
+            .find(|rule| rule.metadata("type") == Some("nodeSelector"))
+            .and_then(|rule| rule.line())
+            .unwrap()
+    };
+    assert_eq!(selector(&within), selector(&alone) + lines_before);
+
+    let manifests = BTreeMap::from([("m.yaml".to_string(), manifest.as_bytes().to_vec())]);
+    let removed =
+        K8sPlugin::remove_rules_from_manifests(vec![within], &[moved], &manifests).unwrap();
+    let documents = removed["m.yaml"].split("---\n").collect::<Vec<_>>();
+    assert_eq!(documents.len(), 2);
+    assert_eq!(documents[0], format!("# db and web\n{db}"));
+    assert!(!documents[1].contains("labelSelector"));
+    assert!(documents[1].contains("disktype: ssd"));
+}
+
+/*
+    Test: the deployments db and web of one manifest, their names normalized from app= to
+    svc=, injected back into the manifest
+    Expected: each document found by the name its workload was renamed to, the injection
+    failing without the renames
+*/
+#[test]
+fn test_k8s_inject_normalized_multi_document() {
+    use deployfix::plugin::k8s::K8sPlugin;
+
+    let deployment = |name: &str, other: &str| {
+        format!(
+            "apiVersion: apps/v1\n\
+             kind: Deployment\n\
+             metadata:\n  name: {name}\n\
+             spec:\n  selector:\n    matchLabels:\n      app: {name}\n\
+             \x20 template:\n    metadata:\n      labels:\n        app: {name}\n\
+             \x20   spec:\n      affinity:\n        podAntiAffinity:\n\
+             \x20         requiredDuringSchedulingIgnoredDuringExecution:\n\
+             \x20         - labelSelector:\n              matchExpressions:\n\
+             \x20             - key: app\n                operator: In\n\
+             \x20               values:\n                - {other}\n\
+             \x20           topologyKey: kubernetes.io/hostname\n\
+             \x20     containers:\n        - name: {name}\n          image: nginx\n"
+        )
+    };
+    let manifest = format!(
+        "{}---\n{}",
+        deployment("db", "web"),
+        deployment("web", "db")
+    );
+    let manifests = BTreeMap::from([("m.yaml".to_string(), manifest)]);
+
+    let entities = K8sPlugin::default()
+        .extract_entities_from_manifests(&manifests)
+        .unwrap();
+    let normalizations =
+        serde_yaml::from_str::<Normalizations>("- pattern: '^app=(.+)$'\n  replace: svc=$1\n")
+            .unwrap();
+    let (entities, renames) = normalizations.apply_with_renames(entities);
+    assert_eq!(renames["app=web"], "svc=web");
+
+    let err = K8sPlugin::default()
+        .inject_manifests(entities.clone(), &manifests)
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("No document of m.yaml declares entity"));
+
+    let injected = K8sPlugin::default()
+        .with_renames(renames)
+        .inject_manifests(entities, &manifests)
+        .unwrap();
+    let documents = injected["m.yaml"].split("---\n").collect::<Vec<_>>();
+    assert_eq!(documents.len(), 2);
+    assert!(documents[0].contains("name: db"));
+    assert!(documents[1].contains("name: web"));
+}
+
+/*
+    Test: a pod created through the ReplicaSet of the Deployment web, requiring and excluding
+    db, and a pod of the standalone ReplicaSet web-frontend, whose name has no template hash
+    Expected: the first pod folded into app=web and its conflict reported for app=web with the
+    pod on its rules, the second pod kept as app=web-frontend
+*/
+#[test]
+fn test_k8s_pods_fold_into_their_owner() {
+    use deployfix::plugin::k8s::K8sPlugin;
+
+    let pod = |name: &str, owner: &str, labels: &str, affinity: &str| {
+        format!(
+            "apiVersion: v1\n\
+             kind: Pod\n\
+             metadata:\n  name: {name}\n  labels:\n{labels}\
+             \x20 ownerReferences:\n  - apiVersion: apps/v1\n    kind: ReplicaSet\n\
+             \x20   name: {owner}\n    uid: \"1\"\n    controller: true\n\
+             spec:\n{affinity}  containers:\n  - name: app\n    image: nginx\n"
+        )
+    };
+    let term = |key: &str| {
+        format!(
+            "    {key}:\n      requiredDuringSchedulingIgnoredDuringExecution:\n\
+             \x20     - labelSelector:\n          matchExpressions:\n\
+             \x20         - key: app\n            operator: In\n            values:\n\
+             \x20           - db\n        topologyKey: kubernetes.io/hostname\n"
+        )
+    };
+    let manifests = BTreeMap::from([
+        (
+            "web.yaml".to_string(),
+            pod(
+                "web-6d4cf56db6-x2x7k",
+                "web-6d4cf56db6",
+                "    app: web\n    pod-template-hash: 6d4cf56db6\n",
+                &format!(
+                    "  affinity:\n{}{}",
+                    term("podAffinity"),
+                    term("podAntiAffinity")
+                ),
+            ),
+        ),
+        (
+            "front.yaml".to_string(),
+            pod(
+                "web-frontend-8kq2n",
+                "web-frontend",
+                "    app: web-frontend\n",
+                &format!("  affinity:\n{}", term("podAffinity")),
+            ),
+        ),
+        (
+            "db.yaml".to_string(),
+            "apiVersion: v1\nkind: Pod\nmetadata:\n  name: db\n  labels:\n    app: db\n\
+             spec:\n  containers:\n  - name: db\n    image: postgres\n"
+                .to_string(),
+        ),
+    ]);
+
+    let entities = K8sPlugin::default()
+        .extract_entities_from_manifests(&manifests)
+        .unwrap();
+    let names = entities
+        .iter()
+        .map(|entity| entity.name.0.as_str())
+        .collect::<BTreeSet<_>>();
+    assert!(names.contains("app=web"));
+    assert!(names.contains("app=web-frontend"));
+    assert!(!names.contains("app=web-6d4cf56db6"));
+
+    let map: EntityMap = entities.clone().try_into().unwrap();
+    let SolverOutput::Conflict(conflicts, solvers) = get_solver("z3").unwrap().solve(&map) else {
+        panic!("the pod of web requires and excludes db");
+    };
+    assert!(conflicts["app=web"]
+        .iter()
+        .all(|rule| rule.pod() == Some("web-6d4cf56db6-x2x7k")));
+
+    let mut report = ConflictReport::new(OutputLevel::Quiet).with_session_outputs();
+    report.add(&conflicts, &solvers, &entities);
+    assert!(report
+        .recorded_conflicts()
+        .iter()
+        .any(|conflict| conflict.entity == "app=web"));
+    assert!(report
+        .recorded_conflicts()
+        .iter()
+        .all(|conflict| conflict.entity != "app=web-6d4cf56db6-x2x7k"));
+}